@@ -0,0 +1,85 @@
+//! stream process creation events to tcp clients as json lines
+//!
+//! cargo run --example event_server --features async -- -n win10 -j win10.json --listen 127.0.0.1:7070
+
+use std::net::SocketAddr;
+
+use clap::Parser;
+use loonaro_vmi::async_session::AsyncSession;
+use loonaro_vmi::cli::VmiArgs;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_stream::StreamExt;
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    vmi: VmiArgs,
+
+    /// address clients connect to
+    #[arg(long, default_value = "127.0.0.1:7070")]
+    listen: SocketAddr,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    // init and event registration talk to libvmi, keep them off the runtime
+    let builder = args.vmi.to_builder();
+    let session = tokio::task::spawn_blocking(move || {
+        let session = builder.build()?;
+        session.monitor_processes(false)?;
+        anyhow::Ok(session)
+    })
+    .await??;
+
+    let session = AsyncSession::start(session);
+    let mut events = session.session().event_stream()?;
+
+    // every client gets every event from when it connected, slow ones lag
+    let (tx, _) = broadcast::channel::<String>(1024);
+    let lines = tx.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            let _ = lines.send(event.to_json().to_string());
+        }
+    });
+
+    let listener = TcpListener::bind(args.listen).await?;
+    eprintln!("Serving events on {}. Press Ctrl+C to stop.", args.listen);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (mut socket, peer) = accepted?;
+                let mut rx = tx.subscribe();
+                tokio::spawn(async move {
+                    loop {
+                        let line = match rx.recv().await {
+                            Ok(line) => line,
+                            Err(broadcast::error::RecvError::Lagged(n)) => {
+                                eprintln!("[event_server] {} lagged, skipped {} events", peer, n);
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        };
+                        if socket.write_all(format!("{}\n", line).as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+
+    eprintln!("\nExiting...");
+    let dropped = session.session().dropped_events();
+    session.shutdown().await?;
+    if dropped > 0 {
+        eprintln!("{} events dropped, the consumer fell behind", dropped);
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! log every NtProtectVirtualMemory call made by one process
+//!
+//! cargo run --example protect_monitor -- -n win10 -j win10.json --pid 1234
+
+use clap::Parser;
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::ffi::{R8, R9, RDX};
+use loonaro_vmi::hook::HookContext;
+use loonaro_vmi::os::windows::user_hook::hook_user_api;
+use loonaro_vmi::vmi::OsType;
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    vmi: VmiArgs,
+
+    /// process to watch
+    #[arg(long)]
+    pid: u64,
+}
+
+/// NtProtectVirtualMemory(Process, *BaseAddress, *RegionSize, NewProtect, *OldProtect)
+fn on_protect(ctx: &HookContext) {
+    let regs = match unsafe { ctx.regs.as_ref() } {
+        Some(regs) => regs,
+        None => return,
+    };
+    let vmi = ctx.vmi;
+
+    // BaseAddress and RegionSize are in/out pointers into the caller's memory
+    let read_ptr = |vaddr: u64| {
+        vmi.read_va_dtb(regs.cr3, vaddr, 8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap_or([0; 8])))
+            .unwrap_or(0)
+    };
+    let base_ptr = vmi.get_vcpureg(RDX as u64, ctx.vcpu_id).unwrap_or(0);
+    let size_ptr = vmi.get_vcpureg(R8 as u64, ctx.vcpu_id).unwrap_or(0);
+    let protect = vmi.get_vcpureg(R9 as u64, ctx.vcpu_id).unwrap_or(0);
+
+    println!(
+        "NtProtectVirtualMemory | Base: {:#x} | Size: {:#x} | NewProtect: {:#x}",
+        read_ptr(base_ptr),
+        read_ptr(size_ptr),
+        protect
+    );
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let session = args
+        .vmi
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    if session.vmi().lock().unwrap().os_type() != OsType::Windows {
+        anyhow::bail!("only Windows supported");
+    }
+
+    // declared after the session so it unpatches before the session shuts down
+    let _hook = {
+        let vmi = session.vmi();
+        let vmi_lock = vmi.lock().unwrap();
+        hook_user_api(
+            &vmi_lock,
+            &session.windows_offsets(),
+            session.hooks(),
+            args.pid,
+            "ntdll.dll",
+            "NtProtectVirtualMemory",
+            on_protect,
+        )
+        .map_err(|e| anyhow::anyhow!("hook failed: {}", e))?
+    };
+
+    eprintln!("Watching pid {}. Press Ctrl+C to stop.", args.pid);
+
+    session.run_until_ctrlc()?;
+
+    Ok(())
+}
@@ -0,0 +1,129 @@
+//! tokio integration, behind the `async` feature
+//!
+//! libvmi calls block, and the listen loop holds the vmi lock for whole
+//! rounds, so none of this touches the vmi on a runtime thread: the loop
+//! keeps its own thread, actions run on spawn_blocking and the event stream
+//! is fed by a bridge thread.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::error::{Result, VmiError};
+use crate::event_stream::{GuestEvent, EVENT_QUEUE_LEN};
+use crate::os::Action;
+use crate::session::{join_loop, Session};
+
+impl Session {
+    /// the event stream as a futures Stream, taken once like events().
+    /// events are still dropped and counted when the consumer falls behind
+    pub fn event_stream(&self) -> Result<ReceiverStream<GuestEvent>> {
+        let rx = self.events()?;
+        let (tx, stream_rx) = mpsc::channel(EVENT_QUEUE_LEN);
+        thread::spawn(move || {
+            for event in rx {
+                if tx.blocking_send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(ReceiverStream::new(stream_rx))
+    }
+
+    /// execute() on a blocking task, queued behind the loop like execute
+    pub async fn execute_async<A, T>(&self, action: A) -> Result<T>
+    where
+        A: Action<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let dispatcher = self.dispatcher().clone();
+        blocking(move || dispatcher.call(move |vmi| action.execute(vmi))?).await
+    }
+}
+
+/// run `f` on tokio's blocking pool, resuming its panic on the caller
+async fn blocking<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce() -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(VmiError::Other(format!("blocking task: {}", e))),
+    }
+}
+
+/// a session with its event loop running, owned so it can move into a task.
+/// SessionHandle borrows the session, which doesn't survive an await.
+/// methods of session() other than the async ones still block the caller
+pub struct AsyncSession {
+    session: Option<Session>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl AsyncSession {
+    /// start dispatching events on the loop's own thread
+    pub fn start(session: Session) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread = session.spawn_loop(running.clone());
+        Self {
+            session: Some(session),
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        self.session
+            .as_ref()
+            .expect("session is only taken on shutdown")
+    }
+
+    /// false once shutting down or the loop exited on an error
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+            && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// stop the loop and tear the session down on a blocking task. events
+    /// are disabled and hooks restored by the time this completes. returns
+    /// the loop's error if it had already stopped on one
+    pub async fn shutdown(mut self) -> Result<()> {
+        let teardown = self.teardown();
+        blocking(teardown).await
+    }
+
+    fn teardown(&mut self) -> impl FnOnce() -> Result<()> + Send + 'static {
+        self.running.store(false, Ordering::SeqCst);
+        let thread = self.thread.take();
+        let session = self.session.take();
+        move || {
+            let result = thread.map_or(Ok(()), join_loop);
+            // the loop dispatches into the session, drop it only after the join
+            drop(session);
+            result
+        }
+    }
+}
+
+impl Drop for AsyncSession {
+    /// without shutdown().await the teardown still leaves runtime threads
+    /// alone, but nothing waits for the hooks to be restored
+    fn drop(&mut self) {
+        let teardown = self.teardown();
+        match Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(teardown);
+            }
+            Err(_) => {
+                let _ = teardown();
+            }
+        }
+    }
+}
@@ -1,14 +1,172 @@
 //! common CLI args for all bins
 
-use clap::Args;
+use clap::{Args, ValueEnum};
 use std::path::PathBuf;
 
+use crate::session::SessionBuilder;
+use crate::vmi::{Backend, VmiBuilder, DEFAULT_KVMI_SOCKET};
+
+/// --output values
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// aligned text for people
+    #[default]
+    Table,
+    /// one JSON document
+    Json,
+    /// one JSON object per line
+    Jsonl,
+}
+
+/// how commands print their results
+#[derive(Args, Debug, Clone, Copy, Default)]
+pub struct OutputArgs {
+    /// output format, json and jsonl carry a versioned "schema" field
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+}
+
+/// --backend values
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackendArg {
+    #[default]
+    Kvm,
+    Xen,
+}
+
 #[derive(Args, Debug, Clone)]
 pub struct VmiArgs {
-    #[arg(short, long)]
-    pub name: String,
+    /// guest domain name
+    #[arg(short, long, required_unless_present = "domain_id")]
+    pub name: Option<String>,
+    /// numeric guest domain id, instead of --name
+    #[arg(long, conflicts_with = "name")]
+    pub domain_id: Option<u64>,
     #[arg(short, long)]
     pub json: PathBuf,
-    #[arg(short = 'k', long, default_value = "/tmp/introspector")]
+    /// kvmi introspector socket, kvm backend only
+    #[arg(short = 'k', long, default_value = DEFAULT_KVMI_SOCKET)]
     pub socket_path: PathBuf,
+    /// hypervisor to introspect through
+    #[arg(long, value_enum, default_value_t = BackendArg::Kvm)]
+    pub backend: BackendArg,
+    /// flush libvmi's translation caches after writes and pauses.
+    /// avoids stale mappings while hooking at the cost of slower reads
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+impl VmiArgs {
+    /// vmi init options from the command line
+    pub fn builder(&self) -> VmiBuilder {
+        let json = self.json.to_string_lossy();
+        let socket = self.socket_path.to_string_lossy();
+        let builder = match self.domain_id {
+            Some(id) => VmiBuilder::for_domain_id(id, &json, &socket),
+            None => VmiBuilder::new(self.name.as_deref().unwrap_or_default(), &json, &socket),
+        };
+        let backend = match self.backend {
+            BackendArg::Kvm => Backend::Kvm {
+                socket: socket.into_owned(),
+            },
+            BackendArg::Xen => Backend::Xen,
+        };
+        builder.backend(backend).no_cache(self.no_cache)
+    }
+
+    /// session options from the command line
+    pub fn to_builder(&self) -> SessionBuilder {
+        SessionBuilder::from(self.builder())
+    }
+
+    /// guest name or `domain <id>`, for messages
+    pub fn domain_label(&self) -> String {
+        match (&self.name, self.domain_id) {
+            (Some(name), _) => name.clone(),
+            (None, Some(id)) => format!("domain {}", id),
+            (None, None) => "<no domain>".into(),
+        }
+    }
+}
+
+/// clap parser for addresses, hex with 0x or plain decimal
+pub fn parse_addr(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|e| format!("invalid address {}: {}", s, e))
+}
+
+/// clap parser for sizes: bytes, or a K/M/G suffix (powers of 1024)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, shift) = match s.as_bytes().last().map(u8::to_ascii_uppercase) {
+        Some(b'K') => (&s[..s.len() - 1], 10),
+        Some(b'M') => (&s[..s.len() - 1], 20),
+        Some(b'G') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("invalid size {}, expected e.g. 4096, 64K or 100M", s))
+}
+
+/// clap parser for byte counts, same syntax as parse_addr but nonzero
+pub fn parse_len(s: &str) -> Result<u64, String> {
+    match parse_addr(s) {
+        Ok(0) => Err("length must be nonzero".into()),
+        Ok(len) => Ok(len),
+        Err(_) => Err(format!("invalid length {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vmi::Domain;
+
+    fn args() -> VmiArgs {
+        VmiArgs {
+            name: Some("win10".into()),
+            domain_id: None,
+            json: "/profiles/win10.json".into(),
+            socket_path: DEFAULT_KVMI_SOCKET.into(),
+            backend: BackendArg::Kvm,
+            no_cache: false,
+        }
+    }
+
+    #[test]
+    fn parse_len_wants_a_nonzero_count() {
+        assert_eq!(parse_len("0x1000"), Ok(0x1000));
+        assert_eq!(parse_len("4096"), Ok(4096));
+        assert!(parse_len("0").is_err());
+        assert!(parse_len("0x0").is_err());
+        assert!(parse_len("-1").is_err());
+        assert!(parse_len("4K").is_err());
+    }
+
+    #[test]
+    fn builder_from_flags() {
+        let builder = args().builder();
+        assert!(builder.validate().is_ok());
+        assert!(matches!(builder.domain(), Ok(Domain::Name(name)) if name == "win10"));
+        assert_eq!(builder.json_path(), Some("/profiles/win10.json"));
+    }
+
+    #[test]
+    fn builder_by_domain_id_on_xen() {
+        let args = VmiArgs {
+            name: None,
+            domain_id: Some(7),
+            backend: BackendArg::Xen,
+            ..args()
+        };
+        let builder = args.to_builder();
+        assert!(builder.validate().is_ok());
+        assert!(matches!(builder.vmi().domain(), Ok(Domain::Id(7))));
+    }
 }
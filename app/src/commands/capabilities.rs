@@ -0,0 +1,23 @@
+//! capabilities command implementation
+
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::os::Capability;
+
+pub fn run(args: &VmiArgs) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    println!("OS: {:?}\n", session.os_type());
+    for &capability in Capability::ALL {
+        let mark = if session.supports(capability) {
+            "yes"
+        } else {
+            "no"
+        };
+        println!("{:<24} {}", capability.name(), mark);
+    }
+
+    Ok(())
+}
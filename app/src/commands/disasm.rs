@@ -0,0 +1,165 @@
+//! disasm command implementation
+//!
+//! json output is schema loonaro.disasm.v1: `start` and `instructions`,
+//! each with `addr`, `bytes` (hex string), `text`, `target`,
+//! `target_symbol` and `hooked`. jsonl prints one instruction per line
+
+use super::output;
+use loonaro_vmi::cli::{OutputFormat, VmiArgs};
+use loonaro_vmi::disasm::{disassemble_region, Bitness};
+use loonaro_vmi::symbols::{format_location, resolve_symbol_expr};
+use loonaro_vmi::vmi::AddressSpace;
+use serde::Serialize;
+
+const SCHEMA: &str = "loonaro.disasm.v1";
+
+/// longest x86 instruction
+const MAX_INSN_LEN: u64 = 15;
+
+/// where the listing begins
+pub enum Start {
+    Symbol(String),
+    Virtual { va: u64, pid: u32 },
+    Physical(u64),
+}
+
+impl Start {
+    pub fn new(
+        sym: Option<String>,
+        va: Option<u64>,
+        pa: Option<u64>,
+        pid: Option<u32>,
+    ) -> anyhow::Result<Self> {
+        match (sym, va, pa) {
+            (Some(sym), _, _) => Ok(Start::Symbol(sym)),
+            (_, Some(va), _) => Ok(Start::Virtual {
+                va,
+                pid: pid.unwrap_or(0),
+            }),
+            (_, _, Some(pa)) => Ok(Start::Physical(pa)),
+            _ => anyhow::bail!("give --sym, --va or --pa"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Instruction {
+    addr: u64,
+    bytes: String,
+    text: String,
+    target: Option<u64>,
+    target_symbol: Option<String>,
+    /// one of the bytes is the 0xCC of one of our hooks, shown as the
+    /// original instruction
+    hooked: bool,
+}
+
+#[derive(Serialize)]
+struct Listing<'a> {
+    start: u64,
+    instructions: &'a [Instruction],
+}
+
+pub fn run(args: &VmiArgs, start: Start, count: usize, format: OutputFormat) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .events(false)
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    let vmi = session.vmi();
+
+    let (space, addr) = match start {
+        Start::Symbol(expr) => {
+            let addr = resolve_symbol_expr(&vmi.lock().unwrap(), &expr)
+                .map_err(|e| anyhow::anyhow!("{}: {}", expr, e))?;
+            (AddressSpace::Pid(0), addr)
+        }
+        Start::Virtual { va, pid } => (AddressSpace::Pid(pid), va),
+        Start::Physical(pa) => (AddressSpace::Physical, pa),
+    };
+
+    let len = count as u64 * MAX_INSN_LEN;
+    let dump = session
+        .dump_memory(space.clone(), addr, len, true)
+        .map_err(|e| anyhow::anyhow!("read failed: {}", e))?;
+    // decode only what was read, zero fill would disassemble as add
+    let mut code = dump.bytes;
+    if let Some(gap) = dump.gaps.first() {
+        code.truncate((gap.start - addr) as usize);
+    }
+    if code.is_empty() {
+        anyhow::bail!("{:#x} is unreadable", addr);
+    }
+
+    // put back what our own int3s replaced
+    let end = addr + code.len() as u64;
+    let mut hooked = Vec::new();
+    for (va, pa, orig) in session.hooks().patches() {
+        let site = match space {
+            AddressSpace::Physical => pa,
+            _ => va,
+        };
+        if (addr..end).contains(&site) {
+            let i = (site - addr) as usize;
+            if code[i] == 0xCC {
+                code[i] = orig;
+                hooked.push(site);
+            }
+        }
+    }
+
+    let vmi = vmi.lock().unwrap();
+    let bitness = Bitness::from_address_width(vmi.address_width());
+    let instructions: Vec<Instruction> = disassemble_region(&code, addr, bitness, count)
+        .into_iter()
+        .map(|line| {
+            let range = line.addr..line.addr + line.bytes.len() as u64;
+            Instruction {
+                hooked: hooked.iter().any(|site| range.contains(site)),
+                target_symbol: line.target.and_then(|t| {
+                    vmi.v2ksym(t)
+                        .ok()
+                        .map(|resolved| format_location(Some(resolved), t))
+                }),
+                addr: line.addr,
+                bytes: line.bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+                text: line.text,
+                target: line.target,
+            }
+        })
+        .collect();
+    drop(vmi);
+
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => {
+            return output::print_json(
+                SCHEMA,
+                &Listing {
+                    start: addr,
+                    instructions: &instructions,
+                },
+            );
+        }
+        OutputFormat::Jsonl => return output::print_jsonl(SCHEMA, &instructions),
+    }
+
+    for insn in &instructions {
+        let mut line = format!("{:016x}  {:<30} {}", insn.addr, insn.bytes, insn.text);
+        if let Some(symbol) = &insn.target_symbol {
+            line.push_str(&format!("  ; {}", symbol));
+        }
+        if insn.hooked {
+            line.push_str("  [hook]");
+        }
+        println!("{}", line);
+    }
+    if instructions.len() < count {
+        eprintln!(
+            "stopped after {} instructions, the rest didn't decode",
+            instructions.len()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! dump-memory command implementation
+
+use super::output;
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::vmi::AddressSpace;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub fn run(
+    args: &VmiArgs,
+    pa: Option<u64>,
+    va: Option<u64>,
+    pid: Option<u32>,
+    len: u64,
+    out: Option<&Path>,
+    pause: bool,
+) -> anyhow::Result<()> {
+    let (space, start) = match (pa, va) {
+        (Some(pa), _) => (AddressSpace::Physical, pa),
+        (None, Some(va)) => (AddressSpace::Pid(pid.unwrap_or(0)), va),
+        (None, None) => anyhow::bail!("give --pa or --va"),
+    };
+
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    let dump = session
+        .dump_memory(space, start, len, pause)
+        .map_err(|e| anyhow::anyhow!("dump failed: {}", e))?;
+
+    if !dump.gaps.is_empty() {
+        eprintln!(
+            "Warning: {} of {} bytes unreadable, zero-filled:",
+            dump.gap_bytes(),
+            len
+        );
+        for gap in &dump.gaps {
+            eprintln!("  {:#x}..{:#x}", gap.start, gap.end);
+        }
+    }
+
+    match out {
+        Some(path) => {
+            fs::write(path, &dump.bytes)
+                .map_err(|e| anyhow::anyhow!("write {}: {}", path.display(), e))?;
+            eprintln!("{} bytes written to {}", dump.bytes.len(), path.display());
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            for line in output::hexdump(dump.start, &dump.bytes) {
+                writeln!(stdout, "{}", line)?;
+            }
+        }
+    }
+
+    Ok(())
+}
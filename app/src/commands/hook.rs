@@ -0,0 +1,40 @@
+//! hook command implementation
+
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::event_stream::GuestEvent;
+use loonaro_vmi::hook::HookContext;
+
+pub fn run(args: &VmiArgs, target: &str) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    let printer = super::print_events(&session)?;
+
+    let addr = {
+        let vmi = session.vmi();
+        let vmi_lock = vmi.lock().unwrap();
+        let label = target.to_string();
+        let events = session.event_sink();
+        session
+            .hooks()
+            .add_hook_symbol(&vmi_lock, target, move |ctx: &HookContext| {
+                events.emit(GuestEvent::HookHit {
+                    label: label.clone(),
+                    vcpu: ctx.vcpu_id,
+                    rip: ctx.rip,
+                });
+            })
+            .map_err(|e| anyhow::anyhow!("hook failed: {}", e))?
+    };
+
+    eprintln!("Hooked {} @ {:#x}. Press Ctrl+C to stop.", target, addr);
+
+    // restores hooks on exit to avoid BSOD
+    session
+        .run_until_ctrlc()
+        .map_err(|e| anyhow::anyhow!("event loop stopped: {}", e))?;
+
+    super::finish(session, printer);
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! info command implementation
+
+use loonaro_vmi::cli::VmiArgs;
+
+pub fn run(args: &VmiArgs, json: bool) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    let info = session
+        .info()
+        .map_err(|e| anyhow::anyhow!("info failed: {}", e))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    let opt = |v: Option<String>| v.unwrap_or_else(|| "unknown".into());
+    println!("{:<16} {}", "Domain:", opt(info.name));
+    println!("{:<16} {}", "VM id:", info.vmid);
+    println!("{:<16} {:?}", "OS:", info.os);
+    if let Some(build) = info.windows_build {
+        println!("{:<16} {}", "Windows build:", build);
+    }
+    println!(
+        "{:<16} {}-bit",
+        "Address width:",
+        info.address_width as u32 * 8
+    );
+    println!("{:<16} {:?}", "Page mode:", info.page_mode);
+    println!("{:<16} {}", "vCPUs:", info.vcpus);
+    println!(
+        "{:<16} {} MiB (max paddr {:#x})",
+        "Memory:",
+        info.memory_bytes >> 20,
+        info.max_physical_address
+    );
+    println!(
+        "{:<16} {}",
+        "Kernel base:",
+        opt(info.kernel_base.map(|b| format!("{:#x}", b)))
+    );
+
+    Ok(())
+}
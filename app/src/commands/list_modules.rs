@@ -0,0 +1,151 @@
+//! list-modules command implementation
+//!
+//! json output is schema loonaro.modlist.v1: `modules`, each a serialized
+//! ModuleInfo. jsonl prints one module per line under the same schema
+
+use super::output;
+use clap::ValueEnum;
+use loonaro_vmi::cli::{OutputFormat, VmiArgs};
+use loonaro_vmi::os::{Capability, ModuleInfo};
+use serde::Serialize;
+
+const SCHEMA: &str = "loonaro.modlist.v1";
+
+/// --sort values
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Base,
+    /// case-insensitive
+    Name,
+    /// largest first
+    Size,
+}
+
+#[derive(Serialize)]
+struct ModuleList<'a> {
+    modules: &'a [ModuleInfo],
+}
+
+pub fn run(args: &VmiArgs, sort: Option<SortKey>, format: OutputFormat) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    if format == OutputFormat::Table {
+        println!("OS: {:?}", session.os_type());
+    }
+    super::require(&session, Capability::ListModules)?;
+
+    let mut modules = session
+        .list_modules()
+        .map_err(|e| super::failed("module list", e))?;
+
+    if let Some(key) = sort {
+        sort_modules(&mut modules, key);
+    }
+
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => {
+            return output::print_json(SCHEMA, &ModuleList { modules: &modules });
+        }
+        OutputFormat::Jsonl => return output::print_jsonl(SCHEMA, &modules),
+    }
+
+    println!(
+        "
+{:<18} {:<10} {:<30} Path",
+        "Base", "Size", "Name"
+    );
+    println!("{:-<18} {:-<10} {:-<30} {:-<4}", "", "", "", "");
+
+    for m in modules {
+        println!(
+            "0x{:016x} {:<10} {:<30} {}",
+            m.base,
+            m.size,
+            m.name,
+            m.path.as_deref().unwrap_or("-")
+        );
+    }
+
+    Ok(())
+}
+
+/// stable, ties keep the guest's list order
+fn sort_modules(modules: &mut [ModuleInfo], key: SortKey) {
+    match key {
+        SortKey::Base => modules.sort_by_key(|m| m.base),
+        SortKey::Name => modules.sort_by_key(|m| m.name.to_lowercase()),
+        SortKey::Size => modules.sort_by_key(|m| std::cmp::Reverse(m.size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, base: u64, size: u64) -> ModuleInfo {
+        ModuleInfo {
+            name: name.into(),
+            base,
+            size,
+            path: None,
+        }
+    }
+
+    fn sorted(key: SortKey) -> Vec<String> {
+        let mut modules = vec![
+            module("ntoskrnl.exe", 0xfffff800_0a000000, 0x104_6000),
+            module("hal.dll", 0xfffff800_09e00000, 0x6_0000),
+            module("ACPI.sys", 0xfffff800_0c200000, 0xd_4000),
+            module("kd.dll", 0xfffff800_09d00000, 0xd000),
+            module("Wdf01000.sys", 0xfffff800_0c000000, 0xd_4000),
+        ];
+        sort_modules(&mut modules, key);
+        modules.into_iter().map(|m| m.name).collect()
+    }
+
+    #[test]
+    fn by_base_ascending() {
+        assert_eq!(
+            sorted(SortKey::Base),
+            [
+                "kd.dll",
+                "hal.dll",
+                "ntoskrnl.exe",
+                "Wdf01000.sys",
+                "ACPI.sys"
+            ]
+        );
+    }
+
+    #[test]
+    fn by_name_ignoring_case() {
+        assert_eq!(
+            sorted(SortKey::Name),
+            [
+                "ACPI.sys",
+                "hal.dll",
+                "kd.dll",
+                "ntoskrnl.exe",
+                "Wdf01000.sys"
+            ]
+        );
+    }
+
+    #[test]
+    fn by_size_largest_first_ties_in_list_order() {
+        assert_eq!(
+            sorted(SortKey::Size),
+            [
+                "ntoskrnl.exe",
+                "ACPI.sys",
+                "Wdf01000.sys",
+                "hal.dll",
+                "kd.dll"
+            ]
+        );
+    }
+}
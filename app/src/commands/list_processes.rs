@@ -1,34 +1,192 @@
 //! list-processes command implementation
+//!
+//! json output is schema loonaro.pslist.v1: `source`, `truncated` and
+//! `processes`, each process a serialized ProcessInfo. jsonl prints one
+//! process object per line under the same schema.
+//!
+//! --watch prints changes instead, schema loonaro.pschange.v1 one per line
+//! in both json modes: `time` (unix seconds), `change` (started or exited)
+//! and `process`
 
-use loonaro_vmi::cli::VmiArgs;
-use loonaro_vmi::os::windows::actions::list_processes::ListProcesses;
-use loonaro_vmi::session::Session;
-use loonaro_vmi::vmi::OsType;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub fn run(args: &VmiArgs) -> anyhow::Result<()> {
-    let json_str = args.json.to_string_lossy();
-    let socket_str = args.socket_path.to_string_lossy();
+use super::output;
+use loonaro_vmi::cli::{OutputFormat, VmiArgs};
+use loonaro_vmi::os::{Capability, ListSource, ProcessInfo, ProcessList};
+use loonaro_vmi::process_diff;
+use loonaro_vmi::session::{ctrlc_flag, Session};
+use serde::Serialize;
 
+const SCHEMA: &str = "loonaro.pslist.v1";
+const CHANGE_SCHEMA: &str = "loonaro.pschange.v1";
+
+#[derive(Serialize)]
+struct Change<'a> {
+    time: f64,
+    change: &'static str,
+    process: &'a ProcessInfo,
+}
+
+pub fn run(
+    args: &VmiArgs,
+    details: bool,
+    watch: Option<u64>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
     // session owns the vmi handle
-    let session = Session::new(&args.name, &json_str, &socket_str)
+    let session = args
+        .to_builder()
+        .build()
         .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
 
-    let os_type = session.vmi().lock().unwrap().os_type();
-    println!("OS: {:?}", os_type);
+    let table = format == OutputFormat::Table;
+    if table {
+        println!("OS: {:?}", session.os_type());
+    }
+    super::require(&session, Capability::ListProcesses)?;
+    if details {
+        super::require(&session, Capability::ProcessDetails)?;
+    }
+
+    if let Some(secs) = watch {
+        return run_watch(&session, details, Duration::from_secs(secs), format);
+    }
+
+    let list = snapshot(&session, details)?;
+
+    // stdout stays parseable in json modes
+    if list.truncated {
+        eprintln!("Warning: process list truncated by a failed read, showing partial results");
+    }
+    if matches!(
+        list.source,
+        ListSource::InitialSystemProcess | ListSource::Kpcr
+    ) {
+        eprintln!(
+            "Note: PsActiveProcessHead unavailable, walked from {:?}",
+            list.source
+        );
+    }
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => return output::print_json(SCHEMA, &list),
+        OutputFormat::Jsonl => return output::print_jsonl(SCHEMA, &list.processes),
+    }
 
-    let processes = match os_type {
-        OsType::Windows => session
-            .execute(ListProcesses)
-            .map_err(|e| anyhow::anyhow!("list failed: {}", e))?,
-        _ => return Err(anyhow::anyhow!("unsupported OS")),
-    };
+    println!("Source: {:?}", list.source);
 
     println!("\n{:<8} {:<30} {:<18}", "PID", "Name", "Address");
     println!("{:-<8} {:-<30} {:-<18}", "", "", "");
 
-    for p in processes {
+    for p in list.processes {
         println!("{:<8} {:<30} 0x{:016x}", p.pid, p.name, p.addr);
+        if let Some(path) = p.full_image_path {
+            println!("{:<8} image: {}", "", path);
+        }
+        if let Some(cmd) = p.command_line {
+            println!("{:<8} cmd:   {}", "", cmd);
+        }
+        if let Some(cred) = p.credentials {
+            println!(
+                "{:<8} cred:  uid={} gid={} euid={}",
+                "", cred.uid, cred.gid, cred.euid
+            );
+        }
+        if let Some(count) = p.thread_count {
+            println!("{:<8} threads: {}", "", count);
+        }
+        if let Some(ns) = p.namespace_pid.filter(|ns| ns.is_nested()) {
+            println!("{:<8} pidns: level {}, pid {}", "", ns.level, ns.pid);
+        }
     }
 
     Ok(())
 }
+
+/// one listing, taken with the vm paused by the action itself
+fn snapshot(session: &Session, details: bool) -> anyhow::Result<ProcessList> {
+    session
+        .list_processes(details)
+        .map_err(|e| anyhow::anyhow!("list failed: {}", e))
+}
+
+/// re-list every `interval` and print what started or exited, until Ctrl+C.
+/// a truncated listing isn't diffed, its missing tail would read as exits
+fn run_watch(
+    session: &Session,
+    details: bool,
+    interval: Duration,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let running = ctrlc_flag()?;
+    let mut prev = snapshot(session, details)?;
+    eprintln!(
+        "{} processes, watching every {}s. Press Ctrl+C to stop.",
+        prev.processes.len(),
+        interval.as_secs()
+    );
+
+    loop {
+        let started = Instant::now();
+        while running.load(Ordering::SeqCst) && started.elapsed() < interval {
+            thread::sleep(Duration::from_millis(100));
+        }
+        if !running.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let next = snapshot(session, details)?;
+        if next.truncated {
+            eprintln!("Warning: process list truncated by a failed read, skipping this round");
+            continue;
+        }
+        let diff = process_diff::diff(&prev.processes, &next.processes);
+        let now = unix_time();
+        let changes = diff
+            .started
+            .iter()
+            .map(|process| Change {
+                time: now,
+                change: "started",
+                process,
+            })
+            .chain(diff.exited.iter().map(|process| Change {
+                time: now,
+                change: "exited",
+                process,
+            }));
+        match format {
+            OutputFormat::Table => {
+                for c in changes {
+                    println!(
+                        "{} {:<7} {:<8} {:<30} 0x{:016x}",
+                        clock(c.time),
+                        c.change,
+                        c.process.pid,
+                        c.process.name,
+                        c.process.addr
+                    );
+                }
+            }
+            OutputFormat::Json | OutputFormat::Jsonl => {
+                output::print_jsonl(CHANGE_SCHEMA, &changes.collect::<Vec<_>>())?
+            }
+        }
+        prev = next;
+    }
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// HH:MM:SS, UTC
+fn clock(time: f64) -> String {
+    let secs = time as u64 % 86400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,64 @@
+//! list-threads command implementation
+//!
+//! json output is schema loonaro.threads.v1: `pid` and `threads`, each a
+//! serialized ThreadInfo. jsonl prints one thread per line under the same
+//! schema
+
+use super::output;
+use loonaro_vmi::cli::{OutputFormat, VmiArgs};
+use loonaro_vmi::os::windows::actions::list_threads::ThreadInfo;
+use loonaro_vmi::os::Capability;
+use serde::Serialize;
+
+const SCHEMA: &str = "loonaro.threads.v1";
+
+#[derive(Serialize)]
+struct ThreadList<'a> {
+    pid: u64,
+    threads: &'a [ThreadInfo],
+}
+
+pub fn run(args: &VmiArgs, pid: u64, format: OutputFormat) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    super::require(&session, Capability::ListThreads)?;
+    let threads = session
+        .list_threads(pid)
+        .map_err(|e| super::failed("thread list", e))?;
+
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => {
+            return output::print_json(
+                SCHEMA,
+                &ThreadList {
+                    pid,
+                    threads: &threads,
+                },
+            );
+        }
+        OutputFormat::Jsonl => return output::print_jsonl(SCHEMA, &threads),
+    }
+
+    if threads.is_empty() {
+        println!("PID {} has no threads left", pid);
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<18} {:<18} {:<18}",
+        "TID", "Start", "TEB", "ETHREAD"
+    );
+    println!("{:-<8} {:-<18} {:-<18} {:-<18}", "", "", "", "");
+    for t in threads {
+        println!(
+            "{:<8} 0x{:016x} 0x{:016x} 0x{:016x}",
+            t.tid, t.start_addr, t.teb, t.addr
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! mem command implementation, reads and writes single ranges
+
+use super::output;
+use clap::Subcommand;
+use loonaro_vmi::cli::{parse_addr, parse_len, VmiArgs};
+use loonaro_vmi::symbols::resolve_symbol_expr;
+use loonaro_vmi::vmi::AddressSpace;
+
+#[derive(Subcommand)]
+pub enum MemCommand {
+    /// hex dump a range
+    Read {
+        /// virtual address, kernel unless --pid is given
+        #[arg(long, value_parser = parse_addr, required_unless_present = "sym", conflicts_with = "sym")]
+        va: Option<u64>,
+        /// kernel symbol, optionally offset: PsInitialSystemProcess+0x8
+        #[arg(long, conflicts_with = "pid")]
+        sym: Option<String>,
+        /// translate --va through this process's page tables
+        #[arg(long)]
+        pid: Option<u32>,
+        #[arg(long, value_parser = parse_len, default_value = "64")]
+        len: u64,
+    },
+    /// overwrite guest memory, printing the bytes it replaced
+    Write {
+        /// virtual address, kernel unless --pid is given
+        #[arg(long, value_parser = parse_addr)]
+        va: u64,
+        /// translate --va through this process's page tables
+        #[arg(long)]
+        pid: Option<u32>,
+        /// hex string, e.g. cc90 or "cc 90"
+        #[arg(long, value_parser = parse_hex_bytes, required_unless_present_any = ["u64", "u32"], conflicts_with_all = ["u64", "u32"])]
+        bytes: Option<Vec<u8>>,
+        /// little-endian 8 byte value
+        #[arg(long, value_parser = parse_addr, conflicts_with = "u32")]
+        u64: Option<u64>,
+        /// little-endian 4 byte value
+        #[arg(long, value_parser = parse_u32)]
+        u32: Option<u32>,
+        /// confirm the write, a wrong one can crash the guest
+        #[arg(long)]
+        yes_i_know: bool,
+    },
+}
+
+pub fn run(args: &VmiArgs, command: MemCommand) -> anyhow::Result<()> {
+    match command {
+        MemCommand::Read { va, sym, pid, len } => read(args, va, sym.as_deref(), pid, len),
+        MemCommand::Write {
+            va,
+            pid,
+            bytes,
+            u64,
+            u32,
+            yes_i_know,
+        } => {
+            let data = match (bytes, u64, u32) {
+                (Some(bytes), _, _) => bytes,
+                (None, Some(v), _) => v.to_le_bytes().to_vec(),
+                (None, None, Some(v)) => v.to_le_bytes().to_vec(),
+                (None, None, None) => anyhow::bail!("give --bytes, --u64 or --u32"),
+            };
+            if !yes_i_know {
+                anyhow::bail!(
+                    "writing guest memory can crash the guest, pass --yes-i-know to go ahead"
+                );
+            }
+            write(args, va, pid.unwrap_or(0), &data)
+        }
+    }
+}
+
+fn read(
+    args: &VmiArgs,
+    va: Option<u64>,
+    sym: Option<&str>,
+    pid: Option<u32>,
+    len: u64,
+) -> anyhow::Result<()> {
+    // nothing is hooked, so skip event setup
+    let session = args
+        .to_builder()
+        .events(false)
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    let start = match (va, sym) {
+        (Some(va), _) => va,
+        (None, Some(expr)) => {
+            let vmi = session.vmi();
+            let vmi_lock = vmi.lock().unwrap();
+            resolve_symbol_expr(&vmi_lock, expr).map_err(|e| anyhow::anyhow!("{}: {}", expr, e))?
+        }
+        (None, None) => anyhow::bail!("give --va or --sym"),
+    };
+
+    let dump = session
+        .dump_memory(AddressSpace::Pid(pid.unwrap_or(0)), start, len, true)
+        .map_err(|e| anyhow::anyhow!("read failed: {}", e))?;
+    for gap in &dump.gaps {
+        eprintln!(
+            "Warning: {:#x}..{:#x} unreadable, shown as zeros",
+            gap.start, gap.end
+        );
+    }
+    for line in output::hexdump(dump.start, &dump.bytes) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn write(args: &VmiArgs, va: u64, pid: u32, data: &[u8]) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    let vmi = session.vmi();
+    let vmi_lock = vmi.lock().unwrap();
+    // keep the old bytes and the write from interleaving with the guest
+    let _pause = vmi_lock
+        .pause_guard()
+        .map_err(|e| anyhow::anyhow!("pause failed: {}", e))?;
+
+    let previous = vmi_lock
+        .read_va(va, pid, data.len())
+        .map_err(|e| anyhow::anyhow!("read of the previous bytes failed: {}", e))?;
+    vmi_lock
+        .write_va(va, pid, data)
+        .map_err(|e| anyhow::anyhow!("write failed: {}", e))?;
+
+    println!("previous: {}", hex_string(&previous));
+    println!("written:  {}", hex_string(data));
+    let pid = if pid != 0 {
+        format!(" --pid {}", pid)
+    } else {
+        String::new()
+    };
+    println!(
+        "undo with: mem write --va {:#x}{} --bytes {} --yes-i-know",
+        va,
+        pid,
+        hex_string(&previous)
+    );
+    Ok(())
+}
+
+/// clap parser for --bytes, pairs of hex digits with optional whitespace
+fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    let digits = digits.strip_prefix("0x").unwrap_or(&digits);
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(format!("{:?} is not an even number of hex digits", s));
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+fn parse_u32(s: &str) -> Result<u32, String> {
+    let value = parse_addr(s)?;
+    u32::try_from(value).map_err(|_| format!("{} doesn't fit in 32 bits", s))
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_pairs() {
+        assert_eq!(parse_hex_bytes("90c3"), Ok(vec![0x90, 0xc3]));
+        assert_eq!(
+            parse_hex_bytes("DE AD be ef"),
+            Ok(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+    }
+
+    #[test]
+    fn strips_0x_prefix() {
+        assert_eq!(parse_hex_bytes("0xcc"), Ok(vec![0xcc]));
+        assert_eq!(parse_hex_bytes("0x 4889e5"), Ok(vec![0x48, 0x89, 0xe5]));
+    }
+
+    #[test]
+    fn rejects_odd_length() {
+        assert!(parse_hex_bytes("abc").is_err());
+        assert!(parse_hex_bytes("0x9").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_characters() {
+        assert!(parse_hex_bytes("zz").is_err());
+        assert!(parse_hex_bytes("90,c3").is_err());
+        assert!(parse_hex_bytes("-1").is_err());
+    }
+
+    #[test]
+    fn rejects_empty() {
+        assert!(parse_hex_bytes("").is_err());
+        assert!(parse_hex_bytes("0x").is_err());
+        assert!(parse_hex_bytes("   ").is_err());
+    }
+
+    #[test]
+    fn hex_string_round_trips() {
+        let bytes = [0x00, 0x7f, 0xff];
+        assert_eq!(hex_string(&bytes), "007fff");
+        assert_eq!(parse_hex_bytes(&hex_string(&bytes)), Ok(bytes.to_vec()));
+    }
+}
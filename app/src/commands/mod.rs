@@ -1,4 +1,200 @@
 //! command modules for loonaro CLI
 
+use loonaro_vmi::error::VmiError;
+use loonaro_vmi::event_filter::EventFilter;
+use loonaro_vmi::event_log::{EventLog, FLUSH_INTERVAL};
+use loonaro_vmi::event_stream::GuestEvent;
+use loonaro_vmi::os::Capability;
+use loonaro_vmi::session::Session;
+use loonaro_vmi::watch::format_access;
+use std::io::{self, Write};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+
+pub mod capabilities;
+pub mod disasm;
+pub mod dump_memory;
+pub mod hook;
+pub mod info;
+pub mod list_modules;
 pub mod list_processes;
+pub mod list_threads;
+pub mod mem;
 pub mod monitor;
+pub mod output;
+pub mod regs;
+pub mod stderr_log;
+pub mod symbol;
+pub mod tree;
+pub mod watch;
+
+/// fail early, naming the feature, when the guest OS doesn't have it
+pub fn require(session: &Session, capability: Capability) -> anyhow::Result<()> {
+    if !session.supports(capability) {
+        anyhow::bail!(
+            "{} is not supported for {:?} guests yet",
+            capability.name(),
+            session.os_type()
+        );
+    }
+    Ok(())
+}
+
+/// `<what> failed: ...`, telling the user how to get a missing offset
+pub fn failed(what: &str, e: VmiError) -> anyhow::Error {
+    match e {
+        VmiError::OffsetNotFound(name) => anyhow::anyhow!(
+            "{} failed: the profile has no {}, regenerate it from this kernel's debug symbols",
+            what,
+            name
+        ),
+        e => anyhow::anyhow!("{} failed: {}", what, e),
+    }
+}
+
+/// print guest events to stdout as they arrive. the printer ends once the
+/// session and every monitor holding a sender are gone
+pub fn print_events(session: &Session) -> anyhow::Result<JoinHandle<()>> {
+    print_filtered_events(session, EventFilter::default())
+}
+
+/// print_events, skipping what `filter` rejects
+pub fn print_filtered_events(
+    session: &Session,
+    filter: EventFilter,
+) -> anyhow::Result<JoinHandle<()>> {
+    let mut out = io::stdout();
+    session
+        .forward_events(move |event| {
+            if !filter.matches(&event) {
+                return;
+            }
+            // a closed stdout only loses output, the session keeps running
+            let _ = writeln!(out, "{}", format_event(&event));
+        })
+        .map_err(|e| anyhow::anyhow!("event stream: {}", e))
+}
+
+/// print_filtered_events that also appends every passing event to `log`,
+/// plus a line whenever the queue dropped some. `quiet` skips stdout.
+/// runs on its own thread so the disk never holds up the vcpu
+pub fn log_events(
+    session: &Session,
+    filter: EventFilter,
+    mut log: EventLog,
+    quiet: bool,
+) -> anyhow::Result<JoinHandle<()>> {
+    let rx = session
+        .events()
+        .map_err(|e| anyhow::anyhow!("event stream: {}", e))?;
+    let dropped = session.drop_counter();
+    Ok(thread::spawn(move || {
+        let mut out = io::stdout();
+        let mut logging = true;
+        loop {
+            let event = match rx.recv_timeout(FLUSH_INTERVAL) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let event = event.filter(|e| filter.matches(e));
+            if let Some(event) = &event
+                && !quiet
+            {
+                let _ = writeln!(out, "{}", format_event(event));
+            }
+            if !logging {
+                continue;
+            }
+            // a failing disk stops the log, stdout carries on
+            let written = event
+                .map_or(Ok(()), |e| log.write_event(&e))
+                .and_then(|_| log.note_dropped(dropped.get()))
+                .and_then(|_| log.flush_if_due());
+            if let Err(e) = written {
+                eprintln!("[EventLog] writing {} failed: {}", log.path().display(), e);
+                logging = false;
+            }
+        }
+        if logging {
+            let _ = log.note_dropped(dropped.get()).and_then(|_| log.flush());
+        }
+    }))
+}
+
+/// tear the session down, then let the printer flush what is still queued
+pub fn finish(session: Session, printer: JoinHandle<()>) {
+    let dropped = session.dropped_events();
+    drop(session);
+    let _ = printer.join();
+    if dropped > 0 {
+        eprintln!("{} events dropped, output couldn't keep up", dropped);
+    }
+}
+
+/// one event as printed by the CLI, environment variables on extra lines
+pub fn format_event(event: &GuestEvent) -> String {
+    match event {
+        GuestEvent::ProcessCreated {
+            pid,
+            ppid,
+            image,
+            cmdline,
+            time,
+            environment,
+        } => {
+            let mut line = format!(
+                "Process Create | PID: {} | PPID: {} | Image: {} | CmdLine: {} | Time: {}",
+                pid,
+                ppid,
+                image.as_deref().unwrap_or("<unknown>"),
+                cmdline.as_deref().unwrap_or("<unknown>"),
+                time
+            );
+            for (name, value) in environment.iter().flatten() {
+                line.push_str(&format!("\n    {}={}", name, value));
+            }
+            line
+        }
+        GuestEvent::TaskCreated {
+            pid,
+            ppid,
+            comm,
+            uid,
+        } => format!(
+            "Process Create | PID: {} | PPID: {} | Comm: {} | UID: {}",
+            pid,
+            ppid,
+            comm.as_deref().unwrap_or("<unknown>"),
+            uid.map(|uid| uid.to_string()).unwrap_or_else(|| "?".into())
+        ),
+        GuestEvent::Disconnected => "Session | kvmi connection lost".to_string(),
+        GuestEvent::GuestGone { rebooted: true } => {
+            "Session | guest rebooted, hooks dropped".to_string()
+        }
+        GuestEvent::GuestGone { rebooted: false } => {
+            "Session | guest gone, hooks dropped".to_string()
+        }
+        GuestEvent::Reconnected { attempt, hooks } => format!(
+            "Session | reconnected on attempt {} | hooks re-armed: {}",
+            attempt, hooks
+        ),
+        GuestEvent::Stats(stats) => format!("Stats | {}", stats),
+        GuestEvent::HookHit { label, vcpu, rip } => {
+            format!("Hook | {} | vCPU: {} | RIP: {:#x}", label, vcpu, rip)
+        }
+        GuestEvent::MemoryAccess {
+            vcpu,
+            rip,
+            access,
+            gla,
+        } => format!(
+            "Watch | vCPU: {} | RIP: {:#x} | Access: {} | GLA: {}",
+            vcpu,
+            rip,
+            format_access(*access),
+            gla.map(|gla| format!("{:#x}", gla))
+                .unwrap_or_else(|| "?".into())
+        ),
+    }
+}
@@ -1,42 +1,150 @@
 //! monitor command implementation
 
-use loonaro_vmi::cli::VmiArgs;
-use loonaro_vmi::os::windows::events::process_create::ProcessCreateMonitor;
-use loonaro_vmi::session::Session;
-use loonaro_vmi::vmi::OsType;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::Duration;
 
-pub fn run(args: &VmiArgs) -> anyhow::Result<()> {
-    let json_str = args.json.to_string_lossy();
-    let socket_str = args.socket_path.to_string_lossy();
+use clap::Args;
+use loonaro_vmi::cli::{parse_size, VmiArgs};
+use loonaro_vmi::error::VmiError;
+use loonaro_vmi::event_filter::EventFilter;
+use loonaro_vmi::event_log::{EventLog, DEFAULT_KEEP};
+use loonaro_vmi::os::{Capability, MonitorKind, MonitorSelection};
+use loonaro_vmi::session::ctrlc_flag;
 
-    eprintln!("Init monitor for {}", args.name);
+/// which process events to print. repeats of one flag are OR'd, different
+/// flags AND'd, excludes always win
+#[derive(Args, Debug, Clone, Default)]
+pub struct FilterArgs {
+    /// image path, command line or comm containing this, or matching it
+    /// as a glob when it has * or ?. case-insensitive
+    #[arg(long = "filter-name", value_name = "PATTERN")]
+    names: Vec<String>,
+    /// drop processes whose name matches, same syntax as --filter-name
+    #[arg(long = "exclude-name", value_name = "PATTERN")]
+    excludes: Vec<String>,
+    #[arg(long = "filter-pid", value_name = "PID")]
+    pids: Vec<u64>,
+    /// children of this pid
+    #[arg(long = "filter-ppid", value_name = "PID")]
+    ppids: Vec<u64>,
+}
 
-    let mut session = Session::new(&args.name, &json_str, &socket_str)
-        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+impl FilterArgs {
+    pub fn build(&self) -> EventFilter {
+        let mut filter = EventFilter::new();
+        for name in &self.names {
+            filter = filter.name(name);
+        }
+        for name in &self.excludes {
+            filter = filter.exclude_name(name);
+        }
+        for &pid in &self.pids {
+            filter = filter.pid(pid);
+        }
+        for &ppid in &self.ppids {
+            filter = filter.ppid(ppid);
+        }
+        filter
+    }
+}
 
-    if session.vmi().lock().unwrap().os_type() != OsType::Windows {
-        anyhow::bail!("only Windows supported");
+/// where events go besides stdout
+#[derive(Args, Debug, Clone, Default)]
+pub struct LogArgs {
+    /// also write every event to this file as json lines
+    #[arg(long, value_name = "FILE")]
+    out: Option<PathBuf>,
+    /// rotate the file past this size: bytes or e.g. 64K, 100M, 1G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "out")]
+    max_size: Option<u64>,
+    /// rotated files to keep (FILE.1 .. FILE.N)
+    #[arg(long, default_value_t = DEFAULT_KEEP, requires = "max_size")]
+    keep: u32,
+    /// don't print events to stdout, only to --out
+    #[arg(long, requires = "out")]
+    quiet: bool,
+}
+
+impl LogArgs {
+    fn open(&self) -> anyhow::Result<Option<EventLog>> {
+        let Some(path) = &self.out else {
+            return Ok(None);
+        };
+        let log = EventLog::create(path)
+            .map_err(|e| anyhow::anyhow!("can't create {}: {}", path.display(), e))?;
+        Ok(Some(log.max_size(self.max_size).keep(self.keep)))
     }
+}
 
-    eprintln!("Enabling Process Monitor...");
-    session
-        .add_event(ProcessCreateMonitor::new())
-        .map_err(|e| anyhow::anyhow!("enable failed: {}", e))?;
+pub fn run(
+    args: &VmiArgs,
+    env: bool,
+    duration: Option<u64>,
+    events: &MonitorSelection,
+    filter: EventFilter,
+    log: &LogArgs,
+) -> anyhow::Result<()> {
+    eprintln!("Init monitor for {}", args.domain_label());
 
-    eprintln!("Monitor running. Press Ctrl+C to stop.");
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    let printer = match log.open()? {
+        Some(file) => super::log_events(&session, filter, file, log.quiet)?,
+        None => super::print_filtered_events(&session, filter)?,
+    };
 
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
+    // refuse the whole selection before any hook goes in
+    let kinds = events.resolve(session.os_type())?;
+    if env {
+        if !kinds.contains(&MonitorKind::ProcessCreate) {
+            anyhow::bail!("--env needs the process-create event");
+        }
+        super::require(&session, Capability::EnvironmentMonitor)?;
+    }
 
-    // handle SIGINT for graceful cleanup (restores hooks to avoid BSOD)
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-        eprintln!("\nExiting...");
-    })?;
+    // each kind is its own event, so teardown reports them one by one
+    for kind in kinds {
+        eprintln!("Enabling {} monitor...", kind.name());
+        session.enable_monitor(kind, env).map_err(|e| match e {
+            VmiError::OffsetNotFound(name) => {
+                anyhow::anyhow!(
+                    "enable {} failed: profile is missing offset {} (fix your profile)",
+                    kind.name(),
+                    name
+                )
+            }
+            VmiError::SymbolNotFound(name) => {
+                anyhow::anyhow!(
+                    "enable {} failed: kernel symbol {} not found",
+                    kind.name(),
+                    name
+                )
+            }
+            e => anyhow::anyhow!("enable {} failed: {}", kind.name(), e),
+        })?;
+    }
 
-    session.run(running)?;
+    eprintln!("Monitor running. Press Ctrl+C to stop.");
+
+    // restores hooks on exit to avoid BSOD
+    match duration {
+        Some(secs) => {
+            let summary = session
+                .run_for(Duration::from_secs(secs), ctrlc_flag()?)
+                .map_err(|e| anyhow::anyhow!("event loop stopped: {}", e))?;
+            eprintln!(
+                "Monitored for {:.1}s, {} events",
+                summary.elapsed.as_secs_f64(),
+                summary.events
+            );
+        }
+        None => session
+            .run_until_ctrlc()
+            .map_err(|e| anyhow::anyhow!("event loop stopped: {}", e))?,
+    }
 
+    super::finish(session, printer);
     Ok(())
 }
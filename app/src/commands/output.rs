@@ -0,0 +1,186 @@
+//! json, jsonl and hex dump output shared by commands
+//!
+//! every object printed carries a "schema" naming its shape and version,
+//! e.g. loonaro.pslist.v1. fields may be added within a version; removing,
+//! renaming or retyping one bumps it
+
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// `{"schema": ..., <fields of body>}`
+#[derive(Serialize)]
+struct Document<'a, T> {
+    schema: &'static str,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+/// print `body` as one pretty JSON document
+pub fn print_json<T: Serialize>(schema: &'static str, body: &T) -> anyhow::Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&Document { schema, body })?
+    );
+    Ok(())
+}
+
+/// `body` as one compact document, what print_jsonl writes per line
+fn document_line<T: Serialize>(schema: &'static str, body: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&Document { schema, body })
+}
+
+/// print each item as a compact JSON object on its own line
+pub fn print_jsonl<'a, T, I>(schema: &'static str, items: I) -> anyhow::Result<()>
+where
+    T: Serialize + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    let mut out = io::stdout().lock();
+    for body in items {
+        writeln!(out, "{}", document_line(schema, body)?)?;
+    }
+    Ok(())
+}
+
+/// bytes per hex dump line
+const LINE: usize = 16;
+
+/// `address  hex bytes  |ascii|`, 16 bytes per line
+pub fn hexdump(start: u64, bytes: &[u8]) -> impl Iterator<Item = String> + '_ {
+    bytes.chunks(LINE).enumerate().map(move |(i, chunk)| {
+        let mut hex = String::with_capacity(LINE * 3);
+        for (j, b) in chunk.iter().enumerate() {
+            if j == LINE / 2 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x} ", b));
+        }
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        format!(
+            "{:016x}  {:<width$} |{}|",
+            start + (i * LINE) as u64,
+            hex,
+            ascii,
+            width = LINE * 3 + 1
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use loonaro_vmi::os::{ListSource, ModuleInfo, ProcessInfo, ProcessList};
+    use serde_json::Value;
+
+    fn process(pid: u64, name: &str) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: name.into(),
+            addr: 0xffff_a000_0000_0000 + pid,
+            command_line: Some(format!("{} -x", name)),
+            full_image_path: None,
+            credentials: None,
+            namespace_pid: None,
+            thread_count: Some(3),
+            create_time: Some(133_000_000_000_000_000),
+        }
+    }
+
+    #[test]
+    fn document_carries_schema_beside_body_fields() {
+        let list = ProcessList {
+            processes: vec![process(4, "System")],
+            source: ListSource::ActiveProcessHead,
+            truncated: false,
+        };
+        let doc: Value = serde_json::to_value(Document {
+            schema: "loonaro.pslist.v1",
+            body: &list,
+        })
+        .unwrap();
+        assert_eq!(doc["schema"], "loonaro.pslist.v1");
+        assert_eq!(doc["source"], "ActiveProcessHead");
+        assert_eq!(doc["truncated"], false);
+        assert_eq!(doc["processes"][0]["pid"], 4);
+        assert_eq!(doc["processes"][0]["full_image_path"], Value::Null);
+    }
+
+    #[test]
+    fn jsonl_line_is_one_compact_object_with_schema() {
+        let module = ModuleInfo {
+            name: "ntdll.dll".into(),
+            base: 0x7ffe_0000_0000,
+            size: 0x1f_0000,
+            path: None,
+        };
+        let line = document_line("loonaro.modlist.v1", &module).unwrap();
+        assert!(!line.contains('\n'));
+        let doc: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(doc["schema"], "loonaro.modlist.v1");
+        assert_eq!(doc["name"], "ntdll.dll");
+    }
+
+    #[test]
+    fn documents_round_trip() {
+        let list = ProcessList {
+            processes: vec![process(4, "System"), process(600, "smss.exe")],
+            source: ListSource::Kpcr,
+            truncated: true,
+        };
+        let line = document_line("loonaro.pslist.v1", &list).unwrap();
+        let back: ProcessList = serde_json::from_str(&line).unwrap();
+        assert_eq!(back.processes, list.processes);
+        assert_eq!(back.source, list.source);
+        assert_eq!(back.truncated, list.truncated);
+
+        let module = ModuleInfo {
+            name: "libc.so.6".into(),
+            base: 0x7f00_0000_0000,
+            size: 0x20_0000,
+            path: Some("/usr/lib/libc.so.6".into()),
+        };
+        let line = document_line("loonaro.modlist.v1", &module).unwrap();
+        assert_eq!(serde_json::from_str::<ModuleInfo>(&line).unwrap(), module);
+    }
+
+    #[test]
+    fn hexdump_full_row() {
+        let bytes: Vec<u8> = (0x41..0x51).collect();
+        let lines: Vec<String> = hexdump(0x1000, &bytes).collect();
+        assert_eq!(
+            lines,
+            ["0000000000001000  41 42 43 44 45 46 47 48  49 4a 4b 4c 4d 4e 4f 50  |ABCDEFGHIJKLMNOP|"]
+        );
+    }
+
+    #[test]
+    fn hexdump_partial_last_row_keeps_ascii_column() {
+        let mut bytes = vec![0u8; 16];
+        bytes.extend_from_slice(b"hi\n");
+        let lines: Vec<String> = hexdump(0xfff0, &bytes).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "000000000000fff0  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|"
+        );
+        assert_eq!(
+            lines[1],
+            format!("{:016x}  {:<49} |hi.|", 0x10000, "68 69 0a ")
+        );
+        assert_eq!(lines[0].find('|'), lines[1].find('|'));
+    }
+
+    #[test]
+    fn hexdump_empty() {
+        assert_eq!(hexdump(0, &[]).count(), 0);
+    }
+}
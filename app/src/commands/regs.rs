@@ -0,0 +1,77 @@
+//! regs command implementation
+//!
+//! json output is schema loonaro.regs.v1: `vcpus`, each a serialized
+//! VcpuRegisters. jsonl prints one vcpu per line under the same schema
+
+use super::output;
+use loonaro_vmi::cli::{OutputFormat, VmiArgs};
+use loonaro_vmi::session::VcpuRegisters;
+use serde::Serialize;
+
+const SCHEMA: &str = "loonaro.regs.v1";
+
+#[derive(Serialize)]
+struct Dump<'a> {
+    vcpus: &'a [VcpuRegisters],
+}
+
+pub fn run(args: &VmiArgs, vcpu: Option<u32>, format: OutputFormat) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+
+    let vcpus = session
+        .registers(vcpu)
+        .map_err(|e| anyhow::anyhow!("register read failed: {}", e))?;
+
+    match format {
+        OutputFormat::Table => {}
+        OutputFormat::Json => return output::print_json(SCHEMA, &Dump { vcpus: &vcpus }),
+        OutputFormat::Jsonl => return output::print_jsonl(SCHEMA, &vcpus),
+    }
+
+    for (i, r) in vcpus.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("vCPU {}", r.vcpu);
+        let rows = [
+            [
+                ("rax", r.rax),
+                ("rbx", r.rbx),
+                ("rcx", r.rcx),
+                ("rdx", r.rdx),
+            ],
+            [
+                ("rsi", r.rsi),
+                ("rdi", r.rdi),
+                ("rbp", r.rbp),
+                ("rsp", r.rsp),
+            ],
+            [("r8", r.r8), ("r9", r.r9), ("r10", r.r10), ("r11", r.r11)],
+            [
+                ("r12", r.r12),
+                ("r13", r.r13),
+                ("r14", r.r14),
+                ("r15", r.r15),
+            ],
+            [
+                ("rip", r.rip),
+                ("rfl", r.rflags),
+                ("cr0", r.cr0),
+                ("cr3", r.cr3),
+            ],
+        ];
+        for row in rows {
+            let line: Vec<String> = row
+                .iter()
+                .map(|(name, value)| format!("{:<3} {:016x}", name, value))
+                .collect();
+            println!("  {}", line.join("  "));
+        }
+        println!("  {:<3} {:016x}", "cr4", r.cr4);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+//! library diagnostics go through the log facade, the CLI prints them to
+//! stderr so stdout stays parseable
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+struct StderrLog;
+
+static LOGGER: StderrLog = StderrLog;
+
+impl Log for StderrLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// install the stderr logger, debug records only when `verbose`
+pub fn init(verbose: bool) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(if verbose {
+            LevelFilter::Debug
+        } else {
+            LevelFilter::Info
+        });
+    }
+}
@@ -0,0 +1,146 @@
+//! symbol command implementation
+//!
+//! json output is schema loonaro.symbols.v1: `symbols`, each with `query`,
+//! `va`, `pa`, `symbol`, `offset` and `error`, unresolved parts null.
+//! jsonl prints one entry per line under the same schema
+
+use std::io::{self, BufRead};
+
+use super::output;
+use loonaro_vmi::cli::{parse_addr, OutputFormat, VmiArgs};
+use loonaro_vmi::symbols::{format_location, resolve_symbol_expr};
+use loonaro_vmi::vmi::Vmi;
+use serde::Serialize;
+
+const SCHEMA: &str = "loonaro.symbols.v1";
+
+/// one looked up name or address
+#[derive(Serialize)]
+struct Resolution {
+    query: String,
+    va: Option<u64>,
+    /// with --to-phys only
+    pa: Option<u64>,
+    /// nearest symbol and the distance past it, --reverse only
+    symbol: Option<String>,
+    offset: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Batch<'a> {
+    symbols: &'a [Resolution],
+}
+
+/// resolve every query, `-` standing for one query per stdin line.
+/// failures are reported per query and the rest carry on; the command
+/// fails at the end if any did, unless `ignore_missing`
+pub fn run(
+    args: &VmiArgs,
+    queries: &[String],
+    to_phys: bool,
+    reverse: bool,
+    ignore_missing: bool,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let queries = expand_stdin(queries)?;
+    let session = args
+        .to_builder()
+        .events(false)
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    let vmi = session.vmi();
+    let vmi = vmi.lock().unwrap();
+
+    let results: Vec<Resolution> = queries
+        .into_iter()
+        .map(|query| {
+            let mut r = Resolution {
+                query,
+                va: None,
+                pa: None,
+                symbol: None,
+                offset: None,
+                error: None,
+            };
+            if let Err(e) = resolve(&vmi, &mut r, to_phys, reverse) {
+                r.error = Some(e);
+            }
+            r
+        })
+        .collect();
+    drop(vmi);
+
+    let failed = results.iter().filter(|r| r.error.is_some()).count();
+    match format {
+        OutputFormat::Table => print_table(&results),
+        OutputFormat::Json => output::print_json(SCHEMA, &Batch { symbols: &results })?,
+        OutputFormat::Jsonl => output::print_jsonl(SCHEMA, &results)?,
+    }
+
+    if failed > 0 && !ignore_missing {
+        anyhow::bail!("{} of {} lookups failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// fill in `r` from its query, the error message on failure
+fn resolve(vmi: &Vmi, r: &mut Resolution, to_phys: bool, reverse: bool) -> Result<(), String> {
+    let va = if reverse {
+        let addr = parse_addr(&r.query)?;
+        r.va = Some(addr);
+        let (name, offset) = vmi.v2ksym(addr).map_err(|e| e.to_string())?;
+        r.symbol = Some(name);
+        r.offset = Some(offset);
+        addr
+    } else {
+        let va = resolve_symbol_expr(vmi, &r.query).map_err(|e| e.to_string())?;
+        r.va = Some(va);
+        va
+    };
+    if to_phys {
+        r.pa = Some(vmi.translate_kv2p(va).map_err(|e| e.to_string())?);
+    }
+    Ok(())
+}
+
+/// queries with each `-` replaced by the non-empty lines of stdin
+fn expand_stdin(queries: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut out = Vec::new();
+    for query in queries {
+        if query != "-" {
+            out.push(query.clone());
+            continue;
+        }
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() {
+                out.push(line.to_string());
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn print_table(results: &[Resolution]) {
+    for r in results {
+        if let Some(e) = &r.error {
+            eprintln!("{}: {}", r.query, e);
+            continue;
+        }
+        let va = r.va.unwrap_or(0);
+        let mut line = match &r.symbol {
+            Some(name) => format!(
+                "0x{:016x}  {}",
+                va,
+                format_location(Some((name.clone(), r.offset.unwrap_or(0))), va)
+            ),
+            None => format!("{:<30} 0x{:016x}", r.query, va),
+        };
+        if let Some(pa) = r.pa {
+            line.push_str(&format!("  pa 0x{:x}", pa));
+        }
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,24 @@
+//! tree command implementation
+
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::os::Capability;
+
+pub fn run(args: &VmiArgs, json: bool) -> anyhow::Result<()> {
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    super::require(&session, Capability::ProcessTree)?;
+
+    let tree = session
+        .process_tree()
+        .map_err(|e| anyhow::anyhow!("tree failed: {}", e))?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&tree.to_json())?);
+    } else {
+        tree.write_tree(&mut std::io::stdout().lock())?;
+    }
+
+    Ok(())
+}
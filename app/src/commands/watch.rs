@@ -0,0 +1,38 @@
+//! watch command implementation
+
+use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::watch::{parse_access, Watchpoint};
+
+pub fn run(
+    args: &VmiArgs,
+    vaddr: u64,
+    access: &str,
+    len: u64,
+    dtb: Option<u64>,
+) -> anyhow::Result<()> {
+    let access = parse_access(access).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+    let session = args
+        .to_builder()
+        .build()
+        .map_err(|e| anyhow::anyhow!("init failed: {}", e))?;
+    let printer = super::print_events(&session)?;
+
+    let mut watchpoint = Watchpoint::new(vaddr, access).len(len);
+    if let Some(dtb) = dtb {
+        watchpoint = watchpoint.dtb(dtb);
+    }
+    session
+        .add_event(watchpoint)
+        .map_err(|e| anyhow::anyhow!("watch failed: {}", e))?;
+
+    eprintln!("Watching {:#x}. Press Ctrl+C to stop.", vaddr);
+
+    // the page is unprotected again before we detach
+    session
+        .run_until_ctrlc()
+        .map_err(|e| anyhow::anyhow!("event loop stopped: {}", e))?;
+
+    super::finish(session, printer);
+    Ok(())
+}
@@ -15,7 +15,10 @@
 //!
 //! anything else and the hook becomes one-shot (restore original, bail).
 
-use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, OpKind, Register};
+use iced_x86::{
+    Decoder, DecoderOptions, FlowControl, Formatter, Instruction, IntelFormatter, Mnemonic, OpKind,
+    Register,
+};
 
 use crate::error::{Result, VmiError};
 use crate::ffi::{R10, R11, R12, R13, R14, R15, R8, R9, RAX, RBP, RBX, RCX, RDI, RDX, RSI, RSP};
@@ -72,6 +75,11 @@ pub enum EmulationStrategy {
     /// sub reg, imm
     /// e.g. `sub rsp, 0x40` - stack allocation
     SubImm { reg: u64, imm: u64, len: u64 },
+    /// and reg, imm
+    /// e.g. `and rsp, -16` - stack alignment. imm is already sign-extended
+    /// to the operand size, a 32-bit destination gets a 32-bit mask so the
+    /// and also clears the upper half like the cpu does
+    AndImm { reg: u64, imm: u64, len: u64 },
     /// lea dst, [base + disp]
     /// e.g. `lea rbp, [rsp+0x20]` - another frame setup pattern
     Lea {
@@ -106,6 +114,7 @@ pub fn analyze_instruction(
         Mnemonic::Push => decode_push(&instr),
         Mnemonic::Mov => decode_mov(&instr),
         Mnemonic::Sub => decode_sub_imm(&instr),
+        Mnemonic::And => decode_and_imm(&instr),
         Mnemonic::Lea => decode_lea(&instr),
         _ => None,
     };
@@ -206,6 +215,37 @@ fn decode_sub_imm(instr: &Instruction) -> Option<EmulationStrategy> {
     })
 }
 
+/// decode and reg, imm - stack alignment
+fn decode_and_imm(instr: &Instruction) -> Option<EmulationStrategy> {
+    if instr.op_count() != 2 || instr.op0_kind() != OpKind::Register {
+        return None;
+    }
+
+    // -16 is encoded as imm8 0xf0. immediate() sign-extends it, the
+    // immediate32/immediate64 getters would hand back 0xf0
+    let imm = match instr.op1_kind() {
+        OpKind::Immediate8to32
+        | OpKind::Immediate32
+        | OpKind::Immediate8to64
+        | OpKind::Immediate32to64 => instr.immediate(1),
+        _ => return None,
+    };
+
+    let dst = instr.op0_register();
+    let vmi_reg = iced_reg_to_vmi(dst)?;
+    let imm = match dst.size() {
+        8 => imm,
+        4 => imm & 0xFFFF_FFFF,
+        _ => return None,
+    };
+
+    Some(EmulationStrategy::AndImm {
+        reg: vmi_reg,
+        imm,
+        len: instr.len() as u64,
+    })
+}
+
 /// decode lea dst, [base+disp] - frame pointer setup
 fn decode_lea(instr: &Instruction) -> Option<EmulationStrategy> {
     if instr.op_count() != 2 {
@@ -232,6 +272,139 @@ fn decode_lea(instr: &Instruction) -> Option<EmulationStrategy> {
     })
 }
 
+/// where a patched prologue sends execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detour {
+    /// jmp/call rel, mov reg, imm + jmp reg, push imm + ret
+    Direct(u64),
+    /// jmp/call [mem] - the target is the pointer stored at `slot`
+    Indirect { slot: u64 },
+}
+
+/// follow the first few instructions of `code` looking for the transfer a
+/// hook inserts. stops at the first branch that isn't one of the patterns
+pub fn find_detour(code: &[u8], addr: u64, bitness: Bitness) -> Option<Detour> {
+    let mut decoder = Decoder::with_ip(bitness.as_u32(), code, addr, DecoderOptions::NONE);
+    let mut pushed = None;
+    let mut loaded: Option<(Register, u64)> = None;
+
+    for _ in 0..4 {
+        if !decoder.can_decode() {
+            break;
+        }
+        let instr = decoder.decode();
+        if instr.is_invalid() {
+            break;
+        }
+
+        match instr.flow_control() {
+            FlowControl::UnconditionalBranch | FlowControl::Call => {
+                return match instr.op0_kind() {
+                    OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                        Some(Detour::Direct(instr.near_branch_target()))
+                    }
+                    _ => None,
+                };
+            }
+            FlowControl::IndirectBranch | FlowControl::IndirectCall => {
+                return match instr.op0_kind() {
+                    OpKind::Memory if instr.is_ip_rel_memory_operand() => Some(Detour::Indirect {
+                        slot: instr.ip_rel_memory_address(),
+                    }),
+                    OpKind::Memory
+                        if instr.memory_base() == Register::None
+                            && instr.memory_index() == Register::None =>
+                    {
+                        Some(Detour::Indirect {
+                            slot: instr.memory_displacement64(),
+                        })
+                    }
+                    OpKind::Register => loaded
+                        .filter(|(reg, _)| *reg == instr.op0_register())
+                        .map(|(_, target)| Detour::Direct(target)),
+                    _ => None,
+                };
+            }
+            FlowControl::Return => return pushed.map(Detour::Direct),
+            FlowControl::Next => {}
+            _ => return None,
+        }
+
+        match (instr.mnemonic(), instr.op0_kind()) {
+            (Mnemonic::Push, OpKind::Immediate32 | OpKind::Immediate8to32) => {
+                pushed = Some(instr.immediate32() as u64);
+            }
+            (Mnemonic::Push, OpKind::Immediate32to64 | OpKind::Immediate8to64) => {
+                pushed = Some(instr.immediate32to64() as u64);
+            }
+            (Mnemonic::Mov, OpKind::Register) => {
+                let imm = match instr.op1_kind() {
+                    OpKind::Immediate64 => Some(instr.immediate64()),
+                    OpKind::Immediate32 => Some(instr.immediate32() as u64),
+                    OpKind::Immediate32to64 => Some(instr.immediate32to64() as u64),
+                    _ => None,
+                };
+                if let Some(imm) = imm {
+                    loaded = Some((instr.op0_register(), imm));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// one decoded instruction, see disassemble_region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisasmLine {
+    pub addr: u64,
+    pub bytes: Vec<u8>,
+    /// intel syntax
+    pub text: String,
+    /// destination of a direct jmp, jcc or call
+    pub target: Option<u64>,
+}
+
+/// decode up to `max` instructions from `code`, stopping early at the
+/// first invalid one
+pub fn disassemble_region(code: &[u8], addr: u64, bitness: Bitness, max: usize) -> Vec<DisasmLine> {
+    let mut decoder = Decoder::with_ip(bitness.as_u32(), code, addr, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let mut lines = Vec::new();
+
+    while decoder.can_decode() && lines.len() < max {
+        let instr = decoder.decode();
+        if instr.is_invalid() {
+            break;
+        }
+        let mut text = String::new();
+        formatter.format(&instr, &mut text);
+        let start = (instr.ip() - addr) as usize;
+        let target = match instr.op0_kind() {
+            OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64 => {
+                Some(instr.near_branch_target())
+            }
+            _ => None,
+        };
+        lines.push(DisasmLine {
+            addr: instr.ip(),
+            bytes: code[start..start + instr.len()].to_vec(),
+            text,
+            target,
+        });
+    }
+    lines
+}
+
+/// intel syntax listing of up to `max` instructions, `addr: text` per line
+pub fn disassemble(code: &[u8], addr: u64, bitness: Bitness, max: usize) -> Vec<String> {
+    disassemble_region(code, addr, bitness, max)
+        .into_iter()
+        .map(|line| format!("{:#x}: {}", line.addr, line.text))
+        .collect()
+}
+
 /// map iced-x86 register to libvmi register constant
 fn iced_reg_to_vmi(reg: Register) -> Option<u64> {
     match reg {
@@ -254,3 +427,63 @@ fn iced_reg_to_vmi(reg: Register) -> Option<u64> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn and_imm(code: &[u8], bitness: Bitness) -> (u64, u64, u64) {
+        match analyze_instruction(code, 0x1000, bitness).unwrap() {
+            Some(EmulationStrategy::AndImm { reg, imm, len }) => (reg, imm, len),
+            other => panic!("expected AndImm, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn and_rsp_minus_16_sign_extends() {
+        // and rsp, -16
+        let (reg, imm, len) = and_imm(&[0x48, 0x83, 0xE4, 0xF0], Bitness::Bits64);
+        assert_eq!(reg, RSP as u64);
+        assert_eq!(imm, 0xFFFF_FFFF_FFFF_FFF0);
+        assert_eq!(len, 4);
+    }
+
+    #[test]
+    fn and_rsp_imm32_sign_extends() {
+        // and rsp, 0xffffff00
+        let (_, imm, len) = and_imm(&[0x48, 0x81, 0xE4, 0x00, 0xFF, 0xFF, 0xFF], Bitness::Bits64);
+        assert_eq!(imm, 0xFFFF_FFFF_FFFF_FF00);
+        assert_eq!(len, 7);
+    }
+
+    #[test]
+    fn and_esp_masks_to_32_bits() {
+        // and esp, -16 in 32-bit code
+        let (reg, imm, len) = and_imm(&[0x83, 0xE4, 0xF0], Bitness::Bits32);
+        assert_eq!(reg, RSP as u64);
+        assert_eq!(imm, 0xFFFF_FFF0);
+        assert_eq!(len, 3);
+    }
+
+    #[test]
+    fn and_r32_in_long_mode_masks_to_32_bits() {
+        // and r9d, -8: writing a 32-bit register zeroes the upper half
+        let (reg, imm, _) = and_imm(&[0x41, 0x83, 0xE1, 0xF8], Bitness::Bits64);
+        assert_eq!(reg, R9 as u64);
+        assert_eq!(imm, 0xFFFF_FFF8);
+    }
+
+    #[test]
+    fn and_with_unsupported_operands_is_not_emulated() {
+        // and sp, -16 (16-bit destination)
+        let code = [0x66, 0x83, 0xE4, 0xF0];
+        assert!(analyze_instruction(&code, 0, Bitness::Bits64)
+            .unwrap()
+            .is_none());
+        // and rsp, rax (register source)
+        let code = [0x48, 0x21, 0xC4];
+        assert!(analyze_instruction(&code, 0, Bitness::Bits64)
+            .unwrap()
+            .is_none());
+    }
+}
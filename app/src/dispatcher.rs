@@ -0,0 +1,552 @@
+//! event dispatcher - one listen loop for every registered libvmi event
+//!
+//! owns each VmiEvent and points its data at the registration, so a single
+//! C trampoline can route every event type (int3, mem, cr3, singlestep...)
+//! to its rust handler. handlers run inside events_listen while the
+//! dispatcher's caller holds the vmi lock, so they get a VmiRef to the
+//! locked Vmi and must not lock the shared one. see the vmi module for why
+//! that borrow is sound.
+//!
+//! while `run` is looping, other threads don't fight it for the lock:
+//! `call` queues a closure that the loop runs between listen rounds. a
+//! queued call starts at most one listen timeout (LISTEN_TIMEOUT_MS unless
+//! changed) plus the handler time of the round in progress after it was
+//! queued. `call_unlocked` is the same for code that takes the lock itself,
+//! such as Event enable/disable: it runs on the loop thread between rounds
+//! with the lock released.
+//!
+//! a round runs the calls queued when it starts, in order, then listens.
+//! a call queued while those run waits for the next round, so one thread
+//! issuing calls back to back (Session::execute_chunked) always has a
+//! listen between two of them.
+
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, ThreadId};
+
+use crate::error::{Result, VmiError};
+use crate::ffi::{event_response_t, vmi_event_t, vmi_instance_t};
+use crate::vmi::{Vmi, VmiEvent, VmiRef};
+
+/// per-event handler, returns the libvmi event response flags
+pub type EventHandler = Box<dyn Fn(VmiRef<'_>, *mut vmi_event_t) -> event_response_t + Send + Sync>;
+
+/// work queued for the listen loop
+type Job = Box<dyn FnOnce(&Vmi) + Send>;
+/// work run by the loop thread while it doesn't hold the vmi lock
+type UnlockedJob = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct JobQueue {
+    locked: VecDeque<Job>,
+    unlocked: VecDeque<UnlockedJob>,
+}
+
+/// default events_listen timeout per round of `run`, bounds how long a
+/// queued call waits for the loop
+pub const LISTEN_TIMEOUT_MS: u32 = 50;
+
+/// handle to a registered event, used to unregister it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandlerId(u64);
+
+struct Registration {
+    event: VmiEvent,
+    handler: EventHandler,
+    /// the dispatcher's counter, bumped by the trampoline
+    dispatched: Arc<AtomicU64>,
+}
+
+/// what one `step` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StepOutcome {
+    /// events dispatched to handlers during the listen
+    pub events: u64,
+    /// queued calls serviced
+    pub calls: usize,
+}
+
+pub struct EventDispatcher {
+    vmi: Arc<Mutex<Vmi>>,
+    /// boxed so the event and data pointer handed to libvmi never move
+    registrations: Mutex<HashMap<HandlerId, Box<Registration>>>,
+    next_id: AtomicU64,
+    /// calls waiting for the loop, Some only while `run` is looping
+    jobs: Mutex<Option<JobQueue>>,
+    /// thread inside `run`, a call from it would wait on itself
+    listener: Mutex<Option<ThreadId>>,
+    listen_timeout: AtomicU32,
+    /// events dispatched since creation
+    dispatched: Arc<AtomicU64>,
+    /// events_listen calls since creation
+    rounds: AtomicU64,
+}
+
+unsafe impl Send for EventDispatcher {}
+unsafe impl Sync for EventDispatcher {}
+
+impl EventDispatcher {
+    pub fn new(vmi: Arc<Mutex<Vmi>>) -> Arc<Self> {
+        Arc::new(Self {
+            vmi,
+            registrations: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            jobs: Mutex::new(None),
+            listener: Mutex::new(None),
+            listen_timeout: AtomicU32::new(LISTEN_TIMEOUT_MS),
+            dispatched: Arc::new(AtomicU64::new(0)),
+            rounds: AtomicU64::new(0),
+        })
+    }
+
+    /// register a configured event (set_interrupt, set_mem_event, ...).
+    /// its callback and data are overwritten to route through the dispatcher
+    pub fn register<F>(&self, vmi_lock: &Vmi, event: VmiEvent, handler: F) -> Result<HandlerId>
+    where
+        F: Fn(VmiRef<'_>, *mut vmi_event_t) -> event_response_t + Send + Sync + 'static,
+    {
+        let mut reg = Box::new(Registration {
+            event,
+            handler: Box::new(handler),
+            dispatched: self.dispatched.clone(),
+        });
+        let reg_ptr: *mut Registration = &mut *reg;
+        reg.event.set_callback(Some(dispatch_cb));
+        reg.event.set_data(reg_ptr);
+        vmi_lock.register_event(reg.event.as_mut_ptr())?;
+
+        let id = HandlerId(self.next_id.fetch_add(1, Ordering::SeqCst));
+        self.registrations.lock().unwrap().insert(id, reg);
+        Ok(id)
+    }
+
+    /// clear an event and drop its handler
+    pub fn unregister(&self, vmi_lock: &Vmi, id: HandlerId) -> Result<()> {
+        let mut reg = self
+            .registrations
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or_else(|| VmiError::Other(format!("no event handler {:?}", id)))?;
+        vmi_lock.clear_event(reg.event.as_mut_ptr())
+    }
+
+    /// register every event again on a fresh vmi, after a reconnect
+    pub(crate) fn reregister(&self, vmi_lock: &Vmi) -> Result<()> {
+        for reg in self.registrations.lock().unwrap().values_mut() {
+            vmi_lock.register_event(reg.event.as_mut_ptr())?;
+        }
+        Ok(())
+    }
+
+    /// number of registered events
+    pub fn len(&self) -> usize {
+        self.registrations.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// events handed to handlers so far, across every loop and step
+    pub fn dispatched(&self) -> u64 {
+        self.dispatched.load(Ordering::Relaxed)
+    }
+
+    /// events_listen rounds so far, across every loop and step
+    pub fn rounds(&self) -> u64 {
+        self.rounds.load(Ordering::Relaxed)
+    }
+
+    /// wait up to `timeout` ms for events and dispatch them
+    pub fn listen(&self, timeout: u32) -> Result<()> {
+        let vmi_lock = self.vmi.lock().unwrap();
+        vmi_lock.events_listen(timeout)
+    }
+
+    /// ms each round of `run` waits for events. shorter rounds get queued
+    /// calls in sooner at the cost of more wakeups
+    pub fn set_listen_timeout(&self, ms: u32) {
+        self.listen_timeout.store(ms, Ordering::Relaxed);
+    }
+
+    pub fn listen_timeout(&self) -> u32 {
+        self.listen_timeout.load(Ordering::Relaxed)
+    }
+
+    /// listen until `running` is cleared, running queued calls between
+    /// rounds. the lock is also dropped between rounds so plain lockers
+    /// get a turn
+    pub fn run(&self, running: &AtomicBool) -> Result<()> {
+        self.run_with(running, listen_events)
+    }
+
+    /// `run` with the listen swapped out, so tests can drive the loop
+    /// without a guest
+    pub(crate) fn run_with(
+        &self,
+        running: &AtomicBool,
+        listen: impl Fn(&Vmi, u32) -> Result<()>,
+    ) -> Result<()> {
+        self.open_queue()?;
+        let mut result = Ok(());
+        while running.load(Ordering::SeqCst) {
+            if let Err(e) = self.round(self.listen_timeout(), &listen) {
+                result = Err(e);
+                break;
+            }
+        }
+        self.close_queue();
+        result
+    }
+
+    /// one round of `run` for callers with their own loop: queued calls,
+    /// then a single listen of `timeout` ms. calls made while it listens
+    /// are serviced before it returns. errors if `run` is active
+    pub fn step(&self, timeout: u32) -> Result<StepOutcome> {
+        self.open_queue()?;
+        let before = self.dispatched();
+        let result = self.round(timeout, listen_events);
+        let late = self.close_queue();
+        let calls = result? + late;
+        Ok(StepOutcome {
+            events: self.dispatched() - before,
+            calls,
+        })
+    }
+
+    /// claim the loop for this thread and start queueing calls
+    fn open_queue(&self) -> Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.is_some() {
+            return Err(VmiError::Other("event loop already running".into()));
+        }
+        *jobs = Some(JobQueue::default());
+        *self.listener.lock().unwrap() = Some(thread::current().id());
+        Ok(())
+    }
+
+    /// stop queueing and run what is left, so nothing is stranded behind a
+    /// dead loop. returns the number of calls run
+    fn close_queue(&self) -> usize {
+        let left = self.jobs.lock().unwrap().take().unwrap_or_default();
+        *self.listener.lock().unwrap() = None;
+        let count = left.locked.len() + left.unlocked.len();
+        {
+            let vmi_lock = self.vmi.lock().unwrap();
+            for job in left.locked {
+                job(&vmi_lock);
+            }
+        }
+        for job in left.unlocked {
+            job();
+        }
+        count
+    }
+
+    /// queued calls then one listen, returns the calls run
+    fn round(&self, timeout: u32, listen: impl Fn(&Vmi, u32) -> Result<()>) -> Result<usize> {
+        let mut calls = self.run_unlocked_jobs();
+        let vmi_lock = self.vmi.lock().unwrap();
+        calls += self.run_jobs(&vmi_lock);
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+        listen(&vmi_lock, timeout).map(|()| calls)
+    }
+
+    /// only the calls queued when the round starts. one queued meanwhile,
+    /// e.g. the next chunk of a chunked action, waits for a listen first
+    fn run_jobs(&self, vmi_lock: &Vmi) -> usize {
+        let queued = self.queued(|queue| queue.locked.len());
+        let mut count = 0;
+        while count < queued
+            && let Some(job) = self.next_job(|queue| queue.locked.pop_front())
+        {
+            job(vmi_lock);
+            count += 1;
+        }
+        count
+    }
+
+    fn run_unlocked_jobs(&self) -> usize {
+        let queued = self.queued(|queue| queue.unlocked.len());
+        let mut count = 0;
+        while count < queued
+            && let Some(job) = self.next_job(|queue| queue.unlocked.pop_front())
+        {
+            job();
+            count += 1;
+        }
+        count
+    }
+
+    fn queued(&self, len: impl FnOnce(&JobQueue) -> usize) -> usize {
+        self.jobs.lock().unwrap().as_ref().map_or(0, len)
+    }
+
+    fn next_job<T>(&self, pop: impl FnOnce(&mut JobQueue) -> Option<T>) -> Option<T> {
+        self.jobs.lock().unwrap().as_mut().and_then(pop)
+    }
+
+    /// run `f` with the vmi. queued for the listen loop while `run` is
+    /// active, otherwise runs on the caller under the lock. errors when
+    /// called from inside an event handler, which already has a Vmi.
+    /// a panic in `f` is caught where it runs, so it neither kills the
+    /// loop nor poisons the vmi lock, and resumes on the caller
+    pub fn call<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Vmi) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.check_caller()?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.as_mut() {
+                Some(queue) => queue.locked.push_back(Box::new(move |vmi: &Vmi| {
+                    let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(|| f(vmi))));
+                })),
+                None => {
+                    drop(jobs);
+                    let outcome = {
+                        let vmi_lock = self.vmi.lock().unwrap();
+                        panic::catch_unwind(AssertUnwindSafe(|| f(&vmi_lock)))
+                    };
+                    return Ok(outcome.unwrap_or_else(|p| panic::resume_unwind(p)));
+                }
+            }
+        }
+        Self::wait(rx)
+    }
+
+    /// like `call`, but `f` gets no vmi and runs with the lock released, so
+    /// it may lock the shared vmi itself. while `run` is active it runs on
+    /// the loop thread between rounds, where no event is being dispatched
+    pub fn call_unlocked<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.check_caller()?;
+        let (tx, rx) = mpsc::sync_channel(1);
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.as_mut() {
+                Some(queue) => queue.unlocked.push_back(Box::new(move || {
+                    let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+                })),
+                None => {
+                    drop(jobs);
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+                    return Ok(outcome.unwrap_or_else(|p| panic::resume_unwind(p)));
+                }
+            }
+        }
+        Self::wait(rx)
+    }
+
+    /// the loop thread would queue work and then wait on itself
+    fn check_caller(&self) -> Result<()> {
+        if *self.listener.lock().unwrap() == Some(thread::current().id()) {
+            return Err(VmiError::Other(
+                "vmi call from an event handler, use the handler's vmi".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn wait<R>(rx: mpsc::Receiver<thread::Result<R>>) -> Result<R> {
+        match rx.recv() {
+            Ok(outcome) => Ok(outcome.unwrap_or_else(|p| panic::resume_unwind(p))),
+            Err(_) => Err(VmiError::Other("event loop dropped a queued call".into())),
+        }
+    }
+}
+
+impl Drop for EventDispatcher {
+    fn drop(&mut self) {
+        let mut registrations = self.registrations.lock().unwrap();
+        if registrations.is_empty() {
+            return;
+        }
+        let vmi = self.vmi.lock().unwrap();
+        for (_, mut reg) in registrations.drain() {
+            let _ = vmi.clear_event(reg.event.as_mut_ptr());
+        }
+    }
+}
+
+unsafe extern "C" fn dispatch_cb(
+    vmi_handle: vmi_instance_t,
+    event: *mut vmi_event_t,
+) -> event_response_t {
+    unsafe {
+        let reg = (*event).data as *const Registration;
+        if reg.is_null() {
+            return 0;
+        }
+        // libvmi only calls back from inside events_listen, which recorded
+        // the Vmi it runs on
+        let Some(vmi) = VmiRef::listening(vmi_handle) else {
+            log::warn!("[EventDispatcher] event from an unknown vmi handle, ignored");
+            return 0;
+        };
+        (*reg).dispatched.fetch_add(1, Ordering::Relaxed);
+        ((*reg).handler)(vmi, event)
+    }
+}
+
+/// one events_listen, telling a dead socket apart from a failed call
+fn listen_events(vmi_lock: &Vmi, timeout: u32) -> Result<()> {
+    match vmi_lock.events_listen(timeout) {
+        Ok(()) => Ok(()),
+        // a dead kvmi socket fails every call, a live one answers
+        Err(e) if vmi_lock.responds() => Err(e),
+        Err(_) => Err(VmiError::Disconnected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dispatcher() -> Arc<EventDispatcher> {
+        EventDispatcher::new(Arc::new(Mutex::new(Vmi::detached())))
+    }
+
+    #[test]
+    fn call_runs_on_the_caller_without_a_loop() {
+        let dispatcher = dispatcher();
+        assert!(!dispatcher.call(|vmi| vmi.events_enabled()).unwrap());
+    }
+
+    #[test]
+    fn panicking_call_resumes_on_the_caller_and_leaves_the_lock_usable() {
+        let dispatcher = dispatcher();
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+            dispatcher.call(|_| -> () { panic!("boom") })
+        }));
+        let message = outcome.unwrap_err();
+        assert_eq!(message.downcast_ref::<&str>(), Some(&"boom"));
+        assert!(!dispatcher.vmi.is_poisoned());
+        assert_eq!(dispatcher.call(|_| 7).unwrap(), 7);
+    }
+
+    #[test]
+    fn panicking_queued_call_resumes_on_the_caller() {
+        let dispatcher = dispatcher();
+        dispatcher.open_queue().unwrap();
+        let caller = {
+            let dispatcher = dispatcher.clone();
+            thread::spawn(move || dispatcher.call(|_| -> () { panic!("boom") }))
+        };
+        while dispatcher.queued(|queue| queue.locked.len()) == 0 {
+            thread::yield_now();
+        }
+        // the loop thread runs the job and survives it
+        let vmi = dispatcher.vmi.clone();
+        assert_eq!(dispatcher.run_jobs(&vmi.lock().unwrap()), 1);
+        assert!(caller.join().is_err());
+        assert!(!dispatcher.vmi.is_poisoned());
+        dispatcher.close_queue();
+    }
+
+    #[test]
+    fn unlocked_call_can_take_the_lock_itself() {
+        let dispatcher = dispatcher();
+        let vmi = dispatcher.vmi.clone();
+        let events = dispatcher
+            .call_unlocked(move || vmi.lock().unwrap().events_enabled())
+            .unwrap();
+        assert!(!events);
+    }
+
+    #[test]
+    fn unlocked_call_is_queued_for_the_loop_thread() {
+        let dispatcher = dispatcher();
+        dispatcher.open_queue().unwrap();
+        let caller = {
+            let dispatcher = dispatcher.clone();
+            let vmi = dispatcher.vmi.clone();
+            thread::spawn(move || {
+                dispatcher.call_unlocked(move || {
+                    // runs with the lock released
+                    let _vmi = vmi.try_lock().expect("lock held during an unlocked call");
+                    thread::current().id()
+                })
+            })
+        };
+        while dispatcher.queued(|queue| queue.unlocked.len()) == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(dispatcher.run_unlocked_jobs(), 1);
+        assert_eq!(caller.join().unwrap().unwrap(), thread::current().id());
+        dispatcher.close_queue();
+    }
+
+    #[test]
+    fn calls_from_the_loop_thread_are_refused() {
+        let dispatcher = dispatcher();
+        dispatcher.open_queue().unwrap();
+        assert!(dispatcher.call(|_| ()).is_err());
+        assert!(dispatcher.call_unlocked(|| ()).is_err());
+        // and a second loop can't start
+        assert!(dispatcher.open_queue().is_err());
+        dispatcher.close_queue();
+        assert!(dispatcher.call_unlocked(|| ()).is_ok());
+    }
+
+    #[test]
+    fn a_round_runs_only_the_calls_queued_when_it_started() {
+        let dispatcher = dispatcher();
+        dispatcher.open_queue().unwrap();
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let push = |dispatcher: &EventDispatcher, job: Job| {
+            let mut jobs = dispatcher.jobs.lock().unwrap();
+            jobs.as_mut().unwrap().locked.push_back(job);
+        };
+
+        // the first job queues the next chunk, as execute_chunked does
+        let first = {
+            let (d, ran) = (dispatcher.clone(), ran.clone());
+            Box::new(move |_: &Vmi| {
+                ran.lock().unwrap().push("first");
+                let ran = ran.clone();
+                push(
+                    &d,
+                    Box::new(move |_: &Vmi| ran.lock().unwrap().push("next")),
+                );
+            })
+        };
+        push(&dispatcher, first);
+        let ran_second = ran.clone();
+        push(
+            &dispatcher,
+            Box::new(move |_: &Vmi| ran_second.lock().unwrap().push("second")),
+        );
+
+        let vmi = dispatcher.vmi.clone();
+        assert_eq!(dispatcher.run_jobs(&vmi.lock().unwrap()), 2);
+        assert_eq!(*ran.lock().unwrap(), ["first", "second"]);
+        // a listen would go here before the next round
+        assert_eq!(dispatcher.run_jobs(&vmi.lock().unwrap()), 1);
+        assert_eq!(*ran.lock().unwrap(), ["first", "second", "next"]);
+        assert_eq!(dispatcher.close_queue(), 0);
+    }
+
+    #[test]
+    fn closing_the_queue_runs_what_is_left() {
+        let dispatcher = dispatcher();
+        dispatcher.open_queue().unwrap();
+        let caller = {
+            let dispatcher = dispatcher.clone();
+            thread::spawn(move || dispatcher.call(|_| 42))
+        };
+        while dispatcher.queued(|queue| queue.locked.len()) == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(dispatcher.close_queue(), 1);
+        assert_eq!(caller.join().unwrap().unwrap(), 42);
+    }
+}
@@ -0,0 +1,161 @@
+//! memory dumps that carry on past unreadable pages
+//!
+//! reads go a page at a time so one hole only costs its own page, which
+//! is zero-filled and recorded as a gap
+
+use std::ops::Range;
+
+use crate::error::{Result, VmiError};
+use crate::vmi::{page_base, AddressSpace, ReadContext, Vmi, PAGE_SIZE};
+
+/// bytes of a range, see Session::dump_memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    pub start: u64,
+    /// always the requested length, gaps read as zeros
+    pub bytes: Vec<u8>,
+    /// unreadable ranges, ascending, adjacent pages merged
+    pub gaps: Vec<Range<u64>>,
+}
+
+impl MemoryDump {
+    /// zero-filled bytes in total
+    pub fn gap_bytes(&self) -> u64 {
+        self.gaps.iter().map(|g| g.end - g.start).sum()
+    }
+}
+
+/// read `len` bytes at `start` page by page, no pausing
+pub fn dump(vmi: &Vmi, space: &AddressSpace, start: u64, len: u64) -> Result<MemoryDump> {
+    if let AddressSpace::KernelSymbol(_) = space {
+        return Err(VmiError::Other(
+            "dumps need an address, not a kernel symbol".into(),
+        ));
+    }
+    dump_with(start, len, |addr, chunk| {
+        let ctx = ReadContext {
+            addr,
+            space: space.clone(),
+        };
+        vmi.read_ctx(&ctx, chunk)
+    })
+}
+
+/// the page by page loop, `read(addr, len)` never crossing a page
+pub fn dump_with(
+    start: u64,
+    len: u64,
+    mut read: impl FnMut(u64, usize) -> Result<Vec<u8>>,
+) -> Result<MemoryDump> {
+    let end = start
+        .checked_add(len)
+        .ok_or_else(|| VmiError::Other(format!("{:#x}+{:#x} overflows", start, len)))?;
+
+    let mut bytes = Vec::with_capacity(len as usize);
+    let mut gaps: Vec<Range<u64>> = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        let chunk_end = page_base(addr).saturating_add(PAGE_SIZE).min(end);
+        let chunk = (chunk_end - addr) as usize;
+        match read(addr, chunk) {
+            Ok(data) => bytes.extend_from_slice(&data),
+            Err(_) => {
+                bytes.resize(bytes.len() + chunk, 0);
+                match gaps.last_mut() {
+                    Some(last) if last.end == addr => last.end = chunk_end,
+                    _ => gaps.push(addr..chunk_end),
+                }
+            }
+        }
+        addr = chunk_end;
+    }
+
+    Ok(MemoryDump { start, bytes, gaps })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// guest memory where each byte is the low byte of its address,
+    /// except the pages listed as unmapped
+    fn memory(unmapped: &[u64]) -> impl FnMut(u64, usize) -> Result<Vec<u8>> + '_ {
+        |addr, len| {
+            if unmapped.contains(&page_base(addr)) {
+                return Err(VmiError::ReadFailed {
+                    addr,
+                    msg: "unmapped".into(),
+                });
+            }
+            Ok((addr..addr + len as u64).map(|a| a as u8).collect())
+        }
+    }
+
+    #[test]
+    fn reads_split_at_page_boundaries() {
+        let mut reads = Vec::new();
+        let dump = dump_with(0x1ff0, 0x2020, |addr, len| {
+            reads.push((addr, len));
+            Ok(vec![0xaa; len])
+        })
+        .unwrap();
+        assert_eq!(
+            reads,
+            [
+                (0x1ff0, 0x10),
+                (0x2000, 0x1000),
+                (0x3000, 0x1000),
+                (0x4000, 0x10)
+            ]
+        );
+        assert_eq!(dump.bytes.len(), 0x2020);
+        assert!(dump.gaps.is_empty());
+    }
+
+    #[test]
+    fn within_one_page_is_one_read() {
+        let dump = dump_with(0x1010, 0x20, memory(&[])).unwrap();
+        assert_eq!(dump.start, 0x1010);
+        assert_eq!(dump.bytes, (0x10..0x30).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn unreadable_pages_are_zero_filled_gaps() {
+        let dump = dump_with(0x0800, 0x3000, memory(&[0x1000, 0x2000])).unwrap();
+        // adjacent unreadable pages merge into one gap
+        assert_eq!(
+            dump.gaps,
+            [Range {
+                start: 0x1000,
+                end: 0x3000
+            }]
+        );
+        assert_eq!(dump.gap_bytes(), 0x2000);
+        assert_eq!(dump.bytes.len(), 0x3000);
+        assert_eq!(dump.bytes[0x7ff], 0xff);
+        assert!(dump.bytes[0x800..0x2800].iter().all(|&b| b == 0));
+        assert_eq!(dump.bytes[0x2800], 0x00);
+        assert_eq!(dump.bytes[0x2801], 0x01);
+    }
+
+    #[test]
+    fn gaps_are_clipped_to_the_range() {
+        let dump = dump_with(0x1800, 0x1000, memory(&[0x1000, 0x2000])).unwrap();
+        assert_eq!(
+            dump.gaps,
+            [Range {
+                start: 0x1800,
+                end: 0x2800
+            }]
+        );
+        let dump = dump_with(0x0, 0x3000, memory(&[0x0, 0x2000])).unwrap();
+        assert_eq!(dump.gaps, [0x0..0x1000, 0x2000..0x3000]);
+    }
+
+    #[test]
+    fn range_past_the_address_space_is_an_error() {
+        assert!(dump_with(u64::MAX - 0xf, 0x20, memory(&[])).is_err());
+        let top = dump_with(u64::MAX - 0xf, 0xf, memory(&[])).unwrap();
+        assert_eq!(top.bytes.len(), 0xf);
+    }
+}
@@ -16,6 +16,12 @@ pub enum VmiError {
     #[error("Symbol not found: {0}")]
     SymbolNotFound(String),
 
+    #[error("Invalid symbol expression '{0}', expected name, name+off or name-off")]
+    InvalidSymbolExpr(String),
+
+    #[error("Offset not found in profile: {0}")]
+    OffsetNotFound(String),
+
     #[error("Invalid UTF-8 in process name")]
     InvalidProcessName,
 
@@ -28,11 +34,54 @@ pub enum VmiError {
     #[error("Hook already exists at {0:#x}")]
     HookExists(u64),
 
+    #[error("Listening for events failed")]
+    ListenFailed,
+
+    #[error("Lost the connection to the hypervisor")]
+    Disconnected,
+
+    #[error("The driver doesn't support slat (altp2m) views")]
+    SlatUnsupported,
+
+    #[error("Slat (altp2m) {0} failed")]
+    SlatFailed(&'static str),
+
+    #[error("MSR {0:#x} isn't readable through libvmi")]
+    UnsupportedMsr(u32),
+
     #[error("Failed to set memory access for GFN {0:#x}")]
     MemAccessFailed(u64),
 
+    #[error("Profile is for a {profile}-bit kernel but the guest is {guest}-bit")]
+    WidthMismatch { profile: u32, guest: u32 },
+
+    #[error("Expected a {expected} guest, found {found}")]
+    OsMismatch { expected: String, found: String },
+
+    #[error("{action} isn't implemented for {os} yet")]
+    NotImplemented { action: &'static str, os: String },
+
+    #[error("Invalid init options: {0}")]
+    Config(#[from] ConfigError),
+
     #[error("Error: {0}")]
     Other(String),
 }
 
+/// init options rejected before libvmi is called
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("no domain name or domain id given")]
+    MissingDomain,
+
+    #[error("both a domain name and a domain id given, pick one")]
+    ConflictingDomain,
+
+    #[error("no profile given")]
+    MissingProfile,
+
+    #[error("the kvm backend needs a kvmi socket path")]
+    MissingSocket,
+}
+
 pub type Result<T> = std::result::Result<T, VmiError>;
@@ -0,0 +1,253 @@
+//! process filters for guest event consumers
+//!
+//! filters run where events are consumed, not in the hook callbacks, so
+//! a filtered monitor costs the guest the same as an unfiltered one.
+//!
+//! values within a category are OR'd, categories are AND'd: `--filter-name
+//! a --filter-name b --filter-ppid 4` keeps children of pid 4 named a or
+//! b. excludes win over everything. events that aren't about a process
+//! (hook hits, session notices, stats) always pass
+
+use crate::event_stream::GuestEvent;
+
+/// who an event is about, see GuestEvent::process
+pub struct ProcessIdentity<'a> {
+    pub pid: u64,
+    pub ppid: u64,
+    /// image path, command line, comm, whatever the event carries
+    pub names: Vec<&'a str>,
+}
+
+impl GuestEvent {
+    /// the process an event is about, None for the rest. new per-process
+    /// events add an arm here and get filtering for free
+    pub fn process(&self) -> Option<ProcessIdentity<'_>> {
+        match self {
+            GuestEvent::ProcessCreated {
+                pid,
+                ppid,
+                image,
+                cmdline,
+                ..
+            } => Some(ProcessIdentity {
+                pid: *pid,
+                ppid: *ppid,
+                names: image.iter().chain(cmdline).map(String::as_str).collect(),
+            }),
+            GuestEvent::TaskCreated {
+                pid, ppid, comm, ..
+            } => Some(ProcessIdentity {
+                pid: *pid,
+                ppid: *ppid,
+                names: comm.iter().map(String::as_str).collect(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// which process events to keep, everything by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    names: Vec<String>,
+    excludes: Vec<String>,
+    pids: Vec<u64>,
+    ppids: Vec<u64>,
+}
+
+impl EventFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// keep processes with a name matching `pattern`, see name_matches
+    pub fn name(mut self, pattern: &str) -> Self {
+        self.names.push(pattern.to_lowercase());
+        self
+    }
+
+    /// drop processes with a name matching `pattern`
+    pub fn exclude_name(mut self, pattern: &str) -> Self {
+        self.excludes.push(pattern.to_lowercase());
+        self
+    }
+
+    pub fn pid(mut self, pid: u64) -> Self {
+        self.pids.push(pid);
+        self
+    }
+
+    pub fn ppid(mut self, ppid: u64) -> Self {
+        self.ppids.push(ppid);
+        self
+    }
+
+    /// true when nothing would be filtered
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, event: &GuestEvent) -> bool {
+        let Some(process) = event.process() else {
+            return true;
+        };
+        let names: Vec<String> = process.names.iter().map(|n| n.to_lowercase()).collect();
+        let any_name = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|p| names.iter().any(|n| name_matches(p, n)))
+        };
+
+        (self.pids.is_empty() || self.pids.contains(&process.pid))
+            && (self.ppids.is_empty() || self.ppids.contains(&process.ppid))
+            && (self.names.is_empty() || any_name(&self.names))
+            && !any_name(&self.excludes)
+    }
+}
+
+/// a pattern with `*` or `?` globs over the whole name, anything else is a
+/// substring. callers lowercase both sides
+fn name_matches(pattern: &str, name: &str) -> bool {
+    if pattern.contains(['*', '?']) {
+        glob(pattern.as_bytes(), name.as_bytes())
+    } else {
+        name.contains(pattern)
+    }
+}
+
+/// `*` any run, `?` any one byte. backtracks to the last star only, so
+/// it stays linear-ish on long command lines
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    p = sp + 1;
+                    t = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn created(pid: u64, ppid: u64, image: &str) -> GuestEvent {
+        GuestEvent::ProcessCreated {
+            pid,
+            ppid,
+            image: Some(image.to_string()),
+            cmdline: None,
+            time: 0,
+            environment: None,
+        }
+    }
+
+    fn task(pid: u64, ppid: u64, comm: &str) -> GuestEvent {
+        GuestEvent::TaskCreated {
+            pid,
+            ppid,
+            comm: Some(comm.to_string()),
+            uid: None,
+        }
+    }
+
+    #[test]
+    fn empty_filter_keeps_everything() {
+        let filter = EventFilter::new();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&created(4, 0, "System")));
+        assert!(filter.matches(&task(1, 0, "init")));
+        assert!(!filter.clone().pid(4).is_empty());
+    }
+
+    #[test]
+    fn pid_and_ppid() {
+        let filter = EventFilter::new().pid(100).pid(200);
+        assert!(filter.matches(&created(100, 4, "a.exe")));
+        assert!(filter.matches(&created(200, 4, "b.exe")));
+        assert!(!filter.matches(&created(300, 4, "c.exe")));
+
+        let children = EventFilter::new().ppid(4);
+        assert!(children.matches(&task(50, 4, "kworker")));
+        assert!(!children.matches(&task(50, 1, "kworker")));
+    }
+
+    #[test]
+    fn categories_are_anded() {
+        let filter = EventFilter::new().name("cmd").ppid(4);
+        assert!(filter.matches(&created(10, 4, "cmd.exe")));
+        assert!(!filter.matches(&created(10, 8, "cmd.exe")));
+        assert!(!filter.matches(&created(10, 4, "notepad.exe")));
+    }
+
+    #[test]
+    fn name_substring_and_glob() {
+        let sub = EventFilter::new().name("power");
+        assert!(sub.matches(&created(1, 0, r"C:\Windows\powershell.exe")));
+        assert!(!sub.matches(&created(1, 0, r"C:\Windows\cmd.exe")));
+
+        // a glob has to cover the whole name
+        let glob = EventFilter::new().name("*.exe");
+        assert!(glob.matches(&created(1, 0, "svchost.exe")));
+        assert!(!glob.matches(&created(1, 0, "svchost.exe.mui")));
+        let single = EventFilter::new().name("sh?");
+        assert!(single.matches(&task(1, 0, "shx")));
+        assert!(!single.matches(&task(1, 0, "sh")));
+    }
+
+    #[test]
+    fn names_ignore_case() {
+        let filter = EventFilter::new().name("CMD.EXE");
+        assert!(filter.matches(&created(1, 0, r"C:\Windows\System32\cmd.exe")));
+        let filter = EventFilter::new().name("cmd*");
+        assert!(filter.matches(&created(1, 0, "CMD.EXE")));
+    }
+
+    #[test]
+    fn excludes_win() {
+        let filter = EventFilter::new().name("*.exe").exclude_name("SVCHOST");
+        assert!(filter.matches(&created(1, 0, "cmd.exe")));
+        assert!(!filter.matches(&created(1, 0, "svchost.exe")));
+    }
+
+    #[test]
+    fn cmdline_counts_as_a_name() {
+        let event = GuestEvent::ProcessCreated {
+            pid: 1,
+            ppid: 0,
+            image: None,
+            cmdline: Some("python3 evil.py".into()),
+            time: 0,
+            environment: None,
+        };
+        assert!(EventFilter::new().name("evil").matches(&event));
+    }
+
+    #[test]
+    fn non_process_events_always_pass() {
+        let filter = EventFilter::new().pid(1).name("nothing");
+        let hit = GuestEvent::HookHit {
+            label: "x".into(),
+            vcpu: 0,
+            rip: 0,
+        };
+        assert!(filter.matches(&hit));
+        assert!(filter.matches(&GuestEvent::Disconnected));
+    }
+}
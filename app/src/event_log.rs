@@ -0,0 +1,156 @@
+//! guest events written to disk as json lines, for long captures
+//!
+//! meant for the consumer side of the event stream, never an event handler:
+//! a slow disk only fills the queue, and what the queue dropped is written
+//! into the log as an `events_dropped` line so gaps stay visible
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::event_stream::GuestEvent;
+
+/// buffered lines reach the file at least this often while events arrive
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// rotated files kept unless told otherwise
+pub const DEFAULT_KEEP: u32 = 5;
+
+/// `path` written one event per line, `{"ts": <unix seconds>, "type": ...}`.
+/// with a max size the live file is rotated to `path.1`, `path.1` to
+/// `path.2` and so on, the oldest past `keep` is deleted
+pub struct EventLog {
+    path: PathBuf,
+    file: BufWriter<File>,
+    written: u64,
+    max_size: Option<u64>,
+    keep: u32,
+    last_flush: Instant,
+    dropped_logged: u64,
+}
+
+impl EventLog {
+    /// create (or truncate) `path`, no rotation until max_size is set
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            file,
+            written: 0,
+            max_size: None,
+            keep: DEFAULT_KEEP,
+            last_flush: Instant::now(),
+            dropped_logged: 0,
+        })
+    }
+
+    /// rotate once the live file would grow past `bytes`
+    pub fn max_size(mut self, bytes: Option<u64>) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    /// rotated files kept beside the live one, 0 just truncates
+    pub fn keep(mut self, files: u32) -> Self {
+        self.keep = files;
+        self
+    }
+
+    /// append one event, flushing when FLUSH_INTERVAL has passed
+    pub fn write_event(&mut self, event: &GuestEvent) -> io::Result<()> {
+        self.write_value(event.to_json())
+    }
+
+    /// record drops the log hasn't mentioned yet. `total` is the running
+    /// count, e.g. Session::drop_counter, the line carries the difference
+    pub fn note_dropped(&mut self, total: u64) -> io::Result<()> {
+        if total <= self.dropped_logged {
+            return Ok(());
+        }
+        let count = total - self.dropped_logged;
+        self.dropped_logged = total;
+        self.write_value(json!({
+            "type": "events_dropped",
+            "count": count,
+            "total": total,
+        }))
+    }
+
+    /// flush if nothing was flushed for FLUSH_INTERVAL, for idle periods
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.file.flush()
+    }
+
+    /// the live file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_value(&mut self, mut value: Value) -> io::Result<()> {
+        if let Value::Object(fields) = &mut value {
+            fields.insert("ts".into(), json!(unix_time()));
+        }
+        let mut line = serde_json::to_vec(&value)?;
+        line.push(b'\n');
+
+        let len = line.len() as u64;
+        if let Some(max) = self.max_size
+            && self.written > 0
+            && self.written + len > max
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.written += len;
+        self.flush_if_due()
+    }
+
+    /// path.N-1 -> path.N down to path -> path.1, then reopen path
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep > 0 {
+            for n in (1..self.keep).rev() {
+                match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        name.into()
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+/// host wall clock as fractional unix seconds
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
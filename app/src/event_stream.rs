@@ -0,0 +1,183 @@
+//! structured guest events, pushed by monitors and consumed by the session owner
+//!
+//! producers run inside events_listen with the vcpu paused, so they never
+//! block: a full queue drops the new event and bumps a counter instead.
+//! a slow consumer loses events, it never stalls the guest or deadlocks
+//! against the vmi lock.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::stats::SessionStats;
+
+/// events buffered before new ones are dropped
+pub const EVENT_QUEUE_LEN: usize = 4096;
+
+/// something observed in the guest
+#[derive(Debug, Clone)]
+pub enum GuestEvent {
+    /// windows process insertion (PspInsertProcess)
+    ProcessCreated {
+        pid: u64,
+        ppid: u64,
+        /// None when the PEB wasn't readable yet
+        image: Option<String>,
+        cmdline: Option<String>,
+        /// EPROCESS.CreateTime, FILETIME ticks
+        time: u64,
+        /// NAME=value pairs when environment capture is on
+        environment: Option<Vec<(String, String)>>,
+    },
+    /// linux process (thread group leader) creation
+    TaskCreated {
+        pid: u64,
+        ppid: u64,
+        comm: Option<String>,
+        uid: Option<u32>,
+    },
+    /// a hook set up by the caller was hit
+    HookHit { label: String, vcpu: u32, rip: u64 },
+    /// the kvmi connection dropped, hooks stay armed until reconnected
+    Disconnected,
+    /// the guest is not the one the hooks were placed in: it never came
+    /// back, or came back as a new boot (`rebooted`). hooks were dropped
+    /// without restoring anything
+    GuestGone { rebooted: bool },
+    /// the vmi was re-initialized, events re-registered and `hooks` hooks
+    /// re-armed
+    Reconnected { attempt: u32, hooks: usize },
+    /// a watched address was accessed, `access` is VMI_MEMACCESS_* bits
+    MemoryAccess {
+        vcpu: u32,
+        rip: u64,
+        access: u32,
+        gla: Option<u64>,
+    },
+    /// periodic counters, see SessionBuilder::stats_interval
+    Stats(SessionStats),
+}
+
+impl GuestEvent {
+    /// `{"type": ..., fields...}` object, one line per event for log shippers
+    pub fn to_json(&self) -> Value {
+        match self {
+            GuestEvent::ProcessCreated {
+                pid,
+                ppid,
+                image,
+                cmdline,
+                time,
+                environment,
+            } => json!({
+                "type": "process_created",
+                "pid": pid,
+                "ppid": ppid,
+                "image": image,
+                "cmdline": cmdline,
+                "time": time,
+                "environment": environment.as_ref().map(|env| {
+                    env.iter()
+                        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+                        .collect::<serde_json::Map<_, _>>()
+                }),
+            }),
+            GuestEvent::TaskCreated {
+                pid,
+                ppid,
+                comm,
+                uid,
+            } => json!({
+                "type": "task_created",
+                "pid": pid,
+                "ppid": ppid,
+                "comm": comm,
+                "uid": uid,
+            }),
+            GuestEvent::HookHit { label, vcpu, rip } => json!({
+                "type": "hook_hit",
+                "label": label,
+                "vcpu": vcpu,
+                "rip": rip,
+            }),
+            GuestEvent::Disconnected => json!({ "type": "disconnected" }),
+            GuestEvent::GuestGone { rebooted } => json!({
+                "type": "guest_gone",
+                "rebooted": rebooted,
+            }),
+            GuestEvent::Reconnected { attempt, hooks } => json!({
+                "type": "reconnected",
+                "attempt": attempt,
+                "hooks": hooks,
+            }),
+            GuestEvent::MemoryAccess {
+                vcpu,
+                rip,
+                access,
+                gla,
+            } => json!({
+                "type": "memory_access",
+                "vcpu": vcpu,
+                "rip": rip,
+                "access": access,
+                "gla": gla,
+            }),
+            GuestEvent::Stats(stats) => json!({
+                "type": "stats",
+                "stats": stats,
+            }),
+        }
+    }
+}
+
+/// sending half handed to monitors, cheap to clone
+#[derive(Clone)]
+pub struct EventSink {
+    tx: SyncSender<GuestEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventSink {
+    /// bounded queue of `capacity` events
+    pub fn channel(capacity: usize) -> (Self, Receiver<GuestEvent>) {
+        let (tx, rx) = mpsc::sync_channel(capacity);
+        let sink = Self {
+            tx,
+            dropped: Arc::new(AtomicU64::new(0)),
+        };
+        (sink, rx)
+    }
+
+    /// queue an event, dropping it if the queue is full or nobody listens
+    pub fn emit(&self, event: GuestEvent) {
+        match self.tx.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// events lost to a full or closed queue so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// read-only view of the drop count. unlike a sink it holds no sender,
+    /// so a consumer can keep one without keeping the stream open
+    pub fn drop_counter(&self) -> DropCounter {
+        DropCounter(self.dropped.clone())
+    }
+}
+
+/// see EventSink::drop_counter
+#[derive(Clone)]
+pub struct DropCounter(Arc<AtomicU64>);
+
+impl DropCounter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
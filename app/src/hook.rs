@@ -1,24 +1,69 @@
 //! hook manager - INT3 hooks with dynamic instruction emulation
+//!
+//! decoder emulation moves the vcpu past the replayed instruction by
+//! returning VMI_EVENT_RESPONSE_SET_REGISTERS with rip advanced. some kvmi
+//! versions drop that response, rip stays on the 0xCC and the vcpu traps
+//! on it forever, hanging the guest. the first emulation that changes a
+//! register is a probe, see Probe::classify, and once the response is known
+//! to be dropped rip is written with set_vcpureg instead
 
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::ffi::c_void;
-use std::mem::ManuallyDrop;
+use std::ops::BitOr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 
 use crate::disasm::{self, EmulationStrategy};
+use crate::dispatcher::{EventDispatcher, HandlerId};
 use crate::error::{Result, VmiError};
 use crate::ffi::{
-    event_response_t, vmi_event_t, vmi_instance_t, INT3, RIP, RSP,
-    VMI_EVENTS_VERSION, VMI_EVENT_RESPONSE_SET_REGISTERS,
+    emul_insn_t, event_response_t, vmi_event_t, x86_regs, INT3, R10, R11, R12, R13, R14, R15, R8,
+    R9, RAX, RBP, RBX, RCX, RDI, RDX, RFLAGS, RIP, RSI, RSP, VMI_EVENTS_VERSION,
+    VMI_EVENT_RESPONSE_EMULATE, VMI_EVENT_RESPONSE_SET_EMUL_INSN, VMI_EVENT_RESPONSE_SET_REGISTERS,
+    VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP,
 };
-use crate::vmi::{event_helpers, Vmi, VmiEvent};
+use crate::stats::HookStats;
+use crate::symbols::{format_location, resolve_symbol_expr, SymbolResolver};
+use crate::vmi::{event_helpers, page_base, Vmi, VmiEvent, VmiRef};
+
+/// register snapshot taken from the trapping vcpu
+pub type Registers = x86_regs;
+
+/// response flags a callback asked for, or'd into the hook manager's own
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventResponse(event_response_t);
+
+impl EventResponse {
+    pub const NONE: Self = Self(0);
+    pub const SET_REGISTERS: Self = Self(VMI_EVENT_RESPONSE_SET_REGISTERS);
+
+    pub fn bits(self) -> event_response_t {
+        self.0
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for EventResponse {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
 
 /// context passed to hook callbacks
 pub struct HookContext<'a> {
-    pub vmi: &'a Vmi,
+    /// the listening vmi, only valid for the callback
+    pub vmi: VmiRef<'a>,
     pub vcpu_id: u32,
     pub rip: u64,
-    pub regs: *mut crate::ffi::x86_regs,
+    pub regs: *mut x86_regs,
+    response: &'a Cell<EventResponse>,
+    /// the hook manager's resolver, v2ksym fallback in callbacks
+    symbols: Option<Arc<SymbolResolver>>,
 }
 
 impl HookContext<'_> {
@@ -26,77 +71,490 @@ impl HookContext<'_> {
     where
         F: FnOnce(&Vmi) -> R,
     {
-        f(self.vmi)
+        f(&self.vmi)
+    }
+
+    /// copy of the vcpu registers at the hook
+    pub fn registers(&self) -> Result<Registers> {
+        unsafe { self.regs.as_ref() }
+            .copied()
+            .ok_or(VmiError::NullPointer)
+    }
+
+    /// write back a modified snapshot, applied through SET_REGISTERS when
+    /// the event returns. changing rip redirects the vcpu and skips the
+    /// hooked instruction, other changes land before it is replayed
+    pub fn set_registers(&self, regs: &Registers) -> Result<EventResponse> {
+        let target = unsafe { self.regs.as_mut() }.ok_or(VmiError::NullPointer)?;
+        *target = *regs;
+        let response = self.response.get() | EventResponse::SET_REGISTERS;
+        self.response.set(response);
+        Ok(response)
+    }
+
+    /// flags requested by the callback so far
+    pub fn response(&self) -> EventResponse {
+        self.response.get()
+    }
+
+    /// return addresses from the saved rbp chain, innermost first.
+    /// x64 with frame pointers only: [rbp] is the caller's rbp, [rbp+8] the
+    /// return address. a hook on a function's first instruction hasn't
+    /// pushed its own frame yet, so its direct caller is at [rsp] and the
+    /// chain starts one level up. stops at max_frames, a broken chain or
+    /// the first failed read
+    pub fn stack_trace(&self, max_frames: usize) -> Result<Vec<u64>> {
+        let regs = self.registers()?;
+        let mut frames = Vec::new();
+        let mut rbp = regs.rbp;
+
+        while frames.len() < max_frames && rbp != 0 && rbp & 7 == 0 {
+            let Ok((next, ret)) = self.vmi.read_va_dtb_with(regs.cr3, rbp, 16, |frame| {
+                (
+                    u64::from_le_bytes(frame[..8].try_into().unwrap()),
+                    u64::from_le_bytes(frame[8..].try_into().unwrap()),
+                )
+            }) else {
+                break;
+            };
+            if ret == 0 {
+                break;
+            }
+            frames.push(ret);
+            // stacks grow down, so callers' frames sit higher
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+
+        Ok(frames)
+    }
+
+    /// `symbol+0x12` via v2ksym, the bare address if nothing resolves
+    pub fn symbolize(&self, addr: u64) -> String {
+        let resolved = self.vmi.v2ksym(addr).ok().or_else(|| {
+            self.symbols
+                .as_ref()
+                .and_then(|symbols| symbols.resolve(addr))
+        });
+        format_location(resolved, addr)
+    }
+
+    /// stack_trace with every frame symbolized
+    pub fn symbolized_stack_trace(&self, max_frames: usize) -> Result<Vec<String>> {
+        Ok(self
+            .stack_trace(max_frames)?
+            .into_iter()
+            .map(|addr| self.symbolize(addr))
+            .collect())
     }
 }
 
 pub type HookCallback = Box<dyn Fn(&HookContext) + Send + Sync>;
 
+/// whether the driver applies VMI_EVENT_RESPONSE_SET_REGISTERS on int3
+/// events, learned from the first emulated hook hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SetRegisters {
+    /// no emulation finished yet, or the probe is still out
+    #[default]
+    Unknown,
+    Honored,
+    /// rip is advanced with set_vcpureg instead
+    Ignored,
+}
+
+/// an emulation that returned SET_REGISTERS, waiting for the vcpu's next
+/// int3. only emulations that changed a register are used as probes, see
+/// classify
+struct Probe {
+    vcpu: u32,
+    addr: u64,
+    next: u64,
+    /// registers right after the emulation
+    after: Gprs,
+}
+
+impl Probe {
+    /// what the vcpu's next int3 says about SET_REGISTERS. a dropped
+    /// response traps again at the same rip with every register exactly as
+    /// the emulation left it. a real re-hit (a loop, a re-entered handler)
+    /// arrives with the registers from before the instruction, which the
+    /// probe differed from, so it can't be mistaken for a drop. Unknown
+    /// without a register snapshot
+    fn classify(&self, rip: u64, regs: Option<&x86_regs>) -> SetRegisters {
+        match regs {
+            None => SetRegisters::Unknown,
+            Some(regs) if rip == self.addr && Gprs::of(regs) == self.after => SetRegisters::Ignored,
+            Some(_) => SetRegisters::Honored,
+        }
+    }
+}
+
+/// general purpose registers and rflags, everything an emulated
+/// instruction can change besides rip and memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Gprs([u64; 17]);
+
+impl Gprs {
+    const REGS: [u32; 17] = [
+        RAX, RCX, RDX, RBX, RSP, RBP, RSI, RDI, R8, R9, R10, R11, R12, R13, R14, R15, RFLAGS,
+    ];
+
+    fn of(r: &x86_regs) -> Self {
+        Gprs([
+            r.rax, r.rcx, r.rdx, r.rbx, r.rsp, r.rbp, r.rsi, r.rdi, r.r8, r.r9, r.r10, r.r11,
+            r.r12, r.r13, r.r14, r.r15, r.rflags,
+        ])
+    }
+
+    /// current values, including what set_vcpureg wrote during this event
+    fn read(vmi: &Vmi, vcpu: u32) -> Result<Self> {
+        let mut values = [0u64; 17];
+        for (value, reg) in values.iter_mut().zip(Self::REGS) {
+            *value = vmi.get_vcpureg(reg as u64, vcpu)?;
+        }
+        Ok(Gprs(values))
+    }
+}
+
+#[derive(Default)]
+struct SetRegistersProbe {
+    support: SetRegisters,
+    pending: Option<Probe>,
+}
+
+/// how execution continues past the INT3 after a hook callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmulationMode {
+    /// replay the overwritten instruction with our decoder, one-shot if it
+    /// can't be decoded. works on AMD
+    #[default]
+    Decoder,
+    /// restore the original byte, singlestep it, rearm. Intel only, and other
+    /// vcpus can run through the hook unnoticed while it is disarmed
+    Singlestep,
+    /// hand the original instruction bytes to the hypervisor to emulate.
+    /// needs driver support for VMI_EVENT_RESPONSE_SET_EMUL_INSN
+    VmmEmulate,
+}
+
 struct Hook {
     addr: u64,
+    /// patch location, restores go through this so user hooks need no DTB
+    phys: u64,
     orig_byte: u8,
+    /// only run the callback when CR3 is one of these, None for kernel hooks.
+    /// hits from other address spaces still get emulated past
+    dtbs: Option<Vec<u64>>,
     callback: HookCallback,
     strategy: Option<EmulationStrategy>,
+    /// original instruction bytes, for VmmEmulate
+    emul_insn: Box<emul_insn_t>,
+    /// false while disabled: orig byte is back in place, record is kept
+    active: bool,
+    /// return-site hook: removes itself after one hit and lets the original
+    /// instruction run, so it needs no emulation
+    transient: bool,
+    /// in-scope hits, bumped under the read lock
+    hits: AtomicU64,
+}
+
+impl Hook {
+    /// count a trap from address space `cr3`, true when the callback should
+    /// run. disabled hooks and other processes' hits aren't counted
+    fn count_hit(&self, cr3: u64) -> bool {
+        let in_scope = match &self.dtbs {
+            Some(dtbs) => dtbs.iter().any(|&d| page_base(d) == page_base(cr3)),
+            None => true,
+        };
+        if self.active && in_scope {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        self.active && in_scope
+    }
 }
 
 struct HookState {
     hooks: HashMap<u64, Hook>,
+    /// hook address each vcpu is singlestepping over, to rearm afterwards
+    stepping: HashMap<u32, u64>,
 }
 
 pub struct HookManager {
     vmi: Arc<Mutex<Vmi>>,
     state: Arc<RwLock<HookState>>,
-    int_event: *mut VmiEvent,
-    mgr_ptr: Mutex<Option<*const HookManager>>,
+    dispatcher: Arc<EventDispatcher>,
+    /// int3 event, plus the (disabled) singlestep event in Singlestep mode
+    handlers: Mutex<Vec<HandlerId>>,
+    mode: EmulationMode,
+    symbols: RwLock<Option<Arc<SymbolResolver>>>,
+    /// return hooks requested from inside a callback, where the state lock
+    /// is held. installed once the interrupt handler lets go of it
+    pending: Mutex<Vec<(u64, HookCallback)>>,
+    set_registers: Mutex<SetRegistersProbe>,
+    /// emulated hits whose rip set_vcpureg couldn't move on
+    rip_write_failures: AtomicU64,
 }
 
 unsafe impl Send for HookManager {}
 unsafe impl Sync for HookManager {}
 
 impl HookManager {
-    pub fn init(vmi: Arc<Mutex<Vmi>>) -> Result<Arc<Self>> {
+    pub fn init(vmi: Arc<Mutex<Vmi>>, dispatcher: &Arc<EventDispatcher>) -> Result<Arc<Self>> {
+        Self::init_with_mode(vmi, dispatcher, EmulationMode::default())
+    }
+
+    pub fn init_with_mode(
+        vmi: Arc<Mutex<Vmi>>,
+        dispatcher: &Arc<EventDispatcher>,
+        mode: EmulationMode,
+    ) -> Result<Arc<Self>> {
+        if mode == EmulationMode::Singlestep && !vmi.lock().unwrap().supports_singlestep() {
+            return Err(VmiError::Other(
+                "singlestep emulation mode needs singlestep support (Intel)".into(),
+            ));
+        }
+
         let state = Arc::new(RwLock::new(HookState {
             hooks: HashMap::new(),
+            stepping: HashMap::new(),
         }));
 
-        let int_event = Box::into_raw(Box::new(VmiEvent::new(VMI_EVENTS_VERSION)));
-
         let mgr = Arc::new(Self {
             vmi: vmi.clone(),
             state,
-            int_event,
-            mgr_ptr: Mutex::new(None),
+            dispatcher: dispatcher.clone(),
+            handlers: Mutex::new(Vec::new()),
+            mode,
+            symbols: RwLock::new(None),
+            pending: Mutex::new(Vec::new()),
+            set_registers: Mutex::new(SetRegistersProbe::default()),
+            rip_write_failures: AtomicU64::new(0),
         });
 
-        let mgr_ptr = Arc::into_raw(mgr.clone());
-        {
-            let mut p = mgr.mgr_ptr.lock().unwrap();
-            *p = Some(mgr_ptr);
+        // without VMI_INIT_EVENTS nothing can be registered, and with no
+        // handlers insert_hook refuses to plant breakpoints
+        if !vmi.lock().unwrap().events_enabled() {
+            log::warn!("[HookManager] events disabled, hooks unavailable");
+            return Ok(mgr);
         }
 
-        unsafe {
+        // handlers hold a weak ref so the dispatcher doesn't keep us alive.
+        // on failure whatever got registered is torn down here, under the
+        // same lock, rather than left for Drop
+        let registered = {
             let vmi_lock = vmi.lock().unwrap();
-            (*int_event).set_interrupt(INT3, 0, 0);
-            (*int_event).set_callback(Some(Self::interrupt_cb));
-            (*int_event).set_data(mgr_ptr as *mut c_void);
-            vmi_lock.register_event((*int_event).as_mut_ptr())?;
-        }
+            register_or_roll_back(
+                |ids| mgr.register_handlers(&vmi_lock, ids),
+                |id| {
+                    let _ = dispatcher.unregister(&vmi_lock, id);
+                },
+            )
+        };
+        *mgr.handlers.lock().unwrap() = registered?;
 
-        eprintln!("[HookManager] initialized");
+        log::debug!("[HookManager] initialized ({:?})", mode);
         Ok(mgr)
     }
 
+    /// int3 event, plus the singlestep event in Singlestep mode. ids are
+    /// pushed as they register so the caller can roll back a partial setup
+    fn register_handlers(self: &Arc<Self>, vmi: &Vmi, ids: &mut Vec<HandlerId>) -> Result<()> {
+        let mut int_event = VmiEvent::new(VMI_EVENTS_VERSION);
+        int_event.set_interrupt(INT3, 0, 0);
+        let weak = Arc::downgrade(self);
+        ids.push(self.dispatcher.register(
+            vmi,
+            int_event,
+            move |vmi, event| match weak.upgrade() {
+                Some(mgr) => unsafe { mgr.on_interrupt(vmi, event) },
+                None => 0,
+            },
+        )?);
+
+        if self.mode == EmulationMode::Singlestep {
+            // registered off, toggled per vcpu from on_interrupt
+            let vcpus = vmi.num_vcpus();
+            let mask = if vcpus >= 32 {
+                u32::MAX
+            } else {
+                (1u32 << vcpus) - 1
+            };
+            let mut ss_event = VmiEvent::new(VMI_EVENTS_VERSION);
+            ss_event.set_singlestep_vcpus(mask, false);
+            let weak = Arc::downgrade(self);
+            ids.push(self.dispatcher.register(
+                vmi,
+                ss_event,
+                move |vmi, event| match weak.upgrade() {
+                    Some(mgr) => unsafe { mgr.on_singlestep(vmi, event) },
+                    None => 0,
+                },
+            )?);
+        }
+        Ok(())
+    }
+
+    /// use a symbol resolver to annotate hook addresses in logs
+    pub fn set_symbols(&self, symbols: Arc<SymbolResolver>) {
+        *self.symbols.write().unwrap() = Some(symbols);
+    }
+
+    /// `symbol+offset` when a resolver is set, bare address otherwise
+    fn describe(&self, addr: u64) -> String {
+        match self.symbols.read().unwrap().as_ref() {
+            Some(symbols) => symbols.format(addr),
+            None => format!("{:#x}", addr),
+        }
+    }
+
     pub fn add_hook<F>(&self, vmi_lock: &Vmi, addr: u64, callback: F) -> Result<()>
     where
         F: Fn(&HookContext) + Send + Sync + 'static,
     {
+        let dtb = vmi_lock.kernel_dtb()?;
+        self.insert_hook(vmi_lock, addr, dtb, None, Box::new(callback), false)
+    }
+
+    /// hook a kernel symbol, optionally offset into it (`Foo+0x1a`) to land
+    /// past a prologue. returns the hooked address for remove_hook
+    pub fn add_hook_symbol<F>(&self, vmi_lock: &Vmi, expr: &str, callback: F) -> Result<u64>
+    where
+        F: Fn(&HookContext) + Send + Sync + 'static,
+    {
+        let addr = resolve_symbol_expr(vmi_lock, expr)?;
+        self.add_hook(vmi_lock, addr, callback)?;
+        Ok(addr)
+    }
+
+    /// hook a user-mode address of one process. `dtbs` are the process's
+    /// page table roots (kernel and, with KPTI, user); the callback only runs
+    /// when one of them is loaded. the patch goes to the physical page, so if
+    /// that page is shared (e.g. a DLL's code) every process mapping it traps
+    /// too, and those hits are emulated past without calling back.
+    pub fn add_process_hook<F>(
+        &self,
+        vmi_lock: &Vmi,
+        dtbs: Vec<u64>,
+        addr: u64,
+        callback: F,
+    ) -> Result<()>
+    where
+        F: Fn(&HookContext) + Send + Sync + 'static,
+    {
+        let dtb = *dtbs
+            .first()
+            .ok_or_else(|| VmiError::Other("process hook needs a dtb".into()))?;
+        self.insert_hook(vmi_lock, addr, dtb, Some(dtbs), Box::new(callback), false)
+    }
+
+    /// hook a kernel function's entry and, on every hit, its return: the
+    /// entry callback runs, then `[rsp]` is read and a one-shot hook placed
+    /// on that address calls `on_return` and removes itself. the expression
+    /// must point at the first instruction, where `[rsp]` still holds the
+    /// return address. a return site only holds one pending hook, so when
+    /// calls from the same site overlap (threads, recursion) the first
+    /// return to it fires and the later ones go unseen
+    pub fn add_entry_return_hook<E, R>(
+        self: &Arc<Self>,
+        vmi_lock: &Vmi,
+        expr: &str,
+        on_entry: E,
+        on_return: R,
+    ) -> Result<u64>
+    where
+        E: Fn(&HookContext) + Send + Sync + 'static,
+        R: Fn(&HookContext) + Send + Sync + 'static,
+    {
+        let weak = Arc::downgrade(self);
+        let on_return = Arc::new(on_return);
+        self.add_hook_symbol(vmi_lock, expr, move |ctx| {
+            on_entry(ctx);
+            let Some(mgr) = weak.upgrade() else {
+                return;
+            };
+            let ret = ctx.registers().and_then(|regs| {
+                ctx.vmi.read_va_dtb_with(regs.cr3, regs.rsp, 8, |bytes| {
+                    u64::from_le_bytes(bytes.try_into().unwrap())
+                })
+            });
+            match ret {
+                Ok(ret) => {
+                    let on_return = on_return.clone();
+                    mgr.pending
+                        .lock()
+                        .unwrap()
+                        .push((ret, Box::new(move |ctx: &HookContext| on_return(ctx))));
+                }
+                Err(e) => log::warn!("[HookManager] return address unreadable: {}", e),
+            }
+        })
+    }
+
+    /// place the return hooks queued by entry callbacks
+    fn install_pending(&self, vmi: &Vmi) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for (addr, callback) in pending {
+            let result = vmi
+                .kernel_dtb()
+                .and_then(|dtb| self.insert_hook(vmi, addr, dtb, None, callback, true));
+            match result {
+                Ok(()) => {}
+                Err(VmiError::HookExists(_)) => {
+                    // another call from the same site is still outstanding
+                }
+                Err(e) => log::warn!(
+                    "[HookManager] return hook at {} failed: {}",
+                    self.describe(addr),
+                    e
+                ),
+            }
+        }
+    }
+
+    fn insert_hook(
+        &self,
+        vmi_lock: &Vmi,
+        addr: u64,
+        dtb: u64,
+        dtbs: Option<Vec<u64>>,
+        callback: HookCallback,
+        transient: bool,
+    ) -> Result<()> {
+        // an int3 nobody handles would crash the guest
+        if self.handlers.lock().unwrap().is_empty() {
+            return Err(VmiError::Other(
+                "hooks need events, none are registered".into(),
+            ));
+        }
+
         let mut state = self.state.write().unwrap();
 
         if state.hooks.contains_key(&addr) {
             return Err(VmiError::HookExists(addr));
         }
 
-        let phys = vmi_lock.v2p(addr)?;
+        // a 0xCC on a non-present or NX page never fires (or faults instead)
+        let page = vmi_lock
+            .pagetable_lookup_extended(dtb, addr)
+            .ok()
+            .filter(|page| page.executable)
+            .ok_or_else(|| {
+                VmiError::Other(format!("hook target not executable/mapped: {:#x}", addr))
+            })?;
+        let phys = page.paddr;
+
+        // user code pages are read-only image mappings shared between processes,
+        // patching the frame patches it for all of them
+        if dtbs.is_some() && !page.writable {
+            log::warn!(
+                "[HookManager] {:#x} is on a shared read-only page, the patch is visible to every process mapping it",
+                addr
+            );
+        }
+
         let orig_byte = vmi_lock.read_8_pa(phys)?;
 
         // if the byte is already 0xCC, we might be overlapping with another hook
@@ -111,60 +569,229 @@ impl HookManager {
         // read 16 bytes for instruction decode (max x86 instr is 15)
         let mut code_bytes = [0u8; 16];
         for i in 0..16 {
-            if let Ok(b) = vmi_lock.read_8_va(addr + i as u64, 0) {
-                code_bytes[i] = b;
+            if let Ok(b) = vmi_lock.read_va_dtb(dtb, addr + i as u64, 1) {
+                code_bytes[i] = b[0];
             } else {
                 break;
             }
         }
 
-        // use guest bitness for correct decoding - matters for 32 vs 64 bit
-        let bitness = disasm::Bitness::from_address_width(vmi_lock.address_width());
-        let strategy = match disasm::analyze_instruction(&code_bytes, addr, bitness) {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("[HookManager] disasm failed at {:#x}: {}", addr, e);
-                None
-            }
-        };
+        let mut emul_insn = Box::new(emul_insn_t::default());
+        let insn_len = emul_insn.data.len();
+        emul_insn.data.copy_from_slice(&code_bytes[..insn_len]);
+        // libvmi must not free our buffer
+        emul_insn.dont_free = 1;
 
-        if let Some(ref s) = strategy {
-            eprintln!(
-                "[HookManager] Auto-Emulation enabled for {:#x}: {:?}",
-                addr, s
-            );
+        // only the decoder mode needs to understand the instruction
+        let strategy = if self.mode == EmulationMode::Decoder && !transient {
+            // use guest bitness for correct decoding - matters for 32 vs 64 bit
+            let bitness = disasm::Bitness::from_address_width(vmi_lock.address_width());
+            let strategy = match disasm::analyze_instruction(&code_bytes, addr, bitness) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!(
+                        "[HookManager] disasm failed at {}: {}",
+                        vmi_lock.symbolize(addr),
+                        e
+                    );
+                    None
+                }
+            };
+
+            if let Some(ref s) = strategy {
+                log::debug!(
+                    "[HookManager] Auto-Emulation enabled for {}: {:?}",
+                    vmi_lock.symbolize(addr),
+                    s
+                );
+            } else {
+                log::warn!(
+                    "[HookManager] no emulation for {}, hook is one-shot",
+                    vmi_lock.symbolize(addr)
+                );
+            }
+            strategy
         } else {
-            eprintln!(
-                "[HookManager] no emulation for {:#x}, hook is one-shot",
-                addr
-            );
-        }
+            None
+        };
 
-        vmi_lock.write_8_va(addr, 0, 0xCC)?;
+        vmi_lock.write_8_pa(phys, 0xCC)?;
 
         state.hooks.insert(
             addr,
             Hook {
                 addr,
+                phys,
                 orig_byte,
-                callback: Box::new(callback),
+                dtbs,
+                callback,
                 strategy,
+                emul_insn,
+                active: true,
+                transient,
+                hits: AtomicU64::new(0),
             },
         );
 
-        eprintln!("[HookManager] Hook added at {:#x}", addr);
+        if !transient {
+            log::debug!("[HookManager] Hook added at {}", self.describe(addr));
+        }
         Ok(())
     }
 
     pub fn remove_hook(&self, vmi_lock: &Vmi, addr: u64) -> Result<()> {
         let mut state = self.state.write().unwrap();
         if let Some(hook) = state.hooks.remove(&addr) {
-            vmi_lock.write_8_va(addr, 0, hook.orig_byte)?;
-            eprintln!("[HookManager] Hook removed at {:#x}", addr);
+            vmi_lock.write_8_pa(hook.phys, hook.orig_byte)?;
+            log::debug!("[HookManager] Hook removed at {}", self.describe(addr));
+        }
+        Ok(())
+    }
+
+    /// restore the original byte but keep the hook (and its decoded strategy)
+    /// so it can be re-armed with enable_hook
+    pub fn disable_hook(&self, vmi_lock: &Vmi, addr: u64) -> Result<()> {
+        self.set_active(addr, false, |phys, byte| vmi_lock.write_8_pa(phys, byte))
+    }
+
+    /// re-arm a hook disabled with disable_hook
+    pub fn enable_hook(&self, vmi_lock: &Vmi, addr: u64) -> Result<()> {
+        self.set_active(addr, true, |phys, byte| vmi_lock.write_8_pa(phys, byte))
+    }
+
+    /// arm or disarm the hook at `addr`, writing 0xCC or the original byte
+    /// through `write`. a failed write leaves the hook as it was
+    fn set_active(
+        &self,
+        addr: u64,
+        active: bool,
+        write: impl FnOnce(u64, u8) -> Result<()>,
+    ) -> Result<()> {
+        let mut state = self.state.write().unwrap();
+        let hook = state
+            .hooks
+            .get_mut(&addr)
+            .ok_or_else(|| VmiError::Other(format!("no hook at {:#x}", addr)))?;
+        if hook.active != active {
+            write(hook.phys, if active { 0xCC } else { hook.orig_byte })?;
+            hook.active = active;
+            let verb = if active { "enabled" } else { "disabled" };
+            log::debug!("[HookManager] Hook {} at {}", verb, self.describe(addr));
         }
         Ok(())
     }
 
+    /// put the INT3s back after the vmi was re-initialized. each patch site
+    /// is checked first: the original byte gets the 0xCC again, an existing
+    /// 0xCC is left alone, and anything else means the page no longer holds
+    /// that code (e.g. the guest rebooted), so the hook is dropped without
+    /// writing. returns the number of hooks kept
+    pub fn rearm(&self, vmi_lock: &Vmi) -> Result<usize> {
+        let mut state = self.state.write().unwrap();
+        // singlesteps in flight died with the old connection
+        state.stepping.clear();
+
+        let mut stale = Vec::new();
+        for (&addr, hook) in state.hooks.iter() {
+            match vmi_lock.read_8_pa(hook.phys)? {
+                0xCC => {}
+                byte if byte == hook.orig_byte => {
+                    if hook.active {
+                        vmi_lock.write_8_pa(hook.phys, 0xCC)?;
+                    }
+                }
+                _ => stale.push(addr),
+            }
+        }
+        for addr in stale {
+            state.hooks.remove(&addr);
+            log::warn!(
+                "[HookManager] dropped hook at {}, its code changed",
+                self.describe(addr)
+            );
+        }
+        Ok(state.hooks.len())
+    }
+
+    /// drop every hook without touching guest memory, for when the patched
+    /// code is gone (guest shut down or rebooted). restoring original bytes
+    /// then would corrupt whatever lives there now. returns the hooks dropped
+    pub fn forget(&self) -> usize {
+        let mut state = self.state.write().unwrap();
+        state.stepping.clear();
+        let count = state.hooks.len();
+        state.hooks.clear();
+        if count > 0 {
+            log::info!(
+                "[HookManager] forgot {} hooks, guest memory left untouched",
+                count
+            );
+        }
+        count
+    }
+
+    /// hit counts of every hook, return-site hooks left out
+    pub fn stats(&self) -> Vec<HookStats> {
+        let state = self.state.read().unwrap();
+        let mut stats: Vec<HookStats> = state
+            .hooks
+            .values()
+            .filter(|h| !h.transient)
+            .map(|h| HookStats {
+                addr: h.addr,
+                location: self.describe(h.addr),
+                hits: h.hits.load(Ordering::Relaxed),
+                active: h.active,
+            })
+            .collect();
+        stats.sort_by_key(|h| h.addr);
+        stats
+    }
+
+    /// what the first emulated hit showed of the driver's SET_REGISTERS
+    /// support. kept across reconnects, the driver stays the same
+    pub fn set_registers_support(&self) -> SetRegisters {
+        self.set_registers.lock().unwrap().support
+    }
+
+    /// emulated hits left at their int3 because writing rip failed, the
+    /// vcpu traps there again
+    pub fn rip_write_failures(&self) -> u64 {
+        self.rip_write_failures.load(Ordering::Relaxed)
+    }
+
+    /// armed patch sites as (address, physical address, original byte), to
+    /// read code around a hook as it was before the 0xCC went in
+    pub fn patches(&self) -> Vec<(u64, u64, u8)> {
+        let state = self.state.read().unwrap();
+        let mut patches: Vec<_> = state
+            .hooks
+            .values()
+            .filter(|h| h.active)
+            .map(|h| (h.addr, h.phys, h.orig_byte))
+            .collect();
+        patches.sort_unstable();
+        patches
+    }
+
+    /// whether a hook exists and is armed
+    pub fn is_hook_active(&self, addr: u64) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .hooks
+            .get(&addr)
+            .is_some_and(|h| h.active)
+    }
+
+    /// remove a hook using the manager's own vmi handle.
+    /// for owners that don't hold a vmi (e.g. drop guards), deadlocks if the
+    /// caller already holds the lock
+    pub fn unhook(&self, addr: u64) -> Result<()> {
+        let vmi = self.vmi.lock().unwrap();
+        self.remove_hook(&vmi, addr)
+    }
+
     /// restore all hooks and clear event. must be called before dropping the session.
     pub fn shutdown(&self) {
         let vmi = self.vmi.lock().unwrap();
@@ -174,68 +801,133 @@ impl HookManager {
             return;
         }
 
-        eprintln!(
+        log::info!(
             "[HookManager] restoring {} hooks during shutdown...",
             state.hooks.len()
         );
         for (_, hook) in state.hooks.drain() {
-            if let Err(e) = vmi.write_8_va(hook.addr, 0, hook.orig_byte) {
-                eprintln!("[HookManager] restore failed at {:#x}: {}", hook.addr, e);
+            if let Err(e) = vmi.write_8_pa(hook.phys, hook.orig_byte) {
+                log::warn!("[HookManager] restore failed at {:#x}: {}", hook.addr, e);
             }
         }
 
-        if !self.int_event.is_null() {
-            let _ = vmi.clear_event(self.int_event as *mut _);
+        for id in self.handlers.lock().unwrap().drain(..) {
+            let _ = self.dispatcher.unregister(&vmi, id);
         }
+    }
 
-        // recover the Arc to decrement count and allow Drop to run
-        let mut p = self.mgr_ptr.lock().unwrap();
-        if let Some(ptr) = p.take() {
-            unsafe {
-                let _ = Arc::from_raw(ptr);
-            }
-        }
+    /// int3 handler, runs inside events_listen
+    unsafe fn on_interrupt(
+        &self,
+        vmi_events: VmiRef<'_>,
+        event: *mut vmi_event_t,
+    ) -> event_response_t {
+        let requested = Cell::new(EventResponse::NONE);
+        let response = unsafe { self.handle_interrupt(vmi_events, event, &requested) };
+        self.install_pending(&vmi_events);
+        response | requested.get().bits()
     }
 
-    unsafe extern "C" fn interrupt_cb(
-        vmi_handle: vmi_instance_t,
+    unsafe fn handle_interrupt(
+        &self,
+        vmi_events: VmiRef<'_>,
         event: *mut vmi_event_t,
+        requested: &Cell<EventResponse>,
     ) -> event_response_t {
         unsafe {
             event_helpers::set_reinject(event, 1);
-
-            let data = (*event).data as *const HookManager;
-            if data.is_null() {
-                return 0;
-            }
-
-            let mgr = &*data;
-            let vmi_events = ManuallyDrop::new(Vmi::from_handle(vmi_handle));
+            let mgr = self;
 
             let vcpu_id = (*event).vcpu_id;
             let rip = match vmi_events.get_vcpureg(RIP as u64, vcpu_id) {
                 Ok(r) => r,
                 Err(e) => {
-                    eprintln!("[HookManager] RIP read failed: {:?}", e);
+                    log::warn!("[HookManager] RIP read failed: {:?}", e);
                     return 0;
                 }
             };
 
+            let trap_regs = event_helpers::get_x86_regs(event).as_ref();
+            if mgr.settle_probe(&vmi_events, vcpu_id, rip, trap_regs) {
+                event_helpers::set_reinject(event, 0);
+                return 0;
+            }
+
             let state = mgr.state.read().unwrap();
 
-            let hook_data = state.hooks.get(&rip).map(|h| (h.addr, h.orig_byte));
+            // a vcpu can trap on the 0xCC just before disable_hook restores it,
+            // the original byte is back so just let it re-execute
+            if state.hooks.get(&rip).is_some_and(|h| !h.active) {
+                event_helpers::set_reinject(event, 0);
+                return 0;
+            }
 
-            if let Some((addr, orig_byte)) = hook_data {
+            let hook_data = state.hooks.get(&rip).map(|h| (h.addr, h.phys, h.orig_byte));
+
+            if let Some((addr, phys, orig_byte)) = hook_data {
                 event_helpers::set_reinject(event, 0);
 
                 if let Some(hook) = state.hooks.get(&rip) {
-                    let ctx = HookContext {
-                        vmi: &vmi_events,
-                        vcpu_id,
-                        rip,
-                        regs: event_helpers::get_x86_regs(event),
-                    };
-                    (hook.callback)(&ctx);
+                    let regs = event_helpers::get_x86_regs(event);
+                    let cr3 = regs.as_ref().map(|r| r.cr3).unwrap_or(0);
+
+                    // process hooks write through the current address space,
+                    // kernel hooks keep using kernel translation
+                    let mem_dtb = hook.dtbs.as_ref().map(|_| cr3);
+                    if hook.count_hit(cr3) {
+                        let ctx = HookContext {
+                            vmi: vmi_events,
+                            vcpu_id,
+                            rip,
+                            regs,
+                            response: requested,
+                            symbols: mgr.symbols.read().unwrap().clone(),
+                        };
+                        (hook.callback)(&ctx);
+
+                        if hook.transient {
+                            // put the instruction back and let it run, the
+                            // vcpu resumes at rip with nothing to emulate
+                            if let Err(e) = vmi_events.write_8_pa(phys, orig_byte) {
+                                log::warn!("[HookManager] restore failed at {:#x}: {}", addr, e);
+                            }
+                            drop(state);
+                            mgr.state.write().unwrap().hooks.remove(&addr);
+                            return 0;
+                        }
+
+                        // the callback redirected the vcpu, the hooked
+                        // instruction never runs so there's nothing to replay
+                        if ctx.response().contains(EventResponse::SET_REGISTERS)
+                            && let Some(target) = regs.as_ref().map(|r| r.rip).filter(|&r| r != rip)
+                        {
+                            if mgr.set_registers_support() == SetRegisters::Ignored
+                                && let Err(e) = vmi_events.set_vcpureg(RIP as u64, target, vcpu_id)
+                            {
+                                log::warn!("[HookManager] redirect failed at {:#x}: {}", addr, e);
+                            }
+                            return 0;
+                        }
+                    }
+
+                    match mgr.mode {
+                        EmulationMode::Decoder => {}
+                        EmulationMode::Singlestep => {
+                            // disarm, step the original instruction, rearm in on_singlestep
+                            if let Err(e) = vmi_events.write_8_pa(phys, orig_byte) {
+                                log::warn!("[HookManager] disarm failed at {:#x}: {}", addr, e);
+                                return 0;
+                            }
+                            drop(state);
+                            mgr.state.write().unwrap().stepping.insert(vcpu_id, addr);
+                            return VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP;
+                        }
+                        EmulationMode::VmmEmulate => {
+                            (*event).__bindgen_anon_3.emul_insn =
+                                hook.emul_insn.as_ref() as *const emul_insn_t as *mut emul_insn_t;
+                            return VMI_EVENT_RESPONSE_EMULATE | VMI_EVENT_RESPONSE_SET_EMUL_INSN;
+                        }
+                    }
 
                     if let Some(strategy) = &hook.strategy {
                         match strategy {
@@ -251,32 +943,33 @@ impl HookManager {
                                     let base_val = vmi_events.get_vcpureg(*base_reg, vcpu_id)?;
                                     let target = base_val.wrapping_add(*displacement as u64);
 
-                                    match operand_size_bits {
-                                        8 => vmi_events.write_8_va(target, 0, src_val as u8)?,
-                                        16 => vmi_events.write_16_va(target, 0, src_val as u16)?,
-                                        32 => vmi_events.write_32_va(target, 0, src_val as u32)?,
-                                        64 => vmi_events.write_64_va(target, 0, src_val)?,
-                                        _ => {
-                                            return Err(VmiError::Other(format!(
-                                                "unsupported operand size {}",
-                                                operand_size_bits
-                                            )));
-                                        }
-                                    }
-
-                                    (*event_helpers::get_x86_regs(event)).rip = rip + len;
+                                    write_guest(
+                                        &vmi_events,
+                                        mem_dtb,
+                                        target,
+                                        src_val,
+                                        *operand_size_bits,
+                                    )?;
+
                                     Ok(())
                                 };
 
                                 if let Err(e) = execute_emulation() {
-                                    eprintln!(
-                                        "[HookManager] emulation failed: {}, removing hook",
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
                                         e
                                     );
-                                    let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
                                     event_helpers::set_reinject(event, 1);
                                 } else {
-                                    return VMI_EVENT_RESPONSE_SET_REGISTERS;
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
                                 }
                             }
                             EmulationStrategy::Push { src_reg, len } => {
@@ -284,21 +977,27 @@ impl HookManager {
                                     let src_val = vmi_events.get_vcpureg(*src_reg, vcpu_id)?;
                                     let mut rsp = vmi_events.get_vcpureg(RSP as u64, vcpu_id)?;
                                     rsp = rsp.wrapping_sub(8);
-                                    vmi_events.write_64_va(rsp, 0, src_val)?;
-                                    (*event_helpers::get_x86_regs(event)).rip = rip + len;
+                                    write_guest(&vmi_events, mem_dtb, rsp, src_val, 64)?;
                                     vmi_events.set_vcpureg(RSP as u64, rsp, vcpu_id)?;
                                     Ok(())
                                 };
 
                                 if let Err(e) = execute_emulation() {
-                                    eprintln!(
-                                        "[HookManager] emulation failed: {}, removing hook",
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
                                         e
                                     );
-                                    let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
                                     event_helpers::set_reinject(event, 1);
                                 } else {
-                                    return VMI_EVENT_RESPONSE_SET_REGISTERS;
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
                                 }
                             }
                             EmulationStrategy::MovRegReg {
@@ -309,19 +1008,25 @@ impl HookManager {
                                 let execute_emulation = || -> Result<()> {
                                     let src_val = vmi_events.get_vcpureg(*src_reg, vcpu_id)?;
                                     vmi_events.set_vcpureg(*dst_reg, src_val, vcpu_id)?;
-                                    (*event_helpers::get_x86_regs(event)).rip = rip + len;
                                     Ok(())
                                 };
 
                                 if let Err(e) = execute_emulation() {
-                                    eprintln!(
-                                        "[HookManager] emulation failed: {}, removing hook",
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
                                         e
                                     );
-                                    let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
                                     event_helpers::set_reinject(event, 1);
                                 } else {
-                                    return VMI_EVENT_RESPONSE_SET_REGISTERS;
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
                                 }
                             }
                             EmulationStrategy::SubImm { reg, imm, len } => {
@@ -332,19 +1037,50 @@ impl HookManager {
                                         val.wrapping_sub(*imm),
                                         vcpu_id,
                                     )?;
-                                    (*event_helpers::get_x86_regs(event)).rip = rip + len;
                                     Ok(())
                                 };
 
                                 if let Err(e) = execute_emulation() {
-                                    eprintln!(
-                                        "[HookManager] emulation failed: {}, removing hook",
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
                                         e
                                     );
-                                    let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
                                     event_helpers::set_reinject(event, 1);
                                 } else {
-                                    return VMI_EVENT_RESPONSE_SET_REGISTERS;
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
+                                }
+                            }
+                            EmulationStrategy::AndImm { reg, imm, len } => {
+                                let execute_emulation = || -> Result<()> {
+                                    let val = vmi_events.get_vcpureg(*reg, vcpu_id)?;
+                                    vmi_events.set_vcpureg(*reg, val & *imm, vcpu_id)?;
+                                    Ok(())
+                                };
+
+                                if let Err(e) = execute_emulation() {
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
+                                        e
+                                    );
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
+                                    event_helpers::set_reinject(event, 1);
+                                } else {
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
                                 }
                             }
                             EmulationStrategy::Lea {
@@ -357,28 +1093,34 @@ impl HookManager {
                                     let base_val = vmi_events.get_vcpureg(*base_reg, vcpu_id)?;
                                     let result = base_val.wrapping_add(*displacement as u64);
                                     vmi_events.set_vcpureg(*dst_reg, result, vcpu_id)?;
-                                    (*event_helpers::get_x86_regs(event)).rip = rip + len;
                                     Ok(())
                                 };
 
                                 if let Err(e) = execute_emulation() {
-                                    eprintln!(
-                                        "[HookManager] emulation failed: {}, removing hook",
+                                    log::warn!(
+                                        "[HookManager] emulation failed at {}: {}, removing hook",
+                                        vmi_events.symbolize(addr),
                                         e
                                     );
-                                    let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                                    let _ = vmi_events.write_8_pa(phys, orig_byte);
                                     event_helpers::set_reinject(event, 1);
                                 } else {
-                                    return VMI_EVENT_RESPONSE_SET_REGISTERS;
+                                    return mgr.advance(
+                                        &vmi_events,
+                                        event,
+                                        vcpu_id,
+                                        addr,
+                                        rip + len,
+                                    );
                                 }
                             }
                         }
                     } else {
-                        eprintln!(
-                            "[HookManager] no emulation for {:#x}, removing hook (one-shot)",
-                            addr
+                        log::warn!(
+                            "[HookManager] no emulation for {}, removing hook (one-shot)",
+                            vmi_events.symbolize(addr)
                         );
-                        let _ = vmi_events.write_8_va(addr, 0, orig_byte);
+                        let _ = vmi_events.write_8_pa(phys, orig_byte);
                         event_helpers::set_reinject(event, 1);
                     }
                 }
@@ -387,6 +1129,95 @@ impl HookManager {
             0
         }
     }
+
+    /// move the vcpu past an emulated instruction. the first call probes
+    /// SET_REGISTERS, see settle_probe
+    unsafe fn advance(
+        &self,
+        vmi_events: &Vmi,
+        event: *mut vmi_event_t,
+        vcpu_id: u32,
+        addr: u64,
+        next: u64,
+    ) -> event_response_t {
+        let mut probe = self.set_registers.lock().unwrap();
+        if probe.support == SetRegisters::Ignored {
+            self.write_rip(vmi_events, vcpu_id, next);
+            return 0;
+        }
+
+        let regs = unsafe { event_helpers::get_x86_regs(event) };
+        // an emulation that changed nothing would look like a real re-hit
+        if probe.support == SetRegisters::Unknown
+            && probe.pending.is_none()
+            && let Some(before) = unsafe { regs.as_ref() }.map(Gprs::of)
+            && let Ok(after) = Gprs::read(vmi_events, vcpu_id)
+            && after != before
+        {
+            probe.pending = Some(Probe {
+                vcpu: vcpu_id,
+                addr,
+                next,
+                after,
+            });
+        }
+        unsafe { (*regs).rip = next };
+        VMI_EVENT_RESPONSE_SET_REGISTERS
+    }
+
+    /// resolve the outstanding probe on its vcpu's next int3. true when
+    /// this trap was the dropped response: the emulation already landed,
+    /// so rip is moved on and the hit is not handled again
+    fn settle_probe(
+        &self,
+        vmi_events: &Vmi,
+        vcpu_id: u32,
+        rip: u64,
+        regs: Option<&x86_regs>,
+    ) -> bool {
+        let mut probe = self.set_registers.lock().unwrap();
+        let Some(pending) = probe.pending.take_if(|p| p.vcpu == vcpu_id) else {
+            return false;
+        };
+
+        probe.support = pending.classify(rip, regs);
+        if probe.support != SetRegisters::Ignored {
+            return false;
+        }
+
+        self.write_rip(vmi_events, vcpu_id, pending.next);
+        true
+    }
+
+    /// advance rip directly, for drivers that ignore SET_REGISTERS
+    fn write_rip(&self, vmi_events: &Vmi, vcpu_id: u32, next: u64) {
+        if vmi_events.set_vcpureg(RIP as u64, next, vcpu_id).is_err() {
+            self.rip_write_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// rearm the hook a vcpu just stepped over (Singlestep mode)
+    unsafe fn on_singlestep(
+        &self,
+        vmi_events: VmiRef<'_>,
+        event: *mut vmi_event_t,
+    ) -> event_response_t {
+        unsafe {
+            let mgr = self;
+            let vcpu_id = (*event).vcpu_id;
+
+            let mut state = mgr.state.write().unwrap();
+            // the hook may have been removed while we were stepping
+            if let Some(addr) = state.stepping.remove(&vcpu_id)
+                && let Some(phys) = state.hooks.get(&addr).filter(|h| h.active).map(|h| h.phys)
+                && let Err(e) = vmi_events.write_8_pa(phys, 0xCC)
+            {
+                log::warn!("[HookManager] rearm failed at {:#x}: {}", addr, e);
+            }
+
+            VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP
+        }
+    }
 }
 
 impl Drop for HookManager {
@@ -394,19 +1225,335 @@ impl Drop for HookManager {
         let state = self.state.read().unwrap();
         let vmi = self.vmi.lock().unwrap();
 
-        eprintln!("[HookManager] restoring {} hooks...", state.hooks.len());
+        log::info!("[HookManager] restoring {} hooks...", state.hooks.len());
         for (_, hook) in state.hooks.iter() {
-            if let Err(e) = vmi.write_8_va(hook.addr, 0, hook.orig_byte) {
-                eprintln!("[HookManager] restore failed at {:#x}: {}", hook.addr, e);
+            if let Err(e) = vmi.write_8_pa(hook.phys, hook.orig_byte) {
+                log::warn!("[HookManager] restore failed at {:#x}: {}", hook.addr, e);
             }
         }
 
-        if !self.int_event.is_null() {
-            unsafe {
-                let _ = vmi.clear_event(self.int_event as *mut _);
-                let _ = Box::from_raw(self.int_event);
-            }
+        for id in self.handlers.lock().unwrap().drain(..) {
+            let _ = self.dispatcher.unregister(&vmi, id);
         }
-        eprintln!("[HookManager] cleanup complete");
+        log::info!("[HookManager] cleanup complete");
+    }
+}
+
+/// run `register`, which pushes each id as it registers. if it fails
+/// partway, every id it pushed is handed to `unregister` before the error
+/// is returned, so nothing is left registered
+fn register_or_roll_back<Id>(
+    register: impl FnOnce(&mut Vec<Id>) -> Result<()>,
+    mut unregister: impl FnMut(Id),
+) -> Result<Vec<Id>> {
+    let mut ids = Vec::new();
+    match register(&mut ids) {
+        Ok(()) => Ok(ids),
+        Err(e) => {
+            ids.into_iter().for_each(&mut unregister);
+            Err(e)
+        }
+    }
+}
+
+/// write an emulated store of `bits` width. `dtb` is the address space of a
+/// process hook, None means kernel translation
+fn write_guest(vmi: &Vmi, dtb: Option<u64>, vaddr: u64, val: u64, bits: u8) -> Result<()> {
+    if let Some(dtb) = dtb {
+        let bytes = val.to_le_bytes();
+        return match bits {
+            8 | 16 | 32 | 64 => vmi.write_va_dtb(dtb, vaddr, &bytes[..bits as usize / 8]),
+            _ => Err(VmiError::Other(format!(
+                "unsupported operand size {}",
+                bits
+            ))),
+        };
+    }
+
+    match bits {
+        8 => vmi.write_8_va(vaddr, 0, val as u8),
+        16 => vmi.write_16_va(vaddr, 0, val as u16),
+        32 => vmi.write_32_va(vaddr, 0, val as u32),
+        64 => vmi.write_64_va(vaddr, 0, val),
+        _ => Err(VmiError::Other(format!(
+            "unsupported operand size {}",
+            bits
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const ADDR: u64 = 0xfffff800_12340000;
+
+    /// `push rbx` at ADDR, emulated: rsp went down by 8
+    fn push_probe() -> (x86_regs, Probe) {
+        let before = x86_regs {
+            rip: ADDR,
+            rsp: 0xffffa000_00001000,
+            rbx: 0x42,
+            ..Default::default()
+        };
+        let after = x86_regs {
+            rsp: before.rsp - 8,
+            ..before
+        };
+        let probe = Probe {
+            vcpu: 0,
+            addr: ADDR,
+            next: ADDR + 1,
+            after: Gprs::of(&after),
+        };
+        (before, probe)
+    }
+
+    #[test]
+    fn dropped_response_traps_with_emulated_registers() {
+        let (before, probe) = push_probe();
+        let retrap = x86_regs {
+            rsp: before.rsp - 8,
+            ..before
+        };
+        assert_eq!(probe.classify(ADDR, Some(&retrap)), SetRegisters::Ignored);
+    }
+
+    #[test]
+    fn rehit_from_same_stack_depth_is_honored() {
+        // a loop calling the hooked function again enters with the
+        // pre-instruction registers, not the emulated ones
+        let (before, probe) = push_probe();
+        assert_eq!(probe.classify(ADDR, Some(&before)), SetRegisters::Honored);
+    }
+
+    #[test]
+    fn rehit_differing_in_any_register_is_honored() {
+        let (before, probe) = push_probe();
+        let retrap = x86_regs {
+            rsp: before.rsp - 8,
+            rflags: 0x246,
+            ..before
+        };
+        assert_eq!(probe.classify(ADDR, Some(&retrap)), SetRegisters::Honored);
+    }
+
+    #[test]
+    fn trap_elsewhere_is_honored() {
+        let (before, probe) = push_probe();
+        let elsewhere = x86_regs {
+            rsp: before.rsp - 8,
+            ..before
+        };
+        assert_eq!(
+            probe.classify(ADDR + 0x100, Some(&elsewhere)),
+            SetRegisters::Honored
+        );
+    }
+
+    #[test]
+    fn no_snapshot_is_unknown() {
+        let (_, probe) = push_probe();
+        assert_eq!(probe.classify(ADDR, None), SetRegisters::Unknown);
+    }
+
+    fn manager() -> (Arc<HookManager>, Arc<EventDispatcher>) {
+        let vmi = Arc::new(Mutex::new(Vmi::detached()));
+        let dispatcher = EventDispatcher::new(vmi.clone());
+        (HookManager::init(vmi, &dispatcher).unwrap(), dispatcher)
+    }
+
+    #[test]
+    fn failed_registration_rolls_back_what_registered() {
+        let unregistered = RefCell::new(Vec::new());
+        let result = register_or_roll_back(
+            |ids| {
+                ids.push(1);
+                ids.push(2);
+                Err(VmiError::Other("singlestep event refused".into()))
+            },
+            |id| unregistered.borrow_mut().push(id),
+        );
+        assert!(result.is_err());
+        assert_eq!(*unregistered.borrow(), [1, 2]);
+    }
+
+    #[test]
+    fn first_registration_failing_leaves_nothing_to_roll_back() {
+        let mut unregistered = Vec::new();
+        let result = register_or_roll_back::<u32>(
+            |_| Err(VmiError::Other("int3 event refused".into())),
+            |id| unregistered.push(id),
+        );
+        assert!(result.is_err());
+        assert!(unregistered.is_empty());
+    }
+
+    #[test]
+    fn successful_registration_keeps_every_id() {
+        let mut unregistered = Vec::new();
+        let ids = register_or_roll_back(
+            |ids| {
+                ids.extend([1, 2]);
+                Ok(())
+            },
+            |id| unregistered.push(id),
+        )
+        .unwrap();
+        assert_eq!(ids, [1, 2]);
+        assert!(unregistered.is_empty());
+    }
+
+    #[test]
+    fn manager_without_handlers_refuses_hooks() {
+        // no events, nothing registered: a planted int3 would go unhandled
+        let (mgr, _) = manager();
+        assert!(mgr.handlers.lock().unwrap().is_empty());
+        let vmi = mgr.vmi.clone();
+        let result = mgr.add_process_hook(&vmi.lock().unwrap(), vec![0x1000], ADDR, |_| {});
+        assert!(matches!(result, Err(VmiError::Other(_))));
+        assert!(mgr.patches().is_empty());
+    }
+
+    #[test]
+    fn hook_calls_keep_flowing_while_the_loop_listens() {
+        use std::sync::atomic::AtomicBool;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let (mgr, dispatcher) = manager();
+        dispatcher.set_listen_timeout(20);
+        let running = Arc::new(AtomicBool::new(true));
+        let refused = Arc::new(AtomicBool::new(false));
+        let looper = {
+            let (dispatcher, running, refused) =
+                (dispatcher.clone(), running.clone(), refused.clone());
+            thread::spawn(move || {
+                dispatcher.run_with(&running, |_, timeout| {
+                    // where handlers run: a callback asking for the vmi
+                    // again must be turned away, not wait on itself
+                    if dispatcher.call(|_| ()).is_err() {
+                        refused.store(true, Ordering::SeqCst);
+                    }
+                    thread::sleep(Duration::from_millis(timeout.into()));
+                    Ok(())
+                })
+            })
+        };
+        while dispatcher.rounds() == 0 {
+            thread::yield_now();
+        }
+
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let (mgr, dispatcher) = (mgr.clone(), dispatcher.clone());
+                thread::spawn(move || {
+                    let mut slowest = Duration::ZERO;
+                    for _ in 0..10 {
+                        let start = Instant::now();
+                        let m = mgr.clone();
+                        let added = dispatcher
+                            .call(move |vmi| m.add_process_hook(vmi, vec![0x1000], ADDR, |_| {}))
+                            .unwrap();
+                        assert!(added.is_err());
+                        let m = mgr.clone();
+                        dispatcher
+                            .call(move |vmi| m.remove_hook(vmi, ADDR))
+                            .unwrap()
+                            .unwrap();
+                        assert!(mgr.stats().is_empty());
+                        slowest = slowest.max(start.elapsed());
+                    }
+                    slowest
+                })
+            })
+            .collect();
+        for caller in callers {
+            // two queued calls, each waiting out at most one listen
+            assert!(caller.join().unwrap() < Duration::from_millis(500));
+        }
+
+        running.store(false, Ordering::SeqCst);
+        looper.join().unwrap().unwrap();
+        assert!(refused.load(Ordering::SeqCst));
+    }
+
+    /// an armed process hook at ADDR, as insert_hook would leave it.
+    /// forget it before the manager drops, there is no guest to restore to
+    fn plant(mgr: &HookManager, dtbs: Option<Vec<u64>>) {
+        mgr.state.write().unwrap().hooks.insert(
+            ADDR,
+            Hook {
+                addr: ADDR,
+                phys: 0x5000,
+                orig_byte: 0x55,
+                dtbs,
+                callback: Box::new(|_| {}),
+                strategy: None,
+                emul_insn: Box::default(),
+                active: true,
+                transient: false,
+                hits: AtomicU64::new(0),
+            },
+        );
+    }
+
+    fn hits(mgr: &HookManager, cr3: u64) -> u64 {
+        let state = mgr.state.read().unwrap();
+        let hook = &state.hooks[&ADDR];
+        hook.count_hit(cr3);
+        hook.hits.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn toggled_hook_counts_only_while_enabled() {
+        let (mgr, _) = manager();
+        plant(&mgr, Some(vec![0x1000]));
+        let writes = RefCell::new(Vec::new());
+        let write = |phys, byte| {
+            writes.borrow_mut().push((phys, byte));
+            Ok(())
+        };
+
+        assert_eq!(hits(&mgr, 0x1000), 1);
+        mgr.set_active(ADDR, false, write).unwrap();
+        assert!(!mgr.is_hook_active(ADDR));
+        // a vcpu racing the restore still traps, but it isn't a hit
+        assert_eq!(hits(&mgr, 0x1000), 1);
+        mgr.set_active(ADDR, true, write).unwrap();
+        assert!(mgr.is_hook_active(ADDR));
+        assert_eq!(hits(&mgr, 0x1000), 2);
+        // another process through the same page isn't a hit either
+        assert_eq!(hits(&mgr, 0x2000), 2);
+
+        assert_eq!(*writes.borrow(), [(0x5000, 0x55), (0x5000, 0xCC)]);
+        let stats = mgr.stats();
+        assert_eq!((stats[0].hits, stats[0].active), (2, true));
+        mgr.forget();
+    }
+
+    #[test]
+    fn toggling_to_the_current_state_writes_nothing() {
+        let (mgr, _) = manager();
+        plant(&mgr, None);
+        let write = |_, _| -> Result<()> { panic!("hook already in that state") };
+        mgr.set_active(ADDR, true, write).unwrap();
+        assert!(mgr.is_hook_active(ADDR));
+        mgr.forget();
+    }
+
+    #[test]
+    fn failed_toggle_leaves_the_hook_as_it_was() {
+        let (mgr, _) = manager();
+        plant(&mgr, None);
+        let refused = mgr.set_active(ADDR, false, |_, _| {
+            Err(VmiError::Other("page not present".into()))
+        });
+        assert!(refused.is_err());
+        assert!(mgr.is_hook_active(ADDR));
+        assert_eq!(hits(&mgr, 0), 1);
+        assert!(mgr.set_active(ADDR + 1, false, |_, _| Ok(())).is_err());
+        mgr.forget();
     }
 }
@@ -7,11 +7,22 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 
+#[cfg(feature = "async")]
+pub mod async_session;
 pub mod cli;
 pub mod disasm;
+pub mod dispatcher;
+pub mod dump;
 pub mod error;
+pub mod event_filter;
+pub mod event_log;
+pub mod event_stream;
 pub mod ffi;
 pub mod hook;
 pub mod os;
+pub mod process_diff;
 pub mod session;
+pub mod stats;
+pub mod symbols;
 pub mod vmi;
+pub mod watch;
@@ -1,7 +1,9 @@
 //! loonaro - KVM introspection toolkit
 
 use clap::{Parser, Subcommand};
-use loonaro_vmi::cli::VmiArgs;
+use loonaro_vmi::cli::{parse_addr, parse_len, OutputArgs, VmiArgs};
+use loonaro_vmi::os::MonitorSelection;
+use std::path::PathBuf;
 
 mod commands;
 
@@ -11,24 +13,235 @@ struct Cli {
     #[command(flatten)]
     vmi: VmiArgs,
 
+    #[command(flatten)]
+    output: OutputArgs,
+
+    /// also print debug diagnostics, e.g. each hook placed or removed
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
+    /// print what the session sees of the vm: name, OS, vcpus, memory
+    Info {
+        /// print JSON instead of a text block
+        #[arg(long)]
+        json: bool,
+    },
     /// list running processes
-    ListProcesses,
-    /// monitor process creation
-    Monitor,
+    ListProcesses {
+        /// also read command lines, plus image paths (windows) or credentials (linux)
+        #[arg(long)]
+        details: bool,
+        /// re-list every SECS seconds and print started and exited processes
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        watch: Option<u64>,
+    },
+    /// dump general purpose and control registers, with the vm paused
+    Regs {
+        /// only this vcpu
+        #[arg(long, conflicts_with = "all")]
+        vcpu: Option<u32>,
+        /// every vcpu, the default
+        #[arg(long)]
+        all: bool,
+    },
+    /// copy guest memory to a file, or hex dump it
+    DumpMemory {
+        /// physical start address
+        #[arg(long, value_parser = parse_addr, required_unless_present = "va", conflicts_with = "va")]
+        pa: Option<u64>,
+        /// virtual start address, kernel unless --pid is given
+        #[arg(long, value_parser = parse_addr)]
+        va: Option<u64>,
+        /// translate --va through this process's page tables
+        #[arg(long, requires = "va")]
+        pid: Option<u32>,
+        /// bytes to dump
+        #[arg(long, value_parser = parse_len)]
+        len: u64,
+        /// write raw bytes here
+        #[arg(long, required_unless_present = "hex")]
+        out: Option<PathBuf>,
+        /// hex dump to stdout instead
+        #[arg(long, conflicts_with = "out")]
+        hex: bool,
+        /// read while the vm runs, pages may come from different moments
+        #[arg(long)]
+        no_pause: bool,
+    },
+    /// read or write a single range of guest memory
+    Mem {
+        #[command(subcommand)]
+        action: commands::mem::MemCommand,
+    },
+    /// list the threads of a process
+    ListThreads {
+        #[arg(long)]
+        pid: u64,
+    },
+    /// disassemble guest code
+    Disasm {
+        /// kernel symbol, optionally offset: NtOpenProcess+0x10
+        #[arg(long, group = "start")]
+        sym: Option<String>,
+        /// virtual address, kernel unless --pid is given
+        #[arg(long, value_parser = parse_addr, group = "start")]
+        va: Option<u64>,
+        /// physical address
+        #[arg(long, value_parser = parse_addr, group = "start", conflicts_with = "pid")]
+        pa: Option<u64>,
+        /// read through this process's page tables
+        #[arg(long)]
+        pid: Option<u32>,
+        /// instructions to print
+        #[arg(long, default_value_t = 20)]
+        count: usize,
+    },
+    /// resolve kernel symbols to addresses, or addresses to symbols
+    Symbol {
+        /// symbols (name or name+off), addresses with --reverse; `-` reads
+        /// one per line from stdin
+        #[arg(required = true)]
+        queries: Vec<String>,
+        /// also translate each address to physical
+        #[arg(long)]
+        to_phys: bool,
+        /// take addresses and print the nearest symbol
+        #[arg(long)]
+        reverse: bool,
+        /// exit successfully even if some lookups failed
+        #[arg(long)]
+        ignore_missing: bool,
+    },
+    /// show which features are available for the guest OS
+    Capabilities,
+    /// list loaded kernel modules
+    ListModules {
+        /// order of the listing, the kernel's load order when not given
+        #[arg(long, value_enum)]
+        sort: Option<commands::list_modules::SortKey>,
+    },
+    /// print the process tree
+    Tree {
+        /// print JSON instead of an indented tree
+        #[arg(long)]
+        json: bool,
+    },
+    /// monitor guest events (process creation by default) until Ctrl+C
+    Monitor {
+        /// also print each new process's environment variables
+        #[arg(long)]
+        env: bool,
+        /// stop after this many seconds instead of waiting for Ctrl+C
+        #[arg(long)]
+        duration: Option<u64>,
+        /// monitors to enable, comma separated, or all the guest supports
+        #[arg(
+            long,
+            value_name = "EVENTS",
+            default_value = "process-create",
+            value_parser = MonitorSelection::parse
+        )]
+        events: MonitorSelection,
+        #[command(flatten)]
+        filter: commands::monitor::FilterArgs,
+        #[command(flatten)]
+        log: commands::monitor::LogArgs,
+    },
+    /// print every hit of a kernel hook until Ctrl+C
+    Hook {
+        /// kernel symbol, optionally offset: NtCreateFile, NtCreateFile+0x1a
+        target: String,
+    },
+    /// print every access to a memory address until Ctrl+C
+    Watch {
+        /// virtual address, kernel unless --dtb is given
+        #[arg(value_parser = parse_addr)]
+        vaddr: u64,
+        /// accesses to report, any mix of r, w and x
+        #[arg(long, default_value = "w")]
+        access: String,
+        /// bytes to watch from vaddr, within one page
+        #[arg(long, default_value_t = 1)]
+        len: u64,
+        /// translate vaddr through this page table root
+        #[arg(long, value_parser = parse_addr)]
+        dtb: Option<u64>,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    commands::stderr_log::init(cli.verbose);
 
     match cli.command {
-        Commands::ListProcesses => commands::list_processes::run(&cli.vmi)?,
-        Commands::Monitor => commands::monitor::run(&cli.vmi)?,
+        Commands::Info { json } => commands::info::run(&cli.vmi, json)?,
+        Commands::ListProcesses { details, watch } => {
+            commands::list_processes::run(&cli.vmi, details, watch, cli.output.output)?
+        }
+        Commands::Regs { vcpu, all: _ } => commands::regs::run(&cli.vmi, vcpu, cli.output.output)?,
+        Commands::DumpMemory {
+            pa,
+            va,
+            pid,
+            len,
+            out,
+            hex: _,
+            no_pause,
+        } => commands::dump_memory::run(&cli.vmi, pa, va, pid, len, out.as_deref(), !no_pause)?,
+        Commands::Mem { action } => commands::mem::run(&cli.vmi, action)?,
+        Commands::ListThreads { pid } => {
+            commands::list_threads::run(&cli.vmi, pid, cli.output.output)?
+        }
+        Commands::Disasm {
+            sym,
+            va,
+            pa,
+            pid,
+            count,
+        } => commands::disasm::run(
+            &cli.vmi,
+            commands::disasm::Start::new(sym, va, pa, pid)?,
+            count,
+            cli.output.output,
+        )?,
+        Commands::ListModules { sort } => {
+            commands::list_modules::run(&cli.vmi, sort, cli.output.output)?
+        }
+        Commands::Symbol {
+            queries,
+            to_phys,
+            reverse,
+            ignore_missing,
+        } => commands::symbol::run(
+            &cli.vmi,
+            &queries,
+            to_phys,
+            reverse,
+            ignore_missing,
+            cli.output.output,
+        )?,
+        Commands::Capabilities => commands::capabilities::run(&cli.vmi)?,
+        Commands::Tree { json } => commands::tree::run(&cli.vmi, json)?,
+        Commands::Monitor {
+            env,
+            duration,
+            events,
+            filter,
+            log,
+        } => commands::monitor::run(&cli.vmi, env, duration, &events, filter.build(), &log)?,
+        Commands::Hook { target } => commands::hook::run(&cli.vmi, &target)?,
+        Commands::Watch {
+            vaddr,
+            access,
+            len,
+            dtb,
+        } => commands::watch::run(&cli.vmi, vaddr, &access, len, dtb)?,
     };
 
     Ok(())
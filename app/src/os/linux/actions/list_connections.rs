@@ -0,0 +1,215 @@
+//! linux sockets by owner
+//!
+//! a descriptor is a socket when file->f_op is socket_file_ops. its
+//! private_data is the struct socket, socket->sk the struct sock whose
+//! embedded sock_common holds family, state, addresses and ports.
+//! only the family is required, everything else is read when the profile
+//! has it so odd kernels still give partial rows.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::error::Result;
+use crate::os::linux::actions::list_processes::list_processes_impl;
+use crate::os::linux::actions::open_files::fd_files;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+pub const AF_UNIX: u16 = 1;
+pub const AF_INET: u16 = 2;
+pub const AF_INET6: u16 = 10;
+
+/// one socket descriptor
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub pid: u64,
+    pub fd: u64,
+    /// AF_* address family
+    pub family: u16,
+    /// SOCK_* type
+    pub sock_type: Option<u16>,
+    /// IPPROTO_* for inet sockets
+    pub protocol: Option<u16>,
+    pub local: Option<SocketAddr>,
+    pub remote: Option<SocketAddr>,
+    /// skc_state, TCP_* values for tcp sockets
+    pub state: Option<u8>,
+    /// struct sock
+    pub sock: u64,
+}
+
+impl ConnectionInfo {
+    /// "ESTABLISHED", "LISTEN", ... for the TCP_* state
+    pub fn state_name(&self) -> Option<&'static str> {
+        self.state.map(tcp_state_name)
+    }
+}
+
+/// name of a TCP_* state from include/net/tcp_states.h
+pub fn tcp_state_name(state: u8) -> &'static str {
+    match state {
+        1 => "ESTABLISHED",
+        2 => "SYN_SENT",
+        3 => "SYN_RECV",
+        4 => "FIN_WAIT1",
+        5 => "FIN_WAIT2",
+        6 => "TIME_WAIT",
+        7 => "CLOSE",
+        8 => "CLOSE_WAIT",
+        9 => "LAST_ACK",
+        10 => "LISTEN",
+        11 => "CLOSING",
+        12 => "NEW_SYN_RECV",
+        _ => "UNKNOWN",
+    }
+}
+
+/// struct file/socket/sock offsets, optional ones are None when absent
+struct SockOffsets {
+    socket_file_ops: u64,
+    f_op: u64,
+    private_data: u64,
+    socket_sk: u64,
+    /// sock.__sk_common, 0 on every kernel so far
+    common: u64,
+    family: u64,
+    state: Option<u64>,
+    rcv_saddr: Option<u64>,
+    daddr: Option<u64>,
+    v6_rcv_saddr: Option<u64>,
+    v6_daddr: Option<u64>,
+    num: Option<u64>,
+    dport: Option<u64>,
+    /// u16 members since 5.6. older kernels pack them into a bitfield
+    /// whose byte offset the profile may still report, so treat as a hint
+    protocol: Option<u64>,
+    sock_type: Option<u64>,
+}
+
+impl SockOffsets {
+    fn load(vmi: &Vmi) -> Result<Self> {
+        let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+        let common = |f: &str| member("sock_common", f).ok();
+        Ok(Self {
+            socket_file_ops: vmi.ksym2v("socket_file_ops")?,
+            f_op: member("file", "f_op")?,
+            private_data: member("file", "private_data")?,
+            socket_sk: member("socket", "sk")?,
+            common: member("sock", "__sk_common").unwrap_or(0),
+            family: member("sock_common", "skc_family")?,
+            state: common("skc_state"),
+            rcv_saddr: common("skc_rcv_saddr"),
+            daddr: common("skc_daddr"),
+            v6_rcv_saddr: common("skc_v6_rcv_saddr"),
+            v6_daddr: common("skc_v6_daddr"),
+            num: common("skc_num"),
+            dport: common("skc_dport"),
+            protocol: member("sock", "sk_protocol").ok(),
+            sock_type: member("sock", "sk_type").ok(),
+        })
+    }
+}
+
+/// sockets of every process
+#[derive(Default)]
+pub struct ListConnections {
+    pid: Option<u64>,
+}
+
+impl ListConnections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// only one process's sockets
+    pub fn pid(mut self, pid: u64) -> Self {
+        self.pid = Some(pid);
+        self
+    }
+}
+
+impl Action<Vec<ConnectionInfo>> for ListConnections {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<ConnectionInfo>> {
+        vmi.read_consistent(|vmi| {
+            let offsets = SockOffsets::load(vmi)?;
+            let mut connections = Vec::new();
+            for p in list_processes_impl(vmi)?.processes {
+                if self.pid.is_some_and(|pid| pid != p.pid) {
+                    continue;
+                }
+                // kernel threads and exiting tasks have nothing to report
+                let Ok(files) = fd_files(vmi, p.addr) else {
+                    continue;
+                };
+                for (fd, file) in files {
+                    if let Some(conn) = read_socket(vmi, &offsets, p.pid, fd, file) {
+                        connections.push(conn);
+                    }
+                }
+            }
+            Ok(connections)
+        })
+    }
+}
+
+/// None when the file isn't a socket or has no sock attached
+fn read_socket(
+    vmi: &Vmi,
+    offsets: &SockOffsets,
+    pid: u64,
+    fd: u64,
+    file: u64,
+) -> Option<ConnectionInfo> {
+    if vmi.read_addr_va(file + offsets.f_op, 0).ok()? != offsets.socket_file_ops {
+        return None;
+    }
+    let socket = vmi.read_addr_va(file + offsets.private_data, 0).ok()?;
+    let sock = vmi.read_addr_va(socket + offsets.socket_sk, 0).ok()?;
+    if sock == 0 {
+        return None;
+    }
+
+    let common = sock + offsets.common;
+    let family = vmi.read_16_va(common + offsets.family, 0).ok()?;
+    let read_u16 =
+        |offset: Option<u64>, base: u64| offset.and_then(|o| vmi.read_16_va(base + o, 0).ok());
+
+    // skc_num is host order, skc_dport network order
+    let local_port = read_u16(offsets.num, common);
+    let remote_port = read_u16(offsets.dport, common).map(u16::from_be);
+    let (local_ip, remote_ip) = match family {
+        AF_INET => (
+            read_ipv4(vmi, offsets.rcv_saddr, common),
+            read_ipv4(vmi, offsets.daddr, common),
+        ),
+        AF_INET6 => (
+            read_ipv6(vmi, offsets.v6_rcv_saddr, common),
+            read_ipv6(vmi, offsets.v6_daddr, common),
+        ),
+        _ => (None, None),
+    };
+    let addr = |ip: Option<IpAddr>, port: Option<u16>| Some(SocketAddr::new(ip?, port?));
+
+    Some(ConnectionInfo {
+        pid,
+        fd,
+        family,
+        sock_type: read_u16(offsets.sock_type, sock),
+        protocol: read_u16(offsets.protocol, sock),
+        local: addr(local_ip, local_port),
+        remote: addr(remote_ip, remote_port),
+        state: offsets
+            .state
+            .and_then(|o| vmi.read_8_va(common + o, 0).ok()),
+        sock,
+    })
+}
+
+fn read_ipv4(vmi: &Vmi, offset: Option<u64>, common: u64) -> Option<IpAddr> {
+    let bytes: [u8; 4] = vmi.read_va(common + offset?, 0, 4).ok()?.try_into().ok()?;
+    Some(IpAddr::V4(Ipv4Addr::from(bytes)))
+}
+
+fn read_ipv6(vmi: &Vmi, offset: Option<u64>, common: u64) -> Option<IpAddr> {
+    let bytes: [u8; 16] = vmi.read_va(common + offset?, 0, 16).ok()?.try_into().ok()?;
+    Some(IpAddr::V6(Ipv6Addr::from(bytes)))
+}
@@ -0,0 +1,119 @@
+use crate::error::{Result, VmiError};
+use crate::os::list::walk_list;
+use crate::os::{Action, ModuleInfo};
+use crate::vmi::Vmi;
+
+/// walks the kernel `modules` list of `struct module`
+pub struct ListModules;
+
+impl Action<Vec<ModuleInfo>> for ListModules {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<ModuleInfo>> {
+        vmi.read_consistent(|vmi| {
+            Ok(list_modules_impl(vmi)?
+                .into_iter()
+                // unformed modules are still being loaded, their layout is garbage
+                .filter(|m| m.state != ModuleState::Unformed)
+                .map(|m| m.info)
+                .collect())
+        })
+    }
+}
+
+/// enum module_state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleState {
+    Live,
+    Coming,
+    Going,
+    Unformed,
+    Unknown(u32),
+}
+
+impl From<u32> for ModuleState {
+    fn from(state: u32) -> Self {
+        match state {
+            0 => ModuleState::Live,
+            1 => ModuleState::Coming,
+            2 => ModuleState::Going,
+            3 => ModuleState::Unformed,
+            other => ModuleState::Unknown(other),
+        }
+    }
+}
+
+/// a module with its load state
+#[derive(Debug, Clone)]
+pub struct LinuxModule {
+    pub info: ModuleInfo,
+    pub state: ModuleState,
+}
+
+/// offsets of the core text base pointer and 32-bit size within struct
+/// module. the layout moved twice upstream
+fn text_offsets(vmi: &Vmi) -> Result<(u64, u64)> {
+    let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+
+    // 6.4+: mem[MOD_TEXT] of struct module_memory, MOD_TEXT is index 0
+    if let Ok(mem) = member("module", "mem") {
+        return Ok((
+            mem + member("module_memory", "base")?,
+            mem + member("module_memory", "size")?,
+        ));
+    }
+    // 4.5 - 6.3: core_layout of struct module_layout
+    if let Ok(layout) = member("module", "core_layout") {
+        return Ok((
+            layout + member("module_layout", "base")?,
+            layout + member("module_layout", "size")?,
+        ));
+    }
+    // older: module_core pointer and core_size
+    if let Ok(base) = member("module", "module_core") {
+        return Ok((base, member("module", "core_size")?));
+    }
+
+    Err(VmiError::OffsetNotFound(
+        "module.mem/core_layout/module_core".into(),
+    ))
+}
+
+/// every module on the list with its state, no pausing.
+/// bounded by walk_list's entry cap
+pub fn list_modules_impl(vmi: &Vmi) -> Result<Vec<LinuxModule>> {
+    let list_offset = vmi.get_struct_offset("module", "list")?;
+    let name_offset = vmi.get_struct_offset("module", "name")?;
+    let state_offset = vmi.get_struct_offset("module", "state").ok();
+    let (base_offset, size_offset) = text_offsets(vmi)?;
+
+    // `modules` is a bare list_head, not a pointer to one
+    let head = vmi.ksym2v("modules")?;
+
+    let mut modules = Vec::new();
+    for entry in walk_list(vmi, head, 0)? {
+        let module = entry - list_offset;
+
+        // name is an inline char array, not a pointer
+        let name = vmi
+            .read_str_va(module + name_offset, 0)
+            .unwrap_or_else(|_| "<unknown>".into());
+        let base = vmi.read_addr_va(module + base_offset, 0).unwrap_or(0);
+        let size = vmi.read_32_va(module + size_offset, 0).unwrap_or(0) as u64;
+        let state = state_offset
+            .and_then(|o| vmi.read_32_va(module + o, 0).ok())
+            .map(ModuleState::from)
+            .unwrap_or(ModuleState::Live);
+
+        modules.push(LinuxModule {
+            // struct module doesn't keep the .ko path
+            info: ModuleInfo {
+                name,
+                base,
+                size,
+                path: None,
+            },
+            state,
+        });
+    }
+
+    Ok(modules)
+}
@@ -0,0 +1,117 @@
+use crate::error::{Result, VmiError};
+use crate::os::linux::actions::list_threads::{threads_of, ThreadOffsets};
+use crate::os::linux::task::{
+    read_args, read_credentials, read_namespace_pid, MmOffsets, TaskDetailOffsets,
+};
+use crate::os::list::walk_list_partial;
+use crate::os::{Action, ListSource, ProcessInfo, ProcessList};
+use crate::vmi::Vmi;
+
+/// TASK_COMM_LEN, task_struct.comm is an inline array of this size
+const TASK_COMM_LEN: usize = 16;
+
+/// walks task_struct.tasks starting at init_task
+#[derive(Default)]
+pub struct ListProcesses {
+    details: bool,
+}
+
+impl ListProcesses {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// also read each task's command line, credentials, pid namespace and
+    /// thread count
+    pub fn with_details(mut self, enabled: bool) -> Self {
+        self.details = enabled;
+        self
+    }
+}
+
+impl Action<ProcessList> for ListProcesses {
+    fn execute(&self, vmi: &Vmi) -> Result<ProcessList> {
+        vmi.read_consistent(|vmi| {
+            let mut list = list_processes_impl(vmi)?;
+            if self.details {
+                let offsets = TaskDetailOffsets::load(vmi)?;
+                let thread_offsets = ThreadOffsets::load(vmi).ok();
+                let mm_offsets = MmOffsets::load(vmi).ok();
+                for p in &mut list.processes {
+                    p.command_line = mm_offsets
+                        .as_ref()
+                        .and_then(|mm| read_args(vmi, mm, p.addr).ok().flatten())
+                        .map(|args| args.join(" "));
+                    p.credentials = read_credentials(vmi, &offsets, p.addr).ok();
+                    p.namespace_pid = read_namespace_pid(vmi, &offsets, p.addr).ok();
+                    p.thread_count = thread_offsets
+                        .as_ref()
+                        .and_then(|t| threads_of(vmi, t, p.addr).ok())
+                        .map(|list| list.threads.len());
+                }
+            }
+            Ok(list)
+        })
+    }
+}
+
+pub(crate) fn list_processes_impl(vmi: &Vmi) -> Result<ProcessList> {
+    let tasks_offset = vmi.get_offset("linux_tasks")?;
+    let name_offset = vmi.get_offset("linux_name")?;
+    let pid_offset = vmi.get_offset("linux_pid")?;
+
+    // init_task (pid 0, swapper) is a real task_struct and part of the cycle,
+    // walk_list stops when it gets back to it
+    let init_task = vmi.ksym2v("init_task")?;
+    let start = init_task + tasks_offset;
+
+    let walk = walk_list_partial(vmi, start, 0)?;
+    let mut entries = vec![start];
+    entries.extend(walk.entries);
+
+    let processes = entries
+        .into_iter()
+        .map(|entry| {
+            let task = entry - tasks_offset;
+
+            let pid = vmi.read_32_va(task + pid_offset, 0).unwrap_or(0) as u64;
+            let name = read_comm(vmi, task + name_offset).unwrap_or_else(|_| "<unknown>".into());
+
+            ProcessInfo {
+                pid,
+                name,
+                addr: task,
+                command_line: None,
+                full_image_path: None,
+                credentials: None,
+                namespace_pid: None,
+                thread_count: None,
+                create_time: None,
+            }
+        })
+        .collect();
+
+    Ok(ProcessList {
+        processes,
+        source: ListSource::InitTask,
+        truncated: walk.failed_at.is_some(),
+    })
+}
+
+/// comm is a fixed char array, NUL-terminated unless exactly 15 chars long
+pub(crate) fn read_comm(vmi: &Vmi, addr: u64) -> Result<String> {
+    let bytes = vmi.read_va(addr, 0, TASK_COMM_LEN)?;
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// find the task_struct of a pid by walking the task list.
+/// caller is responsible for pausing.
+pub fn find_task(vmi: &Vmi, pid: u64) -> Result<u64> {
+    list_processes_impl(vmi)?
+        .processes
+        .into_iter()
+        .find(|p| p.pid == pid)
+        .map(|p| p.addr)
+        .ok_or_else(|| VmiError::Other(format!("no task with pid {}", pid)))
+}
@@ -0,0 +1,151 @@
+//! threads of a linux process
+//!
+//! 6.7+ chain every thread through task->thread_node into
+//! signal_struct.thread_head, a bare list_head owned by the signal struct.
+//! older kernels link the threads to each other through task->thread_group,
+//! a ring without a separate head, so the walk starts from the leader.
+
+use crate::error::Result;
+use crate::os::linux::actions::list_processes::find_task;
+use crate::os::list::walk_list_partial;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// one thread (task_struct) of a process
+#[derive(Debug, Clone)]
+pub struct LinuxThread {
+    pub tid: u64,
+    /// raw __state/state, None if unreadable. see state_letter
+    pub state: Option<u32>,
+    /// saved kernel stack pointer, thread.sp
+    pub kernel_sp: u64,
+    /// task_struct
+    pub addr: u64,
+}
+
+impl LinuxThread {
+    /// ps-style state letter
+    pub fn state_letter(&self) -> char {
+        match self.state {
+            Some(0) => 'R',
+            // TASK_IDLE is TASK_UNINTERRUPTIBLE | TASK_NOLOAD
+            Some(s) if s & 0x402 == 0x402 => 'I',
+            Some(s) if s & 0x1 != 0 => 'S',
+            Some(s) if s & 0x2 != 0 => 'D',
+            Some(s) if s & 0x4 != 0 => 'T',
+            Some(s) if s & 0x8 != 0 => 't',
+            _ => '?',
+        }
+    }
+}
+
+/// how a task's threads are chained
+enum ThreadLink {
+    /// signal->thread_head, entries at task + thread_node
+    Signal {
+        signal: u64,
+        thread_head: u64,
+        thread_node: u64,
+    },
+    /// task->thread_group ring through every thread
+    ThreadGroup(u64),
+}
+
+/// task_struct offsets for thread walks
+pub struct ThreadOffsets {
+    link: ThreadLink,
+    pid: u64,
+    state: u64,
+    sp: u64,
+}
+
+impl ThreadOffsets {
+    pub fn load(vmi: &Vmi) -> Result<Self> {
+        let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+
+        let link = match member("task_struct", "thread_node") {
+            Ok(thread_node) => ThreadLink::Signal {
+                signal: member("task_struct", "signal")?,
+                thread_head: member("signal_struct", "thread_head")?,
+                thread_node,
+            },
+            Err(_) => ThreadLink::ThreadGroup(member("task_struct", "thread_group")?),
+        };
+
+        Ok(Self {
+            link,
+            pid: member("task_struct", "pid").or_else(|_| vmi.get_offset("linux_pid"))?,
+            // renamed to __state in 5.14
+            state: member("task_struct", "__state").or_else(|_| member("task_struct", "state"))?,
+            sp: member("task_struct", "thread")? + member("thread_struct", "sp")?,
+        })
+    }
+}
+
+/// thread group walk, `truncated` when a link read failed part way
+#[derive(Debug, Clone)]
+pub struct ThreadList {
+    pub threads: Vec<LinuxThread>,
+    pub truncated: bool,
+}
+
+/// every thread in the group of `leader`, leader first. no pausing
+pub fn threads_of(vmi: &Vmi, offsets: &ThreadOffsets, leader: u64) -> Result<ThreadList> {
+    let (tasks, truncated) = match offsets.link {
+        ThreadLink::Signal {
+            signal,
+            thread_head,
+            thread_node,
+        } => {
+            let head = vmi.read_addr_va(leader + signal, 0)? + thread_head;
+            let walk = walk_list_partial(vmi, head, 0)?;
+            let truncated = walk.is_truncated();
+            let mut tasks: Vec<u64> = walk.entries.iter().map(|e| e - thread_node).collect();
+            // list order is creation order, but make sure the leader leads
+            if let Some(i) = tasks.iter().position(|&t| t == leader) {
+                tasks[..=i].rotate_right(1);
+            }
+            (tasks, truncated)
+        }
+        ThreadLink::ThreadGroup(thread_group) => {
+            let walk = walk_list_partial(vmi, leader + thread_group, 0)?;
+            let truncated = walk.is_truncated();
+            let mut tasks = vec![leader];
+            tasks.extend(walk.entries.iter().map(|e| e - thread_group));
+            (tasks, truncated)
+        }
+    };
+
+    let threads = tasks
+        .into_iter()
+        .map(|task| LinuxThread {
+            // pid_t is 32-bit
+            tid: vmi.read_32_va(task + offsets.pid, 0).unwrap_or(0) as u64,
+            state: vmi.read_32_va(task + offsets.state, 0).ok(),
+            kernel_sp: vmi.read_addr_va(task + offsets.sp, 0).unwrap_or(0),
+            addr: task,
+        })
+        .collect();
+
+    Ok(ThreadList { threads, truncated })
+}
+
+/// threads of one process, looked up by pid
+pub struct ListThreads {
+    pid: u64,
+}
+
+impl ListThreads {
+    pub fn new(pid: u64) -> Self {
+        Self { pid }
+    }
+}
+
+impl Action<ThreadList> for ListThreads {
+    fn execute(&self, vmi: &Vmi) -> Result<ThreadList> {
+        vmi.read_consistent(|vmi| {
+            let task = find_task(vmi, self.pid)?;
+            threads_of(vmi, &ThreadOffsets::load(vmi)?, task)
+        })
+    }
+}
@@ -0,0 +1,5 @@
+pub mod list_connections;
+pub mod list_modules;
+pub mod list_processes;
+pub mod list_threads;
+pub mod open_files;
@@ -0,0 +1,90 @@
+use crate::error::Result;
+use crate::os::linux::actions::list_processes::find_task;
+use crate::os::linux::dentry::DentryOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// upper bound on descriptors inspected per task
+pub const MAX_FDS: u64 = 65536;
+
+/// one open file descriptor
+#[derive(Debug, Clone)]
+pub struct OpenFile {
+    pub fd: u64,
+    pub path: String,
+    /// file.f_flags (O_* bits)
+    pub flags: u32,
+    /// struct file address
+    pub file: u64,
+}
+
+/// open files of a task by pid:
+/// task_struct.files -> files_struct.fdt -> fdtable.fd[] -> struct file
+pub struct ListOpenFiles {
+    pid: u64,
+}
+
+impl ListOpenFiles {
+    pub fn new(pid: u64) -> Self {
+        Self { pid }
+    }
+}
+
+impl Action<Vec<OpenFile>> for ListOpenFiles {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<OpenFile>> {
+        vmi.read_consistent(|vmi| {
+            let task = find_task(vmi, self.pid)?;
+            open_files(vmi, task)
+        })
+    }
+}
+
+/// (fd, struct file) for every open descriptor of a task_struct, no
+/// pausing. kernel threads have no files_struct and yield an empty list
+pub fn fd_files(vmi: &Vmi, task: u64) -> Result<Vec<(u64, u64)>> {
+    let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+    let files_offset = member("task_struct", "files")?;
+    let fdt_offset = member("files_struct", "fdt")?;
+    let max_fds_offset = member("fdtable", "max_fds")?;
+    let fd_offset = member("fdtable", "fd")?;
+
+    let files = vmi.read_addr_va(task + files_offset, 0)?;
+    if files == 0 {
+        return Ok(Vec::new());
+    }
+    // __rcu pointer, a plain load is fine while paused
+    let fdt = vmi.read_addr_va(files + fdt_offset, 0)?;
+    let max_fds = vmi.read_32_va(fdt + max_fds_offset, 0)? as u64;
+    let fd_array = vmi.read_addr_va(fdt + fd_offset, 0)?;
+    if fd_array == 0 {
+        return Ok(Vec::new());
+    }
+
+    let width = vmi.address_width() as u64;
+    // only the first max_fds slots are meaningful, the allocation may be larger
+    Ok((0..max_fds.min(MAX_FDS))
+        .filter_map(|fd| match vmi.read_addr_va(fd_array + fd * width, 0) {
+            Ok(file) if file != 0 => Some((fd, file)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// open files of a task_struct with their paths, no pausing
+pub fn open_files(vmi: &Vmi, task: u64) -> Result<Vec<OpenFile>> {
+    let f_path = vmi.get_struct_offset("file", "f_path")?;
+    let f_flags = vmi.get_struct_offset("file", "f_flags")?;
+    let dentries = DentryOffsets::load(vmi)?;
+
+    Ok(fd_files(vmi, task)?
+        .into_iter()
+        .map(|(fd, file)| OpenFile {
+            fd,
+            path: dentries
+                .path(vmi, file + f_path)
+                .unwrap_or_else(|_| "<unknown>".into()),
+            flags: vmi.read_32_va(file + f_flags, 0).unwrap_or(0),
+            file,
+        })
+        .collect())
+}
@@ -0,0 +1,135 @@
+//! dentry -> path reconstruction for struct path/struct file
+//!
+//! walks d_parent up to the filesystem root, then hops from the mount root
+//! to its mountpoint in the parent mount (struct mount embeds the vfsmount
+//! the path points at). names come from dentry.d_name (struct qstr).
+
+use crate::error::Result;
+use crate::vmi::Vmi;
+
+/// deepest component chain followed
+pub const MAX_PATH_DEPTH: usize = 64;
+/// longest path returned, longer ones are cut at a component boundary
+pub const MAX_PATH_LEN: usize = 4096;
+
+/// struct offsets for path resolution, looked up once per action
+#[derive(Debug, Clone)]
+pub struct DentryOffsets {
+    d_parent: u64,
+    /// d_name.name
+    d_name_name: u64,
+    /// d_hash.pprev, NULL once the dentry is unhashed (deleted)
+    d_hash_pprev: Option<u64>,
+    path_mnt: u64,
+    path_dentry: u64,
+    /// mount crossing, absent offsets mean paths stay relative to the mount
+    mounts: Option<MountOffsets>,
+}
+
+#[derive(Debug, Clone)]
+struct MountOffsets {
+    mount_mnt: u64,
+    mount_parent: u64,
+    mount_mountpoint: u64,
+    vfsmount_root: u64,
+}
+
+impl DentryOffsets {
+    pub fn load(vmi: &Vmi) -> Result<Self> {
+        let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+
+        let mounts = (|| {
+            Ok::<_, crate::error::VmiError>(MountOffsets {
+                mount_mnt: member("mount", "mnt")?,
+                mount_parent: member("mount", "mnt_parent")?,
+                mount_mountpoint: member("mount", "mnt_mountpoint")?,
+                vfsmount_root: member("vfsmount", "mnt_root")?,
+            })
+        })()
+        .ok();
+
+        Ok(Self {
+            d_parent: member("dentry", "d_parent")?,
+            d_name_name: member("dentry", "d_name")? + member("qstr", "name")?,
+            d_hash_pprev: member("dentry", "d_hash")
+                .ok()
+                .map(|o| o + vmi.address_width() as u64),
+            path_mnt: member("path", "mnt")?,
+            path_dentry: member("path", "dentry")?,
+            mounts,
+        })
+    }
+
+    /// path of a `struct path` at `path`
+    pub fn path(&self, vmi: &Vmi, path: u64) -> Result<String> {
+        let dentry = vmi.read_addr_va(path + self.path_dentry, 0)?;
+        let vfsmount = vmi.read_addr_va(path + self.path_mnt, 0).unwrap_or(0);
+        self.dentry_path(vmi, dentry, vfsmount)
+    }
+
+    /// rebuild the absolute path of a dentry. `vfsmount` may be 0 when
+    /// unknown, the result is then relative to the dentry's filesystem root
+    pub fn dentry_path(&self, vmi: &Vmi, dentry: u64, vfsmount: u64) -> Result<String> {
+        let deleted = self.is_unhashed(vmi, dentry);
+
+        // struct mount embeds the vfsmount, step back to the container
+        let mut mount = match (&self.mounts, vfsmount) {
+            (Some(m), v) if v != 0 => v - m.mount_mnt,
+            _ => 0,
+        };
+
+        let mut components = Vec::new();
+        let mut len = 0;
+        let mut current = dentry;
+        for _ in 0..MAX_PATH_DEPTH {
+            if let Some(m) = &self.mounts
+                && mount != 0
+            {
+                let root = vmi.read_addr_va(mount + m.mount_mnt + m.vfsmount_root, 0)?;
+                if current == root {
+                    let parent = vmi.read_addr_va(mount + m.mount_parent, 0)?;
+                    // the root mount is its own parent
+                    if parent == mount || parent == 0 {
+                        break;
+                    }
+                    current = vmi.read_addr_va(mount + m.mount_mountpoint, 0)?;
+                    mount = parent;
+                    continue;
+                }
+            }
+
+            let parent = vmi.read_addr_va(current + self.d_parent, 0)?;
+            if parent == current || parent == 0 {
+                break;
+            }
+
+            let name_ptr = vmi.read_addr_va(current + self.d_name_name, 0)?;
+            let name = vmi.read_str_va(name_ptr, 0).unwrap_or_else(|_| "?".into());
+            len += name.len() + 1;
+            if len > MAX_PATH_LEN {
+                break;
+            }
+            components.push(name);
+            current = parent;
+        }
+
+        components.reverse();
+        let mut path = format!("/{}", components.join("/"));
+        if deleted {
+            path.push_str(" (deleted)");
+        }
+        Ok(path)
+    }
+
+    /// unlinked dentries are dropped from the hash, d_hash.pprev goes NULL.
+    /// the root dentry is never hashed, so only check ones with a parent
+    fn is_unhashed(&self, vmi: &Vmi, dentry: u64) -> bool {
+        let Some(pprev) = self.d_hash_pprev else {
+            return false;
+        };
+        let is_root = vmi
+            .read_addr_va(dentry + self.d_parent, 0)
+            .is_ok_and(|parent| parent == dentry);
+        !is_root && vmi.read_addr_va(dentry + pprev, 0).is_ok_and(|p| p == 0)
+    }
+}
@@ -0,0 +1,145 @@
+//! linux process creation monitor - hooks the point a new task is woken
+//!
+//! every candidate takes the new task_struct as its first argument (RDI per
+//! SysV). fork entry points like kernel_clone/_do_fork only get clone args,
+//! the child doesn't exist yet when they are entered.
+
+use crate::error::{Result, VmiError};
+use crate::event_stream::{EventSink, GuestEvent};
+use crate::ffi::RDI;
+use crate::hook::{HookContext, HookManager};
+use crate::os::linux::actions::list_processes::read_comm;
+use crate::os::linux::LinuxOffsets;
+use crate::os::{Event, EventContext};
+use crate::vmi::Vmi;
+use std::sync::{Arc, Mutex};
+
+/// hook targets in order of preference
+const CANDIDATES: &[&str] = &["wake_up_new_task", "sched_post_fork", "cgroup_post_fork"];
+
+/// task_struct/cred offsets the callback reads
+struct TaskOffsets {
+    pid: u64,
+    tgid: Option<u64>,
+    comm: u64,
+    real_parent: u64,
+    cred: u64,
+    cred_uid: u64,
+}
+
+impl TaskOffsets {
+    fn from_cache(cache: &LinuxOffsets) -> Result<Self> {
+        Ok(Self {
+            pid: cache.pid.get()?,
+            tgid: cache.tgid.get().ok(),
+            comm: cache.comm.get()?,
+            real_parent: cache.real_parent.get()?,
+            cred: cache.cred.get()?,
+            cred_uid: cache.cred_uid.get()?,
+        })
+    }
+}
+
+/// process creation monitor for linux guests
+#[derive(Default)]
+pub struct ProcessCreateMonitor {
+    hook_addr: Option<u64>,
+}
+
+impl Event for ProcessCreateMonitor {
+    fn enable(&mut self, ctx: &EventContext) -> Result<()> {
+        self.enable_internal(ctx)
+    }
+
+    fn disable(&mut self, ctx: &EventContext) -> Result<()> {
+        self.disable_internal(ctx.hooks, ctx.vmi)
+    }
+    fn name(&self) -> &str {
+        "LinuxProcessCreateMonitor"
+    }
+}
+
+impl ProcessCreateMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn enable_internal(&mut self, ctx: &EventContext) -> Result<()> {
+        if self.hook_addr.is_some() {
+            return Ok(());
+        }
+
+        let offsets = Arc::new(TaskOffsets::from_cache(ctx.linux()?.offsets())?);
+        let events = ctx.events.clone();
+        let vmi_lock = ctx.vmi.lock().unwrap();
+        let (name, func_addr) = CANDIDATES
+            .iter()
+            .find_map(|&name| vmi_lock.ksym2v(name).ok().map(|addr| (name, addr)))
+            .ok_or_else(|| VmiError::SymbolNotFound(CANDIDATES.join("/")))?;
+        ctx.hooks
+            .add_hook(&vmi_lock, func_addr, move |ctx: &HookContext| {
+                Self::on_task_create(ctx, &offsets, &events);
+            })?;
+
+        self.hook_addr = Some(func_addr);
+        log::info!(
+            "[LinuxProcessCreateMonitor] Enabled on {} @ {:#x}",
+            name,
+            func_addr
+        );
+        Ok(())
+    }
+
+    fn disable_internal(&mut self, hooks: &Arc<HookManager>, vmi: &Arc<Mutex<Vmi>>) -> Result<()> {
+        if let Some(addr) = self.hook_addr.take() {
+            let vmi_lock = vmi.lock().unwrap();
+            hooks.remove_hook(&vmi_lock, addr)?;
+            log::info!("[LinuxProcessCreateMonitor] Disabled");
+        }
+        Ok(())
+    }
+
+    fn on_task_create(ctx: &HookContext, offsets: &TaskOffsets, events: &EventSink) {
+        // RDI = new task_struct per SysV x64 ABI
+        let task = match ctx.vmi.get_vcpureg(RDI as u64, ctx.vcpu_id) {
+            Ok(addr) if addr != 0 => addr,
+            _ => return,
+        };
+
+        let vmi = ctx.vmi;
+
+        // pid_t is 32-bit
+        let pid = vmi.read_32_va(task + offsets.pid, 0).unwrap_or(0);
+        // threads share their group leader's tgid, only report new processes
+        if let Some(tgid) = offsets.tgid
+            && vmi.read_32_va(task + tgid, 0).is_ok_and(|tgid| tgid != pid)
+        {
+            return;
+        }
+
+        // the child can be half set up here, retry paused before giving up
+        let comm = vmi
+            .retry_paused(|vmi| read_comm(vmi, task + offsets.comm))
+            .ok();
+        let ppid = vmi
+            .retry_paused(|vmi| {
+                let parent = vmi.read_addr_va(task + offsets.real_parent, 0)?;
+                vmi.read_32_va(parent + offsets.pid, 0)
+            })
+            .unwrap_or(0);
+        // kuid_t wraps a u32
+        let uid = vmi
+            .retry_paused(|vmi| {
+                let cred = vmi.read_addr_va(task + offsets.cred, 0)?;
+                vmi.read_32_va(cred + offsets.cred_uid, 0)
+            })
+            .ok();
+
+        events.emit(GuestEvent::TaskCreated {
+            pid: pid as u64,
+            ppid: ppid as u64,
+            comm,
+            uid,
+        });
+    }
+}
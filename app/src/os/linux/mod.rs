@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::os::windows::offsets::Offset;
+use crate::vmi::{OsType, Vmi};
+
+pub mod actions;
+pub mod dentry;
+pub mod events;
+pub mod task;
+pub mod vma;
+
+use super::{ModuleInfo, Os, ProcessList, Rejected};
+use actions::list_modules::ListModules;
+use actions::list_processes::ListProcesses;
+
+/// task_struct and cred offsets, resolved once per session. failures are
+/// deferred to get() like WindowsOffsets
+#[derive(Debug, Clone)]
+pub struct LinuxOffsets {
+    pub pid: Offset,
+    pub tgid: Offset,
+    pub comm: Offset,
+    pub real_parent: Offset,
+    pub cred: Offset,
+    pub cred_uid: Offset,
+}
+
+impl LinuxOffsets {
+    pub fn load(vmi: &Vmi) -> Self {
+        let member = |name: &'static str, s: &str, f: &str| {
+            Offset::new(name, vmi.get_struct_offset(s, f).ok())
+        };
+        // older profiles only carry libvmi's config keys
+        let or_config = |offset: Offset, key: &str| {
+            if offset.is_resolved() {
+                offset
+            } else {
+                Offset::new(offset.name(), vmi.get_offset(key).ok())
+            }
+        };
+        Self {
+            pid: or_config(member("task_struct.pid", "task_struct", "pid"), "linux_pid"),
+            tgid: member("task_struct.tgid", "task_struct", "tgid"),
+            comm: or_config(
+                member("task_struct.comm", "task_struct", "comm"),
+                "linux_name",
+            ),
+            real_parent: member("task_struct.real_parent", "task_struct", "real_parent"),
+            cred: member("task_struct.cred", "task_struct", "cred"),
+            cred_uid: member("cred.uid", "cred", "uid"),
+        }
+    }
+}
+
+/// what a session's events know about a linux guest, built once at init
+#[derive(Debug, Clone)]
+pub struct LinuxGuest {
+    offsets: Arc<LinuxOffsets>,
+}
+
+impl LinuxGuest {
+    pub fn load(vmi: &Vmi) -> Self {
+        Self {
+            offsets: Arc::new(LinuxOffsets::load(vmi)),
+        }
+    }
+
+    pub fn offsets(&self) -> &Arc<LinuxOffsets> {
+        &self.offsets
+    }
+}
+
+pub struct LinuxOs {
+    vmi: Vmi,
+}
+
+impl LinuxOs {
+    /// Os::new, but refuses a vmi that didn't detect a linux guest and
+    /// hands it back
+    pub fn try_new(vmi: Vmi) -> std::result::Result<Self, Rejected> {
+        match super::expect_os(vmi.os_type(), OsType::Linux) {
+            Ok(()) => Ok(Self::new(vmi)),
+            Err(e) => Err((e, Box::new(vmi))),
+        }
+    }
+
+    /// tasks reachable from init_task
+    pub fn list_processes(&self) -> Result<ProcessList> {
+        self.execute(ListProcesses::new())
+    }
+
+    /// loaded kernel modules from the `modules` list
+    pub fn list_modules(&self) -> Result<Vec<ModuleInfo>> {
+        self.execute(ListModules)
+    }
+}
+
+impl Os for LinuxOs {
+    fn new(vmi: Vmi) -> Self {
+        Self { vmi }
+    }
+
+    fn vmi(&self) -> &Vmi {
+        &self.vmi
+    }
+}
@@ -0,0 +1,315 @@
+//! per-task details - credentials, pid namespace, argv and environment
+//!
+//! a task's struct pid carries one upid per namespace level, numbers[0] is
+//! the host pid and numbers[pid->level] the pid inside the innermost
+//! namespace. nsproxy->pid_ns_for_children is where the task's *children*
+//! go, not where the task itself lives, so it isn't used here.
+
+use crate::error::{Result, VmiError};
+use crate::os::{Credentials, NamespacePid};
+use crate::vmi::Vmi;
+
+/// MAX_PID_NS_LEVEL, deeper levels mean a bad read
+pub const MAX_PID_NS_LEVEL: u32 = 32;
+/// cap on argv/envp bytes read, the ranges can be up to ARG_MAX
+pub const MAX_ARG_BYTES: u64 = 0x8000;
+
+/// cred/pid offsets for detailed listings
+pub struct TaskDetailOffsets {
+    cred: u64,
+    cred_uid: u64,
+    cred_gid: u64,
+    cred_euid: u64,
+    /// task_struct.thread_pid on 4.19+, pids[PIDTYPE_PID].pid before
+    pid_link: u64,
+    pid_level: u64,
+    pid_numbers: u64,
+    upid_nr: u64,
+    upid_stride: u64,
+}
+
+impl TaskDetailOffsets {
+    pub fn load(vmi: &Vmi) -> Result<Self> {
+        let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+        let width = vmi.address_width() as u64;
+
+        // PIDTYPE_PID is 0, so pids[PIDTYPE_PID] starts at pids itself
+        let pid_link = match member("task_struct", "thread_pid") {
+            Ok(offset) => offset,
+            Err(_) => member("task_struct", "pids")? + member("pid_link", "pid")?,
+        };
+
+        Ok(Self {
+            cred: member("task_struct", "cred")?,
+            cred_uid: member("cred", "uid")?,
+            cred_gid: member("cred", "gid")?,
+            cred_euid: member("cred", "euid")?,
+            pid_link,
+            pid_level: member("pid", "level")?,
+            pid_numbers: member("pid", "numbers")?,
+            upid_nr: member("upid", "nr")?,
+            upid_stride: upid_stride(
+                member("upid", "ns")?,
+                member("upid", "pid_chain").ok(),
+                width,
+            ),
+        })
+    }
+}
+
+/// sizeof(struct upid): { nr, ns } on current kernels, older ones append an
+/// hlist_node pid_chain (two pointers)
+pub fn upid_stride(ns: u64, pid_chain: Option<u64>, width: u64) -> u64 {
+    match pid_chain {
+        Some(chain) => chain.max(ns + width) + 2 * width,
+        None => ns + width,
+    }
+}
+
+/// address of numbers[level].nr in a struct pid
+pub fn upid_nr_addr(pid: u64, numbers: u64, stride: u64, nr: u64, level: u32) -> u64 {
+    pid + numbers + level as u64 * stride + nr
+}
+
+/// uid/gid/euid from task->cred, kuid_t/kgid_t each wrap a u32
+pub fn read_credentials(vmi: &Vmi, offsets: &TaskDetailOffsets, task: u64) -> Result<Credentials> {
+    let cred = vmi.read_addr_va(task + offsets.cred, 0)?;
+    if cred == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    Ok(Credentials {
+        uid: vmi.read_32_va(cred + offsets.cred_uid, 0)?,
+        gid: vmi.read_32_va(cred + offsets.cred_gid, 0)?,
+        euid: vmi.read_32_va(cred + offsets.cred_euid, 0)?,
+    })
+}
+
+/// namespace depth and the pid in the innermost namespace
+pub fn read_namespace_pid(
+    vmi: &Vmi,
+    offsets: &TaskDetailOffsets,
+    task: u64,
+) -> Result<NamespacePid> {
+    namespace_pid_with(
+        offsets,
+        task,
+        |addr| vmi.read_addr_va(addr, 0),
+        |addr| vmi.read_32_va(addr, 0),
+    )
+}
+
+/// read_namespace_pid with pointers and u32s read through closures
+pub fn namespace_pid_with(
+    offsets: &TaskDetailOffsets,
+    task: u64,
+    read_addr: impl Fn(u64) -> Result<u64>,
+    read_32: impl Fn(u64) -> Result<u32>,
+) -> Result<NamespacePid> {
+    let pid = match read_addr(task + offsets.pid_link)? {
+        0 => return Err(VmiError::NullPointer),
+        pid => pid,
+    };
+    let level = read_32(pid + offsets.pid_level)?;
+    if level > MAX_PID_NS_LEVEL {
+        return Err(VmiError::Other(format!(
+            "pid namespace level {} at {:#x}",
+            level, pid
+        )));
+    }
+
+    let nr = upid_nr_addr(
+        pid,
+        offsets.pid_numbers,
+        offsets.upid_stride,
+        offsets.upid_nr,
+        level,
+    );
+    Ok(NamespacePid {
+        level,
+        // pid_t is 32-bit
+        pid: read_32(nr)? as u64,
+    })
+}
+
+/// mm_struct offsets for argv/envp
+pub struct MmOffsets {
+    mm: u64,
+    pgd: u64,
+    arg_start: u64,
+    arg_end: u64,
+    env_start: u64,
+    env_end: u64,
+}
+
+impl MmOffsets {
+    pub fn load(vmi: &Vmi) -> Result<Self> {
+        let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+        Ok(Self {
+            mm: member("task_struct", "mm")?,
+            pgd: member("mm_struct", "pgd")?,
+            arg_start: member("mm_struct", "arg_start")?,
+            arg_end: member("mm_struct", "arg_end")?,
+            env_start: member("mm_struct", "env_start")?,
+            env_end: member("mm_struct", "env_end")?,
+        })
+    }
+}
+
+/// task->mm and its page table root, None for kernel threads
+fn user_mm(vmi: &Vmi, offsets: &MmOffsets, task: u64) -> Result<Option<(u64, u64)>> {
+    let mm = vmi.read_addr_va(task + offsets.mm, 0)?;
+    if mm == 0 {
+        return Ok(None);
+    }
+    // mm->pgd is a kernel VA, cr3 wants its physical address. the kernel
+    // half of a PTI pair still maps user space, so it is enough for reads
+    let pgd = vmi.read_addr_va(mm + offsets.pgd, 0)?;
+    Ok(Some((mm, vmi.translate_kv2p(pgd)?)))
+}
+
+/// NUL separated strings, trailing padding dropped
+pub fn split_nul(bytes: &[u8]) -> Vec<String> {
+    let mut strings: Vec<String> = bytes
+        .split(|&b| b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned())
+        .collect();
+    while strings.last().is_some_and(|s| s.is_empty()) {
+        strings.pop();
+    }
+    strings
+}
+
+fn read_range(
+    vmi: &Vmi,
+    offsets: &MmOffsets,
+    task: u64,
+    start: u64,
+    end: u64,
+) -> Result<Option<Vec<String>>> {
+    let Some((mm, dtb)) = user_mm(vmi, offsets, task)? else {
+        return Ok(None);
+    };
+    let start = vmi.read_addr_va(mm + start, 0)?;
+    let end = vmi.read_addr_va(mm + end, 0)?;
+    if end <= start {
+        return Ok(Some(Vec::new()));
+    }
+    let len = (end - start).min(MAX_ARG_BYTES) as usize;
+    Ok(Some(split_nul(&vmi.read_va_dtb(dtb, start, len)?)))
+}
+
+/// argv from mm->arg_start..arg_end, None for kernel threads
+pub fn read_args(vmi: &Vmi, offsets: &MmOffsets, task: u64) -> Result<Option<Vec<String>>> {
+    read_range(vmi, offsets, task, offsets.arg_start, offsets.arg_end)
+}
+
+/// envp from mm->env_start..env_end as KEY=value strings, None for kernel threads
+pub fn read_environment(vmi: &Vmi, offsets: &MmOffsets, task: u64) -> Result<Option<Vec<String>>> {
+    read_range(vmi, offsets, task, offsets.env_start, offsets.env_end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const TASK: u64 = 0xffff_8880_0100_0000;
+    const PID: u64 = 0xffff_8880_0200_0000;
+
+    /// x86_64 offsets: struct pid { refcount, level@4, ..., numbers@0x60 }
+    fn offsets(upid_stride: u64) -> TaskDetailOffsets {
+        TaskDetailOffsets {
+            cred: 0x728,
+            cred_uid: 4,
+            cred_gid: 8,
+            cred_euid: 0x14,
+            pid_link: 0x518,
+            pid_level: 4,
+            pid_numbers: 0x60,
+            upid_nr: 0,
+            upid_stride,
+        }
+    }
+
+    /// a struct pid at PID with one nr per level, host pid first
+    fn namespace_pid(offsets: &TaskDetailOffsets, nrs: &[u32]) -> Result<NamespacePid> {
+        let mut dwords = HashMap::from([(PID + offsets.pid_level, nrs.len() as u32 - 1)]);
+        for (level, &nr) in nrs.iter().enumerate() {
+            let addr = upid_nr_addr(
+                PID,
+                offsets.pid_numbers,
+                offsets.upid_stride,
+                offsets.upid_nr,
+                level as u32,
+            );
+            dwords.insert(addr, nr);
+        }
+        namespace_pid_with(
+            offsets,
+            TASK,
+            |addr| match addr {
+                a if a == TASK + offsets.pid_link => Ok(PID),
+                _ => Err(VmiError::NullPointer),
+            },
+            |addr| dwords.get(&addr).copied().ok_or(VmiError::NullPointer),
+        )
+    }
+
+    #[test]
+    fn upid_stride_per_layout() {
+        // { int nr; struct pid_namespace *ns; }
+        assert_eq!(upid_stride(8, None, 8), 16);
+        assert_eq!(upid_stride(4, None, 4), 8);
+        // pre-4.15 appends struct hlist_node pid_chain
+        assert_eq!(upid_stride(8, Some(16), 8), 32);
+        assert_eq!(upid_stride(4, Some(8), 4), 16);
+    }
+
+    #[test]
+    fn upid_nr_addr_indexes_numbers() {
+        assert_eq!(upid_nr_addr(PID, 0x60, 16, 0, 0), PID + 0x60);
+        assert_eq!(upid_nr_addr(PID, 0x60, 16, 0, 2), PID + 0x80);
+        assert_eq!(upid_nr_addr(PID, 0x30, 32, 4, 1), PID + 0x54);
+    }
+
+    #[test]
+    fn host_task_is_level_zero() {
+        let ns = namespace_pid(&offsets(16), &[1234]).unwrap();
+        assert_eq!(
+            ns,
+            NamespacePid {
+                level: 0,
+                pid: 1234
+            }
+        );
+    }
+
+    #[test]
+    fn nested_task_reads_the_innermost_pid() {
+        // host 4321, container 57, nested container 1
+        let ns = namespace_pid(&offsets(16), &[4321, 57, 1]).unwrap();
+        assert_eq!(ns, NamespacePid { level: 2, pid: 1 });
+    }
+
+    #[test]
+    fn old_kernel_layout_strides_over_pid_chain() {
+        let ns = namespace_pid(&offsets(upid_stride(8, Some(16), 8)), &[4321, 7]).unwrap();
+        assert_eq!(ns, NamespacePid { level: 1, pid: 7 });
+    }
+
+    #[test]
+    fn implausible_level_is_refused() {
+        let nrs = vec![1; MAX_PID_NS_LEVEL as usize + 2];
+        assert!(matches!(
+            namespace_pid(&offsets(16), &nrs),
+            Err(VmiError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn task_without_pid_is_a_null_pointer() {
+        let offsets = offsets(16);
+        let result = namespace_pid_with(&offsets, TASK, |_| Ok(0), |_| Ok(0));
+        assert!(matches!(result, Err(VmiError::NullPointer)));
+    }
+}
@@ -0,0 +1,122 @@
+//! VMA walking - a task's mapped address ranges, the linux analogue of
+//! the windows VAD walker
+//!
+//! before 6.1 mm_struct.mmap heads a vm_next-linked list of
+//! vm_area_structs in address order. 6.1+ keeps them only in a maple tree
+//! (mm_struct.mm_mt), which isn't decoded yet and is reported as such.
+
+use std::collections::HashSet;
+
+use crate::error::{Result, VmiError};
+use crate::os::linux::actions::list_processes::find_task;
+use crate::os::linux::dentry::DentryOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// vm.max_map_count default is 65530
+pub const MAX_VMAS: usize = 65536;
+
+const VM_READ: u64 = 0x1;
+const VM_WRITE: u64 = 0x2;
+const VM_EXEC: u64 = 0x4;
+const VM_SHARED: u64 = 0x8;
+
+/// one vm_area_struct
+#[derive(Debug, Clone)]
+pub struct VmaRegion {
+    pub start: u64,
+    /// inclusive (vm_end - 1), like VadRegion
+    pub end: u64,
+    /// raw vm_flags
+    pub flags: u64,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+    pub shared: bool,
+    /// backing file for file mappings
+    pub file_name: Option<String>,
+    /// vm_area_struct address
+    pub addr: u64,
+}
+
+/// every VMA of a task in address order, no pausing.
+/// kernel threads (mm == NULL) yield an empty map
+pub fn walk_vmas(vmi: &Vmi, task: u64) -> Result<Vec<VmaRegion>> {
+    let member = |s: &str, f: &str| vmi.get_struct_offset(s, f);
+
+    let mm = vmi.read_addr_va(task + member("task_struct", "mm")?, 0)?;
+    if mm == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mmap = match member("mm_struct", "mmap") {
+        Ok(offset) => offset,
+        Err(e) => {
+            if member("mm_struct", "mm_mt").is_ok() {
+                return Err(VmiError::NotImplemented {
+                    action: "maple tree VMA walk (6.1+ kernels)",
+                    os: "Linux".into(),
+                });
+            }
+            return Err(e);
+        }
+    };
+    let vm_start = member("vm_area_struct", "vm_start")?;
+    let vm_end = member("vm_area_struct", "vm_end")?;
+    let vm_next = member("vm_area_struct", "vm_next")?;
+    let vm_flags = member("vm_area_struct", "vm_flags")?;
+    let vm_file = member("vm_area_struct", "vm_file")?;
+    let f_path = member("file", "f_path")?;
+    let dentries = DentryOffsets::load(vmi)?;
+
+    let mut regions = Vec::new();
+    let mut seen = HashSet::new();
+    let mut vma = vmi.read_addr_va(mm + mmap, 0)?;
+    while vma != 0 && regions.len() < MAX_VMAS && seen.insert(vma) {
+        let start = vmi.read_addr_va(vma + vm_start, 0)?;
+        let end = vmi.read_addr_va(vma + vm_end, 0)?;
+        let flags = vmi.read_addr_va(vma + vm_flags, 0).unwrap_or(0);
+
+        let file_name = vmi
+            .read_addr_va(vma + vm_file, 0)
+            .ok()
+            .filter(|&file| file != 0)
+            .and_then(|file| dentries.path(vmi, file + f_path).ok());
+
+        regions.push(VmaRegion {
+            start,
+            end: end.saturating_sub(1),
+            flags,
+            readable: flags & VM_READ != 0,
+            writable: flags & VM_WRITE != 0,
+            executable: flags & VM_EXEC != 0,
+            shared: flags & VM_SHARED != 0,
+            file_name,
+            addr: vma,
+        });
+
+        vma = vmi.read_addr_va(vma + vm_next, 0).unwrap_or(0);
+    }
+
+    Ok(regions)
+}
+
+/// VMAs of one task by pid
+pub struct ListVmas {
+    pid: u64,
+}
+
+impl ListVmas {
+    pub fn new(pid: u64) -> Self {
+        Self { pid }
+    }
+}
+
+impl Action<Vec<VmaRegion>> for ListVmas {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<VmaRegion>> {
+        vmi.read_consistent(|vmi| {
+            let task = find_task(vmi, self.pid)?;
+            walk_vmas(vmi, task)
+        })
+    }
+}
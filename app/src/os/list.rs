@@ -0,0 +1,327 @@
+//! generic circular list walking (LIST_ENTRY / list_head)
+//!
+//! both windows and linux chain kernel objects through an embedded
+//! forward/back link pair. the walkers here return entry addresses; callers
+//! subtract the link offset to get the containing object. walk_list follows
+//! the forward link only, walk_list_checked walks both ways and reports
+//! link pairs that don't agree, which is what DKOM unlinking leaves behind.
+
+use std::collections::HashSet;
+
+use crate::error::Result;
+use crate::vmi::Vmi;
+
+/// hard cap on entries visited, guards against corrupted lists
+pub const MAX_LIST_ENTRIES: usize = 10000;
+
+/// result of a list walk that may have been cut short
+#[derive(Debug, Clone, Default)]
+pub struct ListWalk {
+    pub entries: Vec<u64>,
+    /// link address whose read failed mid-walk, entries before it are kept
+    pub failed_at: Option<u64>,
+}
+
+impl ListWalk {
+    pub fn is_truncated(&self) -> bool {
+        self.failed_at.is_some()
+    }
+}
+
+/// walk a circular list starting at `head` via the forward link at offset 0.
+///
+/// returns the address of every entry except `head` itself. the walk stops
+/// when it gets back to `head`, hits a null link, revisits an entry (cycle
+/// that doesn't pass through head) or reaches `MAX_LIST_ENTRIES`.
+/// a failed read mid-walk quietly keeps what was gathered, use
+/// `walk_list_partial` to tell that case apart.
+pub fn walk_list(vmi: &Vmi, head: u64, pid: u32) -> Result<Vec<u64>> {
+    Ok(walk_list_partial(vmi, head, pid)?.entries)
+}
+
+/// like `walk_list`, but reports where a mid-walk read failed.
+/// only a failed read of `head` itself is an error
+pub fn walk_list_partial(vmi: &Vmi, head: u64, pid: u32) -> Result<ListWalk> {
+    walk_links(vmi, head, pid, 0)
+}
+
+/// walk following the link at `link` within each entry (0 forward, one
+/// pointer width back)
+fn walk_links(vmi: &Vmi, head: u64, pid: u32, link: u64) -> Result<ListWalk> {
+    walk_with(head, link, |addr| vmi.read_addr_va(addr, pid))
+}
+
+/// the walk itself, pointers read through `read_addr`
+pub fn walk_with(
+    head: u64,
+    link: u64,
+    mut read_addr: impl FnMut(u64) -> Result<u64>,
+) -> Result<ListWalk> {
+    let mut walk = ListWalk::default();
+    let mut seen = HashSet::new();
+
+    let mut cur = read_addr(head + link)?;
+    while cur != head && walk.entries.len() < MAX_LIST_ENTRIES {
+        // null link or cycle - list is corrupted, keep what we have
+        if cur == 0 || !seen.insert(cur) {
+            break;
+        }
+        walk.entries.push(cur);
+        cur = match read_addr(cur + link) {
+            Ok(next) => next,
+            Err(_) => {
+                walk.failed_at = Some(cur);
+                break;
+            }
+        };
+    }
+
+    Ok(walk)
+}
+
+/// which half of a link pair failed to point back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkDirection {
+    /// entry->Flink->Blink != entry
+    Forward,
+    /// entry->Blink->Flink != entry
+    Backward,
+}
+
+/// an entry whose neighbour doesn't link back to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkMismatch {
+    pub entry: u64,
+    pub direction: LinkDirection,
+    /// the neighbour the entry points at
+    pub neighbour: u64,
+    /// what the neighbour points back at instead of `entry`, None if unreadable
+    pub back: Option<u64>,
+}
+
+/// result of walking a list both ways
+#[derive(Debug, Clone, Default)]
+pub struct CheckedWalk {
+    /// entries in Flink order
+    pub forward: ListWalk,
+    /// entries in Blink order
+    pub backward: ListWalk,
+    pub mismatches: Vec<LinkMismatch>,
+}
+
+impl CheckedWalk {
+    /// every entry reached in either direction, Flink order first
+    pub fn entries(&self) -> Vec<u64> {
+        let mut seen: HashSet<u64> = self.forward.entries.iter().copied().collect();
+        let mut entries = self.forward.entries.clone();
+        for &entry in self.backward.entries.iter().rev() {
+            if seen.insert(entry) {
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    /// entries only reachable through Blink, e.g. unlinked from the Flink chain
+    pub fn backward_only(&self) -> Vec<u64> {
+        let forward: HashSet<u64> = self.forward.entries.iter().copied().collect();
+        self.backward
+            .entries
+            .iter()
+            .copied()
+            .filter(|e| !forward.contains(e))
+            .collect()
+    }
+
+    /// entries only reachable through Flink
+    pub fn forward_only(&self) -> Vec<u64> {
+        let backward: HashSet<u64> = self.backward.entries.iter().copied().collect();
+        self.forward
+            .entries
+            .iter()
+            .copied()
+            .filter(|e| !backward.contains(e))
+            .collect()
+    }
+
+    /// both walks agree and every link pair points back
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+            && !self.forward.is_truncated()
+            && !self.backward.is_truncated()
+            && self.backward_only().is_empty()
+            && self.forward_only().is_empty()
+    }
+}
+
+/// walk a circular list via Flink and via Blink, checking that every
+/// entry's neighbours link back to it (head included). the back link sits
+/// one pointer after the forward link, as in LIST_ENTRY and list_head
+pub fn walk_list_checked(vmi: &Vmi, head: u64, pid: u32) -> Result<CheckedWalk> {
+    walk_checked_with(head, vmi.address_width() as u64, |addr| {
+        vmi.read_addr_va(addr, pid)
+    })
+}
+
+/// walk_list_checked with the back link at `back` and pointers read
+/// through `read_addr`
+pub fn walk_checked_with(
+    head: u64,
+    back: u64,
+    mut read_addr: impl FnMut(u64) -> Result<u64>,
+) -> Result<CheckedWalk> {
+    let forward = walk_with(head, 0, &mut read_addr)?;
+    let backward = walk_with(head, back, &mut read_addr)?;
+
+    let mut entries = vec![head];
+    entries.extend(&forward.entries);
+    let mut seen: HashSet<u64> = entries.iter().copied().collect();
+    entries.extend(backward.entries.iter().filter(|&&e| seen.insert(e)));
+
+    let mut mismatches = Vec::new();
+    for &entry in &entries {
+        for (direction, out, into) in [
+            (LinkDirection::Forward, 0, back),
+            (LinkDirection::Backward, back, 0),
+        ] {
+            let Ok(neighbour) = read_addr(entry + out) else {
+                continue;
+            };
+            if neighbour == 0 {
+                continue;
+            }
+            let back_link = read_addr(neighbour + into).ok();
+            if back_link != Some(entry) {
+                mismatches.push(LinkMismatch {
+                    entry,
+                    direction,
+                    neighbour,
+                    back: back_link,
+                });
+            }
+        }
+    }
+
+    Ok(CheckedWalk {
+        forward,
+        backward,
+        mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::VmiError;
+    use std::collections::HashMap;
+
+    const HEAD: u64 = 0x1000;
+
+    /// Flink at +0, Blink at +8, for a ring through HEAD and `entries`
+    fn ring(entries: &[u64]) -> HashMap<u64, u64> {
+        let mut nodes = vec![HEAD];
+        nodes.extend(entries);
+        let mut memory = HashMap::new();
+        for (i, &node) in nodes.iter().enumerate() {
+            let next = nodes[(i + 1) % nodes.len()];
+            let prev = nodes[(i + nodes.len() - 1) % nodes.len()];
+            memory.insert(node, next);
+            memory.insert(node + 8, prev);
+        }
+        memory
+    }
+
+    fn reader(memory: &HashMap<u64, u64>) -> impl FnMut(u64) -> Result<u64> + '_ {
+        |addr| {
+            memory.get(&addr).copied().ok_or(VmiError::ReadFailed {
+                addr,
+                msg: "unmapped".into(),
+            })
+        }
+    }
+
+    /// fails the `n`th read, counting from 1
+    fn failing_at(memory: &HashMap<u64, u64>, n: usize) -> impl FnMut(u64) -> Result<u64> + '_ {
+        let mut reads = 0;
+        let mut read = reader(memory);
+        move |addr| {
+            reads += 1;
+            if reads == n {
+                Err(VmiError::ReadFailed {
+                    addr,
+                    msg: "injected".into(),
+                })
+            } else {
+                read(addr)
+            }
+        }
+    }
+
+    #[test]
+    fn walks_the_whole_ring() {
+        let entries = [0x2000, 0x3000, 0x4000];
+        let walk = walk_with(HEAD, 0, reader(&ring(&entries))).unwrap();
+        assert_eq!(walk.entries, entries);
+        assert!(!walk.is_truncated());
+
+        let back = walk_with(HEAD, 8, reader(&ring(&entries))).unwrap();
+        assert_eq!(back.entries, [0x4000, 0x3000, 0x2000]);
+    }
+
+    #[test]
+    fn failed_read_keeps_entries_before_it() {
+        let entries = [0x2000, 0x3000, 0x4000, 0x5000];
+        let memory = ring(&entries);
+        for n in 2..=entries.len() {
+            let walk = walk_with(HEAD, 0, failing_at(&memory, n)).unwrap();
+            assert!(walk.is_truncated());
+            assert_eq!(walk.entries, entries[..n - 1]);
+            assert_eq!(walk.failed_at, Some(entries[n - 2]));
+        }
+    }
+
+    #[test]
+    fn failed_head_read_is_an_error() {
+        let memory = ring(&[0x2000]);
+        assert!(walk_with(HEAD, 0, failing_at(&memory, 1)).is_err());
+    }
+
+    #[test]
+    fn stops_at_null_link_and_cycles() {
+        let mut memory = ring(&[0x2000, 0x3000]);
+        memory.insert(0x3000, 0);
+        let walk = walk_with(HEAD, 0, reader(&memory)).unwrap();
+        assert_eq!(walk.entries, [0x2000, 0x3000]);
+        assert!(!walk.is_truncated());
+
+        // 0x3000 -> 0x2000, a loop that never returns to HEAD
+        memory.insert(0x3000, 0x2000);
+        let walk = walk_with(HEAD, 0, reader(&memory)).unwrap();
+        assert_eq!(walk.entries, [0x2000, 0x3000]);
+    }
+
+    #[test]
+    fn consistent_ring_checks_clean() {
+        let memory = ring(&[0x2000, 0x3000, 0x4000]);
+        let checked = walk_checked_with(HEAD, 8, reader(&memory)).unwrap();
+        assert!(checked.is_consistent());
+        assert_eq!(checked.entries(), [0x2000, 0x3000, 0x4000]);
+    }
+
+    #[test]
+    fn unlinked_flink_shows_up_backward_only() {
+        // DKOM: 0x2000 skips 0x3000 going forward, Blinks left alone
+        let mut memory = ring(&[0x2000, 0x3000, 0x4000]);
+        memory.insert(0x2000, 0x4000);
+        let checked = walk_checked_with(HEAD, 8, reader(&memory)).unwrap();
+        assert!(!checked.is_consistent());
+        assert_eq!(checked.backward_only(), [0x3000]);
+        assert_eq!(checked.entries(), [0x2000, 0x4000, 0x3000]);
+        assert!(checked.mismatches.contains(&LinkMismatch {
+            entry: 0x2000,
+            direction: LinkDirection::Forward,
+            neighbour: 0x4000,
+            back: Some(0x3000),
+        }));
+    }
+}
@@ -1,21 +1,151 @@
+pub mod linux;
+pub mod list;
 pub mod windows;
 
-#[derive(Debug, Clone)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ProcessInfo {
-    pub pid: i32,
+    /// UniqueProcessId is a HANDLE, so keep the full pointer width
+    pub pid: u64,
     pub name: String,
     pub addr: u64,
+    /// only filled in by detailed listings, None when unreadable
+    pub command_line: Option<String>,
+    /// untruncated image path, detailed listings only.
+    /// None for System and other processes without an image
+    pub full_image_path: Option<String>,
+    /// linux task credentials, detailed listings only
+    pub credentials: Option<Credentials>,
+    /// linux pid namespace view, detailed listings only
+    pub namespace_pid: Option<NamespacePid>,
+    /// threads in the group, linux detailed listings only
+    pub thread_count: Option<usize>,
+    /// EPROCESS.CreateTime in FILETIME ticks, windows detailed listings only
+    pub create_time: Option<u64>,
+}
+
+/// real and effective ids from a linux task's cred
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+}
+
+/// a task's pid in the innermost pid namespace it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NamespacePid {
+    /// nesting depth, 0 is the host's init_pid_ns
+    pub level: u32,
+    /// pid as seen inside that namespace, equals the host pid at level 0
+    pub pid: u64,
+}
+
+impl NamespacePid {
+    /// task lives in a nested pid namespace, e.g. a container
+    pub fn is_nested(&self) -> bool {
+        self.level > 0
+    }
+}
+
+/// how the start of a process list walk was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListSource {
+    /// PsActiveProcessHead symbol
+    ActiveProcessHead,
+    /// System EPROCESS via PsInitialSystemProcess
+    InitialSystemProcess,
+    /// current process on vcpu 0 via KPCR.Prcb.CurrentThread
+    Kpcr,
+    /// linux init_task.tasks
+    InitTask,
 }
 
-use crate::error::Result;
+/// process list plus how it was obtained, useful to judge profile quality
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessList {
+    pub processes: Vec<ProcessInfo>,
+    pub source: ListSource,
+    /// a link read failed mid-walk, `processes` is what came before it
+    pub truncated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    /// full path on disk, where the OS keeps one
+    pub path: Option<String>,
+}
+
+use crate::dispatcher::EventDispatcher;
+use crate::error::{Result, VmiError};
+use crate::event_stream::EventSink;
 use crate::hook::HookManager;
-use crate::vmi::Vmi;
+use crate::vmi::{OsType, Vmi};
+use linux::{LinuxGuest, LinuxOs};
+use std::ops::ControlFlow;
 use std::sync::{Arc, Mutex};
+use windows::{WindowsGuest, WindowsOs};
 
 /// context passed to events for enabling/disabling
 pub struct EventContext<'a> {
     pub vmi: &'a Arc<Mutex<Vmi>>,
     pub hooks: &'a Arc<HookManager>,
+    pub dispatcher: &'a Arc<EventDispatcher>,
+    /// where monitors report what they see
+    pub events: &'a EventSink,
+    /// the session's OS knowledge, see windows() and linux()
+    pub os: &'a GuestOs,
+}
+
+impl EventContext<'_> {
+    /// shared windows offsets and version, NotImplemented on other guests
+    pub fn windows(&self) -> Result<&WindowsGuest> {
+        match self.os {
+            GuestOs::Windows(guest) => Ok(guest),
+            os => Err(not_implemented("windows events", os.os_type())),
+        }
+    }
+
+    /// shared linux offsets, NotImplemented on other guests
+    pub fn linux(&self) -> Result<&LinuxGuest> {
+        match self.os {
+            GuestOs::Linux(guest) => Ok(guest),
+            os => Err(not_implemented("linux events", os.os_type())),
+        }
+    }
+}
+
+/// what the session knows about the guest OS. unlike OsHandle it doesn't
+/// own the vmi, so events can share it
+#[derive(Debug, Clone)]
+pub enum GuestOs {
+    Windows(WindowsGuest),
+    Linux(LinuxGuest),
+    /// no OS support beyond raw memory access
+    Other(OsType),
+}
+
+impl GuestOs {
+    /// resolve offsets for the detected OS, done once per session
+    pub fn load(vmi: &Vmi) -> Self {
+        match vmi.os_type() {
+            OsType::Windows => GuestOs::Windows(WindowsGuest::load(vmi)),
+            OsType::Linux => GuestOs::Linux(LinuxGuest::load(vmi)),
+            os => GuestOs::Other(os),
+        }
+    }
+
+    pub fn os_type(&self) -> OsType {
+        match self {
+            GuestOs::Windows(_) => OsType::Windows,
+            GuestOs::Linux(_) => OsType::Linux,
+            GuestOs::Other(os) => *os,
+        }
+    }
 }
 
 /// trait for actions that perform a specific operation (e.g. list processes)
@@ -23,11 +153,24 @@ pub trait Action<T> {
     fn execute(&self, vmi: &Vmi) -> Result<T>;
 }
 
+/// an action done in pieces, see Session::execute_chunked. events are
+/// dispatched between chunks, and the guest runs there too, so state kept
+/// across chunks can go stale
+pub trait ChunkedAction<T> {
+    /// do the next piece, Break with the result after the last one
+    fn execute_chunk(&mut self, vmi: &Vmi) -> Result<ControlFlow<T>>;
+}
+
 /// trait for events that can be enabled/disabled (e.g. process monitoring)
 /// implementations handle cleanup on Drop.
 pub trait Event: Send {
     fn enable(&mut self, ctx: &EventContext) -> Result<()>;
     fn disable(&mut self, ctx: &EventContext) -> Result<()>;
+
+    /// shown when listing a session's events
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
 }
 
 /// trait for OS abstractions
@@ -48,3 +191,345 @@ pub trait Os {
         ))
     }
 }
+
+/// the Os implementation for a guest, picked from its OsType
+pub enum OsHandle {
+    Windows(WindowsOs),
+    Linux(LinuxOs),
+}
+
+/// an Os constructor's refusal, with the vmi handed back unharmed. boxed,
+/// a Vmi is too big to move around in every Err
+pub type Rejected = (VmiError, Box<Vmi>);
+
+/// wrap a vmi in the Os implementation matching the detected guest.
+/// an unsupported guest gives the vmi back with the error
+pub fn for_vmi(vmi: Vmi) -> std::result::Result<OsHandle, Rejected> {
+    match vmi.os_type() {
+        OsType::Windows => WindowsOs::try_new(vmi).map(OsHandle::Windows),
+        OsType::Linux => LinuxOs::try_new(vmi).map(OsHandle::Linux),
+        os => Err((
+            VmiError::Other(format!("unsupported OS {:?}", os)),
+            Box::new(vmi),
+        )),
+    }
+}
+
+/// a feature the session can offer, availability depends on the guest OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    ListProcesses,
+    /// command lines and the OS specific extras of list_processes(true)
+    ProcessDetails,
+    ListModules,
+    ProcessTree,
+    ProcessCreateMonitor,
+    /// environment dump on process creation
+    EnvironmentMonitor,
+    ListThreads,
+}
+
+impl Capability {
+    pub const ALL: &'static [Capability] = &[
+        Capability::ListProcesses,
+        Capability::ProcessDetails,
+        Capability::ListModules,
+        Capability::ProcessTree,
+        Capability::ProcessCreateMonitor,
+        Capability::EnvironmentMonitor,
+        Capability::ListThreads,
+    ];
+
+    /// human readable name, used in "not implemented" errors
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::ListProcesses => "process listing",
+            Capability::ProcessDetails => "process details",
+            Capability::ListModules => "module listing",
+            Capability::ProcessTree => "process tree",
+            Capability::ProcessCreateMonitor => "process monitor",
+            Capability::EnvironmentMonitor => "environment monitoring",
+            Capability::ListThreads => "thread listing",
+        }
+    }
+}
+
+/// features implemented for a guest OS
+pub fn capabilities(os: OsType) -> Vec<Capability> {
+    match os {
+        OsType::Windows => Capability::ALL.to_vec(),
+        OsType::Linux => vec![
+            Capability::ListProcesses,
+            Capability::ProcessDetails,
+            Capability::ListModules,
+            Capability::ProcessCreateMonitor,
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// a monitor the session can enable, by its `--events` name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MonitorKind {
+    ProcessCreate,
+}
+
+impl MonitorKind {
+    pub const ALL: &'static [MonitorKind] = &[MonitorKind::ProcessCreate];
+
+    /// name on the command line and in errors
+    pub fn name(self) -> &'static str {
+        match self {
+            MonitorKind::ProcessCreate => "process-create",
+        }
+    }
+
+    /// what the guest OS needs to offer for this monitor
+    pub fn capability(self) -> Capability {
+        match self {
+            MonitorKind::ProcessCreate => Capability::ProcessCreateMonitor,
+        }
+    }
+
+    /// monitors implemented for a guest OS
+    pub fn supported(os: OsType) -> Vec<MonitorKind> {
+        let caps = capabilities(os);
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(|kind| caps.contains(&kind.capability()))
+            .collect()
+    }
+}
+
+impl std::str::FromStr for MonitorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| format!("unknown event {}, expected {}", s, monitor_names(Self::ALL)))
+    }
+}
+
+/// which monitors to enable, `all` meaning whatever the guest supports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MonitorSelection {
+    All,
+    Only(Vec<MonitorKind>),
+}
+
+impl MonitorSelection {
+    /// comma separated names or `all`, e.g. "process-create"
+    pub fn parse(s: &str) -> std::result::Result<Self, String> {
+        let mut kinds = Vec::new();
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name == "all" {
+                return Ok(MonitorSelection::All);
+            }
+            let kind = name.parse()?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        if kinds.is_empty() {
+            return Err("no events selected".into());
+        }
+        Ok(MonitorSelection::Only(kinds))
+    }
+
+    /// the monitors to enable on `os`, failing before anything is enabled
+    /// if one of them isn't implemented there
+    pub fn resolve(&self, os: OsType) -> Result<Vec<MonitorKind>> {
+        let supported = MonitorKind::supported(os);
+        let kinds = match self {
+            MonitorSelection::All => supported.clone(),
+            MonitorSelection::Only(kinds) => kinds.clone(),
+        };
+        let missing: Vec<_> = kinds
+            .iter()
+            .copied()
+            .filter(|kind| !supported.contains(kind))
+            .collect();
+        if !missing.is_empty() {
+            return Err(VmiError::Other(format!(
+                "{} not supported for {:?} guests, supported: {}",
+                monitor_names(&missing),
+                os,
+                monitor_names(&supported)
+            )));
+        }
+        if kinds.is_empty() {
+            return Err(not_implemented("event monitoring", os));
+        }
+        Ok(kinds)
+    }
+}
+
+impl Default for MonitorSelection {
+    fn default() -> Self {
+        MonitorSelection::Only(vec![MonitorKind::ProcessCreate])
+    }
+}
+
+fn monitor_names(kinds: &[MonitorKind]) -> String {
+    if kinds.is_empty() {
+        return "none".into();
+    }
+    kinds
+        .iter()
+        .map(|kind| kind.name())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// error for an action that exists for another OS but not this one
+pub fn not_implemented(action: &'static str, os: OsType) -> VmiError {
+    VmiError::NotImplemented {
+        action,
+        os: format!("{:?}", os),
+    }
+}
+
+/// OsMismatch unless the detected guest is `expected`
+pub(crate) fn expect_os(found: OsType, expected: OsType) -> Result<()> {
+    if found != expected {
+        return Err(VmiError::OsMismatch {
+            expected: format!("{:?}", expected),
+            found: format!("{:?}", found),
+        });
+    }
+    Ok(())
+}
+
+impl OsHandle {
+    pub fn vmi(&self) -> &Vmi {
+        match self {
+            OsHandle::Windows(os) => os.vmi(),
+            OsHandle::Linux(os) => os.vmi(),
+        }
+    }
+
+    pub fn os_type(&self) -> OsType {
+        match self {
+            OsHandle::Windows(_) => OsType::Windows,
+            OsHandle::Linux(_) => OsType::Linux,
+        }
+    }
+
+    pub fn capabilities(&self) -> Vec<Capability> {
+        capabilities(self.os_type())
+    }
+
+    pub fn list_processes(&self) -> Result<ProcessList> {
+        match self {
+            OsHandle::Windows(os) => os.list_processes(),
+            OsHandle::Linux(os) => os.list_processes(),
+        }
+    }
+
+    pub fn list_modules(&self) -> Result<Vec<ModuleInfo>> {
+        match self {
+            OsHandle::Windows(os) => os.list_modules(),
+            OsHandle::Linux(os) => os.list_modules(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_keeps_the_full_handle_width() {
+        // UniqueProcessId is a HANDLE; past 32 bits it used to be truncated
+        let info = ProcessInfo {
+            pid: 0x1_0000_0a24,
+            name: "x.exe".into(),
+            addr: 0xffff_b08e_4a3c_2080,
+            command_line: None,
+            full_image_path: None,
+            credentials: None,
+            namespace_pid: None,
+            thread_count: None,
+            create_time: None,
+        };
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(json.contains("\"pid\":4294969892"));
+        assert_eq!(serde_json::from_str::<ProcessInfo>(&json).unwrap(), info);
+    }
+
+    #[test]
+    fn expect_os_names_both_sides() {
+        assert!(expect_os(OsType::Windows, OsType::Windows).is_ok());
+        match expect_os(OsType::Linux, OsType::Windows) {
+            Err(VmiError::OsMismatch { expected, found }) => {
+                assert_eq!(expected, "Windows");
+                assert_eq!(found, "Linux");
+            }
+            other => panic!("expected OsMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn monitor_names_round_trip() {
+        for &kind in MonitorKind::ALL {
+            assert_eq!(kind.name().parse::<MonitorKind>(), Ok(kind));
+        }
+        let err = "process-exit".parse::<MonitorKind>().unwrap_err();
+        assert!(err.contains("process-create"), "{}", err);
+    }
+
+    #[test]
+    fn events_parse() {
+        assert_eq!(MonitorSelection::parse("all"), Ok(MonitorSelection::All));
+        assert_eq!(
+            MonitorSelection::parse(" process-create , process-create,"),
+            Ok(MonitorSelection::Only(vec![MonitorKind::ProcessCreate]))
+        );
+        // all wins wherever it appears
+        assert_eq!(
+            MonitorSelection::parse("process-create,all"),
+            Ok(MonitorSelection::All)
+        );
+        assert!(MonitorSelection::parse("").is_err());
+        assert!(MonitorSelection::parse(" , ").is_err());
+        assert!(MonitorSelection::parse("process-create,bogus").is_err());
+        assert_eq!(
+            MonitorSelection::default(),
+            MonitorSelection::Only(vec![MonitorKind::ProcessCreate])
+        );
+    }
+
+    #[test]
+    fn events_resolve_per_guest() {
+        for os in [OsType::Windows, OsType::Linux] {
+            assert_eq!(
+                MonitorSelection::All.resolve(os).unwrap(),
+                [MonitorKind::ProcessCreate]
+            );
+            assert_eq!(
+                MonitorSelection::default().resolve(os).unwrap(),
+                [MonitorKind::ProcessCreate]
+            );
+        }
+    }
+
+    #[test]
+    fn events_resolve_fails_before_enabling_anything() {
+        // nothing implemented for an unknown guest
+        assert!(matches!(
+            MonitorSelection::All.resolve(OsType::Unknown),
+            Err(VmiError::NotImplemented { .. })
+        ));
+        match MonitorSelection::default().resolve(OsType::FreeBSD) {
+            Err(VmiError::Other(msg)) => {
+                assert!(msg.contains("process-create"), "{}", msg);
+                assert!(msg.contains("supported: none"), "{}", msg);
+            }
+            other => panic!("expected an unsupported error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! cross-view hidden process detection
+//!
+//! DKOM rootkits unlink their EPROCESS from ActiveProcessLinks but have to
+//! leave the PspCidTable entry, or the process can't be opened by pid.
+//! both views are taken in one pause and compared by EPROCESS address.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::os::windows::actions::list_processes::list_processes_impl;
+use crate::os::windows::cid_table;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// an enumeration of processes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessView {
+    /// PsActiveProcessHead / ActiveProcessLinks
+    ActiveList,
+    /// PspCidTable
+    CidTable,
+}
+
+/// a process seen by one view but not the other
+#[derive(Debug, Clone)]
+pub struct HiddenProcess {
+    pub eprocess: u64,
+    pub pid: u64,
+    pub name: String,
+    /// the view that doesn't have it
+    pub missing_from: ProcessView,
+}
+
+/// both views and their difference
+#[derive(Debug, Clone, Default)]
+pub struct HiddenProcessReport {
+    pub findings: Vec<HiddenProcess>,
+    pub active_list: usize,
+    pub cid_table: usize,
+    /// the active list walk broke early, CidTable-only findings past the
+    /// break are not evidence of unlinking
+    pub truncated: bool,
+}
+
+impl HiddenProcessReport {
+    /// processes unlinked from the active list, the usual DKOM signature
+    pub fn unlinked(&self) -> impl Iterator<Item = &HiddenProcess> {
+        self.findings
+            .iter()
+            .filter(|f| f.missing_from == ProcessView::ActiveList)
+    }
+}
+
+/// compares ActiveProcessLinks against PspCidTable
+pub struct DetectHiddenProcesses {
+    offsets: Arc<WindowsOffsets>,
+}
+
+impl DetectHiddenProcesses {
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self { offsets }
+    }
+
+    fn read_name(&self, vmi: &Vmi, eprocess: u64) -> String {
+        self.offsets
+            .pname
+            .get()
+            .and_then(|o| vmi.read_str_va(eprocess + o, 0))
+            .unwrap_or_else(|_| "<unknown>".into())
+    }
+}
+
+impl Action<HiddenProcessReport> for DetectHiddenProcesses {
+    fn execute(&self, vmi: &Vmi) -> Result<HiddenProcessReport> {
+        vmi.read_consistent(|vmi| {
+            let list = list_processes_impl(vmi, &self.offsets)?;
+            let cid = cid_table::enumerate(vmi, &self.offsets)?;
+
+            let listed: HashSet<u64> = list.processes.iter().map(|p| p.addr).collect();
+            let in_cid: HashSet<u64> = cid.processes.iter().map(|e| e.object).collect();
+
+            let mut findings = Vec::new();
+            for entry in &cid.processes {
+                if !listed.contains(&entry.object) {
+                    findings.push(HiddenProcess {
+                        eprocess: entry.object,
+                        pid: entry.cid,
+                        name: self.read_name(vmi, entry.object),
+                        missing_from: ProcessView::ActiveList,
+                    });
+                }
+            }
+            for p in &list.processes {
+                // pid 0 is the idle process, which never has a cid
+                if p.pid != 0 && !in_cid.contains(&p.addr) {
+                    findings.push(HiddenProcess {
+                        eprocess: p.addr,
+                        pid: p.pid,
+                        name: p.name.clone(),
+                        missing_from: ProcessView::CidTable,
+                    });
+                }
+            }
+
+            Ok(HiddenProcessReport {
+                findings,
+                active_list: list.processes.len(),
+                cid_table: cid.processes.len(),
+                truncated: list.truncated,
+            })
+        })
+    }
+}
@@ -0,0 +1,220 @@
+//! cross-process ntdll inline hook detection
+//!
+//! ntdll is one shared image mapping, so an unpatched export is backed by
+//! the same physical page in every process. a patched one has been copied
+//! on write, so comparing physical addresses finds candidates cheaply and
+//! only those get their bytes compared against the reference.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::disasm::{self, Bitness, Detour};
+use crate::error::{Result, VmiError};
+use crate::os::windows::actions::list_processes::list_processes_impl;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::windows::pe;
+use crate::os::windows::user_hook::process_modules;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// bytes compared at the start of each export
+pub const COMPARE_LEN: usize = 32;
+/// instructions shown for a patched prologue
+const DISASM_LINES: usize = 4;
+
+/// an export whose prologue differs from the reference
+#[derive(Debug, Clone)]
+pub struct UserHookFinding {
+    pub pid: u64,
+    pub process: String,
+    pub export: String,
+    pub addr: u64,
+    /// reference bytes
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+    /// index of the first differing byte
+    pub first_diff: usize,
+    /// where the patched code jumps, if it matches a known detour pattern
+    pub detour: Option<u64>,
+    pub disassembly: Vec<String>,
+}
+
+/// ntdll mapping of one process
+struct Target {
+    pid: u64,
+    name: String,
+    dtb: u64,
+    base: u64,
+}
+
+/// compares ntdll export prologues across processes
+pub struct DetectUserHooks {
+    offsets: Arc<WindowsOffsets>,
+    module: String,
+    reference: Option<u64>,
+}
+
+impl DetectUserHooks {
+    /// majority vote across all processes decides the expected bytes
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self {
+            offsets,
+            module: "ntdll.dll".into(),
+            reference: None,
+        }
+    }
+
+    /// trust one process's ntdll instead of voting
+    pub fn reference(mut self, pid: u64) -> Self {
+        self.reference = Some(pid);
+        self
+    }
+
+    /// check a module other than ntdll.dll
+    pub fn module(mut self, name: &str) -> Self {
+        self.module = name.to_string();
+        self
+    }
+}
+
+impl Action<Vec<UserHookFinding>> for DetectUserHooks {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<UserHookFinding>> {
+        vmi.read_consistent(|vmi| self.execute_impl(vmi))
+    }
+}
+
+impl DetectUserHooks {
+    fn targets(&self, vmi: &Vmi) -> Result<Vec<Target>> {
+        let dtb_offset = self.offsets.dtb.get()?;
+        let mut targets = Vec::new();
+
+        for p in list_processes_impl(vmi, &self.offsets)?.processes {
+            // System and minimal processes have no PEB, skip them quietly
+            let Ok(modules) = process_modules(vmi, &self.offsets, p.addr, p.pid as u32) else {
+                continue;
+            };
+            let Some(module) = modules
+                .iter()
+                .find(|m| m.name.eq_ignore_ascii_case(&self.module))
+            else {
+                continue;
+            };
+            let Ok(dtb) = vmi.read_addr_va(p.addr + dtb_offset, 0) else {
+                continue;
+            };
+            targets.push(Target {
+                pid: p.pid,
+                name: p.name,
+                dtb,
+                base: module.base,
+            });
+        }
+
+        Ok(targets)
+    }
+
+    fn execute_impl(&self, vmi: &Vmi) -> Result<Vec<UserHookFinding>> {
+        let targets = self.targets(vmi)?;
+        let source = match self.reference {
+            Some(pid) => targets.iter().position(|t| t.pid == pid).ok_or_else(|| {
+                VmiError::Other(format!("pid {} has no {} mapped", pid, self.module))
+            })?,
+            None if targets.is_empty() => return Ok(Vec::new()),
+            None => 0,
+        };
+
+        // export list as RVAs, bases can differ for WoW64 or odd mappings
+        let src = &targets[source];
+        let exports: Vec<(String, u64)> = pe::exports(vmi, src.dtb, src.base)?
+            .into_iter()
+            .filter(|&(_, addr)| {
+                // data exports legitimately differ between processes
+                vmi.pagetable_lookup_extended(src.dtb, addr)
+                    .is_ok_and(|page| page.executable)
+            })
+            .map(|(name, addr)| (name, addr - src.base))
+            .collect();
+
+        let bitness = Bitness::from_address_width(vmi.address_width());
+        let mut findings = Vec::new();
+
+        for (export, rva) in &exports {
+            let physical: Vec<Option<u64>> = targets
+                .iter()
+                .map(|t| vmi.translate_uv2p(t.dtb, t.base + rva).ok())
+                .collect();
+
+            // the expected frame: the reference's, or the most shared one
+            let expected_pa = match self.reference {
+                Some(_) => physical[source],
+                None => majority(&physical),
+            };
+            let Some(expected_pa) = expected_pa else {
+                continue;
+            };
+            let Some(owner) = physical.iter().position(|&pa| pa == Some(expected_pa)) else {
+                continue;
+            };
+            let owner = &targets[owner];
+            let Ok(expected) = vmi.read_va_dtb(owner.dtb, owner.base + rva, COMPARE_LEN) else {
+                continue;
+            };
+
+            for (target, pa) in targets.iter().zip(&physical) {
+                // fast path: same frame, same bytes
+                if *pa == Some(expected_pa) || pa.is_none() {
+                    continue;
+                }
+
+                let addr = target.base + rva;
+                let Ok(actual) = vmi.read_va_dtb(target.dtb, addr, COMPARE_LEN) else {
+                    continue;
+                };
+                let Some(first_diff) = expected.iter().zip(&actual).position(|(a, b)| a != b)
+                else {
+                    continue;
+                };
+
+                let detour = disasm::find_detour(&actual, addr, bitness).and_then(|d| match d {
+                    Detour::Direct(dest) => Some(dest),
+                    Detour::Indirect { slot } => vmi
+                        .read_va_dtb(target.dtb, slot, vmi.address_width() as usize)
+                        .ok()
+                        .map(|bytes| {
+                            let mut buf = [0u8; 8];
+                            buf[..bytes.len()].copy_from_slice(&bytes);
+                            u64::from_le_bytes(buf)
+                        }),
+                });
+
+                findings.push(UserHookFinding {
+                    pid: target.pid,
+                    process: target.name.clone(),
+                    export: export.clone(),
+                    addr,
+                    expected: expected.clone(),
+                    disassembly: disasm::disassemble(&actual, addr, bitness, DISASM_LINES),
+                    actual,
+                    first_diff,
+                    detour,
+                });
+            }
+        }
+
+        Ok(findings)
+    }
+}
+
+/// most common physical address, ties go to the first seen
+fn majority(physical: &[Option<u64>]) -> Option<u64> {
+    let mut counts: HashMap<u64, (usize, usize)> = HashMap::new();
+    for (i, pa) in physical.iter().enumerate() {
+        if let Some(pa) = pa {
+            counts.entry(*pa).or_insert((0, i)).0 += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .max_by(|(_, (a, ai)), (_, (b, bi))| a.cmp(b).then(bi.cmp(ai)))
+        .map(|(pa, _)| pa)
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::os::list::walk_list;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::{Action, ModuleInfo};
+use crate::vmi::Vmi;
+
+/// walks PsLoadedModuleList (_LDR_DATA_TABLE_ENTRY via InLoadOrderLinks)
+pub struct ListModules {
+    offsets: Arc<WindowsOffsets>,
+}
+
+impl ListModules {
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self { offsets }
+    }
+}
+
+impl Action<Vec<ModuleInfo>> for ListModules {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<ModuleInfo>> {
+        vmi.read_consistent(|vmi| list_modules_impl(vmi, &self.offsets))
+    }
+}
+
+/// loaded kernel modules, no pausing
+pub fn list_modules_impl(vmi: &Vmi, offsets: &WindowsOffsets) -> Result<Vec<ModuleInfo>> {
+    let links_offset = offsets.ldr_links.get()?;
+    let base_offset = offsets.ldr_dll_base.get()?;
+    let size_offset = offsets.ldr_size_of_image.get()?;
+    let name_offset = offsets.ldr_base_dll_name.get()?;
+    // paths are extra, a profile without them still lists modules
+    let path_offset = offsets.ldr_full_dll_name.get().ok();
+
+    // PsLoadedModuleList is a bare LIST_ENTRY, not a pointer to one
+    let head = vmi.ksym2v("PsLoadedModuleList")?;
+
+    let mut modules = Vec::new();
+    for entry in walk_list(vmi, head, 0)? {
+        let ldr = entry - links_offset;
+
+        let name = vmi
+            .read_unicode_string(ldr + name_offset, 0)
+            .unwrap_or_else(|_| "<unknown>".into());
+        let base = vmi.read_addr_va(ldr + base_offset, 0).unwrap_or(0);
+        let size = vmi.read_32_va(ldr + size_offset, 0).unwrap_or(0) as u64;
+
+        let path = path_offset.and_then(|o| vmi.read_unicode_string(ldr + o, 0).ok());
+
+        modules.push(ModuleInfo {
+            name,
+            base,
+            size,
+            path,
+        });
+    }
+
+    Ok(modules)
+}
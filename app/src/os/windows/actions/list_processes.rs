@@ -1,51 +1,355 @@
-use crate::error::Result;
-use crate::os::{Action, ProcessInfo};
-use crate::vmi::Vmi;
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+use std::sync::Arc;
 
-pub struct ListProcesses;
+use crate::error::{Result, VmiError};
+use crate::os::list::{walk_list_partial, MAX_LIST_ENTRIES};
+use crate::os::windows::kpcr;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::{not_implemented, Action, ChunkedAction, ProcessInfo};
+pub use crate::os::{ListSource, ProcessList};
+use crate::vmi::{OsType, Vmi};
 
-impl Action<Vec<ProcessInfo>> for ListProcesses {
-    fn execute(&self, vmi: &Vmi) -> Result<Vec<ProcessInfo>> {
-        vmi.pause()?;
-        let result = list_processes_impl(vmi);
-        let _ = vmi.resume();
-        result
-    }
+/// DISPATCHER_HEADER.Type of a KPROCESS
+pub(crate) const PROCESS_OBJECT_TYPE: u8 = 3;
+
+/// processes read per chunk under Session::execute_chunked
+pub const PROCESSES_PER_CHUNK: usize = 64;
+
+pub struct ListProcesses {
+    offsets: Arc<WindowsOffsets>,
+    details: bool,
+    chunk_size: usize,
+    /// walk in progress under execute_chunked
+    cursor: Option<Cursor>,
 }
 
-fn list_processes_impl(vmi: &Vmi) -> Result<Vec<ProcessInfo>> {
-    let tasks_offset = vmi.get_offset("win_tasks")?;
-    let name_offset = vmi.get_offset("win_pname")?;
-    let pid_offset = vmi.get_offset("win_pid")?;
+/// where a chunked walk left off
+struct Cursor {
+    offsets: Offsets,
+    head: u64,
+    next: u64,
+    seen: HashSet<u64>,
+    processes: Vec<ProcessInfo>,
+}
 
-    let list_head = vmi.read_addr_ksym("PsActiveProcessHead")?;
+impl ListProcesses {
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self {
+            offsets,
+            details: false,
+            chunk_size: PROCESSES_PER_CHUNK,
+            cursor: None,
+        }
+    }
 
-    let mut processes = Vec::new();
-    let mut cur_list_entry = list_head;
-    let mut next_list_entry = vmi.read_addr_va(cur_list_entry, 0)?;
+    /// processes per chunk when run through Session::execute_chunked
+    pub fn chunk_size(mut self, n: usize) -> Self {
+        self.chunk_size = n.max(1);
+        self
+    }
 
-    // limit loop to avoid infinite loops if list is corrupted
-    for _ in 0..10000 {
-        let current_process = cur_list_entry - tasks_offset;
+    fn check_details(&self, vmi: &Vmi) -> Result<()> {
+        if self.details && vmi.address_width() != 8 {
+            return Err(not_implemented(
+                "process details on 32-bit Windows",
+                OsType::Windows,
+            ));
+        }
+        Ok(())
+    }
 
-        let pid = vmi.read_32_va(current_process + pid_offset, 0).unwrap_or(0) as i32;
-        let name = vmi
-            .read_str_va(current_process + name_offset, 0)
-            .unwrap_or_else(|_| "<unknown>".into());
+    fn read_details(&self, vmi: &Vmi, p: &mut ProcessInfo) {
+        if self.details {
+            p.command_line = read_command_line(vmi, &self.offsets, p.addr);
+            p.full_image_path = read_image_path(vmi, &self.offsets, p.addr);
+            p.create_time = self
+                .offsets
+                .create_time
+                .get()
+                .ok()
+                .and_then(|off| vmi.read_addr_va(p.addr + off, 0).ok());
+        }
+    }
 
-        processes.push(ProcessInfo {
-            pid,
-            name,
-            addr: current_process,
-        });
+    /// cursor at the first entry after PsActiveProcessHead, None when the
+    /// symbol is missing and only the one-shot fallback walk works
+    fn start(&self, vmi: &Vmi) -> Result<Option<Cursor>> {
+        self.offsets.check_width(vmi)?;
+        let offsets = Offsets {
+            tasks: self.offsets.tasks.get()?,
+            name: self.offsets.pname.get()?,
+            pid: self.offsets.pid.get()?,
+        };
+        let Ok(head) = vmi.ksym2v("PsActiveProcessHead") else {
+            return Ok(None);
+        };
+        Ok(Some(Cursor {
+            offsets,
+            head,
+            next: vmi.read_addr_va(head, 0)?,
+            seen: HashSet::new(),
+            processes: Vec::new(),
+        }))
+    }
 
-        cur_list_entry = next_list_entry;
-        next_list_entry = vmi.read_addr_va(cur_list_entry, 0)?;
+    /// read up to chunk_size entries. Some(truncated) once the walk is over
+    fn walk_chunk(&self, vmi: &Vmi, cursor: &mut Cursor) -> Option<bool> {
+        // the guest ran since the last chunk, the entry we stopped at may
+        // have been freed with its process
+        if cursor.next != cursor.head
+            && !vmi
+                .read_8_va(cursor.next - cursor.offsets.tasks, 0)
+                .is_ok_and(|t| t == PROCESS_OBJECT_TYPE)
+        {
+            return Some(true);
+        }
 
-        if next_list_entry == list_head {
-            break;
+        for _ in 0..self.chunk_size {
+            let entry = cursor.next;
+            if entry == cursor.head
+                || entry == 0
+                || cursor.processes.len() >= MAX_LIST_ENTRIES
+                || !cursor.seen.insert(entry)
+            {
+                return Some(false);
+            }
+            let mut p = read_process(vmi, &cursor.offsets, entry);
+            self.read_details(vmi, &mut p);
+            cursor.processes.push(p);
+            match vmi.read_addr_va(entry, 0) {
+                Ok(next) => cursor.next = next,
+                Err(_) => return Some(true),
+            }
         }
+        None
+    }
+
+    /// also read each process's command line and full image path.
+    /// x64 guests only, the PEB and UNICODE_STRING reads assume 8-byte pointers
+    pub fn with_details(mut self, enabled: bool) -> Self {
+        self.details = enabled;
+        self
+    }
+}
+
+impl Action<ProcessList> for ListProcesses {
+    fn execute(&self, vmi: &Vmi) -> Result<ProcessList> {
+        self.check_details(vmi)?;
+        vmi.read_consistent(|vmi| {
+            let mut list = list_processes_impl(vmi, &self.offsets)?;
+            for p in &mut list.processes {
+                self.read_details(vmi, p);
+            }
+            Ok(list)
+        })
+    }
+}
+
+impl ChunkedAction<ProcessList> for ListProcesses {
+    /// walks PsActiveProcessHead chunk_size entries at a time, each chunk
+    /// paused on its own. the list isn't one snapshot: processes created
+    /// mid-walk may be missed, and the walk ends early (truncated) if it
+    /// stopped on one that exited. without the symbol the fallback walk
+    /// runs whole in the first chunk
+    fn execute_chunk(&mut self, vmi: &Vmi) -> Result<ControlFlow<ProcessList>> {
+        self.check_details(vmi)?;
+        vmi.read_consistent(|vmi| {
+            let mut cursor = match self.cursor.take() {
+                Some(cursor) => cursor,
+                None => match self.start(vmi)? {
+                    Some(cursor) => cursor,
+                    None => return self.execute(vmi).map(ControlFlow::Break),
+                },
+            };
+            let Some(truncated) = self.walk_chunk(vmi, &mut cursor) else {
+                self.cursor = Some(cursor);
+                return Ok(ControlFlow::Continue(()));
+            };
+            Ok(ControlFlow::Break(ProcessList {
+                processes: cursor.processes,
+                source: ListSource::ActiveProcessHead,
+                truncated,
+            }))
+        })
+    }
+}
+
+struct Offsets {
+    tasks: u64,
+    name: u64,
+    pid: u64,
+}
+
+/// the walk reads pointers at the guest's width and takes its offsets from
+/// the profile, so it works on 32-bit guests as long as the profile matches
+pub(crate) fn list_processes_impl(vmi: &Vmi, cache: &WindowsOffsets) -> Result<ProcessList> {
+    cache.check_width(vmi)?;
+    let offsets = Offsets {
+        tasks: cache.tasks.get()?,
+        name: cache.pname.get()?,
+        pid: cache.pid.get()?,
+    };
+
+    // preferred: address of the head node itself, which is not an EPROCESS
+    if let Ok(list_head) = vmi.ksym2v("PsActiveProcessHead") {
+        let walk = walk_list_partial(vmi, list_head, 0)?;
+        return Ok(ProcessList {
+            processes: read_processes(vmi, &offsets, &walk.entries),
+            source: ListSource::ActiveProcessHead,
+            truncated: walk.is_truncated(),
+        });
+    }
+
+    // fallback chain: start from a known EPROCESS somewhere inside the list
+    let start = vmi
+        .read_addr_ksym("PsInitialSystemProcess")
+        .map(|p| (p, ListSource::InitialSystemProcess))
+        .or_else(|_| kpcr::current_process(vmi, cache, 0).map(|p| (p, ListSource::Kpcr)));
+
+    let (eprocess, source) = start.map_err(|_| {
+        VmiError::SymbolNotFound(
+            "PsActiveProcessHead (PsInitialSystemProcess and KPCR fallbacks also failed)".into(),
+        )
+    })?;
+
+    // walk_list stops when it returns to the start, which is a real entry here
+    let start_entry = eprocess + offsets.tasks;
+    let walk = walk_list_partial(vmi, start_entry, 0)?;
+    let entries = fallback_entries(start_entry, &walk.entries, |entry| {
+        vmi.read_8_va(entry - offsets.tasks, 0)
+            .is_ok_and(|t| t == PROCESS_OBJECT_TYPE)
+    });
+
+    Ok(ProcessList {
+        processes: read_processes(vmi, &offsets, &entries),
+        source,
+        truncated: walk.failed_at.is_some(),
+    })
+}
+
+/// entries of a walk started on a real entry rather than the head: the
+/// start first, then the walk. the list head node sits somewhere in the
+/// cycle, so only entries `is_process` accepts are kept
+fn fallback_entries(start: u64, walked: &[u64], is_process: impl Fn(u64) -> bool) -> Vec<u64> {
+    std::iter::once(start)
+        .chain(walked.iter().copied())
+        .filter(|&entry| is_process(entry))
+        .collect()
+}
+
+fn read_processes(vmi: &Vmi, offsets: &Offsets, entries: &[u64]) -> Vec<ProcessInfo> {
+    entries
+        .iter()
+        .map(|&entry| read_process(vmi, offsets, entry))
+        .collect()
+}
+
+fn read_process(vmi: &Vmi, offsets: &Offsets, entry: u64) -> ProcessInfo {
+    let current_process = entry - offsets.tasks;
+
+    let pid = vmi.read_ptr(current_process + offsets.pid, 0).unwrap_or(0);
+    let name = vmi
+        .read_str_va(current_process + offsets.name, 0)
+        .unwrap_or_else(|_| "<unknown>".into());
+
+    ProcessInfo {
+        pid,
+        name,
+        addr: current_process,
+        command_line: None,
+        full_image_path: None,
+        credentials: None,
+        namespace_pid: None,
+        thread_count: None,
+        create_time: None,
+    }
+}
+
+/// ProcessParameters.CommandLine of a process, no pausing.
+/// None for processes without a PEB (System, minimal processes) or when
+/// the parameter block is paged out.
+pub fn read_command_line(vmi: &Vmi, offsets: &WindowsOffsets, eprocess: u64) -> Option<String> {
+    let dtb = vmi
+        .read_addr_va(eprocess + offsets.dtb.get().ok()?, 0)
+        .ok()?;
+    let peb = vmi
+        .read_addr_va(eprocess + offsets.peb.get().ok()?, 0)
+        .ok()?;
+    if dtb == 0 || peb == 0 {
+        return None;
     }
 
-    Ok(processes)
+    // PEB and parameters are user memory, translate via the DTB
+    let bytes = vmi
+        .read_va_dtb(dtb, peb + offsets.process_params.get().ok()?, 8)
+        .ok()?;
+    let params = u64::from_le_bytes(bytes.try_into().ok()?);
+    if params == 0 {
+        return None;
+    }
+
+    vmi.read_unicode_string_dtb(dtb, params + offsets.command_line.get().ok()?)
+        .ok()
+        .filter(|s| !s.is_empty())
+}
+
+/// full image path from SeAuditProcessCreationInfo.ImageFileName, no pausing.
+/// the name info lives in kernel pool, so no DTB is needed. None for System
+/// and minimal processes, which have no backing image
+pub fn read_image_path(vmi: &Vmi, offsets: &WindowsOffsets, eprocess: u64) -> Option<String> {
+    let audit = eprocess + offsets.se_audit_process_creation_info.get().ok()?;
+    let name_info = vmi
+        .read_addr_va(audit + offsets.audit_image_file_name.get().ok()?, 0)
+        .ok()?;
+    if name_info == 0 {
+        return None;
+    }
+
+    vmi.read_unicode_string(
+        name_info + offsets.object_name_information_name.get().ok()?,
+        0,
+    )
+    .ok()
+    .filter(|s| !s.is_empty())
+}
+
+/// find the EPROCESS of a pid by walking the active process list.
+/// caller is responsible for pausing.
+pub fn find_process(vmi: &Vmi, offsets: &WindowsOffsets, pid: u64) -> Result<u64> {
+    list_processes_impl(vmi, offsets)?
+        .processes
+        .into_iter()
+        .find(|p| p.pid == pid)
+        .map(|p| p.addr)
+        .ok_or_else(|| VmiError::Other(format!("no process with pid {}", pid)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::VmiError;
+    use crate::os::list::walk_with;
+    use std::collections::HashMap;
+
+    #[test]
+    fn fallback_walk_skips_the_list_head() {
+        // System -> smss -> PsActiveProcessHead -> csrss -> System
+        let (system, smss, head, csrss) = (0x1000, 0x2000, 0x9000, 0x3000);
+        let flinks = HashMap::from([(system, smss), (smss, head), (head, csrss), (csrss, system)]);
+        let walk = walk_with(system, 0, |addr| {
+            flinks.get(&addr).copied().ok_or(VmiError::NullPointer)
+        })
+        .unwrap();
+        assert_eq!(walk.entries, [smss, head, csrss]);
+
+        let entries = fallback_entries(system, &walk.entries, |entry| entry != head);
+        assert_eq!(entries, [system, smss, csrss]);
+    }
+
+    #[test]
+    fn fallback_start_is_checked_too() {
+        assert!(fallback_entries(0x1000, &[0x2000], |_| false).is_empty());
+        assert_eq!(fallback_entries(0x1000, &[], |_| true), [0x1000]);
+    }
 }
@@ -0,0 +1,242 @@
+//! service enumeration from the SCM's records inside services.exe
+//!
+//! services.exe keeps a SERVICE_RECORD per installed service on its heap.
+//! the records are found by scanning the default process heap for the
+//! record tag, then decoded with a per-build layout. the layout is private
+//! to the SCM and moves between builds, so builds without a table entry
+//! fail with an "unsupported build" error instead of guessing.
+
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::error::{Result, VmiError};
+use crate::os::windows::actions::list_processes::list_processes_impl;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::windows::version::build_number;
+use crate::os::Action;
+use crate::vmi::{page_base, page_remaining, Vmi, PAGE_SIZE};
+
+/// heap bytes scanned for records before giving up
+pub const MAX_HEAP_SCAN: u64 = 64 * 1024 * 1024;
+/// longest service string read, in UTF-16 units
+const MAX_SERVICE_STRING: usize = 512;
+
+/// SERVICE_WIN32_OWN_PROCESS | SERVICE_WIN32_SHARE_PROCESS
+const SERVICE_WIN32: u32 = 0x30;
+
+/// SERVICE_RECORD field offsets for a range of builds (x64)
+struct ServiceLayout {
+    builds: RangeInclusive<u32>,
+    tag: [u8; 4],
+    tag_offset: u64,
+    name: u64,
+    display_name: u64,
+    /// SERVICE_PROCESS for win32 services, driver name string for drivers
+    process_or_driver: u64,
+    service_type: u64,
+    state: u64,
+    /// SERVICE_PROCESS fields
+    binary_path: u64,
+    process_id: u64,
+}
+
+/// known layouts, from Volatility's svcscan vtypes. add rows as builds are verified
+const LAYOUTS: &[ServiceLayout] = &[
+    // windows 7 / server 2008 R2
+    ServiceLayout {
+        builds: 7600..=7601,
+        tag: *b"sErv",
+        tag_offset: 0x20,
+        name: 0x08,
+        display_name: 0x10,
+        process_or_driver: 0x30,
+        service_type: 0x38,
+        state: 0x3c,
+        binary_path: 0x18,
+        process_id: 0x28,
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceState {
+    Stopped,
+    StartPending,
+    StopPending,
+    Running,
+    ContinuePending,
+    PausePending,
+    Paused,
+    Unknown(u32),
+}
+
+impl From<u32> for ServiceState {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Self::Stopped,
+            2 => Self::StartPending,
+            3 => Self::StopPending,
+            4 => Self::Running,
+            5 => Self::ContinuePending,
+            6 => Self::PausePending,
+            7 => Self::Paused,
+            v => Self::Unknown(v),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub display_name: String,
+    pub service_type: u32,
+    pub state: ServiceState,
+    /// hosting process, only for running win32 services
+    pub pid: Option<u32>,
+    /// service binary, or the driver object name for drivers
+    pub image_path: Option<String>,
+    /// record address in services.exe
+    pub addr: u64,
+}
+
+/// reads the SCM service database out of services.exe
+pub struct ListServices {
+    offsets: Arc<WindowsOffsets>,
+}
+
+impl ListServices {
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self { offsets }
+    }
+}
+
+impl Action<Vec<ServiceInfo>> for ListServices {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<ServiceInfo>> {
+        vmi.read_consistent(|vmi| list_services_impl(vmi, &self.offsets))
+    }
+}
+
+fn list_services_impl(vmi: &Vmi, offsets: &WindowsOffsets) -> Result<Vec<ServiceInfo>> {
+    let build = build_number(vmi)?;
+    let layout = LAYOUTS
+        .iter()
+        .find(|l| l.builds.contains(&build))
+        .ok_or_else(|| {
+            VmiError::Other(format!("service enumeration: unsupported build {}", build))
+        })?;
+
+    let services = list_processes_impl(vmi, offsets)?
+        .processes
+        .into_iter()
+        .find(|p| p.name.eq_ignore_ascii_case("services.exe"))
+        .ok_or_else(|| VmiError::Other("services.exe not found".into()))?;
+
+    let dtb = vmi.read_addr_va(services.addr + offsets.dtb.get()?, 0)?;
+    let peb = vmi.read_addr_va(services.addr + offsets.peb.get()?, 0)?;
+    if peb == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    let heap = read_u64(vmi, dtb, peb + offsets.peb_process_heap.get()?)?;
+
+    let records = scan_for_tag(vmi, dtb, heap, layout)
+        .into_iter()
+        .filter_map(|addr| read_record(vmi, dtb, addr, layout))
+        .collect();
+    Ok(records)
+}
+
+/// record addresses whose tag matches, skipping unmapped heap pages
+fn scan_for_tag(vmi: &Vmi, dtb: u64, start: u64, layout: &ServiceLayout) -> Vec<u64> {
+    let mut found = Vec::new();
+    let mut page = page_base(start);
+    let end = page.saturating_add(MAX_HEAP_SCAN);
+    while page < end {
+        if let Ok(data) = vmi.read_va_dtb(dtb, page, PAGE_SIZE as usize) {
+            // records are heap allocations, so the tag is at least 8-byte aligned
+            for off in (0..PAGE_SIZE).step_by(8) {
+                let o = off as usize;
+                if data[o..o + 4] == layout.tag && page + off >= layout.tag_offset {
+                    found.push(page + off - layout.tag_offset);
+                }
+            }
+        }
+        page += PAGE_SIZE;
+    }
+    found
+}
+
+fn read_record(vmi: &Vmi, dtb: u64, addr: u64, layout: &ServiceLayout) -> Option<ServiceInfo> {
+    // a stray tag match won't have a readable name
+    let name_ptr = read_u64(vmi, dtb, addr + layout.name).ok()?;
+    let name = read_wstr(vmi, dtb, name_ptr).filter(|n| !n.is_empty())?;
+
+    let display_name = read_u64(vmi, dtb, addr + layout.display_name)
+        .ok()
+        .and_then(|p| read_wstr(vmi, dtb, p))
+        .unwrap_or_default();
+    let service_type = read_u32(vmi, dtb, addr + layout.service_type).unwrap_or(0);
+    let state = ServiceState::from(read_u32(vmi, dtb, addr + layout.state).unwrap_or(0));
+
+    let target = read_u64(vmi, dtb, addr + layout.process_or_driver).unwrap_or(0);
+    let (pid, image_path) = if target == 0 {
+        (None, None)
+    } else if service_type & SERVICE_WIN32 != 0 {
+        let pid = read_u32(vmi, dtb, target + layout.process_id)
+            .ok()
+            .filter(|&p| p != 0);
+        let path = read_u64(vmi, dtb, target + layout.binary_path)
+            .ok()
+            .and_then(|p| read_wstr(vmi, dtb, p));
+        (pid, path)
+    } else {
+        (None, read_wstr(vmi, dtb, target))
+    };
+
+    Some(ServiceInfo {
+        name,
+        display_name,
+        service_type,
+        state,
+        pid,
+        image_path,
+        addr,
+    })
+}
+
+fn read_u64(vmi: &Vmi, dtb: u64, vaddr: u64) -> Result<u64> {
+    let bytes = vmi.read_va_dtb(dtb, vaddr, 8)?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap_or([0; 8])))
+}
+
+fn read_u32(vmi: &Vmi, dtb: u64, vaddr: u64) -> Result<u32> {
+    let bytes = vmi.read_va_dtb(dtb, vaddr, 4)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap_or([0; 4])))
+}
+
+/// NUL-terminated UTF-16, None for null or unreadable pointers
+fn read_wstr(vmi: &Vmi, dtb: u64, vaddr: u64) -> Option<String> {
+    if vaddr == 0 {
+        return None;
+    }
+    let mut units = Vec::new();
+    let mut curr = vaddr;
+    while units.len() < MAX_SERVICE_STRING {
+        let to_read = std::cmp::min(
+            page_remaining(curr),
+            2 * (MAX_SERVICE_STRING - units.len()) as u64,
+        );
+        let chunk = match vmi.read_va_dtb(dtb, curr, to_read as usize) {
+            Ok(chunk) => chunk,
+            Err(_) if units.is_empty() => return None,
+            Err(_) => break,
+        };
+        for c in chunk.chunks_exact(2) {
+            let unit = u16::from_le_bytes([c[0], c[1]]);
+            if unit == 0 {
+                return Some(String::from_utf16_lossy(&units));
+            }
+            units.push(unit);
+        }
+        curr += to_read;
+    }
+    Some(String::from_utf16_lossy(&units))
+}
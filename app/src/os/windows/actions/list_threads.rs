@@ -0,0 +1,71 @@
+//! threads of a windows process, from _EPROCESS.ThreadListHead
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::os::list::walk_list;
+use crate::os::windows::actions::list_processes::find_process;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// one _ETHREAD of a process
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThreadInfo {
+    /// Cid.UniqueThread, a HANDLE like the pid
+    pub tid: u64,
+    /// Win32StartAddress, 0 if unreadable
+    pub start_addr: u64,
+    /// user-mode TEB, 0 for system threads
+    pub teb: u64,
+    /// _ETHREAD
+    pub addr: u64,
+}
+
+/// threads of one process, looked up by pid
+pub struct ListThreads {
+    offsets: Arc<WindowsOffsets>,
+    pid: u64,
+}
+
+impl ListThreads {
+    pub fn new(offsets: Arc<WindowsOffsets>, pid: u64) -> Self {
+        Self { offsets, pid }
+    }
+}
+
+impl Action<Vec<ThreadInfo>> for ListThreads {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<ThreadInfo>> {
+        vmi.read_consistent(|vmi| {
+            let eprocess = find_process(vmi, &self.offsets, self.pid)?;
+            threads_of(vmi, &self.offsets, eprocess)
+        })
+    }
+}
+
+/// every thread on an EPROCESS's list, no pausing. an exited process
+/// whose list is empty gives an empty vec
+pub fn threads_of(vmi: &Vmi, offsets: &WindowsOffsets, eprocess: u64) -> Result<Vec<ThreadInfo>> {
+    let head = eprocess + offsets.thread_list_head.get()?;
+    let entry_offset = offsets.thread_list_entry.get()?;
+    let tid_offset = offsets.thread_cid.get()? + offsets.client_id_unique_thread.get()?;
+    // extras, a profile without them still lists tids
+    let start_offset = offsets.thread_start_address.get().ok();
+    let teb_offset = offsets.kthread_teb.get().ok();
+
+    let read = |addr: u64| vmi.read_addr_va(addr, 0).unwrap_or(0);
+    Ok(walk_list(vmi, head, 0)?
+        .into_iter()
+        .map(|entry| {
+            let ethread = entry - entry_offset;
+            ThreadInfo {
+                tid: read(ethread + tid_offset),
+                start_addr: start_offset.map_or(0, |o| read(ethread + o)),
+                teb: teb_offset.map_or(0, |o| read(ethread + o)),
+                addr: ethread,
+            }
+        })
+        .collect())
+}
@@ -1 +1,9 @@
+pub mod detect_hidden_processes;
+pub mod detect_user_hooks;
+pub mod list_modules;
 pub mod list_processes;
+pub mod list_services;
+pub mod list_threads;
+pub mod process_tree;
+pub mod read_environment;
+pub mod read_ssdt;
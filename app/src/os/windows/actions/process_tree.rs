@@ -0,0 +1,172 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::error::Result;
+use crate::os::windows::actions::list_processes::list_processes_impl;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// one process in the tree
+#[derive(Debug, Clone)]
+pub struct ProcessNode {
+    pub pid: u64,
+    /// InheritedFromUniqueProcessId, the parent may have exited since
+    pub ppid: u64,
+    pub name: String,
+    pub addr: u64,
+}
+
+/// processes keyed by pid plus pid -> children
+#[derive(Debug, Clone, Default)]
+pub struct ProcessTree {
+    pub nodes: BTreeMap<u64, ProcessNode>,
+    pub children: BTreeMap<u64, Vec<u64>>,
+}
+
+impl ProcessTree {
+    pub fn from_nodes(nodes: impl IntoIterator<Item = ProcessNode>) -> Self {
+        let nodes: BTreeMap<u64, ProcessNode> = nodes.into_iter().map(|n| (n.pid, n)).collect();
+
+        let mut children: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for node in nodes.values() {
+            if node.ppid != node.pid {
+                children.entry(node.ppid).or_default().push(node.pid);
+            }
+        }
+
+        Self { nodes, children }
+    }
+
+    /// processes whose parent isn't running (or is themselves)
+    pub fn roots(&self) -> Vec<u64> {
+        self.nodes
+            .values()
+            .filter(|n| n.ppid == n.pid || !self.nodes.contains_key(&n.ppid))
+            .map(|n| n.pid)
+            .collect()
+    }
+
+    pub fn children_of(&self, pid: u64) -> &[u64] {
+        self.children.get(&pid).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// indented tree, one `pid name` line per process
+    pub fn write_tree(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut seen = HashSet::new();
+        for root in self.roots() {
+            self.write_node(out, root, 0, &mut seen)?;
+        }
+        // pid reuse can make a parent chain loop, print what the walk missed
+        let missed: Vec<u64> = self
+            .nodes
+            .keys()
+            .filter(|pid| !seen.contains(*pid))
+            .copied()
+            .collect();
+        for pid in missed {
+            self.write_node(out, pid, 0, &mut seen)?;
+        }
+        Ok(())
+    }
+
+    fn write_node(
+        &self,
+        out: &mut dyn Write,
+        pid: u64,
+        depth: usize,
+        seen: &mut HashSet<u64>,
+    ) -> io::Result<()> {
+        if !seen.insert(pid) {
+            return Ok(());
+        }
+        let Some(node) = self.nodes.get(&pid) else {
+            return Ok(());
+        };
+        writeln!(
+            out,
+            "{:indent$}{} {}",
+            "",
+            node.pid,
+            node.name,
+            indent = depth * 2
+        )?;
+        for &child in self.children_of(pid) {
+            self.write_node(out, child, depth + 1, seen)?;
+        }
+        Ok(())
+    }
+
+    /// nested `{pid, ppid, name, addr, children: [...]}` objects, one per root
+    pub fn to_json(&self) -> Value {
+        let mut seen = HashSet::new();
+        let mut roots: Vec<Value> = self
+            .roots()
+            .into_iter()
+            .filter_map(|pid| self.node_json(pid, &mut seen))
+            .collect();
+        let missed: Vec<u64> = self
+            .nodes
+            .keys()
+            .filter(|pid| !seen.contains(*pid))
+            .copied()
+            .collect();
+        roots.extend(
+            missed
+                .into_iter()
+                .filter_map(|pid| self.node_json(pid, &mut seen)),
+        );
+        Value::Array(roots)
+    }
+
+    fn node_json(&self, pid: u64, seen: &mut HashSet<u64>) -> Option<Value> {
+        if !seen.insert(pid) {
+            return None;
+        }
+        let node = self.nodes.get(&pid)?;
+        let children: Vec<Value> = self
+            .children_of(pid)
+            .iter()
+            .filter_map(|&child| self.node_json(child, seen))
+            .collect();
+        Some(json!({
+            "pid": node.pid,
+            "ppid": node.ppid,
+            "name": node.name,
+            "addr": node.addr,
+            "children": children,
+        }))
+    }
+}
+
+/// parent/child view of the active process list
+pub struct BuildProcessTree {
+    offsets: Arc<WindowsOffsets>,
+}
+
+impl BuildProcessTree {
+    pub fn new(offsets: Arc<WindowsOffsets>) -> Self {
+        Self { offsets }
+    }
+}
+
+impl Action<ProcessTree> for BuildProcessTree {
+    fn execute(&self, vmi: &Vmi) -> Result<ProcessTree> {
+        vmi.read_consistent(|vmi| {
+            let parent_pid = self.offsets.parent_pid.get()?;
+            let list = list_processes_impl(vmi, &self.offsets)?;
+
+            Ok(ProcessTree::from_nodes(list.processes.into_iter().map(
+                |p| ProcessNode {
+                    ppid: vmi.read_ptr(p.addr + parent_pid, 0).unwrap_or(0),
+                    pid: p.pid,
+                    name: p.name,
+                    addr: p.addr,
+                },
+            )))
+        })
+    }
+}
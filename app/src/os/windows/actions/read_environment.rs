@@ -0,0 +1,134 @@
+//! read a process's environment block
+//!
+//! PEB.ProcessParameters.Environment points at a run of NUL-terminated
+//! UTF-16 `NAME=value` strings ending in an empty string (double NUL).
+//! EnvironmentSize gives the block size in bytes.
+
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::os::windows::actions::list_processes::find_process;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::{page_remaining, Vmi};
+
+/// upper bound on bytes read, the documented limit is 32767 characters
+pub const MAX_ENVIRONMENT_SIZE: usize = 0x10000;
+
+enum Target {
+    Pid(u64),
+    Process { eprocess: u64, dtb: u64 },
+}
+
+/// reads `NAME=value` pairs from a process's environment block
+pub struct ReadEnvironment {
+    offsets: Arc<WindowsOffsets>,
+    target: Target,
+}
+
+impl ReadEnvironment {
+    /// look the process up by pid in the active process list
+    pub fn for_pid(offsets: Arc<WindowsOffsets>, pid: u64) -> Self {
+        Self {
+            offsets,
+            target: Target::Pid(pid),
+        }
+    }
+
+    /// use a known EPROCESS and DTB, e.g. for a process that is still being created
+    pub fn for_process(offsets: Arc<WindowsOffsets>, eprocess: u64, dtb: u64) -> Self {
+        Self {
+            offsets,
+            target: Target::Process { eprocess, dtb },
+        }
+    }
+}
+
+impl Action<Vec<(String, String)>> for ReadEnvironment {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<(String, String)>> {
+        vmi.read_consistent(|vmi| self.execute_impl(vmi))
+    }
+}
+
+impl ReadEnvironment {
+    fn execute_impl(&self, vmi: &Vmi) -> Result<Vec<(String, String)>> {
+        let (eprocess, dtb) = match self.target {
+            Target::Process { eprocess, dtb } => (eprocess, dtb),
+            Target::Pid(pid) => {
+                let eprocess = find_process(vmi, &self.offsets, pid)?;
+                let dtb = vmi.read_addr_va(eprocess + self.offsets.dtb.get()?, 0)?;
+                (eprocess, dtb)
+            }
+        };
+        read_environment(vmi, &self.offsets, eprocess, dtb)
+    }
+}
+
+/// read and parse the environment block of a process, no pausing.
+/// a missing PEB/parameters/block yields an empty list, and a block that is
+/// partially paged out yields the variables from the readable prefix.
+pub fn read_environment(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    eprocess: u64,
+    dtb: u64,
+) -> Result<Vec<(String, String)>> {
+    let peb = vmi.read_addr_va(eprocess + offsets.peb.get()?, 0)?;
+    if peb == 0 || dtb == 0 {
+        return Ok(Vec::new());
+    }
+
+    // PEB and everything below it is user memory, translate via the DTB
+    let read_ptr = |vaddr: u64| -> Result<u64> {
+        let bytes = vmi.read_va_dtb(dtb, vaddr, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap_or([0; 8])))
+    };
+
+    let params = read_ptr(peb + offsets.process_params.get()?)?;
+    if params == 0 {
+        return Ok(Vec::new());
+    }
+
+    let env_addr = read_ptr(params + offsets.environment.get()?)?;
+    if env_addr == 0 {
+        return Ok(Vec::new());
+    }
+    let env_size = read_ptr(params + offsets.environment_size.get()?)? as usize;
+    let env_size = env_size.min(MAX_ENVIRONMENT_SIZE);
+
+    // read page by page, keep the readable prefix if the tail is paged out
+    let mut data = Vec::with_capacity(env_size);
+    let mut curr = env_addr;
+    let end = env_addr + env_size as u64;
+    while curr < end {
+        let to_read = std::cmp::min(page_remaining(curr), end - curr);
+        match vmi.read_va_dtb(dtb, curr, to_read as usize) {
+            Ok(chunk) => data.extend_from_slice(&chunk),
+            Err(_) => break,
+        }
+        curr += to_read;
+    }
+
+    Ok(parse_environment(&data))
+}
+
+/// split a raw UTF-16LE environment block into `(name, value)` pairs
+pub fn parse_environment(data: &[u8]) -> Vec<(String, String)> {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+
+    units
+        .split(|&c| c == 0)
+        .take_while(|s| !s.is_empty())
+        .map(|s| {
+            let entry = String::from_utf16_lossy(s);
+            // entries like "=C:=C:\\" start with '=', so search past the first char
+            match entry.char_indices().skip(1).find(|&(_, c)| c == '=') {
+                Some((i, _)) => (entry[..i].to_string(), entry[i + 1..].to_string()),
+                None => (entry, String::new()),
+            }
+        })
+        .collect()
+}
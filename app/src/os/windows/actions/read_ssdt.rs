@@ -0,0 +1,84 @@
+use crate::error::{Result, VmiError};
+use crate::os::Action;
+use crate::vmi::{Vmi, IA32_LSTAR};
+
+/// sanity cap, real tables hold a few hundred entries
+const MAX_SERVICES: u32 = 0x1000;
+
+/// resolved targets of the nt syscall table (KiServiceTable), for spotting
+/// entries that point outside ntoskrnl
+pub struct ReadSsdt;
+
+impl ReadSsdt {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for ReadSsdt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Action<Vec<(u32, u64)>> for ReadSsdt {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<(u32, u64)>> {
+        vmi.read_consistent(read_ssdt_impl)
+    }
+}
+
+/// table base and entry count, no pausing
+pub fn locate_service_table(vmi: &Vmi) -> Result<(u64, u32)> {
+    if let Ok(table) = vmi.ksym2v("KiServiceTable")
+        && let Ok(limit) = vmi
+            .ksym2v("KiServiceLimit")
+            .and_then(|limit| vmi.read_32_va(limit, 0))
+    {
+        return Ok((table, limit));
+    }
+
+    // fallback: first descriptor of KeServiceDescriptorTable
+    // { ServiceTableBase, ServiceCounterTableBase, NumberOfServices, ParamTableBase }
+    let descriptor = vmi.ksym2v("KeServiceDescriptorTable").map_err(|_| {
+        VmiError::SymbolNotFound(
+            "KiServiceTable (KeServiceDescriptorTable fallback also failed)".into(),
+        )
+    })?;
+    let width = vmi.address_width() as u64;
+    let table = vmi.read_addr_va(descriptor, 0)?;
+    let limit = vmi.read_32_va(descriptor + 2 * width, 0)?;
+    Ok((table, limit))
+}
+
+/// syscall entry point of a vcpu from LSTAR, normally KiSystemCall64 or
+/// KiSystemCall64Shadow. anything else means the entry was redirected
+pub fn syscall_entry(vmi: &Vmi, vcpu: u32) -> Result<u64> {
+    vmi.read_msr(IA32_LSTAR, vcpu)
+}
+
+/// `(index, target)` for every syscall, no pausing
+pub fn read_ssdt_impl(vmi: &Vmi) -> Result<Vec<(u32, u64)>> {
+    let (table, limit) = locate_service_table(vmi)?;
+    if table == 0 || limit == 0 || limit > MAX_SERVICES {
+        return Err(VmiError::Other(format!(
+            "implausible service table at {:#x} with {} entries",
+            table, limit
+        )));
+    }
+
+    let targets: Vec<u64> = if vmi.address_width() == 8 {
+        // x64: signed offset from the table base, low 4 bits are the stack arg count
+        vmi.read_array::<i32>(table, 0, limit as usize)?
+            .into_iter()
+            .map(|entry| table.wrapping_add_signed((entry >> 4) as i64))
+            .collect()
+    } else {
+        // x86: absolute pointers
+        vmi.read_array::<u32>(table, 0, limit as usize)?
+            .into_iter()
+            .map(u64::from)
+            .collect()
+    };
+
+    Ok((0..).zip(targets).collect())
+}
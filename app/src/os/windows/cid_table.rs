@@ -0,0 +1,85 @@
+//! PspCidTable - the kernel handle table mapping client ids to objects
+//!
+//! every live process and thread has an entry here whether or not it is
+//! linked into ActiveProcessLinks, so it is the second view for spotting
+//! unlinked (DKOM) processes, and resolves a pid/tid with a couple of reads.
+//! entries point at the object body, not the OBJECT_HEADER.
+
+use crate::error::Result;
+use crate::os::windows::actions::list_processes::PROCESS_OBJECT_TYPE;
+use crate::os::windows::handle_table::{self, EntryFormat};
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::vmi::Vmi;
+
+/// DISPATCHER_HEADER.Type of a KTHREAD
+pub const THREAD_OBJECT_TYPE: u8 = 6;
+
+/// one client id and the object it maps to
+#[derive(Debug, Clone, Copy)]
+pub struct CidEntry {
+    pub cid: u64,
+    pub object: u64,
+}
+
+/// PspCidTable split by object type
+#[derive(Debug, Clone, Default)]
+pub struct CidObjects {
+    /// pid -> EPROCESS
+    pub processes: Vec<CidEntry>,
+    /// tid -> ETHREAD
+    pub threads: Vec<CidEntry>,
+}
+
+fn entry_format(offsets: &WindowsOffsets) -> EntryFormat {
+    if offsets.handle_entry_pointer_bits.is_resolved() {
+        EntryFormat::PointerBits
+    } else {
+        EntryFormat::Legacy
+    }
+}
+
+fn table_code(vmi: &Vmi, offsets: &WindowsOffsets) -> Result<u64> {
+    let table = vmi.read_addr_ksym("PspCidTable")?;
+    vmi.read_addr_va(table + offsets.handle_table_code.get()?, 0)
+}
+
+/// walk the whole table and classify each object, no pausing
+pub fn enumerate(vmi: &Vmi, offsets: &WindowsOffsets) -> Result<CidObjects> {
+    let code = table_code(vmi, offsets)?;
+
+    let mut objects = CidObjects::default();
+    for (cid, object) in handle_table::walk(vmi, code, entry_format(offsets))? {
+        let entry = CidEntry { cid, object };
+        match vmi.read_8_va(object, 0) {
+            Ok(PROCESS_OBJECT_TYPE) => objects.processes.push(entry),
+            Ok(THREAD_OBJECT_TYPE) => objects.threads.push(entry),
+            _ => {}
+        }
+    }
+    Ok(objects)
+}
+
+fn lookup_typed(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    cid: u64,
+    object_type: u8,
+) -> Result<Option<u64>> {
+    let code = table_code(vmi, offsets)?;
+    let object = match handle_table::lookup(vmi, code, cid, entry_format(offsets))? {
+        Some(object) => object,
+        None => return Ok(None),
+    };
+    // pids and tids share one id space, check we got the kind asked for
+    Ok((vmi.read_8_va(object, 0)? == object_type).then_some(object))
+}
+
+/// EPROCESS for a pid
+pub fn lookup_pid(vmi: &Vmi, offsets: &WindowsOffsets, pid: u64) -> Result<Option<u64>> {
+    lookup_typed(vmi, offsets, pid, PROCESS_OBJECT_TYPE)
+}
+
+/// ETHREAD for a tid
+pub fn lookup_tid(vmi: &Vmi, offsets: &WindowsOffsets, tid: u64) -> Result<Option<u64>> {
+    lookup_typed(vmi, offsets, tid, THREAD_OBJECT_TYPE)
+}
@@ -2,11 +2,14 @@
 //!
 //! uses HookManager for AMD-compatible hook handling
 
-use crate::error::Result;
+use crate::error::{Result, VmiError};
+use crate::event_stream::{EventSink, GuestEvent};
 use crate::ffi::RCX;
 use crate::hook::{HookContext, HookManager};
+use crate::os::windows::actions::read_environment::read_environment;
+use crate::os::windows::offsets::WindowsOffsets;
 use crate::os::{Event, EventContext};
-use crate::vmi::Vmi;
+use crate::vmi::{Vmi, MAX_UNICODE_STRING_BYTES};
 use std::sync::{Arc, Mutex};
 
 /// offsets needed for reading process info
@@ -19,74 +22,102 @@ struct ProcessOffsets {
     process_params_offset: u64,
     command_line_offset: u64,
     image_path_offset: u64,
+    /// cap for command line and image path reads
+    max_string_bytes: usize,
+    /// set when environment output is enabled
+    environment: Option<Arc<WindowsOffsets>>,
 }
 
 /// process creation monitor
 pub struct ProcessCreateMonitor {
     hook_addr: Option<u64>,
+    with_environment: bool,
+    max_string_bytes: usize,
 }
 
 impl Event for ProcessCreateMonitor {
     fn enable(&mut self, ctx: &EventContext) -> Result<()> {
-        self.enable_internal(ctx.hooks, ctx.vmi)
+        self.enable_internal(ctx)
     }
 
     fn disable(&mut self, ctx: &EventContext) -> Result<()> {
         self.disable_internal(ctx.hooks, ctx.vmi)
     }
+    fn name(&self) -> &str {
+        "ProcessCreateMonitor"
+    }
+}
+
+impl Default for ProcessCreateMonitor {
+    fn default() -> Self {
+        Self {
+            hook_addr: None,
+            with_environment: false,
+            max_string_bytes: MAX_UNICODE_STRING_BYTES,
+        }
+    }
 }
 
 impl ProcessCreateMonitor {
+    /// offsets come from the session's cache when enabled
     pub fn new() -> Self {
-        Self { hook_addr: None }
+        Self::default()
+    }
+
+    /// also print the new process's environment variables
+    pub fn with_environment(mut self, enabled: bool) -> Self {
+        self.with_environment = enabled;
+        self
+    }
+
+    /// cap on command line and image path bytes, longer ones are truncated.
+    /// defaults to MAX_UNICODE_STRING_BYTES
+    pub fn max_string_bytes(mut self, max: usize) -> Self {
+        self.max_string_bytes = max;
+        self
     }
 
     /// enable process monitoring - registers hook with HookManager
-    fn enable_internal(&mut self, hooks: &Arc<HookManager>, vmi: &Arc<Mutex<Vmi>>) -> Result<()> {
+    fn enable_internal(&mut self, ctx: &EventContext) -> Result<()> {
         if self.hook_addr.is_some() {
             return Ok(());
         }
 
-        let func_addr = {
-            let vmi_lock = vmi.lock().unwrap();
-            // find hook target
-            vmi_lock
-                .ksym2v("PspInsertProcess")
-                .or_else(|_| vmi_lock.ksym2v("NtCreateUserProcess"))
-                .map_err(|_| crate::error::VmiError::SymbolNotFound("PspInsertProcess".into()))?
-        };
-
-        // load offsets once
-        let offsets = {
-            let vmi_lock = vmi.lock().unwrap();
-            Arc::new(ProcessOffsets {
-                pid_offset: vmi_lock.get_offset("win_pid")?,
-                parent_pid_offset: vmi_lock
-                    .get_struct_offset("_EPROCESS", "InheritedFromUniqueProcessId")?,
-                create_time_offset: vmi_lock.get_struct_offset("_EPROCESS", "CreateTime")?,
-                dtb_offset: vmi_lock.get_struct_offset("_KPROCESS", "DirectoryTableBase")?,
-                peb_offset: vmi_lock.get_struct_offset("_EPROCESS", "Peb")?,
-                process_params_offset: vmi_lock.get_struct_offset("_PEB", "ProcessParameters")?,
-                command_line_offset: vmi_lock
-                    .get_struct_offset("_RTL_USER_PROCESS_PARAMETERS", "CommandLine")?,
-                image_path_offset: vmi_lock
-                    .get_struct_offset("_RTL_USER_PROCESS_PARAMETERS", "ImagePathName")?,
-            })
-        };
-
-        // callback closure captures offsets
-        let offsets_clone = offsets.clone();
-
-        {
-            let vmi_lock = vmi.lock().unwrap();
-
-            hooks.add_hook(&vmi_lock, func_addr, move |ctx: &HookContext| {
-                Self::on_process_create(ctx, &offsets_clone);
+        // pull what the callback needs from the shared cache
+        let cache = ctx.windows()?.offsets();
+        let offsets = Arc::new(ProcessOffsets {
+            pid_offset: cache.pid.get()?,
+            parent_pid_offset: cache.parent_pid.get()?,
+            create_time_offset: cache.create_time.get()?,
+            dtb_offset: cache.dtb.get()?,
+            peb_offset: cache.peb.get()?,
+            process_params_offset: cache.process_params.get()?,
+            command_line_offset: cache.command_line.get()?,
+            image_path_offset: cache.image_path.get()?,
+            max_string_bytes: self.max_string_bytes,
+            environment: if self.with_environment {
+                // fail early rather than on every hook hit
+                cache.environment.get()?;
+                cache.environment_size.get()?;
+                Some(cache.clone())
+            } else {
+                None
+            },
+        });
+
+        let events = ctx.events.clone();
+        let vmi_lock = ctx.vmi.lock().unwrap();
+        let func_addr = vmi_lock
+            .ksym2v("PspInsertProcess")
+            .or_else(|_| vmi_lock.ksym2v("NtCreateUserProcess"))
+            .map_err(|_| VmiError::SymbolNotFound("PspInsertProcess".into()))?;
+        ctx.hooks
+            .add_hook(&vmi_lock, func_addr, move |ctx: &HookContext| {
+                Self::on_process_create(ctx, &offsets, &events);
             })?;
-        }
 
         self.hook_addr = Some(func_addr);
-        eprintln!(
+        log::info!(
             "[ProcessCreateMonitor] Enabled on PspInsertProcess @ {:#x}",
             func_addr
         );
@@ -98,28 +129,29 @@ impl ProcessCreateMonitor {
         if let Some(addr) = self.hook_addr.take() {
             let vmi_lock = vmi.lock().unwrap();
             hooks.remove_hook(&vmi_lock, addr)?;
-            eprintln!("[ProcessCreateMonitor] Disabled");
+            log::info!("[ProcessCreateMonitor] Disabled");
         }
         Ok(())
     }
 
     /// callback when PspInsertProcess is hit
-    fn on_process_create(ctx: &HookContext, offsets: &ProcessOffsets) {
+    fn on_process_create(ctx: &HookContext, offsets: &ProcessOffsets, events: &EventSink) {
         // RCX = EPROCESS pointer per MSVC x64 ABI
         let eprocess_addr = match ctx.vmi.get_vcpureg(RCX as u64, ctx.vcpu_id) {
             Ok(addr) => addr,
             Err(_) => return,
         };
 
-        let vmi = ctx.vmi;
+        let vmi = &ctx.vmi;
 
         // read process info
+        // both are HANDLE-typed, read pointer width
         let pid = vmi
-            .read_32_va(eprocess_addr + offsets.pid_offset, 0)
+            .read_ptr(eprocess_addr + offsets.pid_offset, 0)
             .unwrap_or(0);
         let ppid = vmi
-            .read_addr_va(eprocess_addr + offsets.parent_pid_offset, 0)
-            .unwrap_or(0) as u32;
+            .read_ptr(eprocess_addr + offsets.parent_pid_offset, 0)
+            .unwrap_or(0);
         let create_time = vmi
             .read_addr_va(eprocess_addr + offsets.create_time_offset, 0)
             .unwrap_or(0);
@@ -129,46 +161,60 @@ impl ProcessCreateMonitor {
             .read_addr_va(eprocess_addr + offsets.dtb_offset, 0)
             .unwrap_or(0);
 
-        let mut cmd_line = String::from("<unknown>");
-        let mut image_path = String::from("<unknown>");
-
+        // the new process's user space may still be settling, so each
+        // string gets one more try with the vm paused before giving up
+        let mut cmd_line = None;
+        let mut image_path = None;
         if dtb != 0 {
-            if let Ok(peb_addr) = vmi.read_addr_va(eprocess_addr + offsets.peb_offset, 0) {
-                if peb_addr != 0 {
-                    // PEB in user space, need DTB for translation
-                    if let Ok(peb_pa) = vmi.translate_uv2p(dtb, peb_addr) {
-                        let params_ptr_bytes = vmi
-                            .read_pa(peb_pa + offsets.process_params_offset, 8)
-                            .unwrap_or_default();
-                        let params_addr =
-                            u64::from_le_bytes(params_ptr_bytes.try_into().unwrap_or([0; 8]));
-
-                        if params_addr != 0 {
-                            if let Ok(s) = vmi.read_unicode_string_dtb(
-                                dtb,
-                                params_addr + offsets.command_line_offset,
-                            ) {
-                                if !s.is_empty() {
-                                    cmd_line = s;
-                                }
-                            }
-                            if let Ok(s) = vmi.read_unicode_string_dtb(
-                                dtb,
-                                params_addr + offsets.image_path_offset,
-                            ) {
-                                if !s.is_empty() {
-                                    image_path = s;
-                                }
-                            }
-                        }
-                    }
-                }
+            let read = |field: u64| {
+                vmi.retry_paused(|vmi| read_params_string(vmi, offsets, eprocess_addr, dtb, field))
+                    .ok()
+                    .filter(|s| !s.is_empty())
+            };
+            cmd_line = read(offsets.command_line_offset);
+            image_path = read(offsets.image_path_offset);
+        }
+
+        let mut environment = None;
+        if let Some(cache) = &offsets.environment {
+            if dtb != 0 {
+                environment = read_environment(vmi, cache, eprocess_addr, dtb).ok();
             }
         }
 
-        println!(
-            "Process Create | PID: {} | PPID: {} | Image: {} | CmdLine: {} | Time: {}",
-            pid, ppid, image_path, cmd_line, create_time
-        );
+        events.emit(GuestEvent::ProcessCreated {
+            pid,
+            ppid,
+            image: image_path,
+            cmdline: cmd_line,
+            time: create_time,
+            environment,
+        });
+    }
+}
+
+/// a UNICODE_STRING in the process's RTL_USER_PROCESS_PARAMETERS
+fn read_params_string(
+    vmi: &Vmi,
+    offsets: &ProcessOffsets,
+    eprocess_addr: u64,
+    dtb: u64,
+    field: u64,
+) -> Result<String> {
+    let peb_addr = vmi.read_addr_va(eprocess_addr + offsets.peb_offset, 0)?;
+    if peb_addr == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    // PEB in user space, need DTB for translation
+    let peb_pa = vmi.translate_uv2p(dtb, peb_addr)?;
+    let mut params_ptr_bytes = [0u8; 8];
+    vmi.read_pa_into(
+        peb_pa + offsets.process_params_offset,
+        &mut params_ptr_bytes,
+    )?;
+    let params_addr = u64::from_le_bytes(params_ptr_bytes);
+    if params_addr == 0 {
+        return Err(VmiError::NullPointer);
     }
+    vmi.read_unicode_string_dtb_max(dtb, params_addr + field, offsets.max_string_bytes)
 }
@@ -0,0 +1,261 @@
+//! _HANDLE_TABLE decoding, shared by PspCidTable and per-process handle tables
+//!
+//! TableCode's low 2 bits are the number of indirection levels (0-2), the
+//! rest points at the top page. leaf pages hold 256 16-byte entries, the
+//! pages above them hold 512 pointers. a handle value is its index * 4.
+
+use crate::error::{Result, VmiError};
+use crate::vmi::{Vmi, PAGE_SIZE};
+
+const ENTRY_SIZE: u64 = 16;
+/// _HANDLE_TABLE_ENTRYs per leaf page
+pub const LOWLEVEL_COUNT: u64 = PAGE_SIZE / ENTRY_SIZE;
+/// page pointers per mid/high level page
+pub const MIDLEVEL_COUNT: u64 = PAGE_SIZE / 8;
+/// handle values step by 4, the low bits are tag bits
+pub const HANDLE_VALUE_INC: u64 = 4;
+
+/// how an entry's first qword stores the object pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryFormat {
+    /// 8.1+: ObjectPointerBits in bits 20-63, shifted left 4 and sign-filled
+    PointerBits,
+    /// 7 and older: the pointer itself with lock/attribute bits in the low 3
+    Legacy,
+}
+
+/// split TableCode into (top page, level)
+pub fn decode_table_code(table_code: u64) -> (u64, u32) {
+    (table_code & !3, (table_code & 3) as u32)
+}
+
+/// object pointer stored in an entry, None for free entries
+pub fn decode_entry(value: u64, format: EntryFormat) -> Option<u64> {
+    let ptr = match format {
+        EntryFormat::PointerBits => {
+            let bits = value >> 20;
+            if bits == 0 {
+                return None;
+            }
+            (bits << 4) | 0xffff_0000_0000_0000
+        }
+        EntryFormat::Legacy => value & !7,
+    };
+    (ptr != 0).then_some(ptr)
+}
+
+pub fn handle_to_index(handle: u64) -> u64 {
+    handle / HANDLE_VALUE_INC
+}
+
+pub fn index_to_handle(index: u64) -> u64 {
+    index * HANDLE_VALUE_INC
+}
+
+/// address of the entry for `index`, walking down the levels
+pub fn entry_address(vmi: &Vmi, table_code: u64, index: u64) -> Result<u64> {
+    entry_address_with(table_code, index, |addr| vmi.read_addr_va(addr, 0))
+}
+
+/// entry_address with page pointers read through `read_addr`
+pub fn entry_address_with(
+    table_code: u64,
+    index: u64,
+    mut read_addr: impl FnMut(u64) -> Result<u64>,
+) -> Result<u64> {
+    let (top, level) = decode_table_code(table_code);
+    let leaf = match level {
+        0 => top,
+        1 => read_addr(top + (index / LOWLEVEL_COUNT) * 8)?,
+        2 => {
+            let per_mid = LOWLEVEL_COUNT * MIDLEVEL_COUNT;
+            let mid = read_addr(top + (index / per_mid) * 8)?;
+            if mid == 0 {
+                return Err(VmiError::NullPointer);
+            }
+            read_addr(mid + ((index % per_mid) / LOWLEVEL_COUNT) * 8)?
+        }
+        _ => {
+            return Err(VmiError::Other(format!("bad handle table level {}", level)));
+        }
+    };
+    if leaf == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    Ok(leaf + (index % LOWLEVEL_COUNT) * ENTRY_SIZE)
+}
+
+/// object pointer for one handle value
+pub fn lookup(vmi: &Vmi, table_code: u64, handle: u64, format: EntryFormat) -> Result<Option<u64>> {
+    let entry = entry_address(vmi, table_code, handle_to_index(handle))?;
+    Ok(decode_entry(vmi.read_addr_va(entry, 0)?, format))
+}
+
+/// every in-use entry as (handle value, object pointer).
+/// unreadable pages are skipped rather than failing the walk
+pub fn walk(vmi: &Vmi, table_code: u64, format: EntryFormat) -> Result<Vec<(u64, u64)>> {
+    let (top, level) = decode_table_code(table_code);
+    if top == 0 {
+        return Err(VmiError::NullPointer);
+    }
+
+    // collect the leaf pages, index base of each in entries
+    let mut leaves = Vec::new();
+    match level {
+        0 => leaves.push((top, 0)),
+        1 => collect_pointers(vmi, top, 0, LOWLEVEL_COUNT, &mut leaves),
+        2 => {
+            let mut mids = Vec::new();
+            let per_mid = LOWLEVEL_COUNT * MIDLEVEL_COUNT;
+            collect_pointers(vmi, top, 0, per_mid, &mut mids);
+            for (mid, base) in mids {
+                collect_pointers(vmi, mid, base, LOWLEVEL_COUNT, &mut leaves);
+            }
+        }
+        _ => {
+            return Err(VmiError::Other(format!("bad handle table level {}", level)));
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (leaf, base) in leaves {
+        let page = match vmi.read_va_dtb(vmi.kernel_dtb()?, leaf, PAGE_SIZE as usize) {
+            Ok(page) => page,
+            Err(_) => continue,
+        };
+        // entry 0 of every leaf is reserved
+        for (i, entry) in page.chunks_exact(ENTRY_SIZE as usize).enumerate().skip(1) {
+            let value = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            if let Some(object) = decode_entry(value, format) {
+                entries.push((index_to_handle(base + i as u64), object));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// non-null page pointers of a directory page, with the entry index each starts at
+fn collect_pointers(vmi: &Vmi, page: u64, base: u64, span: u64, out: &mut Vec<(u64, u64)>) {
+    for i in 0..MIDLEVEL_COUNT {
+        match vmi.read_addr_va(page + i * 8, 0) {
+            Ok(0) => break,
+            Ok(ptr) => out.push((ptr, base + i * span)),
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn reader(memory: &HashMap<u64, u64>) -> impl FnMut(u64) -> Result<u64> + '_ {
+        |addr| {
+            memory.get(&addr).copied().ok_or(VmiError::ReadFailed {
+                addr,
+                msg: "unmapped".into(),
+            })
+        }
+    }
+
+    #[test]
+    fn page_geometry() {
+        assert_eq!(LOWLEVEL_COUNT, 256);
+        assert_eq!(MIDLEVEL_COUNT, 512);
+    }
+
+    #[test]
+    fn table_code_level_bits() {
+        // win10 PspCidTable, one level of indirection
+        assert_eq!(
+            decode_table_code(0xffff_c00c_4a20_a001),
+            (0xffff_c00c_4a20_a000, 1)
+        );
+        assert_eq!(
+            decode_table_code(0xffff_c00c_4a20_a000),
+            (0xffff_c00c_4a20_a000, 0)
+        );
+        assert_eq!(decode_table_code(0x8a40_2002), (0x8a40_2000, 2));
+    }
+
+    #[test]
+    fn handle_values_step_by_four() {
+        // pid 4 is System, index 1 of the cid table
+        assert_eq!(handle_to_index(4), 1);
+        assert_eq!(handle_to_index(0x1a4), 0x69);
+        assert_eq!(index_to_handle(0x69), 0x1a4);
+        // tag bits are dropped
+        assert_eq!(handle_to_index(0x1a7), 0x69);
+    }
+
+    #[test]
+    fn pointer_bits_entry() {
+        // 8.1+: ObjectPointerBits = 0x9b0ca5fee08 at bit 20, lock, refcount
+        // and attribute bits set below it
+        let value = 0x9b0c_a5fe_e08f_ffff;
+        assert_eq!(
+            decode_entry(value, EntryFormat::PointerBits),
+            Some(0xffff_9b0c_a5fe_e080)
+        );
+        // only the low bits set is a free entry
+        assert_eq!(decode_entry(0xf_ffff, EntryFormat::PointerBits), None);
+        assert_eq!(decode_entry(0, EntryFormat::PointerBits), None);
+    }
+
+    #[test]
+    fn legacy_entry() {
+        // win7 x64, lock bit and attributes in the low 3 bits
+        assert_eq!(
+            decode_entry(0xffff_fa80_0cd4_a061, EntryFormat::Legacy),
+            Some(0xffff_fa80_0cd4_a060)
+        );
+        assert_eq!(decode_entry(0x7, EntryFormat::Legacy), None);
+    }
+
+    #[test]
+    fn level_0_entry() {
+        let top = 0xffff_c00c_4a20_a000;
+        let entry = entry_address_with(top, 5, |_| unreachable!()).unwrap();
+        assert_eq!(entry, top + 5 * 16);
+    }
+
+    #[test]
+    fn level_1_entry() {
+        let top = 0xffff_c00c_4a20_a000;
+        let leaf1 = 0xffff_c00c_4b00_0000;
+        let memory = HashMap::from([(top, 0xffff_c00c_4a30_0000), (top + 8, leaf1)]);
+        // index 300 is entry 44 of the second leaf
+        let entry = entry_address_with(top | 1, 300, reader(&memory)).unwrap();
+        assert_eq!(entry, leaf1 + 44 * 16);
+    }
+
+    #[test]
+    fn level_2_entry() {
+        let top = 0xffff_c00c_4a20_a000;
+        let mid1 = 0xffff_c00c_4c00_0000;
+        let leaf = 0xffff_c00c_4d00_0000;
+        let memory = HashMap::from([(top + 8, mid1), (mid1 + 3 * 8, leaf)]);
+        // second mid page, its fourth leaf, entry 5
+        let index = 256 * 512 + 3 * 256 + 5;
+        let entry = entry_address_with(top | 2, index, reader(&memory)).unwrap();
+        assert_eq!(entry, leaf + 5 * 16);
+        assert_eq!(handle_to_index(index_to_handle(index)), index);
+    }
+
+    #[test]
+    fn missing_pages_are_null_pointers() {
+        let top = 0x1000;
+        let memory = HashMap::from([(top, 0)]);
+        assert!(matches!(
+            entry_address_with(top | 1, 0, reader(&memory)),
+            Err(VmiError::NullPointer)
+        ));
+        assert!(matches!(
+            entry_address_with(top | 2, 0, reader(&memory)),
+            Err(VmiError::NullPointer)
+        ));
+        assert!(entry_address_with(top | 3, 0, reader(&memory)).is_err());
+    }
+}
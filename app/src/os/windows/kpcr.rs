@@ -0,0 +1,169 @@
+//! KPCR based lookups - the per-vcpu processor control region
+//!
+//! in kernel mode GS base points at the vcpu's KPCR. in user mode it points
+//! at the TEB and the kernel value is parked in the shadow GS (swapgs).
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::error::{Result, VmiError};
+use crate::ffi::x86_regs;
+use crate::hook::HookContext;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::vmi::{Vmi, IA32_GS_BASE, IA32_KERNEL_GS_BASE};
+
+/// start of the canonical kernel half on x64
+const KERNEL_SPACE_START: u64 = 0xffff_8000_0000_0000;
+
+fn is_kernel_address(addr: u64) -> bool {
+    addr >= KERNEL_SPACE_START
+}
+
+/// get the KPCR address of a vcpu from whichever GS base holds a kernel pointer
+pub fn kpcr(vmi: &Vmi, vcpu: u32) -> Result<u64> {
+    let gs = vmi.read_msr(IA32_GS_BASE, vcpu)?;
+    if is_kernel_address(gs) {
+        return Ok(gs);
+    }
+
+    let shadow_gs = vmi.read_msr(IA32_KERNEL_GS_BASE, vcpu)?;
+    if is_kernel_address(shadow_gs) {
+        return Ok(shadow_gs);
+    }
+
+    Err(VmiError::Other(format!(
+        "no kernel GS base on vcpu {} (gs={:#x}, shadow={:#x})",
+        vcpu, gs, shadow_gs
+    )))
+}
+
+/// get the KPCR from an event's register snapshot, no extra vcpu queries.
+/// CPL 0 means GS base is the kernel one, otherwise it's still in shadow GS
+pub fn kpcr_from_regs(regs: &x86_regs) -> Result<u64> {
+    let kpcr = if regs.cs_sel & 3 == 0 {
+        regs.gs_base
+    } else {
+        regs.shadow_gs
+    };
+    if is_kernel_address(kpcr) {
+        Ok(kpcr)
+    } else {
+        Err(VmiError::Other(format!(
+            "no kernel GS base in regs (gs={:#x}, shadow={:#x})",
+            regs.gs_base, regs.shadow_gs
+        )))
+    }
+}
+
+/// what is executing on a vcpu
+#[derive(Debug, Clone)]
+pub struct CurrentThread {
+    /// ETHREAD address
+    pub thread: u64,
+    /// EPROCESS the thread is attached to
+    pub process: u64,
+    pub pid: u64,
+    pub name: String,
+}
+
+/// get the ETHREAD running on a vcpu given its KPCR
+fn thread_from_kpcr(vmi: &Vmi, offsets: &WindowsOffsets, kpcr: u64) -> Result<u64> {
+    let prcb_offset = offsets.kpcr_prcb.get()?;
+    let thread_offset = offsets.prcb_current_thread.get()?;
+
+    let thread = vmi.read_addr_va(kpcr + prcb_offset + thread_offset, 0)?;
+    if thread == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    Ok(thread)
+}
+
+/// get the EPROCESS a thread is attached to (KTHREAD.ApcState.Process)
+fn process_from_thread(vmi: &Vmi, offsets: &WindowsOffsets, thread: u64) -> Result<u64> {
+    let apc_state_offset = offsets.kthread_apc_state.get()?;
+    let process_offset = offsets.apc_state_process.get()?;
+
+    let process = vmi.read_addr_va(thread + apc_state_offset + process_offset, 0)?;
+    if process == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    Ok(process)
+}
+
+fn describe_thread(vmi: &Vmi, offsets: &WindowsOffsets, kpcr: u64) -> Result<CurrentThread> {
+    let thread = thread_from_kpcr(vmi, offsets, kpcr)?;
+    let process = process_from_thread(vmi, offsets, thread)?;
+    let pid = vmi.read_ptr(process + offsets.pid.get()?, 0)?;
+    let name = vmi
+        .read_str_va(process + offsets.pname.get()?, 0)
+        .unwrap_or_default();
+    Ok(CurrentThread {
+        thread,
+        process,
+        pid,
+        name,
+    })
+}
+
+/// get the ETHREAD running on a vcpu (KPCR.Prcb.CurrentThread)
+pub fn current_thread(vmi: &Vmi, offsets: &WindowsOffsets, vcpu: u32) -> Result<CurrentThread> {
+    describe_thread(vmi, offsets, kpcr(vmi, vcpu)?)
+}
+
+/// get the EPROCESS of the thread running on a vcpu
+/// KPCR.Prcb.CurrentThread -> KTHREAD.ApcState.Process
+pub fn current_process(vmi: &Vmi, offsets: &WindowsOffsets, vcpu: u32) -> Result<u64> {
+    let thread = thread_from_kpcr(vmi, offsets, kpcr(vmi, vcpu)?)?;
+    process_from_thread(vmi, offsets, thread)
+}
+
+/// per-vcpu KPCR addresses, fixed after boot so safe to keep around.
+/// on a hook hit this leaves only the thread/process/pid/name reads
+#[derive(Default)]
+pub struct KpcrCache {
+    kpcrs: RwLock<HashMap<u32, u64>>,
+}
+
+impl KpcrCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// cached KPCR of a vcpu, queried from the vcpu on first use
+    pub fn kpcr(&self, vmi: &Vmi, vcpu: u32) -> Result<u64> {
+        if let Some(&kpcr) = self.kpcrs.read().unwrap().get(&vcpu) {
+            return Ok(kpcr);
+        }
+        let kpcr = kpcr(vmi, vcpu)?;
+        self.kpcrs.write().unwrap().insert(vcpu, kpcr);
+        Ok(kpcr)
+    }
+
+    /// what is executing on a vcpu
+    pub fn current_thread(
+        &self,
+        vmi: &Vmi,
+        offsets: &WindowsOffsets,
+        vcpu: u32,
+    ) -> Result<CurrentThread> {
+        describe_thread(vmi, offsets, self.kpcr(vmi, vcpu)?)
+    }
+
+    /// what is executing on the vcpu that hit a hook. a cache miss is filled
+    /// from the event registers, so this stays valid before the CR3 switch
+    pub fn from_hook(&self, ctx: &HookContext, offsets: &WindowsOffsets) -> Result<CurrentThread> {
+        let cached = self.kpcrs.read().unwrap().get(&ctx.vcpu_id).copied();
+        let kpcr = match cached {
+            Some(kpcr) => kpcr,
+            None => {
+                let kpcr = match unsafe { ctx.regs.as_ref() } {
+                    Some(regs) => kpcr_from_regs(regs)?,
+                    None => kpcr(&ctx.vmi, ctx.vcpu_id)?,
+                };
+                self.kpcrs.write().unwrap().insert(ctx.vcpu_id, kpcr);
+                kpcr
+            }
+        };
+        describe_thread(&ctx.vmi, offsets, kpcr)
+    }
+}
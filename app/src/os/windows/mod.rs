@@ -1,24 +1,158 @@
-use crate::vmi::Vmi;
+use std::sync::{Arc, OnceLock};
+
+use crate::error::Result;
+use crate::hook::{HookContext, HookManager};
+use crate::vmi::{OsType, Vmi};
 
 pub mod actions;
+pub mod cid_table;
 pub mod events;
+pub mod handle_table;
+pub mod kpcr;
+pub mod object_directory;
+pub mod offsets;
+pub mod pe;
+pub mod stack;
+pub mod user_hook;
+pub mod vad;
+pub mod version;
+
+use super::{ModuleInfo, Os, Rejected};
+use actions::list_modules::ListModules;
+use actions::list_processes::{ListProcesses, ProcessList};
+use actions::list_threads::{ListThreads, ThreadInfo};
+use kpcr::{CurrentThread, KpcrCache};
+use offsets::WindowsOffsets;
+use user_hook::UserHook;
+
+/// what a session's events know about a windows guest, built once at init
+#[derive(Debug, Clone)]
+pub struct WindowsGuest {
+    offsets: Arc<WindowsOffsets>,
+    build_number: Option<u32>,
+}
 
-use super::Os;
+impl WindowsGuest {
+    /// resolve the offsets and read the build, missing pieces are deferred
+    /// errors (offsets) or None (build)
+    pub fn load(vmi: &Vmi) -> Self {
+        Self {
+            offsets: Arc::new(WindowsOffsets::load(vmi)),
+            build_number: version::build_number(vmi).ok(),
+        }
+    }
+
+    pub fn offsets(&self) -> &Arc<WindowsOffsets> {
+        &self.offsets
+    }
 
+    /// NtBuildNumber, e.g. 19045. None when it wasn't readable at init
+    pub fn build_number(&self) -> Option<u32> {
+        self.build_number
+    }
+}
+
+/// Os implementation for a windows guest, owning its vmi. the one
+/// windows type outside Session, which runs the same actions on its loop
 pub struct WindowsOs {
     vmi: Vmi,
+    offsets: OnceLock<Arc<WindowsOffsets>>,
+    kpcrs: KpcrCache,
 }
 
 impl WindowsOs {
-    // custom new removed to avoid double-free. use Os::new(vmi) instead.
+    /// Os::new, but refuses a vmi that didn't detect a windows guest and
+    /// hands it back
+    pub fn try_new(vmi: Vmi) -> std::result::Result<Self, Rejected> {
+        match super::expect_os(vmi.os_type(), OsType::Windows) {
+            Ok(()) => Ok(Self::new(vmi)),
+            Err(e) => Err((e, Box::new(vmi))),
+        }
+    }
+
+    /// offsets cache, resolved on first use
+    pub fn offsets(&self) -> Arc<WindowsOffsets> {
+        self.offsets
+            .get_or_init(|| Arc::new(WindowsOffsets::load(&self.vmi)))
+            .clone()
+    }
+
+    /// running processes, same walker the Session path uses via ListProcesses
+    pub fn list_processes(&self) -> Result<ProcessList> {
+        self.execute(ListProcesses::new(self.offsets()))
+    }
+
+    /// loaded kernel modules from PsLoadedModuleList
+    pub fn list_modules(&self) -> Result<Vec<ModuleInfo>> {
+        self.execute(ListModules::new(self.offsets()))
+    }
+
+    /// threads of a process from its ThreadListHead
+    pub fn list_threads(&self, pid: u64) -> Result<Vec<ThreadInfo>> {
+        self.execute(ListThreads::new(self.offsets(), pid))
+    }
+
+    /// thread executing on a vcpu, read via its KPCR rather than CR3
+    pub fn current_thread(&self, vcpu: u32) -> Result<CurrentThread> {
+        self.kpcrs.current_thread(&self.vmi, &self.offsets(), vcpu)
+    }
+
+    /// hook `module!export` in one process, unpatched when the handle drops
+    pub fn hook_user_api<F>(
+        &self,
+        hooks: &Arc<HookManager>,
+        pid: u64,
+        module: &str,
+        export: &str,
+        callback: F,
+    ) -> Result<UserHook>
+    where
+        F: Fn(&HookContext) + Send + Sync + 'static,
+    {
+        user_hook::hook_user_api(
+            &self.vmi,
+            &self.offsets(),
+            hooks,
+            pid,
+            module,
+            export,
+            callback,
+        )
+    }
+
+    /// process executing on a vcpu, same as current_thread but named for intent
+    pub fn current_process(&self, vcpu: u32) -> Result<CurrentThread> {
+        self.current_thread(vcpu)
+    }
 }
 
 impl Os for WindowsOs {
     fn new(vmi: Vmi) -> Self {
-        Self { vmi }
+        Self {
+            vmi,
+            offsets: OnceLock::new(),
+            kpcrs: KpcrCache::new(),
+        }
     }
 
     fn vmi(&self) -> &Vmi {
         &self.vmi
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os::{capabilities, OsHandle};
+
+    #[test]
+    fn handle_wraps_the_one_windows_os() {
+        let os = WindowsOs::new(Vmi::detached());
+        // offsets wait for first use, building the Os reads nothing
+        assert!(os.offsets.get().is_none());
+
+        let handle = OsHandle::Windows(os);
+        assert_eq!(handle.os_type(), OsType::Windows);
+        assert_eq!(handle.capabilities(), capabilities(OsType::Windows));
+    }
+}
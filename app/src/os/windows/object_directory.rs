@@ -0,0 +1,473 @@
+//! object manager namespace - \Driver, \Device, \BaseNamedObjects, ...
+//!
+//! ObpRootDirectoryObject is an _OBJECT_DIRECTORY: 37 hash buckets, each a
+//! singly linked chain of _OBJECT_DIRECTORY_ENTRY { ChainLink, Object }.
+//! an object's name lives in the _OBJECT_HEADER_NAME_INFO in front of its
+//! _OBJECT_HEADER, its type comes from ObTypeIndexTable[TypeIndex].
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::error::{Result, VmiError};
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// _OBJECT_DIRECTORY.HashBuckets length
+pub const NUMBER_HASH_BUCKETS: usize = 37;
+/// guards against corrupted chains
+pub const MAX_ENTRIES_PER_DIRECTORY: usize = 4096;
+/// deepest nesting followed by recursive walks
+pub const MAX_DEPTH: usize = 8;
+
+/// InfoMask bit for _OBJECT_HEADER_NAME_INFO
+const NAME_INFO_BIT: u8 = 0x2;
+
+/// one named object in a directory
+#[derive(Debug, Clone)]
+pub struct ObjectEntry {
+    pub name: String,
+    /// e.g. "Directory", "Driver", "Mutant", "Event"
+    pub type_name: String,
+    /// object body
+    pub object: u64,
+    /// full namespace path, e.g. \Driver\Null
+    pub path: String,
+}
+
+/// objects found under a path, with the directories only partly read
+#[derive(Debug, Clone, Default)]
+pub struct ObjectListing {
+    pub objects: Vec<ObjectEntry>,
+    /// directories cut off at MAX_ENTRIES_PER_DIRECTORY
+    pub truncated: Vec<String>,
+    /// subdirectories a recursive walk couldn't list, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+impl ObjectListing {
+    fn append(&mut self, other: ObjectListing) {
+        self.objects.extend(other.objects);
+        self.truncated.extend(other.truncated);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+/// split a raw bucket array into its pointers
+pub fn decode_pointers(bytes: &[u8], address_width: u8) -> Vec<u64> {
+    match address_width {
+        8 => bytes
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect(),
+        _ => bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes(c.try_into().unwrap()) as u64)
+            .collect(),
+    }
+}
+
+/// real type index, win10 xors TypeIndex with the second byte of the header
+/// address and ObHeaderCookie
+pub fn decode_type_index(raw: u8, header: u64, cookie: Option<u8>) -> u8 {
+    match cookie {
+        Some(cookie) => raw ^ ((header >> 8) as u8) ^ cookie,
+        None => raw,
+    }
+}
+
+/// bytes from an object header back to its _OBJECT_HEADER_NAME_INFO when
+/// ObpInfoMaskToOffset is missing. only CREATOR_INFO (bit 0) can precede
+/// NAME_INFO, both are four pointers wide
+pub fn name_info_distance(mask: u8, address_width: u8) -> u64 {
+    let size = 4 * address_width as u64;
+    if mask & 1 != 0 {
+        2 * size
+    } else {
+        size
+    }
+}
+
+/// object bodies on a directory's bucket chains, in bucket order, nulls
+/// skipped. a chain ends at a null or unreadable link or when it loops,
+/// the whole walk at MAX_ENTRIES_PER_DIRECTORY entries; the flag says
+/// whether that cap was hit
+pub fn chain_objects(
+    buckets: &[u64],
+    chain_link: u64,
+    object_offset: u64,
+    mut read_addr: impl FnMut(u64) -> Result<u64>,
+) -> (Vec<u64>, bool) {
+    let mut objects = Vec::new();
+    let mut seen = HashSet::new();
+    for &bucket in buckets {
+        let mut entry = bucket;
+        while entry != 0 && seen.len() < MAX_ENTRIES_PER_DIRECTORY && seen.insert(entry) {
+            if let Ok(object) = read_addr(entry + object_offset)
+                && object != 0
+            {
+                objects.push(object);
+            }
+            entry = read_addr(entry + chain_link).unwrap_or(0);
+        }
+    }
+    (objects, seen.len() >= MAX_ENTRIES_PER_DIRECTORY)
+}
+
+/// resolves object names and types, caching type names per index
+pub struct ObjectResolver<'a> {
+    vmi: &'a Vmi,
+    offsets: &'a WindowsOffsets,
+    cookie: Option<u8>,
+    /// ObpInfoMaskToOffset, absent on older builds
+    info_mask_to_offset: Option<u64>,
+    types: HashMap<u8, String>,
+}
+
+impl<'a> ObjectResolver<'a> {
+    pub fn new(vmi: &'a Vmi, offsets: &'a WindowsOffsets) -> Self {
+        let cookie = vmi
+            .ksym2v("ObHeaderCookie")
+            .and_then(|addr| vmi.read_8_va(addr, 0))
+            .ok();
+        Self {
+            vmi,
+            offsets,
+            cookie,
+            info_mask_to_offset: vmi.ksym2v("ObpInfoMaskToOffset").ok(),
+            types: HashMap::new(),
+        }
+    }
+
+    fn header(&self, object: u64) -> Result<u64> {
+        Ok(object - self.offsets.object_header_body.get()?)
+    }
+
+    /// name from the object's _OBJECT_HEADER_NAME_INFO, None if unnamed
+    pub fn name(&self, object: u64) -> Result<Option<String>> {
+        let header = self.header(object)?;
+        let mask = self
+            .vmi
+            .read_8_va(header + self.offsets.object_header_info_mask.get()?, 0)?;
+        if mask & NAME_INFO_BIT == 0 {
+            return Ok(None);
+        }
+
+        // optional headers sit below the header in InfoMask bit order
+        let distance = match self.info_mask_to_offset {
+            Some(table) => self.vmi.read_8_va(
+                table + (mask & (NAME_INFO_BIT | (NAME_INFO_BIT - 1))) as u64,
+                0,
+            )? as u64,
+            None => name_info_distance(mask, self.vmi.address_width()),
+        };
+
+        let name_info = header - distance;
+        let name = self
+            .vmi
+            .read_unicode_string(name_info + self.offsets.object_name_info_name.get()?, 0)?;
+        Ok(Some(name))
+    }
+
+    /// type name via ObTypeIndexTable
+    pub fn type_name(&mut self, object: u64) -> Result<String> {
+        let header = self.header(object)?;
+        let raw = self
+            .vmi
+            .read_8_va(header + self.offsets.object_header_type_index.get()?, 0)?;
+        let index = decode_type_index(raw, header, self.cookie);
+
+        if let Some(name) = self.types.get(&index) {
+            return Ok(name.clone());
+        }
+
+        let table = self.vmi.ksym2v("ObTypeIndexTable")?;
+        let width = self.vmi.address_width() as u64;
+        let object_type = self.vmi.read_addr_va(table + index as u64 * width, 0)?;
+        if object_type == 0 {
+            return Err(VmiError::NullPointer);
+        }
+        let name = self
+            .vmi
+            .read_unicode_string(object_type + self.offsets.object_type_name.get()?, 0)?;
+        self.types.insert(index, name.clone());
+        Ok(name)
+    }
+
+    /// objects directly under one _OBJECT_DIRECTORY, unnamed ones skipped
+    pub fn list_directory(&mut self, directory: u64, path: &str) -> Result<ObjectListing> {
+        let width = self.vmi.address_width();
+        let bytes = self.vmi.read_va(
+            directory + self.offsets.object_directory_buckets.get()?,
+            0,
+            NUMBER_HASH_BUCKETS * width as usize,
+        )?;
+        let chain_link = self.offsets.directory_entry_chain_link.get()?;
+        let object_offset = self.offsets.directory_entry_object.get()?;
+
+        let vmi = self.vmi;
+        let (objects, truncated) = chain_objects(
+            &decode_pointers(&bytes, width),
+            chain_link,
+            object_offset,
+            |addr| vmi.read_addr_va(addr, 0),
+        );
+
+        let mut listing = ObjectListing::default();
+        for object in objects {
+            if let Ok(Some(name)) = self.name(object) {
+                let type_name = self
+                    .type_name(object)
+                    .unwrap_or_else(|_| "<unknown>".into());
+                listing.objects.push(ObjectEntry {
+                    path: join(path, &name),
+                    name,
+                    type_name,
+                    object,
+                });
+            }
+        }
+        if truncated {
+            listing.truncated.push(path.to_string());
+        }
+
+        Ok(listing)
+    }
+
+    /// directory object at a namespace path such as \Driver, case-insensitive.
+    /// symbolic links are not followed
+    pub fn find_directory(&mut self, path: &str) -> Result<u64> {
+        let mut directory = self.vmi.read_addr_ksym("ObpRootDirectoryObject")?;
+        let mut current = String::new();
+
+        for component in path.split('\\').filter(|c| !c.is_empty()).take(MAX_DEPTH) {
+            let entry = self
+                .list_directory(directory, &current)?
+                .objects
+                .into_iter()
+                .find(|e| e.name.eq_ignore_ascii_case(component))
+                .ok_or_else(|| VmiError::Other(format!("no object {} in {}", component, path)))?;
+            if entry.type_name != "Directory" {
+                return Err(VmiError::Other(format!(
+                    "{} is a {}, not a directory",
+                    entry.path, entry.type_name
+                )));
+            }
+            directory = entry.object;
+            current = entry.path;
+        }
+
+        Ok(directory)
+    }
+
+    /// every object below a directory, descending at most `depth` levels.
+    /// a subdirectory that can't be listed is recorded and walked past
+    pub fn walk(&mut self, directory: u64, path: &str, depth: usize) -> Result<ObjectListing> {
+        let listing = self.list_directory(directory, path)?;
+        if depth == 0 {
+            return Ok(listing);
+        }
+
+        let mut all = ObjectListing {
+            objects: Vec::with_capacity(listing.objects.len()),
+            truncated: listing.truncated,
+            skipped: Vec::new(),
+        };
+        for entry in listing.objects {
+            let subdirectory = (entry.type_name == "Directory").then_some(entry.object);
+            let subpath = entry.path.clone();
+            all.objects.push(entry);
+            if let Some(subdirectory) = subdirectory {
+                match self.walk(subdirectory, &subpath, depth - 1) {
+                    Ok(children) => all.append(children),
+                    Err(e) => all.skipped.push((subpath, e.to_string())),
+                }
+            }
+        }
+        Ok(all)
+    }
+}
+
+fn join(parent: &str, name: &str) -> String {
+    format!("{}\\{}", parent.trim_end_matches('\\'), name)
+}
+
+/// named objects under a namespace path
+pub struct ListObjects {
+    offsets: Arc<WindowsOffsets>,
+    path: String,
+    depth: usize,
+}
+
+impl ListObjects {
+    pub fn new(offsets: Arc<WindowsOffsets>, path: &str) -> Self {
+        Self {
+            offsets,
+            path: path.to_string(),
+            depth: 0,
+        }
+    }
+
+    /// also descend into subdirectories, capped at MAX_DEPTH
+    pub fn recursive(mut self, depth: usize) -> Self {
+        self.depth = depth.min(MAX_DEPTH);
+        self
+    }
+}
+
+impl Action<ObjectListing> for ListObjects {
+    fn execute(&self, vmi: &Vmi) -> Result<ObjectListing> {
+        vmi.read_consistent(|vmi| {
+            let mut resolver = ObjectResolver::new(vmi, &self.offsets);
+            let directory = resolver.find_directory(&self.path)?;
+            let root = self.path.trim_end_matches('\\');
+            resolver.walk(directory, root, self.depth)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a little-endian guest memory window starting at `base`
+    struct Fixture {
+        base: u64,
+        bytes: Vec<u8>,
+    }
+
+    impl Fixture {
+        fn new(base: u64, len: usize) -> Self {
+            Self {
+                base,
+                bytes: vec![0; len],
+            }
+        }
+
+        fn put(&mut self, addr: u64, value: u64) {
+            let at = (addr - self.base) as usize;
+            self.bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        fn read_addr(&self, addr: u64) -> Result<u64> {
+            let at = addr
+                .checked_sub(self.base)
+                .map(|at| at as usize)
+                .filter(|&at| at + 8 <= self.bytes.len())
+                .ok_or_else(|| VmiError::ReadFailed {
+                    addr,
+                    msg: "outside fixture".into(),
+                })?;
+            Ok(u64::from_le_bytes(
+                self.bytes[at..at + 8].try_into().unwrap(),
+            ))
+        }
+    }
+
+    // win10 x64 _OBJECT_DIRECTORY_ENTRY: ChainLink +0x0, Object +0x8
+    const CHAIN_LINK: u64 = 0x0;
+    const OBJECT: u64 = 0x8;
+
+    #[test]
+    fn decodes_bucket_pointers() {
+        let x64 = [
+            0x30, 0x12, 0x4a, 0x8c, 0x0f, 0xc8, 0xff, 0xff, // 0xffffc80f8c4a1230
+            0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(decode_pointers(&x64, 8), [0xffff_c80f_8c4a_1230, 0]);
+
+        let x86 = [0x58, 0x3e, 0x81, 0x8b, 0, 0, 0, 0, 0x10, 0x20];
+        // a trailing partial pointer is dropped
+        assert_eq!(decode_pointers(&x86, 4), [0x8b81_3e58, 0]);
+    }
+
+    #[test]
+    fn directory_bucket_array_is_37_pointers() {
+        let bytes = vec![0u8; NUMBER_HASH_BUCKETS * 8];
+        assert_eq!(decode_pointers(&bytes, 8).len(), NUMBER_HASH_BUCKETS);
+    }
+
+    #[test]
+    fn walks_bucket_chains() {
+        let base = 0xffff_8000_0000_1000;
+        let mut mem = Fixture::new(base, 0x400);
+        let buckets = base;
+        let (a, b, c, d) = (base + 0x200, base + 0x220, base + 0x240, base + 0x260);
+
+        // bucket 0: a -> b, bucket 5: c with a null object, bucket 36: d
+        mem.put(buckets, a);
+        mem.put(buckets + 5 * 8, c);
+        mem.put(buckets + 36 * 8, d);
+        mem.put(a + CHAIN_LINK, b);
+        mem.put(a + OBJECT, 0xffff_9000_0000_0a00);
+        mem.put(b + OBJECT, 0xffff_9000_0000_0b00);
+        mem.put(d + OBJECT, 0xffff_9000_0000_0d00);
+
+        let bucket_bytes = &mem.bytes[..NUMBER_HASH_BUCKETS * 8];
+        let (objects, truncated) = chain_objects(
+            &decode_pointers(bucket_bytes, 8),
+            CHAIN_LINK,
+            OBJECT,
+            |addr| mem.read_addr(addr),
+        );
+        assert_eq!(
+            objects,
+            [
+                0xffff_9000_0000_0a00,
+                0xffff_9000_0000_0b00,
+                0xffff_9000_0000_0d00
+            ]
+        );
+        assert!(!truncated);
+    }
+
+    #[test]
+    fn chain_stops_at_a_loop_or_bad_link() {
+        let base = 0x1000;
+        let mut mem = Fixture::new(base, 0x100);
+        let (a, b) = (base + 0x40, base + 0x60);
+        mem.put(a + CHAIN_LINK, b);
+        mem.put(a + OBJECT, 0xa0);
+        mem.put(b + CHAIN_LINK, a);
+        mem.put(b + OBJECT, 0xb0);
+        let (objects, _) = chain_objects(&[a], CHAIN_LINK, OBJECT, |addr| mem.read_addr(addr));
+        assert_eq!(objects, [0xa0, 0xb0]);
+
+        // b's link points outside readable memory
+        mem.put(b + CHAIN_LINK, 0xdead_0000);
+        let (objects, _) = chain_objects(&[a], CHAIN_LINK, OBJECT, |addr| mem.read_addr(addr));
+        assert_eq!(objects, [0xa0, 0xb0]);
+    }
+
+    #[test]
+    fn chain_walk_is_capped() {
+        // every entry links to the next, never ending
+        let (objects, truncated) =
+            chain_objects(&[0x10], CHAIN_LINK, OBJECT, |addr| Ok(addr + 0x10));
+        assert_eq!(objects.len(), MAX_ENTRIES_PER_DIRECTORY);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn type_index_cookie() {
+        // win10: Directory is index 3, header 0xffffc28f4d2a1530, cookie 0x9c
+        let header = 0xffff_c28f_4d2a_1530;
+        assert_eq!(decode_type_index(0x8a, header, Some(0x9c)), 3);
+        assert_eq!(decode_type_index(3, header, None), 3);
+    }
+
+    #[test]
+    fn name_info_sits_below_creator_info() {
+        // _OBJECT_HEADER_NAME_INFO and _CREATOR_INFO are 0x20 bytes on x64
+        assert_eq!(name_info_distance(NAME_INFO_BIT, 8), 0x20);
+        assert_eq!(name_info_distance(NAME_INFO_BIT | 1, 8), 0x40);
+        assert_eq!(name_info_distance(NAME_INFO_BIT | 1, 4), 0x20);
+    }
+
+    #[test]
+    fn joins_paths() {
+        assert_eq!(join("", "Driver"), "\\Driver");
+        assert_eq!(join("\\", "Driver"), "\\Driver");
+        assert_eq!(join("\\Driver", "Null"), "\\Driver\\Null");
+    }
+}
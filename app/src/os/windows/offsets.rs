@@ -0,0 +1,481 @@
+//! windows structure offsets, resolved once per session and shared
+//!
+//! every offset the crate uses is looked up in one pass. a failed lookup is
+//! recorded rather than returned, so a profile missing e.g. the PEB layout
+//! still lets the process list work - the error only surfaces when a
+//! feature calls `get()` on the missing offset.
+
+use crate::error::{Result, VmiError};
+use crate::vmi::Vmi;
+
+/// a single named offset which may have failed to resolve
+#[derive(Debug, Clone, Copy)]
+pub struct Offset {
+    name: &'static str,
+    value: Option<u64>,
+}
+
+impl Offset {
+    pub fn new(name: &'static str, value: Option<u64>) -> Self {
+        Self { name, value }
+    }
+
+    /// resolved value, or the lookup error deferred from load time
+    pub fn get(&self) -> Result<u64> {
+        self.value
+            .ok_or_else(|| VmiError::OffsetNotFound(self.name.into()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn is_resolved(&self) -> bool {
+        self.value.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowsOffsets {
+    /// EPROCESS.ActiveProcessLinks (libvmi win_tasks)
+    pub tasks: Offset,
+    /// EPROCESS.ImageFileName (libvmi win_pname)
+    pub pname: Offset,
+    /// EPROCESS.UniqueProcessId (libvmi win_pid)
+    pub pid: Offset,
+    pub parent_pid: Offset,
+    pub create_time: Offset,
+    pub dtb: Offset,
+    /// _KPROCESS.UserDirectoryTableBase, only present on KPTI-aware builds
+    pub user_dtb: Offset,
+    pub se_audit_process_creation_info: Offset,
+    pub audit_image_file_name: Offset,
+    pub object_name_information_name: Offset,
+    pub peb: Offset,
+    pub process_params: Offset,
+    pub peb_ldr: Offset,
+    pub peb_process_heap: Offset,
+    pub ldr_in_load_order: Offset,
+    pub command_line: Offset,
+    pub image_path: Offset,
+    pub environment: Offset,
+    pub environment_size: Offset,
+    pub kpcr_prcb: Offset,
+    pub prcb_current_thread: Offset,
+    pub kthread_apc_state: Offset,
+    pub apc_state_process: Offset,
+    pub kthread_kernel_stack: Offset,
+    /// thread list of a process, _ETHREAD entries
+    pub thread_list_head: Offset,
+    pub thread_list_entry: Offset,
+    pub thread_cid: Offset,
+    pub client_id_unique_thread: Offset,
+    pub thread_start_address: Offset,
+    pub kthread_teb: Offset,
+    /// VAD tree (_EPROCESS.VadRoot is an _RTL_AVL_TREE, root pointer first)
+    pub vad_root: Offset,
+    pub balanced_node_left: Offset,
+    pub balanced_node_right: Offset,
+    pub vad_starting_vpn: Offset,
+    pub vad_ending_vpn: Offset,
+    pub vad_starting_vpn_high: Offset,
+    pub vad_ending_vpn_high: Offset,
+    pub vad_flags: Offset,
+    pub vad_subsection: Offset,
+    pub subsection_control_area: Offset,
+    pub control_area_file_pointer: Offset,
+    pub file_object_file_name: Offset,
+    /// object manager namespace
+    pub object_header_body: Offset,
+    pub object_header_type_index: Offset,
+    pub object_header_info_mask: Offset,
+    pub object_name_info_name: Offset,
+    pub object_type_name: Offset,
+    pub object_directory_buckets: Offset,
+    pub directory_entry_chain_link: Offset,
+    pub directory_entry_object: Offset,
+    pub handle_table_code: Offset,
+    /// only present on 8.1+ where entries hold a compressed object pointer
+    pub handle_entry_pointer_bits: Offset,
+    /// _LDR_DATA_TABLE_ENTRY fields for PsLoadedModuleList
+    pub ldr_links: Offset,
+    pub ldr_dll_base: Offset,
+    pub ldr_size_of_image: Offset,
+    pub ldr_base_dll_name: Offset,
+    pub ldr_full_dll_name: Offset,
+    /// _LIST_ENTRY.Blink, one pointer in, so it gives the profile's width
+    pub list_entry_blink: Offset,
+}
+
+impl WindowsOffsets {
+    /// resolve every offset, recording failures instead of returning them
+    pub fn load(vmi: &Vmi) -> Self {
+        Self::resolve(
+            |name| vmi.get_offset(name).ok(),
+            |s, f| vmi.get_struct_offset(s, f).ok(),
+        )
+    }
+
+    /// load with the lookups supplied: `config_offset` for libvmi config
+    /// names such as win_tasks, `struct_offset` for (struct, field) pairs
+    pub fn resolve(
+        config_offset: impl Fn(&str) -> Option<u64>,
+        struct_offset: impl Fn(&str, &str) -> Option<u64>,
+    ) -> Self {
+        let config = |name: &'static str| Offset::new(name, config_offset(name));
+        let field = |name: &'static str, s: &str, f: &str| Offset::new(name, struct_offset(s, f));
+
+        Self {
+            tasks: config("win_tasks"),
+            pname: config("win_pname"),
+            pid: config("win_pid"),
+            parent_pid: field(
+                "_EPROCESS.InheritedFromUniqueProcessId",
+                "_EPROCESS",
+                "InheritedFromUniqueProcessId",
+            ),
+            create_time: field("_EPROCESS.CreateTime", "_EPROCESS", "CreateTime"),
+            dtb: field(
+                "_KPROCESS.DirectoryTableBase",
+                "_KPROCESS",
+                "DirectoryTableBase",
+            ),
+            user_dtb: field(
+                "_KPROCESS.UserDirectoryTableBase",
+                "_KPROCESS",
+                "UserDirectoryTableBase",
+            ),
+            se_audit_process_creation_info: field(
+                "_EPROCESS.SeAuditProcessCreationInfo",
+                "_EPROCESS",
+                "SeAuditProcessCreationInfo",
+            ),
+            audit_image_file_name: field(
+                "_SE_AUDIT_PROCESS_CREATION_INFO.ImageFileName",
+                "_SE_AUDIT_PROCESS_CREATION_INFO",
+                "ImageFileName",
+            ),
+            object_name_information_name: field(
+                "_OBJECT_NAME_INFORMATION.Name",
+                "_OBJECT_NAME_INFORMATION",
+                "Name",
+            ),
+            peb: field("_EPROCESS.Peb", "_EPROCESS", "Peb"),
+            process_params: field("_PEB.ProcessParameters", "_PEB", "ProcessParameters"),
+            peb_ldr: field("_PEB.Ldr", "_PEB", "Ldr"),
+            peb_process_heap: field("_PEB.ProcessHeap", "_PEB", "ProcessHeap"),
+            ldr_in_load_order: field(
+                "_PEB_LDR_DATA.InLoadOrderModuleList",
+                "_PEB_LDR_DATA",
+                "InLoadOrderModuleList",
+            ),
+            command_line: field(
+                "_RTL_USER_PROCESS_PARAMETERS.CommandLine",
+                "_RTL_USER_PROCESS_PARAMETERS",
+                "CommandLine",
+            ),
+            image_path: field(
+                "_RTL_USER_PROCESS_PARAMETERS.ImagePathName",
+                "_RTL_USER_PROCESS_PARAMETERS",
+                "ImagePathName",
+            ),
+            environment: field(
+                "_RTL_USER_PROCESS_PARAMETERS.Environment",
+                "_RTL_USER_PROCESS_PARAMETERS",
+                "Environment",
+            ),
+            environment_size: field(
+                "_RTL_USER_PROCESS_PARAMETERS.EnvironmentSize",
+                "_RTL_USER_PROCESS_PARAMETERS",
+                "EnvironmentSize",
+            ),
+            kpcr_prcb: field("_KPCR.Prcb", "_KPCR", "Prcb"),
+            prcb_current_thread: field("_KPRCB.CurrentThread", "_KPRCB", "CurrentThread"),
+            kthread_apc_state: field("_KTHREAD.ApcState", "_KTHREAD", "ApcState"),
+            apc_state_process: field("_KAPC_STATE.Process", "_KAPC_STATE", "Process"),
+            kthread_kernel_stack: field("_KTHREAD.KernelStack", "_KTHREAD", "KernelStack"),
+            thread_list_head: field("_EPROCESS.ThreadListHead", "_EPROCESS", "ThreadListHead"),
+            thread_list_entry: field("_ETHREAD.ThreadListEntry", "_ETHREAD", "ThreadListEntry"),
+            thread_cid: field("_ETHREAD.Cid", "_ETHREAD", "Cid"),
+            client_id_unique_thread: field("_CLIENT_ID.UniqueThread", "_CLIENT_ID", "UniqueThread"),
+            thread_start_address: field(
+                "_ETHREAD.Win32StartAddress",
+                "_ETHREAD",
+                "Win32StartAddress",
+            ),
+            kthread_teb: field("_KTHREAD.Teb", "_KTHREAD", "Teb"),
+            vad_root: field("_EPROCESS.VadRoot", "_EPROCESS", "VadRoot"),
+            balanced_node_left: field("_RTL_BALANCED_NODE.Left", "_RTL_BALANCED_NODE", "Left"),
+            balanced_node_right: field("_RTL_BALANCED_NODE.Right", "_RTL_BALANCED_NODE", "Right"),
+            vad_starting_vpn: field("_MMVAD_SHORT.StartingVpn", "_MMVAD_SHORT", "StartingVpn"),
+            vad_ending_vpn: field("_MMVAD_SHORT.EndingVpn", "_MMVAD_SHORT", "EndingVpn"),
+            vad_starting_vpn_high: field(
+                "_MMVAD_SHORT.StartingVpnHigh",
+                "_MMVAD_SHORT",
+                "StartingVpnHigh",
+            ),
+            vad_ending_vpn_high: field(
+                "_MMVAD_SHORT.EndingVpnHigh",
+                "_MMVAD_SHORT",
+                "EndingVpnHigh",
+            ),
+            vad_flags: field("_MMVAD_SHORT.u", "_MMVAD_SHORT", "u"),
+            vad_subsection: field("_MMVAD.Subsection", "_MMVAD", "Subsection"),
+            subsection_control_area: field("_SUBSECTION.ControlArea", "_SUBSECTION", "ControlArea"),
+            control_area_file_pointer: field(
+                "_CONTROL_AREA.FilePointer",
+                "_CONTROL_AREA",
+                "FilePointer",
+            ),
+            file_object_file_name: field("_FILE_OBJECT.FileName", "_FILE_OBJECT", "FileName"),
+            object_header_body: field("_OBJECT_HEADER.Body", "_OBJECT_HEADER", "Body"),
+            object_header_type_index: field(
+                "_OBJECT_HEADER.TypeIndex",
+                "_OBJECT_HEADER",
+                "TypeIndex",
+            ),
+            object_header_info_mask: field("_OBJECT_HEADER.InfoMask", "_OBJECT_HEADER", "InfoMask"),
+            object_name_info_name: field(
+                "_OBJECT_HEADER_NAME_INFO.Name",
+                "_OBJECT_HEADER_NAME_INFO",
+                "Name",
+            ),
+            object_type_name: field("_OBJECT_TYPE.Name", "_OBJECT_TYPE", "Name"),
+            object_directory_buckets: field(
+                "_OBJECT_DIRECTORY.HashBuckets",
+                "_OBJECT_DIRECTORY",
+                "HashBuckets",
+            ),
+            directory_entry_chain_link: field(
+                "_OBJECT_DIRECTORY_ENTRY.ChainLink",
+                "_OBJECT_DIRECTORY_ENTRY",
+                "ChainLink",
+            ),
+            directory_entry_object: field(
+                "_OBJECT_DIRECTORY_ENTRY.Object",
+                "_OBJECT_DIRECTORY_ENTRY",
+                "Object",
+            ),
+            handle_table_code: field("_HANDLE_TABLE.TableCode", "_HANDLE_TABLE", "TableCode"),
+            handle_entry_pointer_bits: field(
+                "_HANDLE_TABLE_ENTRY.ObjectPointerBits",
+                "_HANDLE_TABLE_ENTRY",
+                "ObjectPointerBits",
+            ),
+            ldr_links: field(
+                "_LDR_DATA_TABLE_ENTRY.InLoadOrderLinks",
+                "_LDR_DATA_TABLE_ENTRY",
+                "InLoadOrderLinks",
+            ),
+            ldr_dll_base: field(
+                "_LDR_DATA_TABLE_ENTRY.DllBase",
+                "_LDR_DATA_TABLE_ENTRY",
+                "DllBase",
+            ),
+            ldr_size_of_image: field(
+                "_LDR_DATA_TABLE_ENTRY.SizeOfImage",
+                "_LDR_DATA_TABLE_ENTRY",
+                "SizeOfImage",
+            ),
+            ldr_base_dll_name: field(
+                "_LDR_DATA_TABLE_ENTRY.BaseDllName",
+                "_LDR_DATA_TABLE_ENTRY",
+                "BaseDllName",
+            ),
+            ldr_full_dll_name: field(
+                "_LDR_DATA_TABLE_ENTRY.FullDllName",
+                "_LDR_DATA_TABLE_ENTRY",
+                "FullDllName",
+            ),
+            list_entry_blink: field("_LIST_ENTRY.Blink", "_LIST_ENTRY", "Blink"),
+        }
+    }
+
+    /// every offset, in declaration order
+    pub fn all(&self) -> Vec<&Offset> {
+        vec![
+            &self.tasks,
+            &self.pname,
+            &self.pid,
+            &self.parent_pid,
+            &self.create_time,
+            &self.dtb,
+            &self.user_dtb,
+            &self.se_audit_process_creation_info,
+            &self.audit_image_file_name,
+            &self.object_name_information_name,
+            &self.peb,
+            &self.process_params,
+            &self.peb_ldr,
+            &self.peb_process_heap,
+            &self.ldr_in_load_order,
+            &self.command_line,
+            &self.image_path,
+            &self.environment,
+            &self.environment_size,
+            &self.kpcr_prcb,
+            &self.prcb_current_thread,
+            &self.kthread_apc_state,
+            &self.apc_state_process,
+            &self.kthread_kernel_stack,
+            &self.thread_list_head,
+            &self.thread_list_entry,
+            &self.thread_cid,
+            &self.client_id_unique_thread,
+            &self.thread_start_address,
+            &self.kthread_teb,
+            &self.vad_root,
+            &self.balanced_node_left,
+            &self.balanced_node_right,
+            &self.vad_starting_vpn,
+            &self.vad_ending_vpn,
+            &self.vad_starting_vpn_high,
+            &self.vad_ending_vpn_high,
+            &self.vad_flags,
+            &self.vad_subsection,
+            &self.subsection_control_area,
+            &self.control_area_file_pointer,
+            &self.file_object_file_name,
+            &self.object_header_body,
+            &self.object_header_type_index,
+            &self.object_header_info_mask,
+            &self.object_name_info_name,
+            &self.object_type_name,
+            &self.object_directory_buckets,
+            &self.directory_entry_chain_link,
+            &self.directory_entry_object,
+            &self.handle_table_code,
+            &self.handle_entry_pointer_bits,
+            &self.ldr_links,
+            &self.ldr_dll_base,
+            &self.ldr_size_of_image,
+            &self.ldr_base_dll_name,
+            &self.ldr_full_dll_name,
+            &self.list_entry_blink,
+        ]
+    }
+
+    /// names of the offsets that failed to resolve
+    pub fn report(&self) -> Vec<&'static str> {
+        self.all()
+            .into_iter()
+            .filter(|o| !o.is_resolved())
+            .map(|o| o.name())
+            .collect()
+    }
+
+    /// error unless the profile describes a kernel of the guest's pointer
+    /// width. a 64-bit profile on a 32-bit guest resolves every offset, but
+    /// each read through them lands in the wrong field
+    pub fn check_width(&self, vmi: &Vmi) -> Result<()> {
+        self.check_width_against(vmi.address_width())
+    }
+
+    /// `check_width` against a guest pointer width in bytes
+    fn check_width_against(&self, guest: u8) -> Result<()> {
+        let guest = guest as u64;
+        match self.list_entry_blink.get() {
+            Ok(profile) if profile != guest => Err(VmiError::WidthMismatch {
+                profile: profile as u32 * 8,
+                guest: guest as u32 * 8,
+            }),
+            // nothing to compare against, trust the profile
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::HashSet;
+
+    /// a profile with every offset except the _PEB layout
+    fn without_peb() -> WindowsOffsets {
+        WindowsOffsets::resolve(|_| Some(0x2e8), |s, _| (s != "_PEB").then_some(0x18))
+    }
+
+    #[test]
+    fn missing_offsets_fail_only_when_used() {
+        let offsets = without_peb();
+        assert_eq!(offsets.tasks.get().unwrap(), 0x2e8);
+        assert_eq!(offsets.peb.get().unwrap(), 0x18);
+        match offsets.process_params.get() {
+            Err(VmiError::OffsetNotFound(name)) => assert_eq!(name, "_PEB.ProcessParameters"),
+            other => panic!("expected OffsetNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn report_names_the_unresolved() {
+        assert_eq!(
+            without_peb().report(),
+            ["_PEB.ProcessParameters", "_PEB.Ldr", "_PEB.ProcessHeap"]
+        );
+        let full = WindowsOffsets::resolve(|_| Some(0), |_, _| Some(0));
+        assert!(full.report().is_empty());
+    }
+
+    #[test]
+    fn all_lists_every_offset_once() {
+        let lookups = Cell::new(0);
+        let offsets = WindowsOffsets::resolve(
+            |_| {
+                lookups.set(lookups.get() + 1);
+                None
+            },
+            |_, _| {
+                lookups.set(lookups.get() + 1);
+                None
+            },
+        );
+        let names: HashSet<&str> = offsets.all().iter().map(|o| o.name()).collect();
+        assert_eq!(offsets.all().len(), lookups.get());
+        assert_eq!(names.len(), lookups.get());
+        assert_eq!(offsets.report().len(), lookups.get());
+    }
+
+    /// a profile whose _LIST_ENTRY.Blink sits one `width`-byte pointer in
+    fn with_pointer_width(width: u64) -> WindowsOffsets {
+        WindowsOffsets::resolve(
+            |_| None,
+            move |s, f| {
+                Some(if (s, f) == ("_LIST_ENTRY", "Blink") {
+                    width
+                } else {
+                    0
+                })
+            },
+        )
+    }
+
+    #[test]
+    fn matching_width_passes() {
+        assert!(with_pointer_width(8).check_width_against(8).is_ok());
+        assert!(with_pointer_width(4).check_width_against(4).is_ok());
+    }
+
+    #[test]
+    fn mismatched_width_names_both_in_bits() {
+        match with_pointer_width(8).check_width_against(4) {
+            Err(VmiError::WidthMismatch { profile, guest }) => {
+                assert_eq!((profile, guest), (64, 32))
+            }
+            other => panic!("expected WidthMismatch, got {:?}", other),
+        }
+        assert!(matches!(
+            with_pointer_width(4).check_width_against(8),
+            Err(VmiError::WidthMismatch {
+                profile: 32,
+                guest: 64
+            })
+        ));
+    }
+
+    #[test]
+    fn profile_without_blink_is_trusted() {
+        let offsets = WindowsOffsets::resolve(|_| None, |_, _| None);
+        assert!(offsets.check_width_against(4).is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+//! minimal PE parsing - export lookup in a mapped image
+//!
+//! works on the in-memory image of a module, so RVAs are plain offsets from
+//! the module base. only what hooking and hook detection need: exports.
+
+use crate::error::{Result, VmiError};
+use crate::vmi::{page_remaining, Vmi};
+
+/// IMAGE_DOS_HEADER.e_lfanew
+const DOS_LFANEW: u64 = 0x3c;
+/// IMAGE_NT_HEADERS.OptionalHeader
+const NT_OPTIONAL_HEADER: u64 = 0x18;
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+/// OptionalHeader.DataDirectory[IMAGE_DIRECTORY_ENTRY_EXPORT]
+const PE32_EXPORT_DIR: u64 = 0x60;
+const PE32_PLUS_EXPORT_DIR: u64 = 0x70;
+/// longest export name compared
+const MAX_EXPORT_NAME: usize = 256;
+
+fn read_u16(vmi: &Vmi, dtb: u64, vaddr: u64) -> Result<u16> {
+    let bytes = vmi.read_va_dtb(dtb, vaddr, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(vmi: &Vmi, dtb: u64, vaddr: u64) -> Result<u32> {
+    let bytes = vmi.read_va_dtb(dtb, vaddr, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// NUL-terminated ascii, stops early at an unreadable page
+fn read_cstr(vmi: &Vmi, dtb: u64, vaddr: u64) -> Result<String> {
+    let mut out = Vec::new();
+    let mut curr = vaddr;
+    while out.len() < MAX_EXPORT_NAME {
+        let to_read = std::cmp::min(page_remaining(curr), (MAX_EXPORT_NAME - out.len()) as u64);
+        let chunk = vmi.read_va_dtb(dtb, curr, to_read as usize)?;
+        if let Some(end) = chunk.iter().position(|&b| b == 0) {
+            out.extend_from_slice(&chunk[..end]);
+            break;
+        }
+        out.extend_from_slice(&chunk);
+        curr += to_read;
+    }
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// IMAGE_EXPORT_DIRECTORY fields, tables already made absolute
+struct ExportDirectory {
+    rva: u64,
+    size: u64,
+    num_names: u64,
+    functions: u64,
+    names: u64,
+    ordinals: u64,
+}
+
+impl ExportDirectory {
+    /// forwarded exports point back into the export directory
+    fn is_forwarder(&self, rva: u64) -> bool {
+        rva >= self.rva && rva < self.rva + self.size
+    }
+}
+
+/// locate the export directory, None if the image has no exports
+fn export_directory(vmi: &Vmi, dtb: u64, base: u64) -> Result<Option<ExportDirectory>> {
+    if read_u16(vmi, dtb, base)? != 0x5a4d {
+        return Err(VmiError::Other(format!("no MZ header at {:#x}", base)));
+    }
+    let nt = base + read_u32(vmi, dtb, base + DOS_LFANEW)? as u64;
+    if read_u32(vmi, dtb, nt)? != 0x4550 {
+        return Err(VmiError::Other(format!("no PE header at {:#x}", nt)));
+    }
+
+    let optional = nt + NT_OPTIONAL_HEADER;
+    let dir = match read_u16(vmi, dtb, optional)? {
+        PE32_MAGIC => optional + PE32_EXPORT_DIR,
+        PE32_PLUS_MAGIC => optional + PE32_PLUS_EXPORT_DIR,
+        magic => {
+            return Err(VmiError::Other(format!(
+                "unknown optional header magic {:#x}",
+                magic
+            )));
+        }
+    };
+    let rva = read_u32(vmi, dtb, dir)? as u64;
+    let size = read_u32(vmi, dtb, dir + 4)? as u64;
+    if rva == 0 {
+        return Ok(None);
+    }
+
+    // IMAGE_EXPORT_DIRECTORY
+    let exports = base + rva;
+    Ok(Some(ExportDirectory {
+        rva,
+        size,
+        num_names: read_u32(vmi, dtb, exports + 0x18)? as u64,
+        functions: base + read_u32(vmi, dtb, exports + 0x1c)? as u64,
+        names: base + read_u32(vmi, dtb, exports + 0x20)? as u64,
+        ordinals: base + read_u32(vmi, dtb, exports + 0x24)? as u64,
+    }))
+}
+
+/// resolve an exported function of the image mapped at `base`.
+/// the name table is sorted, so this is a binary search.
+/// forwarded exports (the RVA points back into the export directory) are
+/// reported as errors since they live in another module
+pub fn find_export(vmi: &Vmi, dtb: u64, base: u64, name: &str) -> Result<u64> {
+    let Some(dir) = export_directory(vmi, dtb, base)? else {
+        return Err(VmiError::SymbolNotFound(name.into()));
+    };
+
+    let (mut lo, mut hi) = (0u64, dir.num_names);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let name_rva = read_u32(vmi, dtb, dir.names + mid * 4)? as u64;
+        let candidate = read_cstr(vmi, dtb, base + name_rva)?;
+
+        match candidate.as_str().cmp(name) {
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => {
+                let ordinal = read_u16(vmi, dtb, dir.ordinals + mid * 2)? as u64;
+                let rva = read_u32(vmi, dtb, dir.functions + ordinal * 4)? as u64;
+                if dir.is_forwarder(rva) {
+                    let target = read_cstr(vmi, dtb, base + rva)?;
+                    return Err(VmiError::Other(format!(
+                        "{} is forwarded to {}",
+                        name, target
+                    )));
+                }
+                return Ok(base + rva);
+            }
+        }
+    }
+
+    Err(VmiError::SymbolNotFound(name.into()))
+}
+
+/// every named, non-forwarded export as `(name, address)`, in name order.
+/// entries whose name or address can't be read are skipped
+pub fn exports(vmi: &Vmi, dtb: u64, base: u64) -> Result<Vec<(String, u64)>> {
+    let Some(dir) = export_directory(vmi, dtb, base)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut out = Vec::with_capacity(dir.num_names as usize);
+    for i in 0..dir.num_names {
+        let Ok(name_rva) = read_u32(vmi, dtb, dir.names + i * 4) else {
+            continue;
+        };
+        let Ok(ordinal) = read_u16(vmi, dtb, dir.ordinals + i * 2) else {
+            continue;
+        };
+        let Ok(rva) = read_u32(vmi, dtb, dir.functions + ordinal as u64 * 4) else {
+            continue;
+        };
+        let rva = rva as u64;
+        if rva == 0 || dir.is_forwarder(rva) {
+            continue;
+        }
+        if let Ok(name) = read_cstr(vmi, dtb, base + name_rva as u64) {
+            out.push((name, base + rva));
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,167 @@
+//! heuristic call-stack unwinding
+//!
+//! there is no unwind info to work with, so frames are found by scanning the
+//! stack for values that land inside a loaded kernel module and on an
+//! executable page. anything that looks like a return address is reported:
+//! stale return addresses, function pointers and data that happens to fall
+//! in a module's range all show up as false positives, and frames in modules
+//! that aren't in the list (user mode, unlisted drivers) are skipped.
+
+use std::sync::Arc;
+
+use crate::error::{Result, VmiError};
+use crate::ffi::{CR3, RIP, RSP};
+use crate::hook::HookContext;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::ModuleInfo;
+use crate::symbols::SymbolResolver;
+use crate::vmi::{page_remaining, Vmi};
+
+/// bytes of stack scanned before giving up
+pub const MAX_STACK_SCAN: u64 = 0x4000;
+
+/// one candidate return address
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub addr: u64,
+    /// containing module, None if outside every listed module (only frame 0)
+    pub module: Option<String>,
+    /// nearest symbol when a profile is loaded
+    pub symbol: Option<String>,
+    /// offset from the symbol, or from the module base if there is no symbol
+    pub offset: u64,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let module = self.module.as_deref().unwrap_or("<unknown>");
+        match &self.symbol {
+            Some(sym) => write!(f, "{:#x} {}!{}+{:#x}", self.addr, module, sym, self.offset),
+            None => write!(f, "{:#x} {}+{:#x}", self.addr, module, self.offset),
+        }
+    }
+}
+
+/// scans stacks against a snapshot of the loaded module list
+pub struct StackWalker {
+    offsets: Arc<WindowsOffsets>,
+    /// sorted by base
+    modules: Vec<ModuleInfo>,
+    symbols: Option<Arc<SymbolResolver>>,
+}
+
+impl StackWalker {
+    /// `modules` is the executable range filter, e.g. from windows ListModules
+    pub fn new(
+        offsets: Arc<WindowsOffsets>,
+        mut modules: Vec<ModuleInfo>,
+        symbols: Option<Arc<SymbolResolver>>,
+    ) -> Self {
+        modules.sort_by_key(|m| m.base);
+        Self {
+            offsets,
+            modules,
+            symbols,
+        }
+    }
+
+    /// backtrace of whatever is running on a vcpu, starting at its RIP/RSP
+    pub fn stack_trace(&self, vmi: &Vmi, vcpu: u32, max_frames: usize) -> Result<Vec<Frame>> {
+        let rip = vmi.get_vcpureg(RIP as u64, vcpu)?;
+        let rsp = vmi.get_vcpureg(RSP as u64, vcpu)?;
+        let dtb = vmi.get_vcpureg(CR3 as u64, vcpu)?;
+        Ok(self.walk(vmi, dtb, Some(rip), rsp, max_frames))
+    }
+
+    /// backtrace at a hook hit, from the event's register snapshot
+    pub fn from_hook(&self, ctx: &HookContext, max_frames: usize) -> Result<Vec<Frame>> {
+        let regs = unsafe { ctx.regs.as_ref() }.ok_or(VmiError::NullPointer)?;
+        Ok(self.walk(&ctx.vmi, regs.cr3, Some(regs.rip), regs.rsp, max_frames))
+    }
+
+    /// backtrace of a thread that isn't running, from KTHREAD.KernelStack
+    pub fn stack_trace_for_thread(
+        &self,
+        vmi: &Vmi,
+        ethread: u64,
+        max_frames: usize,
+    ) -> Result<Vec<Frame>> {
+        let kernel_stack =
+            vmi.read_addr_va(ethread + self.offsets.kthread_kernel_stack.get()?, 0)?;
+        if kernel_stack == 0 {
+            return Err(VmiError::NullPointer);
+        }
+        let dtb = vmi.kernel_dtb()?;
+        Ok(self.walk(vmi, dtb, None, kernel_stack, max_frames))
+    }
+
+    fn walk(
+        &self,
+        vmi: &Vmi,
+        dtb: u64,
+        rip: Option<u64>,
+        rsp: u64,
+        max_frames: usize,
+    ) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        if let Some(rip) = rip {
+            frames.push(self.annotate(rip));
+        }
+
+        let mut curr = rsp & !7;
+        let end = curr.saturating_add(MAX_STACK_SCAN);
+        while curr < end && frames.len() < max_frames {
+            // page at a time, a stack ends at the first unmapped page
+            let to_read = std::cmp::min(page_remaining(curr), end - curr);
+            let chunk = match vmi.read_va_dtb(dtb, curr, to_read as usize) {
+                Ok(chunk) => chunk,
+                Err(_) => break,
+            };
+
+            for value in chunk
+                .chunks_exact(8)
+                .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            {
+                if frames.len() >= max_frames {
+                    break;
+                }
+                if self.is_code(vmi, dtb, value) {
+                    frames.push(self.annotate(value));
+                }
+            }
+            curr += to_read;
+        }
+
+        frames
+    }
+
+    fn module_for(&self, addr: u64) -> Option<&ModuleInfo> {
+        let idx = self.modules.partition_point(|m| m.base <= addr);
+        let module = self.modules.get(idx.checked_sub(1)?)?;
+        (addr < module.base + module.size).then_some(module)
+    }
+
+    /// inside a listed module and on an executable page
+    fn is_code(&self, vmi: &Vmi, dtb: u64, addr: u64) -> bool {
+        self.module_for(addr).is_some()
+            && vmi
+                .pagetable_lookup_extended(dtb, addr)
+                .map(|page| page.executable)
+                .unwrap_or(false)
+    }
+
+    fn annotate(&self, addr: u64) -> Frame {
+        let module = self.module_for(addr);
+        let symbol = self.symbols.as_ref().and_then(|s| s.resolve(addr));
+        let (symbol, offset) = match symbol {
+            Some((name, offset)) => (Some(name), offset),
+            None => (None, module.map(|m| addr - m.base).unwrap_or(0)),
+        };
+        Frame {
+            addr,
+            module: module.map(|m| m.name.clone()),
+            symbol,
+            offset,
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! user-mode API hooks scoped to one process
+//!
+//! finds the module in the process's PEB loader list, resolves the export
+//! from its mapped image and patches it through the process's page tables.
+//! system DLL code is a shared image mapping, so the INT3 lands in a frame
+//! that every process maps: other processes trap too but are filtered by
+//! CR3 and emulated past without a callback.
+
+use std::sync::Arc;
+
+use crate::error::{Result, VmiError};
+use crate::hook::{HookContext, HookManager};
+use crate::os::list::walk_list;
+use crate::os::windows::actions::list_processes::find_process;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::windows::pe::find_export;
+use crate::os::ModuleInfo;
+use crate::vmi::Vmi;
+
+/// modules loaded in a process, from PEB.Ldr.InLoadOrderModuleList
+pub fn process_modules(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    eprocess: u64,
+    pid: u32,
+) -> Result<Vec<ModuleInfo>> {
+    let links_offset = offsets.ldr_links.get()?;
+    let base_offset = offsets.ldr_dll_base.get()?;
+    let size_offset = offsets.ldr_size_of_image.get()?;
+    let name_offset = offsets.ldr_base_dll_name.get()?;
+
+    let peb = vmi.read_addr_va(eprocess + offsets.peb.get()?, 0)?;
+    if peb == 0 {
+        return Err(VmiError::NullPointer);
+    }
+    let ldr = vmi.read_addr_va(peb + offsets.peb_ldr.get()?, pid)?;
+    if ldr == 0 {
+        return Err(VmiError::NullPointer);
+    }
+
+    let head = ldr + offsets.ldr_in_load_order.get()?;
+    let mut modules = Vec::new();
+    for entry in walk_list(vmi, head, pid)? {
+        let ldr_entry = entry - links_offset;
+        // a paged-out entry would read back as a module at 0
+        if !vmi.is_mapped_va(ldr_entry, pid) {
+            continue;
+        }
+
+        let name = vmi
+            .read_unicode_string(ldr_entry + name_offset, pid)
+            .unwrap_or_else(|_| "<unknown>".into());
+        let base = vmi.read_addr_va(ldr_entry + base_offset, pid).unwrap_or(0);
+        let size = vmi.read_32_va(ldr_entry + size_offset, pid).unwrap_or(0) as u64;
+
+        modules.push(ModuleInfo {
+            name,
+            base,
+            size,
+            path: None,
+        });
+    }
+
+    Ok(modules)
+}
+
+/// an installed user API hook, unpatched on drop
+pub struct UserHook {
+    hooks: Arc<HookManager>,
+    addr: u64,
+    removed: bool,
+}
+
+impl UserHook {
+    /// patched address of the export
+    pub fn addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// unpatch now and report failures, drop does the same silently.
+    /// locks the hook manager's vmi, don't call while holding it
+    pub fn remove(mut self) -> Result<()> {
+        self.removed = true;
+        self.hooks.unhook(self.addr)
+    }
+}
+
+impl Drop for UserHook {
+    fn drop(&mut self) {
+        if !self.removed {
+            let _ = self.hooks.unhook(self.addr);
+        }
+    }
+}
+
+/// hook `module!export` in process `pid`, e.g. ("ntdll.dll", "NtProtectVirtualMemory").
+/// module names compare case-insensitively. the callback only runs for
+/// hits from this process.
+pub fn hook_user_api<F>(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    hooks: &Arc<HookManager>,
+    pid: u64,
+    module: &str,
+    export: &str,
+    callback: F,
+) -> Result<UserHook>
+where
+    F: Fn(&HookContext) + Send + Sync + 'static,
+{
+    let (addr, dtbs) = vmi.read_consistent(|vmi| {
+        let eprocess = find_process(vmi, offsets, pid)?;
+        let dtb = vmi.read_addr_va(eprocess + offsets.dtb.get()?, 0)?;
+
+        let base = process_modules(vmi, offsets, eprocess, pid as u32)?
+            .into_iter()
+            .find(|m| m.name.eq_ignore_ascii_case(module))
+            .map(|m| m.base)
+            .ok_or_else(|| VmiError::Other(format!("{} not loaded in pid {}", module, pid)))?;
+        let addr = find_export(vmi, dtb, base, export)?;
+
+        // with KPTI user mode runs on a separate root
+        let mut dtbs = vec![dtb];
+        if let Ok(user_offset) = offsets.user_dtb.get() {
+            let user_dtb = vmi.read_addr_va(eprocess + user_offset, 0).unwrap_or(0);
+            if user_dtb > 1 {
+                dtbs.push(user_dtb);
+            }
+        }
+        Ok((addr, dtbs))
+    })?;
+
+    hooks.add_process_hook(vmi, dtbs, addr, callback)?;
+
+    Ok(UserHook {
+        hooks: hooks.clone(),
+        addr,
+        removed: false,
+    })
+}
@@ -0,0 +1,281 @@
+//! VAD tree walking - a process's reserved/committed user address ranges
+//!
+//! EPROCESS.VadRoot is a balanced tree of _MMVAD_SHORT nodes. private
+//! allocations stop there; mapped files and images are full _MMVADs whose
+//! Subsection -> ControlArea -> FilePointer leads to the FILE_OBJECT.
+//! the flag bit positions are the windows 10 _MMVAD_FLAGS layout, which the
+//! profile can't describe since they are bitfields.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::os::windows::actions::list_processes::find_process;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::Action;
+use crate::vmi::Vmi;
+
+/// guards against corrupted trees
+pub const MAX_VAD_NODES: usize = 100_000;
+
+/// _MMVAD_FLAGS.VadType, bits 4-6
+const VAD_TYPE_SHIFT: u32 = 4;
+const VAD_TYPE_MASK: u32 = 0x7;
+/// _MMVAD_FLAGS.Protection, bits 7-11
+const PROTECTION_SHIFT: u32 = 7;
+const PROTECTION_MASK: u32 = 0x1f;
+/// _MMVAD_FLAGS.PrivateMemory
+const PRIVATE_MEMORY_BIT: u32 = 1 << 20;
+/// _MI_VAD_TYPE VadImageMap
+const VAD_IMAGE_MAP: u32 = 2;
+
+/// one VAD node
+#[derive(Debug, Clone)]
+pub struct VadRegion {
+    pub start: u64,
+    /// inclusive
+    pub end: u64,
+    /// MM_* protection index (not a PAGE_* constant)
+    pub protection: u32,
+    pub vad_type: u32,
+    pub private: bool,
+    /// mapped executable image (VadImageMap)
+    pub is_image: bool,
+    /// backing file for mapped sections
+    pub file_name: Option<String>,
+    /// _MMVAD_SHORT address
+    pub addr: u64,
+}
+
+/// object pointer in an EX_FAST_REF, the low bits hold a reference count
+/// (4 bits on x64, 3 on x86)
+pub fn ex_fast_ref_object(value: u64, address_width: u8) -> u64 {
+    let mask = if address_width == 8 { 0xF } else { 0x7 };
+    value & !mask
+}
+
+/// (start, inclusive end) byte addresses from a VAD's page numbers, the
+/// high bytes extending the 32-bit StartingVpn/EndingVpn
+pub fn vpn_range(start_low: u32, start_high: u8, end_low: u32, end_high: u8) -> (u64, u64) {
+    let start = (start_low as u64 | (start_high as u64) << 32) << 12;
+    let end = (((end_low as u64 | (end_high as u64) << 32) + 1) << 12) - 1;
+    (start, end)
+}
+
+/// (vad type, protection, private) from _MMVAD_FLAGS
+pub fn decode_vad_flags(flags: u32) -> (u32, u32, bool) {
+    (
+        (flags >> VAD_TYPE_SHIFT) & VAD_TYPE_MASK,
+        (flags >> PROTECTION_SHIFT) & PROTECTION_MASK,
+        flags & PRIVATE_MEMORY_BIT != 0,
+    )
+}
+
+/// ControlArea -> file name, many VADs map the same section
+#[derive(Default)]
+pub struct FileNameCache {
+    names: HashMap<u64, Option<String>>,
+}
+
+impl FileNameCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// file name behind a control area, resolved once per control area
+    pub fn resolve(
+        &mut self,
+        vmi: &Vmi,
+        offsets: &WindowsOffsets,
+        control_area: u64,
+    ) -> Option<String> {
+        self.names
+            .entry(control_area)
+            .or_insert_with(|| read_file_name(vmi, offsets, control_area))
+            .clone()
+    }
+}
+
+fn read_file_name(vmi: &Vmi, offsets: &WindowsOffsets, control_area: u64) -> Option<String> {
+    let fast_ref = vmi
+        .read_addr_va(
+            control_area + offsets.control_area_file_pointer.get().ok()?,
+            0,
+        )
+        .ok()?;
+    let file_object = ex_fast_ref_object(fast_ref, vmi.address_width());
+    if file_object == 0 {
+        return None;
+    }
+    vmi.read_unicode_string(file_object + offsets.file_object_file_name.get().ok()?, 0)
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// every VAD of a process in address order, no pausing
+pub fn walk_vads(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    eprocess: u64,
+    cache: &mut FileNameCache,
+) -> Result<Vec<VadRegion>> {
+    let left = offsets.balanced_node_left.get()?;
+    let right = offsets.balanced_node_right.get()?;
+
+    let root = vmi.read_addr_va(eprocess + offsets.vad_root.get()?, 0)?;
+
+    // iterative in-order walk
+    let mut regions = Vec::new();
+    let mut visited = HashSet::new();
+    let mut stack = Vec::new();
+    let mut node = root;
+    while (node != 0 || !stack.is_empty()) && visited.len() < MAX_VAD_NODES {
+        while node != 0 && visited.insert(node) {
+            stack.push(node);
+            node = vmi.read_addr_va(node + left, 0).unwrap_or(0);
+        }
+        let Some(current) = stack.pop() else { break };
+        regions.push(read_vad(vmi, offsets, current, cache)?);
+        node = vmi.read_addr_va(current + right, 0).unwrap_or(0);
+    }
+
+    Ok(regions)
+}
+
+fn read_vad(
+    vmi: &Vmi,
+    offsets: &WindowsOffsets,
+    vad: u64,
+    cache: &mut FileNameCache,
+) -> Result<VadRegion> {
+    let start_low = vmi.read_32_va(vad + offsets.vad_starting_vpn.get()?, 0)?;
+    let end_low = vmi.read_32_va(vad + offsets.vad_ending_vpn.get()?, 0)?;
+    // the high bytes only exist on builds with 57-bit user space support
+    let start_high = offsets
+        .vad_starting_vpn_high
+        .get()
+        .and_then(|o| vmi.read_8_va(vad + o, 0))
+        .unwrap_or(0);
+    let end_high = offsets
+        .vad_ending_vpn_high
+        .get()
+        .and_then(|o| vmi.read_8_va(vad + o, 0))
+        .unwrap_or(0);
+    let (start, end) = vpn_range(start_low, start_high, end_low, end_high);
+
+    let flags = vmi.read_32_va(vad + offsets.vad_flags.get()?, 0)?;
+    let (vad_type, protection, private) = decode_vad_flags(flags);
+
+    // only full _MMVADs (mapped sections) have a subsection
+    let file_name = if private {
+        None
+    } else {
+        offsets
+            .vad_subsection
+            .get()
+            .and_then(|o| vmi.read_addr_va(vad + o, 0))
+            .ok()
+            .filter(|&subsection| subsection != 0)
+            .and_then(|subsection| {
+                let o = offsets.subsection_control_area.get().ok()?;
+                vmi.read_addr_va(subsection + o, 0).ok()
+            })
+            .filter(|&control_area| control_area != 0)
+            .and_then(|control_area| cache.resolve(vmi, offsets, control_area))
+    };
+
+    Ok(VadRegion {
+        start,
+        end,
+        protection,
+        vad_type,
+        private,
+        is_image: vad_type == VAD_IMAGE_MAP,
+        file_name,
+        addr: vad,
+    })
+}
+
+/// VADs of one process by pid
+pub struct ListVads {
+    offsets: Arc<WindowsOffsets>,
+    pid: u64,
+}
+
+impl ListVads {
+    pub fn new(offsets: Arc<WindowsOffsets>, pid: u64) -> Self {
+        Self { offsets, pid }
+    }
+}
+
+impl Action<Vec<VadRegion>> for ListVads {
+    fn execute(&self, vmi: &Vmi) -> Result<Vec<VadRegion>> {
+        vmi.read_consistent(|vmi| {
+            let eprocess = find_process(vmi, &self.offsets, self.pid)?;
+            walk_vads(vmi, &self.offsets, eprocess, &mut FileNameCache::new())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_ref_x64_drops_4_refcount_bits() {
+        // ControlArea.FilePointer with a cached reference count of 0xb
+        assert_eq!(
+            ex_fast_ref_object(0xffff_c50f_3b2e_4a5b, 8),
+            0xffff_c50f_3b2e_4a50
+        );
+        assert_eq!(
+            ex_fast_ref_object(0xffff_c50f_3b2e_4a50, 8),
+            0xffff_c50f_3b2e_4a50
+        );
+        assert_eq!(ex_fast_ref_object(0xf, 8), 0);
+    }
+
+    #[test]
+    fn fast_ref_x86_drops_3_refcount_bits() {
+        assert_eq!(ex_fast_ref_object(0x8a3c_1e47, 4), 0x8a3c_1e40);
+        // bit 3 is part of the pointer on x86
+        assert_eq!(ex_fast_ref_object(0x8a3c_1e4f, 4), 0x8a3c_1e48);
+    }
+
+    #[test]
+    fn vpn_range_is_inclusive() {
+        let (start, end) = vpn_range(0x10, 0, 0x10, 0);
+        assert_eq!(start, 0x10000);
+        assert_eq!(end, 0x10fff);
+        assert_eq!(end - start + 1, 0x1000);
+    }
+
+    #[test]
+    fn vpn_high_byte_extends_past_32_bits() {
+        // ntdll at 0x7ffd2a9d0000-0x7ffd2abcffff, page numbers 0x7_ffd2a9d0
+        // and 0x7_ffd2abcf: only the low 32 bits fit in StartingVpn
+        let (start, end) = vpn_range(0xffd2_a9d0, 0x7, 0xffd2_abcf, 0x7);
+        assert_eq!(start, 0x7ffd_2a9d_0000);
+        assert_eq!(end, 0x7ffd_2abc_ffff);
+    }
+
+    #[test]
+    fn flags_of_an_image_mapping() {
+        // VadImageMap, MM_EXECUTE_WRITECOPY, not private
+        assert_eq!(decode_vad_flags(0x3a0), (VAD_IMAGE_MAP, 7, false));
+    }
+
+    #[test]
+    fn flags_of_private_memory() {
+        // VadNone, MM_READWRITE, PrivateMemory
+        assert_eq!(decode_vad_flags(0x10_0200), (0, 4, true));
+    }
+}
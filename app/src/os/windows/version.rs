@@ -0,0 +1,13 @@
+//! guest windows version
+
+use crate::error::Result;
+use crate::vmi::Vmi;
+
+/// NtBuildNumber's top nibble marks checked (0xC) vs free (0xF) builds
+const BUILD_NUMBER_MASK: u32 = 0xFFFF;
+
+/// OS build number, e.g. 7601 or 19045
+pub fn build_number(vmi: &Vmi) -> Result<u32> {
+    let addr = vmi.ksym2v("NtBuildNumber")?;
+    Ok(vmi.read_32_va(addr, 0)? & BUILD_NUMBER_MASK)
+}
@@ -0,0 +1,151 @@
+//! started and exited processes between two process list snapshots
+//!
+//! a pid alone isn't an identity, pids get reused. two listed processes
+//! are the same one when their pids match and so do their create times,
+//! or, when either listing lacks a create time, their object addresses.
+//! the address fallback only misses a reuse that also recycled the object
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::os::ProcessInfo;
+
+/// whether `a` and `b`, from different snapshots, are one process
+pub fn same_process(a: &ProcessInfo, b: &ProcessInfo) -> bool {
+    if a.pid != b.pid {
+        return false;
+    }
+    match (a.create_time, b.create_time) {
+        (Some(x), Some(y)) => x == y,
+        _ => a.addr == b.addr,
+    }
+}
+
+/// processes only in the newer or only in the older snapshot, each in
+/// its snapshot's list order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProcessDiff {
+    pub started: Vec<ProcessInfo>,
+    pub exited: Vec<ProcessInfo>,
+}
+
+impl ProcessDiff {
+    pub fn is_empty(&self) -> bool {
+        self.started.is_empty() && self.exited.is_empty()
+    }
+}
+
+/// compare two snapshots, `prev` taken before `next`
+pub fn diff(prev: &[ProcessInfo], next: &[ProcessInfo]) -> ProcessDiff {
+    ProcessDiff {
+        started: missing_from(next, prev),
+        exited: missing_from(prev, next),
+    }
+}
+
+/// processes of `from` with no match in `other`, in `from`'s order
+fn missing_from(from: &[ProcessInfo], other: &[ProcessInfo]) -> Vec<ProcessInfo> {
+    let mut by_pid: HashMap<u64, Vec<&ProcessInfo>> = HashMap::new();
+    for p in other {
+        by_pid.entry(p.pid).or_default().push(p);
+    }
+    from.iter()
+        .filter(|p| {
+            !by_pid
+                .get(&p.pid)
+                .is_some_and(|same_pid| same_pid.iter().any(|q| same_process(p, q)))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u64, addr: u64, create_time: Option<u64>) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            name: format!("p{}", pid),
+            addr,
+            command_line: None,
+            full_image_path: None,
+            credentials: None,
+            namespace_pid: None,
+            thread_count: None,
+            create_time,
+        }
+    }
+
+    fn pids(list: &[ProcessInfo]) -> Vec<u64> {
+        list.iter().map(|p| p.pid).collect()
+    }
+
+    #[test]
+    fn unchanged_snapshot_is_empty() {
+        let snap = vec![process(4, 0x1000, None), process(100, 0x2000, None)];
+        assert!(diff(&snap, &snap).is_empty());
+    }
+
+    #[test]
+    fn started_process() {
+        let prev = vec![process(4, 0x1000, None)];
+        let next = vec![process(4, 0x1000, None), process(200, 0x3000, None)];
+        let d = diff(&prev, &next);
+        assert_eq!(pids(&d.started), [200]);
+        assert!(d.exited.is_empty());
+    }
+
+    #[test]
+    fn exited_process() {
+        let prev = vec![process(4, 0x1000, None), process(200, 0x3000, None)];
+        let next = vec![process(4, 0x1000, None)];
+        let d = diff(&prev, &next);
+        assert!(d.started.is_empty());
+        assert_eq!(pids(&d.exited), [200]);
+    }
+
+    #[test]
+    fn pid_reuse_with_new_create_time() {
+        // same EPROCESS address recycled, only the create time tells
+        let prev = vec![process(300, 0x4000, Some(1000))];
+        let next = vec![process(300, 0x4000, Some(2000))];
+        let d = diff(&prev, &next);
+        assert_eq!(d.started[0].create_time, Some(2000));
+        assert_eq!(d.exited[0].create_time, Some(1000));
+    }
+
+    #[test]
+    fn pid_reuse_with_new_object_address() {
+        let prev = vec![process(300, 0x4000, None)];
+        let next = vec![process(300, 0x5000, None)];
+        let d = diff(&prev, &next);
+        assert_eq!(d.started[0].addr, 0x5000);
+        assert_eq!(d.exited[0].addr, 0x4000);
+    }
+
+    #[test]
+    fn create_time_in_one_snapshot_only_falls_back_to_address() {
+        let with = vec![process(300, 0x4000, Some(1000))];
+        let without = vec![process(300, 0x4000, None)];
+        assert!(diff(&with, &without).is_empty());
+        assert!(diff(&without, &with).is_empty());
+
+        let moved = vec![process(300, 0x5000, None)];
+        let d = diff(&with, &moved);
+        assert_eq!(pids(&d.started), [300]);
+        assert_eq!(pids(&d.exited), [300]);
+    }
+
+    #[test]
+    fn keeps_snapshot_order() {
+        let prev = vec![process(4, 0x1000, None)];
+        let next = vec![
+            process(9, 0x9000, None),
+            process(4, 0x1000, None),
+            process(7, 0x7000, None),
+        ];
+        assert_eq!(pids(&diff(&prev, &next).started), [9, 7]);
+    }
+}
@@ -1,28 +1,439 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::marker::PhantomData;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::error::Result;
-use crate::hook::HookManager;
-use crate::os::{Event, EventContext};
-use crate::vmi::Vmi;
+use serde::Serialize;
+
+use crate::dispatcher::{EventDispatcher, StepOutcome, LISTEN_TIMEOUT_MS};
+use crate::dump::{self, MemoryDump};
+use crate::error::{Result, VmiError};
+use crate::event_stream::{DropCounter, EventSink, GuestEvent, EVENT_QUEUE_LEN};
+use crate::hook::{HookManager, Registers};
+use crate::os::linux::actions::list_modules::ListModules as LinuxListModules;
+use crate::os::linux::actions::list_processes::ListProcesses as LinuxListProcesses;
+use crate::os::linux::events::process_create::ProcessCreateMonitor as LinuxProcessCreateMonitor;
+use crate::os::windows::actions::list_modules::ListModules;
+use crate::os::windows::actions::list_processes::ListProcesses;
+use crate::os::windows::actions::list_threads::{ListThreads, ThreadInfo};
+use crate::os::windows::actions::process_tree::{BuildProcessTree, ProcessTree};
+use crate::os::windows::events::process_create::ProcessCreateMonitor;
+use crate::os::windows::offsets::WindowsOffsets;
+use crate::os::{
+    capabilities, not_implemented, Capability, Event, EventContext, GuestOs, ModuleInfo,
+    MonitorKind, ProcessList,
+};
+use crate::stats::SessionStats;
+use crate::symbols::SymbolResolver;
+use crate::vmi::{AddressSpace, Backend, InitConfig, OsType, PageMode, Vmi, VmiBuilder};
+
+/// running event loop of a session, stopped and joined on drop
+pub struct SessionHandle<'a> {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<()>>>,
+    /// the loop dispatches into the session's hooks and events
+    _session: PhantomData<&'a Session>,
+}
+
+impl SessionHandle<'_> {
+    /// ask the loop to stop after the current listen round
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    /// false once stopped or the loop exited on an error
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+            && self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// wait for the loop to exit, returning its error if listening failed
+    pub fn join(mut self) -> Result<()> {
+        self.join_thread()
+    }
+
+    fn join_thread(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => join_loop(thread),
+            None => Ok(()),
+        }
+    }
+}
+
+/// wait for an event loop thread, turning a panic into an error
+pub(crate) fn join_loop(thread: JoinHandle<Result<()>>) -> Result<()> {
+    thread.join().unwrap_or_else(|panic| {
+        let msg = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".into());
+        Err(VmiError::Other(format!("event thread panicked: {}", msg)))
+    })
+}
+
+impl Drop for SessionHandle<'_> {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = self.join_thread();
+    }
+}
+
+/// whether the session still has its guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionState {
+    #[default]
+    Attached,
+    /// kvmi connection lost and being retried, hooks are still armed.
+    /// dropping the session now forgets them without writing
+    Disconnected,
+    /// the guest shut down and didn't come back within the reconnect
+    /// policy. hooks were dropped without writing, so teardown leaves guest
+    /// memory alone
+    GuestGone,
+}
+
+/// attachment state, shared with the loop thread
+#[derive(Debug, Default)]
+struct Lifecycle {
+    state: Mutex<SessionState>,
+    /// kernel load address at attach, a different one after a reconnect
+    /// means the guest rebooted and no hook site is valid anymore
+    kernel_base: Mutex<Option<u64>>,
+    /// successful reattaches
+    reconnects: AtomicU64,
+}
+
+impl Lifecycle {
+    fn set(&self, state: SessionState) {
+        *self.state.lock().unwrap() = state;
+    }
+}
+
+/// how a run_for went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSummary {
+    /// events dispatched to handlers
+    pub events: u64,
+    pub elapsed: Duration,
+    /// false when stopped early by the flag or an error
+    pub deadline_reached: bool,
+}
+
+/// identity and shape of the guest, see Session::info
+#[derive(Debug, Clone, Serialize)]
+pub struct VmInfo {
+    pub name: Option<String>,
+    pub vmid: u64,
+    pub os: OsType,
+    /// NtBuildNumber on windows guests, when readable
+    pub windows_build: Option<u32>,
+    /// bytes per pointer
+    pub address_width: u8,
+    /// vcpu 0's
+    pub page_mode: PageMode,
+    pub vcpus: u32,
+    pub memory_bytes: u64,
+    pub max_physical_address: u64,
+    pub kernel_base: Option<u64>,
+}
+
+/// general purpose and control registers of one vcpu, see Session::registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VcpuRegisters {
+    pub vcpu: u32,
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl VcpuRegisters {
+    fn new(vcpu: u32, regs: &Registers) -> Self {
+        Self {
+            vcpu,
+            rax: regs.rax,
+            rbx: regs.rbx,
+            rcx: regs.rcx,
+            rdx: regs.rdx,
+            rsi: regs.rsi,
+            rdi: regs.rdi,
+            rbp: regs.rbp,
+            rsp: regs.rsp,
+            r8: regs.r8,
+            r9: regs.r9,
+            r10: regs.r10,
+            r11: regs.r11,
+            r12: regs.r12,
+            r13: regs.r13,
+            r14: regs.r14,
+            r15: regs.r15,
+            rip: regs.rip,
+            rflags: regs.rflags,
+            cr0: regs.cr0,
+            cr3: regs.cr3,
+            cr4: regs.cr4,
+        }
+    }
+}
+
+/// handle to an event added to a session, used to remove it again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventId(u64);
 
 pub struct Session {
     vmi: Arc<Mutex<Vmi>>,
+    dispatcher: Arc<EventDispatcher>,
     hooks: Arc<HookManager>,
-    events: Vec<Box<dyn Event>>,
+    os_type: OsType,
+    events: Mutex<Vec<(EventId, Box<dyn Event>)>>,
+    next_event_id: AtomicU64,
+    /// json profile, the symbol resolver loads from it
+    profile_path: Option<String>,
+    symbols: Mutex<Option<Arc<SymbolResolver>>>,
+    /// offsets and version of the guest, shared with events
+    guest: Arc<GuestOs>,
+    event_sink: EventSink,
+    /// handed out once by events()
+    event_rx: Mutex<Option<Receiver<GuestEvent>>>,
+    /// re-init options after a disconnect
+    vmi_builder: VmiBuilder,
+    reconnect: ReconnectPolicy,
+    /// core the event thread pins itself to
+    cpu_affinity: Option<usize>,
+    lifecycle: Arc<Lifecycle>,
+    started: Instant,
+    /// period of GuestEvent::Stats on the event stream
+    stats_interval: Option<Duration>,
 }
 
-impl Session {
-    pub fn new(domain_name: &str, json_path: &str, socket_path: &str) -> Result<Self> {
-        let vmi = Arc::new(Mutex::new(Vmi::new(domain_name, json_path, socket_path)?));
-        let hooks = HookManager::init(vmi.clone())?;
-        Ok(Self {
+/// init options for a session: the vmi's plus how the event loop runs
+#[derive(Debug, Clone)]
+pub struct SessionBuilder {
+    vmi: VmiBuilder,
+    listen_timeout: u32,
+    reconnect: ReconnectPolicy,
+    stats_interval: Option<Duration>,
+}
+
+/// what the event loop does when the kvmi connection drops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectPolicy {
+    /// re-init attempts before giving up, 0 stops the loop right away
+    pub attempts: u32,
+    /// wait before the first attempt, doubled after each failure
+    pub backoff: Duration,
+    /// ceiling for the doubled wait
+    pub max_backoff: Duration,
+}
+
+impl ReconnectPolicy {
+    /// fail the loop on the first disconnect
+    pub fn never() -> Self {
+        Self {
+            attempts: 0,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    /// 5 attempts, 1s doubling up to 30s
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self::from(VmiBuilder::default())
+    }
+}
+
+impl From<VmiBuilder> for SessionBuilder {
+    fn from(vmi: VmiBuilder) -> Self {
+        Self {
             vmi,
+            listen_timeout: LISTEN_TIMEOUT_MS,
+            reconnect: ReconnectPolicy::default(),
+            stats_interval: None,
+        }
+    }
+}
+
+impl SessionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// attach by domain name
+    pub fn domain(mut self, name: &str) -> Self {
+        self.vmi = self.vmi.name(name);
+        self
+    }
+
+    /// attach by numeric domain id
+    pub fn domain_id(mut self, id: u64) -> Self {
+        self.vmi = self.vmi.domain_id(id);
+        self
+    }
+
+    /// where the OS profile comes from
+    pub fn profile(mut self, config: InitConfig) -> Self {
+        self.vmi = self.vmi.profile(config);
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.vmi = self.vmi.backend(backend);
+        self
+    }
+
+    /// off for read-only sessions: nothing is registered with the
+    /// hypervisor and hooks, watchpoints and monitors fail to enable
+    pub fn events(mut self, enabled: bool) -> Self {
+        self.vmi = self.vmi.events(enabled);
+        self
+    }
+
+    /// see VmiBuilder::no_cache
+    pub fn no_cache(mut self, enabled: bool) -> Self {
+        self.vmi = self.vmi.no_cache(enabled);
+        self
+    }
+
+    /// see VmiBuilder::translation_cache
+    pub fn translation_cache(mut self, enabled: bool) -> Self {
+        self.vmi = self.vmi.translation_cache(enabled);
+        self
+    }
+
+    /// ms per events_listen round, see EventDispatcher::set_listen_timeout
+    pub fn listen_timeout(mut self, ms: u32) -> Self {
+        self.listen_timeout = ms;
+        self
+    }
+
+    /// how a dropped kvmi connection is retried
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// emit a GuestEvent::Stats snapshot every `every` while the loop runs
+    pub fn stats_interval(mut self, every: Duration) -> Self {
+        self.stats_interval = Some(every);
+        self
+    }
+
+    /// the vmi options gathered so far
+    pub fn vmi(&self) -> &VmiBuilder {
+        &self.vmi
+    }
+
+    /// check the options without touching libvmi
+    pub fn validate(&self) -> Result<()> {
+        self.vmi.validate()
+    }
+
+    pub fn build(&self) -> Result<Session> {
+        let vmi = self.vmi.build()?;
+        let os_type = vmi.os_type();
+        let guest = GuestOs::load(&vmi);
+        let kernel_base = vmi.kernel_base().ok();
+        self.assemble(vmi, os_type, guest, kernel_base)
+    }
+
+    /// the session around an initialized vmi and what was read from it
+    fn assemble(
+        &self,
+        vmi: Vmi,
+        os_type: OsType,
+        guest: GuestOs,
+        kernel_base: Option<u64>,
+    ) -> Result<Session> {
+        let guest = Arc::new(guest);
+        let lifecycle = Lifecycle {
+            kernel_base: Mutex::new(kernel_base),
+            ..Default::default()
+        };
+        let vmi = Arc::new(Mutex::new(vmi));
+        let dispatcher = EventDispatcher::new(vmi.clone());
+        dispatcher.set_listen_timeout(self.listen_timeout);
+        let hooks = HookManager::init(vmi.clone(), &dispatcher)?;
+        let (event_sink, event_rx) = EventSink::channel(EVENT_QUEUE_LEN);
+        Ok(Session {
+            vmi,
+            dispatcher,
             hooks,
-            events: Vec::new(),
+            os_type,
+            events: Mutex::new(Vec::new()),
+            next_event_id: AtomicU64::new(0),
+            profile_path: self.vmi.json_path().map(str::to_string),
+            symbols: Mutex::new(None),
+            guest,
+            event_sink,
+            event_rx: Mutex::new(Some(event_rx)),
+            vmi_builder: self.vmi.clone(),
+            reconnect: self.reconnect,
+            cpu_affinity: None,
+            lifecycle: Arc::new(lifecycle),
+            started: Instant::now(),
+            stats_interval: self.stats_interval,
         })
     }
+}
+
+impl Session {
+    /// kvm session with a json profile, SessionBuilder for anything else
+    pub fn new(domain_name: &str, json_path: &str, socket_path: &str) -> Result<Self> {
+        Self::from_builder(&VmiBuilder::new(domain_name, json_path, socket_path))
+    }
+
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::new()
+    }
+
+    /// session over a vmi built with non-default init options
+    pub fn from_builder(builder: &VmiBuilder) -> Result<Self> {
+        SessionBuilder::from(builder.clone()).build()
+    }
+
+    /// pin the event thread to `core` before it starts listening, to cut
+    /// handler jitter. a core that can't be used only logs a warning
+    pub fn with_cpu_affinity(mut self, core: usize) -> Self {
+        self.cpu_affinity = Some(core);
+        self
+    }
+
+    /// Attached until the connection drops, see SessionState
+    pub fn state(&self) -> SessionState {
+        *self.lifecycle.state.lock().unwrap()
+    }
 
     pub fn vmi(&self) -> Arc<Mutex<Vmi>> {
         self.vmi.clone()
@@ -32,56 +443,817 @@ impl Session {
         &self.hooks
     }
 
-    pub fn add_event<E: Event + 'static>(&mut self, mut event: E) -> Result<()> {
-        let ctx = EventContext {
-            vmi: &self.vmi,
-            hooks: &self.hooks,
+    /// owner of every registered libvmi event, for events beyond int3 hooks
+    pub fn dispatcher(&self) -> &Arc<EventDispatcher> {
+        &self.dispatcher
+    }
+
+    /// receiving end of the event stream monitors report into. can be taken
+    /// once; events arriving while the queue is full are dropped and counted
+    pub fn events(&self) -> Result<Receiver<GuestEvent>> {
+        self.event_rx
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| VmiError::Other("event stream already taken".into()))
+    }
+
+    /// hand every event to `f` on a background thread instead of taking the
+    /// receiver. the callback never runs inside an event handler, so it may
+    /// block or use the vmi. the thread ends when the session is dropped
+    pub fn forward_events<F>(&self, mut f: F) -> Result<JoinHandle<()>>
+    where
+        F: FnMut(GuestEvent) + Send + 'static,
+    {
+        let rx = self.events()?;
+        Ok(thread::spawn(move || {
+            for event in rx {
+                f(event);
+            }
+        }))
+    }
+
+    /// sender for events produced outside the built-in monitors, e.g. hooks
+    pub fn event_sink(&self) -> EventSink {
+        self.event_sink.clone()
+    }
+
+    /// events dropped so far because the stream was full or not taken
+    pub fn dropped_events(&self) -> u64 {
+        self.event_sink.dropped()
+    }
+
+    /// dropped_events for a consumer thread, outliving the session
+    pub fn drop_counter(&self) -> DropCounter {
+        self.event_sink.drop_counter()
+    }
+
+    /// counters since the session was built
+    pub fn stats(&self) -> SessionStats {
+        self.stats_source().snapshot()
+    }
+
+    fn stats_source(&self) -> StatsSource {
+        StatsSource {
+            dispatcher: self.dispatcher.clone(),
+            hooks: self.hooks.clone(),
+            events: self.event_sink.clone(),
+            lifecycle: self.lifecycle.clone(),
+            started: self.started,
+        }
+    }
+
+    /// guest OS detected at init
+    pub fn os_type(&self) -> OsType {
+        self.os_type
+    }
+
+    /// what the session resolved about the guest OS at init
+    pub fn guest(&self) -> &Arc<GuestOs> {
+        &self.guest
+    }
+
+    /// windows offsets cache, resolved at init and shared by actions/events.
+    /// on other guests every offset is unresolved
+    pub fn windows_offsets(&self) -> Arc<WindowsOffsets> {
+        match &*self.guest {
+            GuestOs::Windows(guest) => guest.offsets().clone(),
+            _ => Arc::new(WindowsOffsets::load(&self.vmi.lock().unwrap())),
+        }
+    }
+
+    /// reverse symbol resolver, loaded from the profile on first use.
+    /// also handed to the hook manager so its logs print `symbol+offset`,
+    /// and to the vmi as the v2ksym fallback.
+    pub fn symbols(&self) -> Result<Arc<SymbolResolver>> {
+        let mut cached = self.symbols.lock().unwrap();
+        if let Some(resolver) = cached.as_ref() {
+            return Ok(resolver.clone());
+        }
+
+        let resolver = {
+            let vmi = self.vmi.lock().unwrap();
+            let profile = self
+                .profile_path
+                .as_deref()
+                .ok_or_else(|| VmiError::Other("symbol lookup needs a json profile".into()))?;
+            let resolver = Arc::new(SymbolResolver::load(&vmi, profile)?);
+            vmi.set_symbols(resolver.clone());
+            resolver
+        };
+        self.hooks.set_symbols(resolver.clone());
+        *cached = Some(resolver.clone());
+        Ok(resolver)
+    }
+
+    /// enable an event and keep it alive for the session.
+    /// ids are never reused, so a stale id can't remove a newer event.
+    /// works while the loop runs, enabling between two listen rounds
+    pub fn add_event<E: Event + 'static>(&self, event: E) -> Result<EventId> {
+        let owners = self.event_owners();
+        let mut event: Box<dyn Event> = Box::new(event);
+        let event = self.dispatcher.call_unlocked(move || {
+            event.enable(&owners.context())?;
+            Ok::<_, VmiError>(event)
+        })??;
+
+        let id = EventId(self.next_event_id.fetch_add(1, Ordering::SeqCst));
+        self.events.lock().unwrap().push((id, event));
+        Ok(id)
+    }
+
+    /// disable and drop a previously added event, also while the loop runs.
+    /// the event is dropped even if disabling fails
+    pub fn remove_event(&self, id: EventId) -> Result<()> {
+        let mut event = {
+            let mut events = self.events.lock().unwrap();
+            let idx = events
+                .iter()
+                .position(|(eid, _)| *eid == id)
+                .ok_or_else(|| VmiError::Other(format!("no event with id {:?}", id)))?;
+            events.remove(idx).1
         };
-        event.enable(&ctx)?;
-        self.events.push(Box::new(event));
-        Ok(())
+
+        let owners = self.event_owners();
+        self.dispatcher
+            .call_unlocked(move || event.disable(&owners.context()))?
+    }
+
+    /// ids and names of the events currently enabled, oldest first
+    pub fn active_events(&self) -> Vec<(EventId, String)> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, event)| (*id, event.name().to_string()))
+            .collect()
+    }
+
+    fn event_owners(&self) -> EventOwners {
+        EventOwners {
+            vmi: self.vmi.clone(),
+            hooks: self.hooks.clone(),
+            dispatcher: self.dispatcher.clone(),
+            events: self.event_sink.clone(),
+            os: self.guest.clone(),
+        }
+    }
+
+    /// dispatch events on a background thread until the handle is stopped
+    /// or dropped. other threads reach the vmi meanwhile through
+    /// `dispatcher().call`
+    pub fn start(&self) -> SessionHandle<'_> {
+        self.spawn(Arc::new(AtomicBool::new(true)))
     }
 
+    /// dispatch events until `running` is cleared or listening fails
     pub fn run(&self, running: Arc<AtomicBool>) -> Result<()> {
-        let vmi = self.vmi.clone();
-        let running_events = running.clone();
-
-        let event_thread = thread::spawn(move || {
-            while running_events.load(Ordering::SeqCst) {
-                let res = {
-                    let vmi_lock = vmi.lock().unwrap();
-                    vmi_lock.events_listen(100)
-                };
-                if let Err(e) = res {
-                    println!("Event thread error: {}", e);
-                    break;
+        self.spawn(running).join()
+    }
+
+    fn spawn(&self, running: Arc<AtomicBool>) -> SessionHandle<'_> {
+        SessionHandle {
+            thread: Some(self.spawn_loop(running.clone())),
+            running,
+            _session: PhantomData,
+        }
+    }
+
+    /// the loop thread itself. it dispatches into this session's events, so
+    /// the caller must join it before the session drops
+    pub(crate) fn spawn_loop(&self, running: Arc<AtomicBool>) -> JoinHandle<Result<()>> {
+        let reconnector = Reconnector {
+            vmi: self.vmi.clone(),
+            builder: self.vmi_builder.clone(),
+            dispatcher: self.dispatcher.clone(),
+            hooks: self.hooks.clone(),
+            events: self.event_sink.clone(),
+            policy: self.reconnect,
+            lifecycle: self.lifecycle.clone(),
+        };
+        let cpu_affinity = self.cpu_affinity;
+        let stats = self
+            .stats_interval
+            .map(|every| (self.stats_source(), every));
+        thread::spawn(move || {
+            if let Some(core) = cpu_affinity {
+                pin_current_thread(core);
+            }
+            // stops with the loop
+            let _ticker = stats.map(|(source, every)| StatsTicker::start(source, every));
+            reconnector.run(&running)
+        })
+    }
+
+    /// dispatch events until Ctrl+C. hooks and events are torn down when the
+    /// session drops, so the guest is left clean
+    #[cfg(feature = "ctrlc")]
+    pub fn run_until_ctrlc(&self) -> Result<()> {
+        self.run(ctrlc_flag()?)
+    }
+
+    /// dispatch events until `duration` has passed, `running` is cleared or
+    /// listening fails. `running` is cleared on return. hooks stay armed
+    /// until the session drops
+    pub fn run_for(&self, duration: Duration, running: Arc<AtomicBool>) -> Result<RunSummary> {
+        let started = Instant::now();
+        let before = self.dispatcher.dispatched();
+        let handle = self.spawn(running);
+        let poll = Duration::from_millis(self.dispatcher.listen_timeout().max(1) as u64);
+        while handle.is_running() {
+            let left = duration.saturating_sub(started.elapsed());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(poll));
+        }
+        let deadline_reached = started.elapsed() >= duration;
+        handle.stop();
+        handle.join()?;
+        Ok(RunSummary {
+            events: self.dispatcher.dispatched() - before,
+            elapsed: started.elapsed(),
+            deadline_reached,
+        })
+    }
+
+    /// one listen round of at most `timeout_ms`, for callers driving their
+    /// own loop. calls queued meanwhile are serviced before it returns.
+    /// hooks stay armed between steps. a dropped connection comes back as
+    /// Disconnected, there is no reconnect as with start/run
+    pub fn step(&self, timeout_ms: u32) -> Result<StepOutcome> {
+        self.dispatcher.step(timeout_ms)
+    }
+
+    /// name, size and OS of the guest in one read, a quick check that
+    /// introspection works
+    pub fn info(&self) -> Result<VmInfo> {
+        let windows_build = match &*self.guest {
+            GuestOs::Windows(guest) => guest.build_number(),
+            _ => None,
+        };
+        self.dispatcher.call(move |vmi| VmInfo {
+            name: vmi.name(),
+            vmid: vmi.vmid(),
+            os: vmi.os_type(),
+            windows_build,
+            address_width: vmi.address_width(),
+            page_mode: vmi.page_mode(0),
+            vcpus: vmi.num_vcpus(),
+            memory_bytes: vmi.memsize(),
+            max_physical_address: vmi.max_physical_address(),
+            kernel_base: vmi.kernel_base().ok(),
+        })
+    }
+
+    /// registers of one vcpu, or of every vcpu when None, all read in a
+    /// single pause so they show the same instant
+    pub fn registers(&self, vcpu: Option<u32>) -> Result<Vec<VcpuRegisters>> {
+        self.dispatcher.call(move |vmi| {
+            let count = vmi.num_vcpus();
+            let vcpus = match vcpu {
+                Some(vcpu) if vcpu >= count => {
+                    return Err(VmiError::Other(format!(
+                        "vcpu {} out of range, the vm has {}",
+                        vcpu, count
+                    )));
+                }
+                Some(vcpu) => vcpu..vcpu + 1,
+                None => 0..count,
+            };
+            vmi.read_consistent(|vmi| {
+                vcpus
+                    .map(|vcpu| Ok(VcpuRegisters::new(vcpu, &vmi.get_all_regs(vcpu)?)))
+                    .collect()
+            })
+        })?
+    }
+
+    /// `len` bytes at `start`, unreadable pages zero-filled and listed in
+    /// the dump's gaps. `pause` holds the vm still for the whole read
+    pub fn dump_memory(
+        &self,
+        space: AddressSpace,
+        start: u64,
+        len: u64,
+        pause: bool,
+    ) -> Result<MemoryDump> {
+        self.dispatcher.call(move |vmi| {
+            if pause {
+                vmi.read_consistent(|vmi| dump::dump(vmi, &space, start, len))
+            } else {
+                dump::dump(vmi, &space, start, len)
+            }
+        })?
+    }
+
+    /// features available for the detected guest
+    pub fn capabilities(&self) -> Vec<Capability> {
+        capabilities(self.os_type)
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+
+    /// NotImplemented naming the feature if the guest lacks it
+    pub fn require(&self, capability: Capability) -> Result<()> {
+        if self.supports(capability) {
+            Ok(())
+        } else {
+            Err(not_implemented(capability.name(), self.os_type))
+        }
+    }
+
+    /// running processes, `details` also reads command lines, plus image
+    /// paths on windows or credentials and pid namespaces on linux
+    pub fn list_processes(&self, details: bool) -> Result<ProcessList> {
+        self.require(Capability::ListProcesses)?;
+        if details {
+            self.require(Capability::ProcessDetails)?;
+        }
+        match self.os_type {
+            OsType::Windows => {
+                self.execute(ListProcesses::new(self.windows_offsets()).with_details(details))
+            }
+            OsType::Linux => self.execute(LinuxListProcesses::new().with_details(details)),
+            os => Err(not_implemented(Capability::ListProcesses.name(), os)),
+        }
+    }
+
+    /// loaded kernel modules
+    pub fn list_modules(&self) -> Result<Vec<ModuleInfo>> {
+        match self.os_type {
+            OsType::Windows => self.execute(ListModules::new(self.windows_offsets())),
+            OsType::Linux => self.execute(LinuxListModules),
+            os => Err(not_implemented(Capability::ListModules.name(), os)),
+        }
+    }
+
+    /// threads of a process, empty once it has exited
+    pub fn list_threads(&self, pid: u64) -> Result<Vec<ThreadInfo>> {
+        match self.os_type {
+            OsType::Windows => self.execute(ListThreads::new(self.windows_offsets(), pid)),
+            os => Err(not_implemented(Capability::ListThreads.name(), os)),
+        }
+    }
+
+    /// parent/child view of the process list
+    pub fn process_tree(&self) -> Result<ProcessTree> {
+        match self.os_type {
+            OsType::Windows => self.execute(BuildProcessTree::new(self.windows_offsets())),
+            os => Err(not_implemented(Capability::ProcessTree.name(), os)),
+        }
+    }
+
+    /// log process creation, optionally with each new environment
+    pub fn monitor_processes(&self, environment: bool) -> Result<EventId> {
+        self.require(Capability::ProcessCreateMonitor)?;
+        if environment {
+            self.require(Capability::EnvironmentMonitor)?;
+        }
+        match self.os_type {
+            OsType::Windows => {
+                self.add_event(ProcessCreateMonitor::new().with_environment(environment))
+            }
+            OsType::Linux => self.add_event(LinuxProcessCreateMonitor::new()),
+            os => Err(not_implemented(Capability::ProcessCreateMonitor.name(), os)),
+        }
+    }
+
+    /// enable one monitor by kind. `environment` only affects process-create
+    pub fn enable_monitor(&self, kind: MonitorKind, environment: bool) -> Result<EventId> {
+        match kind {
+            MonitorKind::ProcessCreate => self.monitor_processes(environment),
+        }
+    }
+
+    /// execute a one-off action. while `run` is active it goes through the
+    /// dispatcher's queue, see `EventDispatcher::call` for the latency bound
+    pub fn execute<A, T>(&self, action: A) -> Result<T>
+    where
+        A: crate::os::Action<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.dispatcher.call(move |vmi| action.execute(vmi))?
+    }
+
+    /// execute an action one chunk per dispatcher call. while the loop runs,
+    /// every chunk waits for a listen round after the previous one, so
+    /// events keep flowing during long scans. chunks run in order on the
+    /// loop thread; calls from other threads may run between them
+    pub fn execute_chunked<A, T>(&self, action: A) -> Result<T>
+    where
+        A: crate::os::ChunkedAction<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut action = action;
+        loop {
+            let (returned, step) = self.dispatcher.call(move |vmi| {
+                let step = action.execute_chunk(vmi);
+                (action, step)
+            })?;
+            action = returned;
+            if let ControlFlow::Break(result) = step? {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// run `f` against the paused vm. the pause is refcounted, so it nests
+    /// with pause() and the pauses actions take themselves, and the guard
+    /// resumes even if `f` panics. queued like execute while `run` is active
+    pub fn with_paused<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Vmi) -> Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.dispatcher.call(move |vmi| vmi.read_consistent(f))?
+    }
+
+    /// hold the vm paused until a matching resume(). actions and
+    /// with_paused nest inside and leave it paused
+    pub fn pause(&self) -> Result<()> {
+        self.dispatcher.call(|vmi| vmi.pause())?
+    }
+
+    /// undo one pause(), the vm runs again once every pause is undone
+    pub fn resume(&self) -> Result<()> {
+        self.dispatcher.call(|vmi| vmi.resume())?
+    }
+
+    /// whether a pause is outstanding
+    pub fn is_paused(&self) -> Result<bool> {
+        self.dispatcher.call(|vmi| vmi.is_paused())
+    }
+}
+
+/// flag cleared by Ctrl+C, for run and run_for. the handler can only be
+/// installed once per process
+#[cfg(feature = "ctrlc")]
+pub fn ctrlc_flag() -> Result<Arc<AtomicBool>> {
+    let running = Arc::new(AtomicBool::new(true));
+    let flag = running.clone();
+    ctrlc::set_handler(move || {
+        flag.store(false, Ordering::SeqCst);
+        log::info!("Exiting...");
+    })
+    .map_err(|e| VmiError::Other(format!("ctrl+c handler: {}", e)))?;
+    Ok(running)
+}
+
+/// restrict the calling thread to one core, warn and carry on if refused
+fn pin_current_thread(core: usize) {
+    if core >= libc::CPU_SETSIZE as usize {
+        log::warn!(
+            "[Session] cpu {} out of range, event thread not pinned",
+            core
+        );
+        return;
+    }
+    let status = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if status != 0 {
+        log::warn!(
+            "[Session] could not pin event thread to cpu {}: {}",
+            core,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// what an EventContext borrows, owned so enable/disable can be queued
+struct EventOwners {
+    vmi: Arc<Mutex<Vmi>>,
+    hooks: Arc<HookManager>,
+    dispatcher: Arc<EventDispatcher>,
+    events: EventSink,
+    os: Arc<GuestOs>,
+}
+
+impl EventOwners {
+    fn context(&self) -> EventContext<'_> {
+        EventContext {
+            vmi: &self.vmi,
+            hooks: &self.hooks,
+            dispatcher: &self.dispatcher,
+            events: &self.events,
+            os: &self.os,
+        }
+    }
+}
+
+/// what a stats snapshot reads, owned so the ticker can take it
+struct StatsSource {
+    dispatcher: Arc<EventDispatcher>,
+    hooks: Arc<HookManager>,
+    events: EventSink,
+    lifecycle: Arc<Lifecycle>,
+    started: Instant,
+}
+
+impl StatsSource {
+    fn snapshot(&self) -> SessionStats {
+        SessionStats {
+            listen_rounds: self.dispatcher.rounds(),
+            events_dispatched: self.dispatcher.dispatched(),
+            events_dropped: self.events.dropped(),
+            reconnects: self.lifecycle.reconnects.load(Ordering::Relaxed),
+            rip_write_failures: self.hooks.rip_write_failures(),
+            uptime_ms: self.started.elapsed().as_millis() as u64,
+            hooks: self.hooks.stats(),
+        }
+    }
+}
+
+/// emits GuestEvent::Stats on its own thread, so a long listen timeout
+/// doesn't stretch the period. stopped and joined on drop
+struct StatsTicker {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatsTicker {
+    fn start(source: StatsSource, every: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut next = Instant::now() + every;
+            while !stopped.load(Ordering::SeqCst) {
+                let now = Instant::now();
+                if now < next {
+                    thread::park_timeout(next - now);
+                    continue;
                 }
+                source.events.emit(GuestEvent::Stats(source.snapshot()));
+                next = now + every;
             }
         });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for StatsTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// event loop body: dispatches, and on a dropped kvmi connection rebuilds
+/// the vmi, re-registers every event and re-arms the hooks
+struct Reconnector {
+    vmi: Arc<Mutex<Vmi>>,
+    builder: VmiBuilder,
+    dispatcher: Arc<EventDispatcher>,
+    hooks: Arc<HookManager>,
+    events: EventSink,
+    policy: ReconnectPolicy,
+    lifecycle: Arc<Lifecycle>,
+}
 
-        // wait for event thread
-        let _ = event_thread.join();
-        Ok(())
+impl Reconnector {
+    fn run(&self, running: &AtomicBool) -> Result<()> {
+        loop {
+            match self.dispatcher.run(running) {
+                Err(VmiError::Disconnected) if running.load(Ordering::SeqCst) => {
+                    self.reconnect(running)?
+                }
+                result => return result,
+            }
+        }
     }
 
-    /// execute a one-off action
-    pub fn execute<A: crate::os::Action<T>, T>(&self, action: A) -> Result<T> {
-        let vmi = self.vmi.lock().unwrap();
-        action.execute(&vmi)
+    fn reconnect(&self, running: &AtomicBool) -> Result<()> {
+        log::warn!("[Session] kvmi connection lost, hooks are still armed in the guest");
+        self.lifecycle.set(SessionState::Disconnected);
+        self.events.emit(GuestEvent::Disconnected);
+
+        let mut delay = self.policy.backoff;
+        for attempt in 1..=self.policy.attempts {
+            thread::sleep(delay);
+            if !running.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            match self.reattach() {
+                Ok(hooks) => {
+                    log::info!(
+                        "[Session] reconnected on attempt {}, {} hooks re-armed",
+                        attempt,
+                        hooks
+                    );
+                    self.lifecycle.set(SessionState::Attached);
+                    self.lifecycle.reconnects.fetch_add(1, Ordering::Relaxed);
+                    self.events.emit(GuestEvent::Reconnected { attempt, hooks });
+                    return Ok(());
+                }
+                Err(e) => {
+                    log::warn!(
+                        "[Session] reconnect attempt {}/{} failed: {}",
+                        attempt,
+                        self.policy.attempts,
+                        e
+                    );
+                    delay = (delay * 2).min(self.policy.max_backoff);
+                }
+            }
+        }
+        // whatever comes back later is a new boot, the patched bytes are gone
+        self.hooks.forget();
+        self.lifecycle.set(SessionState::GuestGone);
+        self.events.emit(GuestEvent::GuestGone { rebooted: false });
+        Err(VmiError::Other(format!(
+            "kvmi connection lost, gave up after {} reconnect attempts",
+            self.policy.attempts
+        )))
+    }
+
+    /// swap in a fresh vmi, then restore events and hooks on it. the kernel
+    /// base is checked before any hook is touched: a moved kernel means a
+    /// reboot (or a new KASLR slide), so hooks are dropped, not re-armed
+    fn reattach(&self) -> Result<usize> {
+        let fresh = self.builder.build()?;
+        let base = fresh.kernel_base().ok();
+        let mut vmi = self.vmi.lock().unwrap();
+        if let Some(symbols) = vmi.symbols() {
+            fresh.set_symbols(symbols);
+        }
+        // destroys the dead handle
+        drop(std::mem::replace(&mut *vmi, fresh));
+        self.dispatcher.reregister(&vmi)?;
+
+        let mut known = self.lifecycle.kernel_base.lock().unwrap();
+        // an unreadable base can't prove it's the same boot either
+        if known.is_some() && base != *known {
+            log::warn!(
+                "[Session] kernel base moved from {:#x} to {}, guest rebooted",
+                known.unwrap_or(0),
+                base.map(|b| format!("{:#x}", b))
+                    .unwrap_or_else(|| "unknown".into())
+            );
+            *known = base;
+            self.hooks.forget();
+            self.events.emit(GuestEvent::GuestGone { rebooted: true });
+            return Ok(0);
+        }
+        // without a known base, rearm's per-site byte check is all we have
+        if known.is_none() {
+            *known = base;
+        }
+        self.hooks.rearm(&vmi)
     }
 }
 
 impl Drop for Session {
     fn drop(&mut self) {
-        let ctx = EventContext {
-            vmi: &self.vmi,
-            hooks: &self.hooks,
-        };
-        for event in &mut self.events {
+        log::info!("[Session] {}", self.stats());
+
+        let owners = self.event_owners();
+        let ctx = owners.context();
+        for (_, event) in self.events.get_mut().unwrap().iter_mut() {
             let _ = event.disable(&ctx);
         }
 
+        // only an attached session knows the patch sites still hold its
+        // 0xCCs. stopped mid-reconnect, the guest may have rebooted and the
+        // original bytes would land on whatever lives there now
+        if self.state() != SessionState::Attached {
+            self.hooks.forget();
+        }
         // explicit shutdown to restore hooks and fix Arc leak
         self.hooks.shutdown();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ConfigError;
+    use crate::os::EventContext;
+    use std::sync::atomic::AtomicUsize;
+
+    /// a session over a detached vmi, no guest behind it
+    fn detached() -> Session {
+        let unknown = OsType::Unknown;
+        SessionBuilder::new()
+            .assemble(Vmi::detached(), unknown, GuestOs::Other(unknown), None)
+            .unwrap()
+    }
+
+    /// counts its enables and disables, fails to enable when told to
+    #[derive(Default, Clone)]
+    struct FakeEvent {
+        enabled: Arc<AtomicUsize>,
+        disabled: Arc<AtomicUsize>,
+        refuse: bool,
+    }
+
+    impl Event for FakeEvent {
+        fn enable(&mut self, _ctx: &EventContext) -> Result<()> {
+            if self.refuse {
+                return Err(VmiError::Other("refused".into()));
+            }
+            self.enabled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn disable(&mut self, _ctx: &EventContext) -> Result<()> {
+            self.disabled.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "fake"
+        }
+    }
+
+    #[test]
+    fn add_remove_and_re_add_events() {
+        let session = detached();
+        let event = FakeEvent::default();
+
+        let first = session.add_event(event.clone()).unwrap();
+        assert_eq!(session.active_events(), [(first, "fake".to_string())]);
+        assert_eq!(event.enabled.load(Ordering::SeqCst), 1);
+
+        session.remove_event(first).unwrap();
+        assert!(session.active_events().is_empty());
+        assert_eq!(event.disabled.load(Ordering::SeqCst), 1);
+        assert!(session.remove_event(first).is_err());
+
+        let second = session.add_event(event.clone()).unwrap();
+        assert_ne!(second, first);
+        assert_eq!(session.active_events(), [(second, "fake".to_string())]);
+        assert_eq!(event.enabled.load(Ordering::SeqCst), 2);
+
+        // what is still added gets disabled with the session
+        drop(session);
+        assert_eq!(event.disabled.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn event_that_fails_to_enable_is_not_kept() {
+        let session = detached();
+        let event = FakeEvent {
+            refuse: true,
+            ..Default::default()
+        };
+        assert!(session.add_event(event.clone()).is_err());
+        assert!(session.active_events().is_empty());
+
+        drop(session);
+        assert_eq!(event.disabled.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn events_removed_in_any_order() {
+        let session = detached();
+        let ids: Vec<EventId> = (0..3)
+            .map(|_| session.add_event(FakeEvent::default()).unwrap())
+            .collect();
+        session.remove_event(ids[1]).unwrap();
+        let left: Vec<EventId> = session.active_events().iter().map(|(id, _)| *id).collect();
+        assert_eq!(left, [ids[0], ids[2]]);
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let builder = SessionBuilder::new();
+        assert_eq!(builder.listen_timeout, LISTEN_TIMEOUT_MS);
+        assert_eq!(builder.reconnect, ReconnectPolicy::default());
+        assert_eq!(builder.stats_interval, None);
+        assert_eq!(builder.vmi().json_path(), None);
+        assert!(matches!(
+            builder.validate(),
+            Err(VmiError::Config(ConfigError::MissingDomain))
+        ));
+    }
+
+    #[test]
+    fn setters_reach_the_vmi_builder() {
+        let builder = SessionBuilder::new()
+            .domain("win10")
+            .profile(InitConfig::JsonPath("/p.json".into()))
+            .backend(Backend::Xen)
+            .listen_timeout(50);
+        assert!(builder.validate().is_ok());
+        assert_eq!(builder.vmi().domain_name(), "win10");
+        assert_eq!(builder.vmi().json_path(), Some("/p.json"));
+        assert_eq!(builder.listen_timeout, 50);
+
+        let conflicting = builder.domain_id(7);
+        assert!(matches!(
+            conflicting.validate(),
+            Err(VmiError::Config(ConfigError::ConflictingDomain))
+        ));
+    }
+}
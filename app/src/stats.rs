@@ -0,0 +1,100 @@
+//! runtime counters of a session
+//!
+//! everything is counted with relaxed atomics on the paths that already
+//! run (listen rounds, dispatch, int3 hits), a snapshot just reads them
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// counters of one hook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HookStats {
+    pub addr: u64,
+    /// `symbol+offset` when symbols are loaded, hex otherwise
+    pub location: String,
+    /// callback runs, hits from other address spaces aren't counted
+    pub hits: u64,
+    pub active: bool,
+}
+
+/// point-in-time counters of a session, see Session::stats
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStats {
+    /// events_listen calls, across every loop and step
+    pub listen_rounds: u64,
+    /// events handed to handlers
+    pub events_dispatched: u64,
+    /// guest events lost to a full or untaken stream
+    pub events_dropped: u64,
+    /// successful reattaches after a dropped kvmi connection
+    pub reconnects: u64,
+    /// emulated hook hits whose rip couldn't be advanced, see
+    /// HookManager::rip_write_failures
+    pub rip_write_failures: u64,
+    pub uptime_ms: u64,
+    pub hooks: Vec<HookStats>,
+}
+
+impl SessionStats {
+    /// hits summed over every hook
+    pub fn hook_hits(&self) -> u64 {
+        self.hooks.iter().map(|h| h.hits).sum()
+    }
+}
+
+impl fmt::Display for SessionStats {
+    /// one line summary, per-hook counts left out
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "up {:.1}s | listen rounds: {} | events: {} ({} dropped) | hook hits: {} over {} hooks | reconnects: {}",
+            self.uptime_ms as f64 / 1000.0,
+            self.listen_rounds,
+            self.events_dispatched,
+            self.events_dropped,
+            self.hook_hits(),
+            self.hooks.len(),
+            self.reconnects
+        )?;
+        if self.rip_write_failures > 0 {
+            write!(f, " | rip write failures: {}", self.rip_write_failures)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(rip_write_failures: u64) -> SessionStats {
+        SessionStats {
+            listen_rounds: 10,
+            events_dispatched: 4,
+            events_dropped: 1,
+            reconnects: 0,
+            rip_write_failures,
+            uptime_ms: 1500,
+            hooks: vec![HookStats {
+                addr: 0x1000,
+                location: "nt!NtOpenFile".into(),
+                hits: 3,
+                active: true,
+            }],
+        }
+    }
+
+    #[test]
+    fn summary_line() {
+        assert_eq!(
+            stats(0).to_string(),
+            "up 1.5s | listen rounds: 10 | events: 4 (1 dropped) | hook hits: 3 over 1 hooks | reconnects: 0"
+        );
+    }
+
+    #[test]
+    fn rip_write_failures_shown_once_seen() {
+        assert!(stats(2).to_string().ends_with(" | rip write failures: 2"));
+    }
+}
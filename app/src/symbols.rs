@@ -0,0 +1,211 @@
+//! reverse kernel symbol lookup built from the JSON profile
+//!
+//! libvmi only resolves name -> address. to print `PspInsertProcess+0x12`
+//! instead of a bare address we load the profile's symbol table ourselves,
+//! sort it, and binary search for the closest symbol below an address.
+//!
+//! profile addresses are RVAs (rekall / windows ISF) or link-time VAs
+//! (linux ISF). either way a single bias, measured by resolving an anchor
+//! symbol through libvmi, maps them to runtime VAs - this also covers KASLR.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::error::{Result, VmiError};
+use crate::vmi::Vmi;
+
+/// addresses further than this past the closest symbol are not attributed to it
+pub const MAX_SYMBOL_DISTANCE: u64 = 0x10_0000;
+
+/// symbols tried first when measuring the bias
+const ANCHOR_SYMBOLS: &[&str] = &["PsActiveProcessHead", "KeBugCheckEx", "init_task", "_text"];
+
+/// split `name`, `name+0x1a` or `name-16` into the symbol and a signed offset.
+/// offsets are hex with 0x, decimal otherwise
+pub fn parse_symbol_expr(input: &str) -> Result<(&str, i64)> {
+    let invalid = || VmiError::InvalidSymbolExpr(input.to_string());
+    let expr = input.trim();
+
+    // a leading sign would leave an empty name, so it isn't a split point
+    let split = expr
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '+' || c == '-')
+        .map(|(i, _)| i);
+    let Some(split) = split else {
+        if expr.is_empty() {
+            return Err(invalid());
+        }
+        return Ok((expr, 0));
+    };
+
+    let name = expr[..split].trim();
+    let (sign, offset) = expr[split..].split_at(1);
+    let offset = offset.trim();
+    if name.is_empty() || offset.is_empty() {
+        return Err(invalid());
+    }
+
+    let magnitude = match offset
+        .strip_prefix("0x")
+        .or_else(|| offset.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => offset.parse::<i64>(),
+    }
+    .map_err(|_| invalid())?;
+    if magnitude < 0 {
+        return Err(invalid());
+    }
+
+    Ok((name, if sign == "-" { -magnitude } else { magnitude }))
+}
+
+/// `symbol`, `symbol+0x12`, or the bare address when nothing resolved
+pub fn format_location(resolved: Option<(String, u64)>, addr: u64) -> String {
+    match resolved {
+        Some((name, 0)) => name,
+        Some((name, offset)) => format!("{}+{:#x}", name, offset),
+        None => format!("{:#x}", addr),
+    }
+}
+
+/// resolve a `symbol+offset` expression through libvmi's kernel symbols
+pub fn resolve_symbol_expr(vmi: &Vmi, expr: &str) -> Result<u64> {
+    let (name, offset) = parse_symbol_expr(expr)?;
+    Ok(vmi.ksym2v(name)?.wrapping_add_signed(offset))
+}
+
+/// maps kernel virtual addresses back to `symbol + offset`
+pub struct SymbolResolver {
+    /// (profile address, name), sorted by address
+    symbols: Vec<(u64, String)>,
+    /// added to profile addresses to get runtime VAs
+    bias: u64,
+}
+
+impl SymbolResolver {
+    /// load the symbol table from a rekall or volatility3 ISF profile
+    pub fn from_profile(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| {
+            VmiError::Other(format!("failed to read profile {}: {}", path.display(), e))
+        })?;
+        let json: Value = serde_json::from_slice(&data).map_err(|e| {
+            VmiError::Other(format!("failed to parse profile {}: {}", path.display(), e))
+        })?;
+
+        let mut symbols = Vec::new();
+
+        // rekall: "$CONSTANTS" / "$FUNCTIONS" are name -> rva maps
+        for section in ["$CONSTANTS", "$FUNCTIONS"] {
+            if let Some(map) = json.get(section).and_then(Value::as_object) {
+                for (name, addr) in map {
+                    if let Some(addr) = addr.as_u64() {
+                        symbols.push((addr, name.clone()));
+                    }
+                }
+            }
+        }
+
+        // volatility3 ISF: "symbols" is name -> { "address": .. }
+        if let Some(map) = json.get("symbols").and_then(Value::as_object) {
+            for (name, sym) in map {
+                if let Some(addr) = sym.get("address").and_then(Value::as_u64) {
+                    symbols.push((addr, name.clone()));
+                }
+            }
+        }
+
+        symbols.retain(|(addr, _)| *addr != 0);
+        symbols.sort();
+        symbols.dedup_by(|a, b| a.0 == b.0);
+
+        if symbols.is_empty() {
+            return Err(VmiError::Other(format!(
+                "no symbols found in profile {}",
+                path.display()
+            )));
+        }
+
+        Ok(Self { symbols, bias: 0 })
+    }
+
+    /// load the profile and rebase it onto the running kernel
+    pub fn load(vmi: &Vmi, path: impl AsRef<Path>) -> Result<Self> {
+        let mut resolver = Self::from_profile(path)?;
+        resolver.rebase(vmi)?;
+        Ok(resolver)
+    }
+
+    /// measure the bias by resolving an anchor symbol through libvmi
+    pub fn rebase(&mut self, vmi: &Vmi) -> Result<()> {
+        let candidates = ANCHOR_SYMBOLS
+            .iter()
+            .copied()
+            .chain(self.symbols.iter().take(16).map(|(_, n)| n.as_str()));
+
+        for name in candidates {
+            let Some(profile_addr) = self.profile_address(name) else {
+                continue;
+            };
+            if let Ok(va) = vmi.ksym2v(name) {
+                self.bias = va.wrapping_sub(profile_addr);
+                return Ok(());
+            }
+        }
+
+        Err(VmiError::SymbolNotFound(
+            "no anchor symbol resolvable to rebase profile".into(),
+        ))
+    }
+
+    /// current bias (kernel base for RVA profiles, KASLR slide for absolute ones)
+    pub fn bias(&self) -> u64 {
+        self.bias
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// runtime VA of a symbol
+    pub fn lookup(&self, name: &str) -> Option<u64> {
+        self.profile_address(name)
+            .map(|addr| addr.wrapping_add(self.bias))
+    }
+
+    /// closest symbol at or below `vaddr`, with the offset into it
+    pub fn resolve(&self, vaddr: u64) -> Option<(String, u64)> {
+        let target = vaddr.wrapping_sub(self.bias);
+        let idx = match self.symbols.binary_search_by_key(&target, |(a, _)| *a) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+
+        let (addr, name) = &self.symbols[idx];
+        let offset = target - addr;
+        if offset > MAX_SYMBOL_DISTANCE {
+            return None;
+        }
+        Some((name.clone(), offset))
+    }
+
+    /// `symbol+0x12` if resolvable, otherwise the bare address
+    pub fn format(&self, vaddr: u64) -> String {
+        format_location(self.resolve(vaddr), vaddr)
+    }
+
+    fn profile_address(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|(_, n)| n == name)
+            .map(|(addr, _)| *addr)
+    }
+}
@@ -1,20 +1,640 @@
 //! safe wrapper around libvmi ffi
-
-use std::ffi::{CStr, CString};
+//!
+//! byte order: the fixed-width helpers (`read_16_va`, `read_64_pa`,
+//! `write_32_va`, ...) take and return host-native integers. libvmi reads the
+//! guest's little-endian memory and converts, so no swapping is needed on top.
+//! the buffer helpers (`read_pa`, `read_va_dtb`, ...) return raw guest bytes,
+//! which is why callers decode those with `from_le_bytes`.
+//!
+//! caching: libvmi caches V2P, PID->DTB, symbol and RVA lookups. once the
+//! guest runs or we write to it those entries can be stale. building with
+//! `VmiBuilder::no_cache(true)` flushes them after every write and whenever
+//! the vm is paused again, trading a page walk per read for correctness.
+//! `VmiBuilder::translation_cache(true)` goes the other way for bulk DTB
+//! reads, memoizing page translations until the next pause.
+//!
+//! event handlers: libvmi runs callbacks from inside vmi_events_listen, on
+//! the thread that called it. events_listen records which Vmi is listening
+//! on that thread, and handlers get a VmiRef to it: the same instance the
+//! listener locked, not a second wrapper around the raw handle. a handler
+//! therefore shares the listener's pause depth, caches and symbols, and
+//! holds no owned Vmi whose Drop could destroy the handle. the listener
+//! holds the lock for the whole listen, so nothing else touches the Vmi
+//! while a handler has it; that is what makes Vmi's Send/Sync hold up for
+//! handles used from callbacks.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr, CString};
+use std::ops::Deref;
 use std::ptr;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use serde::Serialize;
 
-use crate::error::{Result, VmiError};
+use crate::error::{ConfigError, Result, VmiError};
 use crate::ffi::*;
+use crate::symbols::SymbolResolver;
+
+/// kvmi socket the introspector listens on unless told otherwise
+pub const DEFAULT_KVMI_SOCKET: &str = "/tmp/introspector";
+
+/// default read_unicode_string_dtb cap in bytes, longer strings are truncated
+pub const MAX_UNICODE_STRING_BYTES: usize = 4096;
+
+/// guest page size, 4K pages only
+pub const PAGE_SIZE: u64 = 0x1000;
+const PAGE_SHIFT: u32 = 12;
+
+/// physical address of the first byte of a frame, e.g. from a mem event
+pub const fn gfn_to_pa(gfn: u64) -> u64 {
+    gfn << PAGE_SHIFT
+}
+
+/// frame number holding a physical address, as mem events want it
+pub const fn pa_to_gfn(pa: u64) -> u64 {
+    pa >> PAGE_SHIFT
+}
+
+/// offset of an address (physical or virtual) within its page
+pub const fn page_offset(addr: u64) -> u64 {
+    addr & (PAGE_SIZE - 1)
+}
+
+/// start of the page holding an address
+pub const fn page_base(addr: u64) -> u64 {
+    addr & !(PAGE_SIZE - 1)
+}
+
+/// bytes from an address to the end of its page, for page-wise reads
+pub const fn page_remaining(addr: u64) -> u64 {
+    PAGE_SIZE - page_offset(addr)
+}
+
+/// architectural MSR numbers read_msr understands
+pub const IA32_SYSENTER_CS: u32 = 0x174;
+pub const IA32_SYSENTER_ESP: u32 = 0x175;
+pub const IA32_SYSENTER_EIP: u32 = 0x176;
+pub const IA32_EFER: u32 = 0xc000_0080;
+pub const IA32_STAR: u32 = 0xc000_0081;
+pub const IA32_LSTAR: u32 = 0xc000_0082;
+pub const IA32_CSTAR: u32 = 0xc000_0083;
+pub const IA32_FMASK: u32 = 0xc000_0084;
+pub const IA32_FS_BASE: u32 = 0xc000_0100;
+pub const IA32_GS_BASE: u32 = 0xc000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xc000_0102;
+pub const IA32_TSC_AUX: u32 = 0xc000_0103;
+
+/// libvmi register for an MSR number, None for MSRs libvmi doesn't expose
+fn msr_register(msr: u32) -> Option<u32> {
+    Some(match msr {
+        IA32_SYSENTER_CS => SYSENTER_CS,
+        IA32_SYSENTER_ESP => SYSENTER_ESP,
+        IA32_SYSENTER_EIP => SYSENTER_EIP,
+        IA32_EFER => MSR_EFER,
+        IA32_STAR => MSR_STAR,
+        IA32_LSTAR => MSR_LSTAR,
+        IA32_CSTAR => MSR_CSTAR,
+        IA32_FMASK => MSR_SYSCALL_MASK,
+        IA32_FS_BASE => FS_BASE,
+        IA32_GS_BASE => GS_BASE,
+        IA32_KERNEL_GS_BASE => SHADOW_GS,
+        IA32_TSC_AUX => MSR_TSC_AUX,
+        _ => return None,
+    })
+}
+
+/// bytes of a UNICODE_STRING to read: its Length clamped to `max_bytes`,
+/// rounded down to whole UTF-16 units
+fn unicode_read_len(length: u16, max_bytes: usize) -> usize {
+    (length as usize).min(max_bytes) & !1
+}
+
+/// `count` values of `T` filled in place by `fill`, which gets them as one
+/// byte slice, element i at `i * size_of::<T>()`
+fn read_array_with<T: bytemuck::Pod>(
+    count: usize,
+    fill: impl FnOnce(&mut [u8]) -> Result<()>,
+) -> Result<Vec<T>> {
+    let mut values = vec![T::zeroed(); count];
+    fill(bytemuck::cast_slice_mut(&mut values))?;
+    Ok(values)
+}
+
+/// get_vcpuregs around `get`, which fills a zeroed register set
+fn get_vcpuregs_with(
+    vcpu: u32,
+    get: impl FnOnce(*mut registers_t) -> status_t,
+) -> Result<registers_t> {
+    let mut regs: registers_t = unsafe { std::mem::zeroed() };
+    if get(&mut regs) != status_VMI_SUCCESS {
+        return Err(VmiError::ReadFailed {
+            addr: 0,
+            msg: format!("failed to get registers of vcpu {}", vcpu),
+        });
+    }
+    Ok(regs)
+}
+
+/// set_vcpuregs around `set`, which gets a copy so the caller's set is
+/// never written through
+fn set_vcpuregs_with(
+    regs: &registers_t,
+    vcpu: u32,
+    set: impl FnOnce(*mut registers_t) -> status_t,
+) -> Result<()> {
+    // libvmi takes a mutable pointer but only reads through it
+    let mut regs = *regs;
+    if set(&mut regs) != status_VMI_SUCCESS {
+        return Err(VmiError::ReadFailed {
+            addr: 0,
+            msg: format!("failed to set registers of vcpu {}", vcpu),
+        });
+    }
+    Ok(())
+}
+
+/// is_mapped_va's choice of translation: `kernel` for pid 0, `user` with
+/// the pid otherwise. mapped when the one it picked succeeds
+fn is_mapped_with(
+    pid: u32,
+    kernel: impl FnOnce() -> status_t,
+    user: impl FnOnce(i32) -> status_t,
+) -> bool {
+    let status = if pid == 0 { kernel() } else { user(pid as i32) };
+    status == status_VMI_SUCCESS
+}
+
+/// zero_va's page walk: `write` gets each run of zeros, none crossing a
+/// page boundary. stops at the first failed write
+fn zero_va_with(
+    vaddr: u64,
+    len: usize,
+    mut write: impl FnMut(u64, &[u8]) -> Result<()>,
+) -> Result<()> {
+    let zeros = [0u8; PAGE_SIZE as usize];
+    let mut done = 0usize;
+    while done < len {
+        let curr_vaddr = vaddr + done as u64;
+        let remainder = page_remaining(curr_vaddr) as usize;
+        let chunk = std::cmp::min(remainder, len - done);
+
+        write(curr_vaddr, &zeros[..chunk])?;
+        done += chunk;
+    }
+    Ok(())
+}
+
+/// a 64-bit read through `read`, which copies the guest's 8 bytes into
+/// the u64 as they are. no swapping, the value is in host-native order
+fn read_64_with(paddr: u64, read: impl FnOnce(*mut u64) -> status_t) -> Result<u64> {
+    let mut val: u64 = 0;
+    if read(&mut val) != status_VMI_SUCCESS {
+        return Err(VmiError::ReadFailed {
+            addr: paddr,
+            msg: "read_64_pa failed".into(),
+        });
+    }
+    Ok(val)
+}
+
+/// a 64-bit write through `write`, which copies the u64's bytes to the
+/// guest as they are
+fn write_64_with(paddr: u64, val: u64, write: impl FnOnce(*mut u64) -> status_t) -> Result<()> {
+    let mut val = val;
+    if write(&mut val) != status_VMI_SUCCESS {
+        return Err(VmiError::ReadFailed {
+            addr: paddr,
+            msg: "write_64_pa failed".into(),
+        });
+    }
+    Ok(())
+}
+
+thread_local! {
+    /// the Vmi inside events_listen on this thread, null outside one
+    static LISTENING: Cell<*const Vmi> = const { Cell::new(ptr::null()) };
+    /// with_scratch's buffer, grown to the largest read seen on this thread
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// lend `f` a zeroed `len`-byte buffer reused across calls on this thread,
+/// so event callbacks reading transient bytes don't allocate each time.
+/// a nested call gets a fresh allocation instead of the shared buffer
+pub(crate) fn with_scratch<R>(len: usize, f: impl FnOnce(&mut [u8]) -> R) -> R {
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut buf) => {
+            buf.clear();
+            buf.resize(len, 0);
+            f(&mut buf)
+        }
+        Err(_) => f(&mut vec![0u8; len]),
+    })
+}
+
+/// the Vmi an event handler runs under, borrowed for one callback.
+/// derefs to Vmi, has no Drop, and can't outlive the callback
+#[derive(Clone, Copy)]
+pub struct VmiRef<'a> {
+    vmi: &'a Vmi,
+}
+
+impl<'a> VmiRef<'a> {
+    /// the Vmi inside events_listen on this thread, if libvmi's `handle`
+    /// is its handle
+    ///
+    /// # Safety
+    /// only call from an event callback and don't keep the result past it,
+    /// the listening Vmi is only known to live until events_listen returns
+    pub(crate) unsafe fn listening(handle: vmi_instance_t) -> Option<Self> {
+        let vmi = LISTENING.get();
+        if vmi.is_null() {
+            return None;
+        }
+        let vmi = unsafe { &*vmi };
+        (vmi.handle == handle).then_some(Self { vmi })
+    }
+
+    /// the plain reference, for APIs that take a &Vmi for the whole callback
+    pub fn get(self) -> &'a Vmi {
+        self.vmi
+    }
+}
+
+impl Deref for VmiRef<'_> {
+    type Target = Vmi;
+
+    fn deref(&self) -> &Vmi {
+        self.vmi
+    }
+}
 
 /// wrapper around vmi_instance_t
 pub struct Vmi {
     handle: vmi_instance_t,
-    paused: AtomicBool,
+    /// nested pause count, the vm is paused while this is non-zero
+    pause_depth: Mutex<usize>,
+    /// false when built with no_cache, libvmi caches are flushed eagerly
+    caching: bool,
+    /// opt-in (dtb, page) memo for translate_uv2p
+    translations: Option<Mutex<TranslationCache>>,
+    /// profile-based fallback for v2ksym
+    symbols: RwLock<Option<Arc<SymbolResolver>>>,
+    /// initialized with VMI_INIT_EVENTS
+    events: bool,
+    /// driver answered a slat query, probed on first use
+    slat: OnceLock<bool>,
+}
+
+/// (dtb, page) -> physical page memo for DTB-based reads.
+/// only valid while the guest stays paused, so it is emptied on every
+/// outermost pause and by flush_caches
+#[derive(Debug, Default)]
+pub struct TranslationCache {
+    pages: HashMap<(u64, u64), u64>,
+    hits: u64,
+    misses: u64,
+}
+
+/// translation cache counters, for tuning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+impl TranslationCache {
+    fn get(&mut self, dtb: u64, page: u64) -> Option<u64> {
+        let found = self.pages.get(&(dtb, page)).copied();
+        match found {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        found
+    }
+
+    fn insert(&mut self, dtb: u64, page: u64, paddr_page: u64) {
+        self.pages.insert((dtb, page), paddr_page);
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.pages.len(),
+        }
+    }
+}
+
+/// how libvmi finds the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Domain {
+    /// VMI_INIT_DOMAINNAME
+    Name(String),
+    /// VMI_INIT_DOMAINID
+    Id(u64),
+}
+
+/// where libvmi loads the OS profile from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitConfig {
+    /// VMI_CONFIG_JSON_PATH, a volatility-style json profile
+    JsonPath(String),
+    /// VMI_CONFIG_GLOBAL_FILE_ENTRY, the domain's entry in libvmi.conf
+    GlobalFile,
+    /// VMI_CONFIG_STRING, a libvmi.conf style entry given inline
+    String(String),
+}
+
+/// hypervisor interface libvmi drives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// KVM through the kvmi introspector socket
+    Kvm {
+        socket: String,
+    },
+    Xen,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Kvm {
+            socket: DEFAULT_KVMI_SOCKET.to_string(),
+        }
+    }
+}
+
+/// init options for a Vmi instance
+#[derive(Debug, Clone)]
+pub struct VmiBuilder {
+    domain_name: String,
+    domain_id: Option<u64>,
+    config: Option<InitConfig>,
+    backend: Backend,
+    events: bool,
+    no_cache: bool,
+    translation_cache: bool,
+}
+
+impl Default for VmiBuilder {
+    /// no domain or profile yet, kvm on the default socket, events on
+    fn default() -> Self {
+        Self {
+            domain_name: String::new(),
+            domain_id: None,
+            config: None,
+            backend: Backend::default(),
+            events: true,
+            no_cache: false,
+            translation_cache: false,
+        }
+    }
+}
+
+impl VmiBuilder {
+    pub fn new(domain_name: &str, json_path: &str, socket_path: &str) -> Self {
+        Self::default()
+            .name(domain_name)
+            .profile(InitConfig::JsonPath(json_path.to_string()))
+            .backend(Backend::Kvm {
+                socket: socket_path.to_string(),
+            })
+    }
+
+    /// identify the guest by numeric domain id instead of by name
+    pub fn for_domain_id(domain_id: u64, json_path: &str, socket_path: &str) -> Self {
+        Self::new("", json_path, socket_path).domain_id(domain_id)
+    }
+
+    /// init by domain name, conflicts with a domain id
+    pub fn name(mut self, domain_name: &str) -> Self {
+        self.domain_name = domain_name.to_string();
+        self
+    }
+
+    /// init by domain id, conflicts with a non-empty domain name
+    pub fn domain_id(mut self, domain_id: u64) -> Self {
+        self.domain_id = Some(domain_id);
+        self
+    }
+
+    /// where the OS profile comes from
+    pub fn profile(mut self, config: InitConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// init with VMI_INIT_EVENTS. without it hooks, watchpoints and monitors
+    /// can't be used, but nothing is registered with the hypervisor
+    pub fn events(mut self, enabled: bool) -> Self {
+        self.events = enabled;
+        self
+    }
+
+    /// flush libvmi's translation caches after writes and on every pause.
+    /// slower (each read redoes the page walk) but never sees stale mappings
+    pub fn no_cache(mut self, enabled: bool) -> Self {
+        self.no_cache = enabled;
+        self
+    }
+
+    /// memoize DTB page translations between pauses, see TranslationCache
+    pub fn translation_cache(mut self, enabled: bool) -> Self {
+        self.translation_cache = enabled;
+        self
+    }
+
+    pub fn domain_name(&self) -> &str {
+        &self.domain_name
+    }
+
+    /// the guest to attach to, exactly one of name or id must be set
+    pub fn domain(&self) -> Result<Domain> {
+        match (self.domain_name.is_empty(), self.domain_id) {
+            (false, None) => Ok(Domain::Name(self.domain_name.clone())),
+            (true, Some(id)) => Ok(Domain::Id(id)),
+            (false, Some(_)) => Err(ConfigError::ConflictingDomain.into()),
+            (true, None) => Err(ConfigError::MissingDomain.into()),
+        }
+    }
+
+    /// json profile path, None for the other profile sources
+    pub fn json_path(&self) -> Option<&str> {
+        match &self.config {
+            Some(InitConfig::JsonPath(path)) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// check the options without touching libvmi
+    pub fn validate(&self) -> Result<()> {
+        self.domain()?;
+        if self.config.is_none() {
+            return Err(ConfigError::MissingProfile.into());
+        }
+        if let Backend::Kvm { socket } = &self.backend
+            && socket.is_empty()
+        {
+            return Err(ConfigError::MissingSocket.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn build(&self) -> Result<Vmi> {
+        self.validate()?;
+        let config = self.config.as_ref().ok_or(ConfigError::MissingProfile)?;
+        let mut vmi = Vmi::new(&self.domain()?, config, &self.backend, self.events)?;
+        vmi.caching = !self.no_cache;
+        if self.translation_cache {
+            vmi.translations = Some(Mutex::new(TranslationCache::default()));
+        }
+        if self.no_cache {
+            vmi.flush_caches();
+        }
+        Ok(vmi)
+    }
+}
+
+/// keeps the vm paused until dropped
+pub struct PauseGuard<'a> {
+    vmi: &'a Vmi,
+}
+
+impl Drop for PauseGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.vmi.resume();
+    }
+}
+
+/// page table walk result with the bits we care about decoded
+#[derive(Debug, Clone, Copy)]
+pub struct PageInfo {
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub size: u64,
+    pub writable: bool,
+    /// false if NX is set at any level of the walk
+    pub executable: bool,
+}
+
+/// how a context read translates its address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressSpace {
+    /// the address is physical
+    Physical,
+    /// walk the page tables at this DTB
+    Dtb(u64),
+    /// the address space of a pid, 0 for the kernel
+    Pid(u32),
+    /// read at a kernel symbol, the address is ignored
+    KernelSymbol(String),
+}
+
+/// address plus translation for the libvmi calls taking an
+/// access_context_t, built safely and converted per call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadContext {
+    pub addr: u64,
+    pub space: AddressSpace,
+}
+
+impl ReadContext {
+    pub fn physical(paddr: u64) -> Self {
+        Self {
+            addr: paddr,
+            space: AddressSpace::Physical,
+        }
+    }
+
+    pub fn dtb(dtb: u64, vaddr: u64) -> Self {
+        Self {
+            addr: vaddr,
+            space: AddressSpace::Dtb(dtb),
+        }
+    }
+
+    pub fn pid(pid: u32, vaddr: u64) -> Self {
+        Self {
+            addr: vaddr,
+            space: AddressSpace::Pid(pid),
+        }
+    }
+
+    pub fn kernel_symbol(symbol: &str) -> Self {
+        Self {
+            addr: 0,
+            space: AddressSpace::KernelSymbol(symbol.into()),
+        }
+    }
+
+    /// run `f` with the raw context, valid only for the call
+    fn with_raw<R>(&self, f: impl FnOnce(&access_context_t) -> R) -> Result<R> {
+        let mut ctx = access_context_t {
+            version: ACCESS_CONTEXT_VERSION,
+            addr: self.addr,
+            ..Default::default()
+        };
+        // outlives the call, ctx only borrows its pointer
+        let symbol;
+        match &self.space {
+            AddressSpace::Physical => ctx.translate_mechanism = translation_mechanism_VMI_TM_NONE,
+            AddressSpace::Dtb(dtb) => {
+                ctx.translate_mechanism = translation_mechanism_VMI_TM_PROCESS_DTB;
+                ctx.__bindgen_anon_1.dtb = *dtb;
+            }
+            AddressSpace::Pid(pid) => {
+                ctx.translate_mechanism = translation_mechanism_VMI_TM_PROCESS_PID;
+                ctx.__bindgen_anon_1.pid = *pid as vmi_pid_t;
+            }
+            AddressSpace::KernelSymbol(name) => {
+                symbol = CString::new(name.as_str())
+                    .map_err(|_| VmiError::SymbolNotFound(name.clone()))?;
+                ctx.translate_mechanism = translation_mechanism_VMI_TM_KERNEL_SYMBOL;
+                ctx.__bindgen_anon_1.ksym = symbol.as_ptr();
+            }
+        }
+        Ok(f(&ctx))
+    }
+}
+
+/// paging mode of a vcpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PageMode {
+    /// 32-bit, no PAE
+    Legacy,
+    Pae,
+    /// 4-level long mode paging
+    Ia32e,
+    Aarch32,
+    Aarch64,
+    Unknown,
+}
+
+impl From<page_mode_t> for PageMode {
+    fn from(mode: page_mode_t) -> Self {
+        match mode {
+            page_mode_VMI_PM_LEGACY => PageMode::Legacy,
+            page_mode_VMI_PM_PAE => PageMode::Pae,
+            page_mode_VMI_PM_IA32E => PageMode::Ia32e,
+            page_mode_VMI_PM_AARCH32 => PageMode::Aarch32,
+            page_mode_VMI_PM_AARCH64 => PageMode::Aarch64,
+            _ => PageMode::Unknown,
+        }
+    }
 }
 
 /// os type detected in the VM
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum OsType {
     Linux,
     Windows,
@@ -36,14 +656,6 @@ impl From<os_t> for OsType {
 }
 
 impl Vmi {
-    /// create Vmi wrapper from raw handle (unsafe)
-    pub unsafe fn from_handle(handle: vmi_instance_t) -> Self {
-        Self {
-            handle,
-            paused: AtomicBool::new(false),
-        }
-    }
-
     /// get raw handle
     pub fn get_handle(&self) -> vmi_instance_t {
         self.handle
@@ -61,45 +673,86 @@ impl Vmi {
         }
     }
 
-    /// init libvmi with domain name, json profile path, and kvmi socket
-    pub(crate) fn new(domain_name: &str, json_path: &str, socket_path: &str) -> Result<Self> {
-        let name_cstr = CString::new(domain_name)
-            .map_err(|_| VmiError::InitFailed("invalid domain name".into()))?;
-        let json_cstr = CString::new(json_path)
-            .map_err(|_| VmiError::InitFailed("invalid json path".into()))?;
-        let socket_cstr = CString::new(socket_path)
-            .map_err(|_| VmiError::InitFailed("invalid socket path".into()))?;
+    /// init libvmi for a domain name or id with the given profile source
+    /// and backend
+    pub(crate) fn new(
+        domain: &Domain,
+        config: &InitConfig,
+        backend: &Backend,
+        events: bool,
+    ) -> Result<Self> {
+        // libvmi reads a C string for names and a uint64_t for ids
+        let name_cstr;
+        let (domain_ptr, domain_flag) = match domain {
+            Domain::Name(name) => {
+                name_cstr = CString::new(name.as_str())
+                    .map_err(|_| VmiError::InitFailed("invalid domain name".into()))?;
+                (name_cstr.as_ptr() as *const c_void, VMI_INIT_DOMAINNAME)
+            }
+            Domain::Id(id) => (id as *const u64 as *const c_void, VMI_INIT_DOMAINID),
+        };
+
+        let config_cstr;
+        let (config_mode, config_ptr) = match config {
+            InitConfig::JsonPath(path) => {
+                config_cstr = CString::new(path.as_str())
+                    .map_err(|_| VmiError::InitFailed("invalid json path".into()))?;
+                (vmi_config_VMI_CONFIG_JSON_PATH, config_cstr.as_ptr())
+            }
+            InitConfig::String(entry) => {
+                config_cstr = CString::new(entry.as_str())
+                    .map_err(|_| VmiError::InitFailed("invalid config string".into()))?;
+                (vmi_config_VMI_CONFIG_STRING, config_cstr.as_ptr())
+            }
+            InitConfig::GlobalFile => (vmi_config_VMI_CONFIG_GLOBAL_FILE_ENTRY, ptr::null()),
+        };
+
+        let socket_cstr = match backend {
+            Backend::Kvm { socket } => Some(
+                CString::new(socket.as_str())
+                    .map_err(|_| VmiError::InitFailed("invalid socket path".into()))?,
+            ),
+            Backend::Xen => None,
+        };
 
         let mut handle: vmi_instance_t = ptr::null_mut();
         let mut error: vmi_init_error_t = 0;
 
         // setup init data for kvmi socket - manual alloc for flexible array
-        let init_data_ptr = unsafe {
-            let header_size = size_of::<vmi_init_data_t>();
-            let entry_align = align_of::<vmi_init_data_entry_t>();
-            // align offset to entry_align
-            let entry_offset = (header_size + entry_align - 1) & !(entry_align - 1);
-            let total_size = entry_offset + size_of::<vmi_init_data_entry_t>();
-
-            let ptr = libc::calloc(1, total_size) as *mut vmi_init_data_t;
-            if ptr.is_null() {
-                return Err(VmiError::InitFailed("failed to allocate init data".into()));
-            }
-            (*ptr).count = 1;
-            let entry_ptr = (ptr as *mut u8).add(entry_offset) as *mut vmi_init_data_entry_t;
-            (*entry_ptr).type_ = vmi_init_data_type_t_VMI_INIT_DATA_KVMI_SOCKET as u64;
-            (*entry_ptr).data = socket_cstr.as_ptr() as *mut _;
-            ptr
+        let init_data_ptr = match &socket_cstr {
+            Some(socket_cstr) => unsafe {
+                let header_size = size_of::<vmi_init_data_t>();
+                let entry_align = align_of::<vmi_init_data_entry_t>();
+                // align offset to entry_align
+                let entry_offset = (header_size + entry_align - 1) & !(entry_align - 1);
+                let total_size = entry_offset + size_of::<vmi_init_data_entry_t>();
+
+                let ptr = libc::calloc(1, total_size) as *mut vmi_init_data_t;
+                if ptr.is_null() {
+                    return Err(VmiError::InitFailed("failed to allocate init data".into()));
+                }
+                (*ptr).count = 1;
+                let entry_ptr = (ptr as *mut u8).add(entry_offset) as *mut vmi_init_data_entry_t;
+                (*entry_ptr).type_ = vmi_init_data_type_t_VMI_INIT_DATA_KVMI_SOCKET as u64;
+                (*entry_ptr).data = socket_cstr.as_ptr() as *mut _;
+                ptr
+            },
+            None => ptr::null_mut(),
         };
 
+        let mut init_flags = domain_flag;
+        if events {
+            init_flags |= VMI_INIT_EVENTS;
+        }
+
         let status = unsafe {
             vmi_init_complete(
                 &mut handle,
-                name_cstr.as_ptr() as *mut _,
-                (VMI_INIT_DOMAINNAME | VMI_INIT_EVENTS) as u64,
+                domain_ptr as *mut _,
+                init_flags as u64,
                 init_data_ptr,
-                vmi_config_VMI_CONFIG_JSON_PATH,
-                json_cstr.as_ptr() as *mut _,
+                config_mode,
+                config_ptr as *mut _,
                 &mut error,
             )
         };
@@ -107,7 +760,9 @@ impl Vmi {
         // socket_cstr ownership transferred to libvmi
         std::mem::forget(socket_cstr);
 
-        unsafe { libc::free(init_data_ptr as *mut _) };
+        if !init_data_ptr.is_null() {
+            unsafe { libc::free(init_data_ptr as *mut _) };
+        }
 
         if status != status_VMI_SUCCESS {
             return Err(VmiError::InitFailed(format!("error code: {}", error)));
@@ -115,36 +770,176 @@ impl Vmi {
 
         Ok(Self {
             handle,
-            paused: AtomicBool::new(false),
+            pause_depth: Mutex::new(0),
+            caching: true,
+            translations: None,
+            symbols: RwLock::new(None),
+            events,
+            slat: OnceLock::new(),
         })
     }
 
-    /// pause vm for consistent memory access
-    pub fn pause(&self) -> Result<()> {
-        let status = unsafe { vmi_pause_vm(self.handle) };
-        if status != status_VMI_SUCCESS {
-            return Err(VmiError::ReadFailed {
-                addr: 0,
-                msg: "failed to pause vm".into(),
-            });
+    /// a Vmi with no libvmi instance behind it, for tests of code that
+    /// holds one without calling into libvmi
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        Self {
+            handle: ptr::null_mut(),
+            pause_depth: Mutex::new(0),
+            caching: true,
+            translations: None,
+            symbols: RwLock::new(None),
+            events: false,
+            slat: OnceLock::new(),
         }
-        self.paused.store(true, Ordering::SeqCst);
-        Ok(())
     }
 
-    /// resume vm after introspection
+    /// whether events were enabled at init, see VmiBuilder::events
+    pub fn events_enabled(&self) -> bool {
+        self.events
+    }
+
+    /// whether libvmi's caches are kept between pauses and writes
+    pub fn caching(&self) -> bool {
+        self.caching
+    }
+
+    /// translation cache counters, None unless enabled in the builder
+    pub fn translation_stats(&self) -> Option<CacheStats> {
+        self.translations
+            .as_ref()
+            .map(|cache| cache.lock().unwrap().stats())
+    }
+
+    fn flush_translations(&self) {
+        if let Some(cache) = &self.translations {
+            cache.lock().unwrap().clear();
+        }
+    }
+
+    /// drop every cached V2P, PID, symbol and RVA translation, ours included
+    pub fn flush_caches(&self) {
+        self.flush_translations();
+        unsafe {
+            // ~0 flushes the v2p entries of every dtb
+            vmi_v2pcache_flush(self.handle, !0);
+            vmi_pidcache_flush(self.handle);
+            vmi_symcache_flush(self.handle);
+            vmi_rvacache_flush(self.handle);
+        }
+    }
+
+    /// flush after a write when caching is disabled
+    fn written(&self) {
+        if !self.caching {
+            self.flush_caches();
+        }
+    }
+
+    /// pause vm for consistent memory access.
+    /// refcounted: only the outermost pause/resume pair reaches libvmi
+    pub fn pause(&self) -> Result<()> {
+        self.pause_with(|| {
+            let status = unsafe { vmi_pause_vm(self.handle) };
+            if status != status_VMI_SUCCESS {
+                return Err(VmiError::ReadFailed {
+                    addr: 0,
+                    msg: "failed to pause vm".into(),
+                });
+            }
+            // the guest ran since the last pause, its page tables may have moved
+            if !self.caching {
+                self.flush_caches();
+            } else {
+                self.flush_translations();
+            }
+            Ok(())
+        })
+    }
+
+    /// resume vm after introspection, undoes one pause()
     pub fn resume(&self) -> Result<()> {
-        let status = unsafe { vmi_resume_vm(self.handle) };
-        if status != status_VMI_SUCCESS {
-            return Err(VmiError::ReadFailed {
-                addr: 0,
-                msg: "failed to resume vm".into(),
-            });
+        self.resume_with(|| {
+            let status = unsafe { vmi_resume_vm(self.handle) };
+            if status != status_VMI_SUCCESS {
+                return Err(VmiError::ReadFailed {
+                    addr: 0,
+                    msg: "failed to resume vm".into(),
+                });
+            }
+            Ok(())
+        })
+    }
+
+    /// pause()'s refcount, `pause_vm` runs only on the 0 -> 1 step
+    fn pause_with(&self, pause_vm: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut depth = self.pause_depth.lock().unwrap();
+        if *depth == 0 {
+            pause_vm()?;
         }
-        self.paused.store(false, Ordering::SeqCst);
+        *depth += 1;
         Ok(())
     }
 
+    /// resume()'s refcount, `resume_vm` runs only on the 1 -> 0 step.
+    /// a resume with nothing paused is a no-op
+    fn resume_with(&self, resume_vm: impl FnOnce() -> Result<()>) -> Result<()> {
+        let mut depth = self.pause_depth.lock().unwrap();
+        match *depth {
+            0 => Ok(()),
+            1 => {
+                resume_vm()?;
+                *depth = 0;
+                Ok(())
+            }
+            _ => {
+                *depth -= 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// whether a pause() is currently outstanding
+    pub fn is_paused(&self) -> bool {
+        *self.pause_depth.lock().unwrap() > 0
+    }
+
+    /// pause until the returned guard is dropped
+    pub fn pause_guard(&self) -> Result<PauseGuard<'_>> {
+        self.pause()?;
+        Ok(PauseGuard { vmi: self })
+    }
+
+    /// run a group of reads against a paused vm so structures can't tear.
+    /// nests with outer pauses, the prior pause state is restored afterwards
+    pub fn read_consistent<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&Vmi) -> Result<R>,
+    {
+        let _guard = self.pause_guard()?;
+        f(self)
+    }
+
+    /// run `f`, and if it fails run it once more inside read_consistent.
+    /// the pause also drops cached translations, so reads at a hook point
+    /// where the guest is mid-update often succeed the second time. costs a
+    /// pause/resume round trip per failure, in exchange for fewer fields
+    /// coming back unknown. nests with outer pauses like read_consistent
+    pub fn retry_paused<F, R>(&self, f: F) -> Result<R>
+    where
+        F: Fn(&Vmi) -> Result<R>,
+    {
+        match f(self) {
+            Ok(value) => Ok(value),
+            Err(_) => self.read_consistent(&f),
+        }
+    }
+
+    /// number of vcpus in the guest
+    pub fn num_vcpus(&self) -> u32 {
+        unsafe { vmi_get_num_vcpus(self.handle) }
+    }
+
     /// get os type
     pub fn os_type(&self) -> OsType {
         let os = unsafe { vmi_get_ostype(self.handle) };
@@ -173,13 +968,28 @@ impl Vmi {
         unsafe { vmi_get_vmid(self.handle) }
     }
 
+    /// guest memory size in bytes
+    pub fn memsize(&self) -> u64 {
+        unsafe { vmi_get_memsize(self.handle) }
+    }
+
+    /// highest guest physical address, above memsize when there are holes
+    pub fn max_physical_address(&self) -> u64 {
+        unsafe { vmi_get_max_physical_address(self.handle) }
+    }
+
+    /// paging mode `vcpu` runs in
+    pub fn page_mode(&self, vcpu: u32) -> PageMode {
+        PageMode::from(unsafe { vmi_get_page_mode(self.handle, vcpu as _) })
+    }
+
     /// get offset from config
     pub fn get_offset(&self, name: &str) -> Result<u64> {
-        let name_cstr = CString::new(name).map_err(|_| VmiError::SymbolNotFound(name.into()))?;
+        let name_cstr = CString::new(name).map_err(|_| VmiError::OffsetNotFound(name.into()))?;
         let mut offset: u64 = 0;
         let status = unsafe { vmi_get_offset(self.handle, name_cstr.as_ptr(), &mut offset) };
         if status != status_VMI_SUCCESS {
-            return Err(VmiError::SymbolNotFound(name.into()));
+            return Err(VmiError::OffsetNotFound(name.into()));
         }
         Ok(offset)
     }
@@ -187,9 +997,9 @@ impl Vmi {
     /// get struct member offset from JSON profile via libvmi API
     pub fn get_struct_offset(&self, struct_name: &str, field_name: &str) -> Result<u64> {
         let s_cstr =
-            CString::new(struct_name).map_err(|_| VmiError::SymbolNotFound(struct_name.into()))?;
+            CString::new(struct_name).map_err(|_| VmiError::OffsetNotFound(struct_name.into()))?;
         let m_cstr =
-            CString::new(field_name).map_err(|_| VmiError::SymbolNotFound(field_name.into()))?;
+            CString::new(field_name).map_err(|_| VmiError::OffsetNotFound(field_name.into()))?;
 
         let mut offset: u64 = 0;
         let status = unsafe {
@@ -197,7 +1007,7 @@ impl Vmi {
         };
 
         if status != status_VMI_SUCCESS {
-            return Err(VmiError::SymbolNotFound(format!(
+            return Err(VmiError::OffsetNotFound(format!(
                 "{}.{}",
                 struct_name, field_name
             )));
@@ -216,6 +1026,58 @@ impl Vmi {
         Ok(addr)
     }
 
+    /// kernel VA to (symbol, offset). libvmi only knows exact symbol starts,
+    /// anything else goes through the resolver set with set_symbols
+    pub fn v2ksym(&self, vaddr: u64) -> Result<(String, u64)> {
+        if let Ok(dtb) = self.kernel_dtb() {
+            let name_ptr = ReadContext::dtb(dtb, vaddr)
+                .with_raw(|ctx| unsafe { vmi_translate_v2ksym(self.handle, ctx, vaddr) })?;
+            if !name_ptr.is_null() {
+                let name = unsafe { CStr::from_ptr(name_ptr) };
+                let result = name.to_string_lossy().into_owned();
+                // a strdup'd copy, ours to free like read_str_va's
+                unsafe { libc::free(name_ptr as *mut _) };
+                return Ok((result, 0));
+            }
+        }
+
+        self.symbols
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|symbols| symbols.resolve(vaddr))
+            .ok_or_else(|| VmiError::SymbolNotFound(format!("{:#x}", vaddr)))
+    }
+
+    /// v2ksym formatted as `symbol+0x12`, the bare address if unresolved
+    pub fn symbolize(&self, vaddr: u64) -> String {
+        crate::symbols::format_location(self.v2ksym(vaddr).ok(), vaddr)
+    }
+
+    /// resolver used by v2ksym for addresses libvmi can't name
+    pub fn set_symbols(&self, symbols: Arc<SymbolResolver>) {
+        *self.symbols.write().unwrap() = Some(symbols);
+    }
+
+    pub fn symbols(&self) -> Option<Arc<SymbolResolver>> {
+        self.symbols.read().unwrap().clone()
+    }
+
+    /// runtime load address of the kernel image, KASLR slide included.
+    /// ksym2v results already have the slide applied, only profile RVAs
+    /// need this added
+    pub fn kernel_base(&self) -> Result<u64> {
+        match self.os_type() {
+            // filled in by libvmi's windows init when it locates ntoskrnl
+            OsType::Windows => match self.get_offset("win_ntoskrnl_va") {
+                Ok(base) if base != 0 => Ok(base),
+                _ => Err(VmiError::SymbolNotFound("win_ntoskrnl_va".into())),
+            },
+            OsType::Linux => self.ksym2v("_text").or_else(|_| self.ksym2v("_stext")),
+            os => Err(VmiError::Other(format!("no kernel base for {:?}", os))),
+        }
+    }
+
     /// read address at kernel symbol
     pub fn read_addr_ksym(&self, symbol: &str) -> Result<u64> {
         let sym_cstr = CString::new(symbol).map_err(|_| VmiError::SymbolNotFound(symbol.into()))?;
@@ -240,6 +1102,114 @@ impl Vmi {
         Ok(addr)
     }
 
+    /// read a pointer-sized value (4 or 8 bytes depending on guest width).
+    /// use this for pointers and HANDLE-typed fields such as UniqueProcessId.
+    pub fn read_ptr(&self, vaddr: u64, pid: u32) -> Result<u64> {
+        self.read_addr_va(vaddr, pid)
+    }
+
+    /// read `length` bytes of virtual memory, raw guest bytes
+    pub fn read_va(&self, vaddr: u64, pid: u32, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; length];
+        self.read_va_into(vaddr, pid, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn read_va_into(&self, vaddr: u64, pid: u32, buffer: &mut [u8]) -> Result<()> {
+        let mut read: usize = 0;
+        let status = unsafe {
+            vmi_read_va(
+                self.handle,
+                vaddr,
+                pid as i32,
+                buffer.len(),
+                buffer.as_mut_ptr() as *mut std::ffi::c_void,
+                &mut read,
+            )
+        };
+        if status == status_VMI_SUCCESS && read == buffer.len() {
+            Ok(())
+        } else {
+            Err(VmiError::ReadFailed {
+                addr: vaddr,
+                msg: format!("read_va: {} of {} bytes", read, buffer.len()),
+            })
+        }
+    }
+
+    /// read `length` raw bytes at a context, any address space
+    pub fn read_ctx(&self, ctx: &ReadContext, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0u8; length];
+        let mut read: usize = 0;
+        let status = ctx.with_raw(|raw| unsafe {
+            vmi_read(
+                self.handle,
+                raw,
+                buffer.len(),
+                buffer.as_mut_ptr() as *mut c_void,
+                &mut read,
+            )
+        })?;
+        if status == status_VMI_SUCCESS && read == length {
+            Ok(buffer)
+        } else {
+            Err(VmiError::ReadFailed {
+                addr: ctx.addr,
+                msg: format!("read_ctx: {} of {} bytes", read, length),
+            })
+        }
+    }
+
+    /// read a UNICODE_STRING (or linux-style string) at a context through
+    /// libvmi, converted from whatever encoding it reports to UTF-8.
+    /// pointer width and struct layout come from the guest, unlike
+    /// read_unicode_string which assumes x64
+    pub fn read_unicode_str_ctx(&self, ctx: &ReadContext) -> Result<String> {
+        let raw = ctx.with_raw(|raw| unsafe { vmi_read_unicode_str(self.handle, raw) })?;
+        if raw.is_null() {
+            return Err(VmiError::ReadFailed {
+                addr: ctx.addr,
+                msg: "vmi_read_unicode_str failed".into(),
+            });
+        }
+
+        let mut out = unicode_string_t::default();
+        let status = unsafe { vmi_convert_str_encoding(raw, &mut out, c"UTF-8".as_ptr()) };
+        unsafe { vmi_free_unicode_str(raw) };
+        // out.contents is malloc'd by the conversion, the struct is ours
+        let converted = (status == status_VMI_SUCCESS).then(|| {
+            if out.contents.is_null() {
+                return String::new();
+            }
+            let bytes = unsafe { std::slice::from_raw_parts(out.contents, out.length) };
+            String::from_utf8_lossy(bytes).into_owned()
+        });
+        if !out.contents.is_null() {
+            unsafe { libc::free(out.contents as *mut c_void) };
+        }
+        converted.ok_or_else(|| VmiError::ReadFailed {
+            addr: ctx.addr,
+            msg: "string encoding conversion failed".into(),
+        })
+    }
+
+    /// read a plain-old-data struct, fields are in guest (little-endian) order
+    pub fn read_struct<T: bytemuck::Pod>(&self, vaddr: u64, pid: u32) -> Result<T> {
+        let mut value = T::zeroed();
+        self.read_va_into(vaddr, pid, bytemuck::bytes_of_mut(&mut value))?;
+        Ok(value)
+    }
+
+    /// read `count` consecutive structs in one call, e.g. handle table pages
+    pub fn read_array<T: bytemuck::Pod>(
+        &self,
+        vaddr: u64,
+        pid: u32,
+        count: usize,
+    ) -> Result<Vec<T>> {
+        read_array_with(count, |bytes| self.read_va_into(vaddr, pid, bytes))
+    }
+
     /// read 32-bit value at virtual address
     pub fn read_32_va(&self, vaddr: u64, pid: u32) -> Result<u32> {
         let mut val: u32 = 0;
@@ -276,9 +1246,40 @@ impl Vmi {
                 msg: "write_8_va failed".into(),
             });
         }
+        self.written();
         Ok(())
     }
 
+    /// write virtual memory, fails unless every byte was written
+    pub fn write_va(&self, vaddr: u64, pid: u32, data: &[u8]) -> Result<()> {
+        let mut written: usize = 0;
+        let status = unsafe {
+            vmi_write_va(
+                self.handle,
+                vaddr,
+                pid as i32,
+                data.len(),
+                data.as_ptr() as *mut std::ffi::c_void,
+                &mut written,
+            )
+        };
+        self.written();
+        if status == status_VMI_SUCCESS && written == data.len() {
+            Ok(())
+        } else {
+            Err(VmiError::ReadFailed {
+                addr: vaddr,
+                msg: format!("write_va: {} of {} bytes", written, data.len()),
+            })
+        }
+    }
+
+    /// overwrite `len` bytes of virtual memory with zeros, a page at a time
+    /// so a large range never needs a large buffer
+    pub fn zero_va(&self, vaddr: u64, pid: u32, len: usize) -> Result<()> {
+        zero_va_with(vaddr, len, |addr, zeros| self.write_va(addr, pid, zeros))
+    }
+
     /// translate kernel virtual to physical address
     pub fn v2p(&self, vaddr: u64) -> Result<u64> {
         let mut paddr: u64 = 0;
@@ -296,10 +1297,40 @@ impl Vmi {
         if status != status_VMI_SUCCESS {
             return Err(VmiError::ReadFailed {
                 addr: paddr,
-                msg: "read_8_pa failed".into(),
+                msg: "read_8_pa failed".into(),
+            });
+        }
+        Ok(val)
+    }
+
+    /// read 64-bit value at physical address, host-native order
+    pub fn read_64_pa(&self, paddr: u64) -> Result<u64> {
+        read_64_with(paddr, |val| unsafe {
+            vmi_read_64_pa(self.handle, paddr, val)
+        })
+    }
+
+    /// write 64-bit value at physical address, host-native order
+    pub fn write_64_pa(&self, paddr: u64, val: u64) -> Result<()> {
+        write_64_with(paddr, val, |val| unsafe {
+            vmi_write_64_pa(self.handle, paddr, val)
+        })?;
+        self.written();
+        Ok(())
+    }
+
+    /// write 8-bit value at physical address
+    pub fn write_8_pa(&self, paddr: u64, val: u8) -> Result<()> {
+        let ptr = &val as *const u8;
+        let status = unsafe { vmi_write_8_pa(self.handle, paddr, ptr as *mut u8) };
+        if status != status_VMI_SUCCESS {
+            return Err(VmiError::ReadFailed {
+                addr: paddr,
+                msg: "write_8_pa failed".into(),
             });
         }
-        Ok(val)
+        self.written();
+        Ok(())
     }
 
     /// read 16-bit memory at virtual address
@@ -332,9 +1363,9 @@ impl Vmi {
 
     /// read unicode string struct at virtual address
     pub fn read_unicode_string(&self, vaddr: u64, pid: u32) -> Result<String> {
-        // manual implementation:
-        // avoids FFI complexity of `vmi_read_unicode_str` (requires context structs)
-        // by reading UNICODE_STRING Length and Buffer, then reading UTF-16 data.
+        // manual implementation: reads UNICODE_STRING Length and Buffer, then
+        // the UTF-16 data. x64 layout and UTF-16 only, read_unicode_str_ctx
+        // is the libvmi path that handles both
 
         let length = self.read_16_va(vaddr, pid).unwrap_or(0);
         let _max_len = self.read_16_va(vaddr + 2, pid).unwrap_or(0);
@@ -378,18 +1409,114 @@ impl Vmi {
         Ok(())
     }
 
-    /// listen for events (blocking)
+    /// change the access restriction on a gfn with a registered mem event,
+    /// VMI_MEMACCESS_N lifts it
+    pub fn set_mem_access(&self, gfn: u64, access: u32) -> Result<()> {
+        self.set_mem_access_in(gfn, access, 0)
+    }
+
+    /// set_mem_access in one slat view, 0 is the default view
+    pub fn set_mem_access_in(&self, gfn: u64, access: u32, view: u16) -> Result<()> {
+        let status =
+            unsafe { vmi_set_mem_event(self.handle, gfn, access as vmi_mem_access_t, view) };
+        if status != status_VMI_SUCCESS {
+            return Err(VmiError::MemAccessFailed(gfn));
+        }
+        Ok(())
+    }
+
+    /// whether the driver supports slat (EPT altp2m) views. probed once by
+    /// asking for the domain's slat state, every other slat call checks it
+    pub fn slat_supported(&self) -> bool {
+        *self.slat.get_or_init(|| {
+            let mut state = false;
+            unsafe { vmi_slat_get_domain_state(self.handle, &mut state) == status_VMI_SUCCESS }
+        })
+    }
+
+    /// SlatUnsupported unless slat_supported
+    fn require_slat(&self) -> Result<()> {
+        if self.slat_supported() {
+            Ok(())
+        } else {
+            Err(VmiError::SlatUnsupported)
+        }
+    }
+
+    fn slat_status(&self, status: status_t, op: &'static str) -> Result<()> {
+        if status == status_VMI_SUCCESS {
+            Ok(())
+        } else {
+            Err(VmiError::SlatFailed(op))
+        }
+    }
+
+    /// whether alternate views are enabled for the domain
+    pub fn slat_enabled(&self) -> Result<bool> {
+        self.require_slat()?;
+        let mut state = false;
+        let status = unsafe { vmi_slat_get_domain_state(self.handle, &mut state) };
+        self.slat_status(status, "get domain state")?;
+        Ok(state)
+    }
+
+    /// enable or disable alternate views for the domain. needed before
+    /// slat_create; disabling drops every view
+    pub fn slat_set_enabled(&self, enabled: bool) -> Result<()> {
+        self.require_slat()?;
+        let status = unsafe { vmi_slat_set_domain_state(self.handle, enabled) };
+        self.slat_status(status, "set domain state")
+    }
+
+    /// new view, a copy of the default one until gfns are remapped in it
+    pub fn slat_create(&self) -> Result<u16> {
+        self.require_slat()?;
+        let mut view: u16 = 0;
+        let status = unsafe { vmi_slat_create(self.handle, &mut view) };
+        self.slat_status(status, "create")?;
+        Ok(view)
+    }
+
+    /// destroy a view, no vcpu may be running on it
+    pub fn slat_destroy(&self, view: u16) -> Result<()> {
+        self.require_slat()?;
+        let status = unsafe { vmi_slat_destroy(self.handle, view) };
+        self.slat_status(status, "destroy")
+    }
+
+    /// move every vcpu to `view`, 0 returns to the default one
+    pub fn slat_switch(&self, view: u16) -> Result<()> {
+        self.require_slat()?;
+        let status = unsafe { vmi_slat_switch(self.handle, view) };
+        self.slat_status(status, "switch")
+    }
+
+    /// back `old_gfn` with `new_gfn`'s frame in `view`, e.g. an execute-only
+    /// view running a patched copy while reads still see the original page.
+    /// `new_gfn` == `old_gfn` undoes the remap
+    pub fn slat_change_gfn(&self, view: u16, old_gfn: u64, new_gfn: u64) -> Result<()> {
+        self.require_slat()?;
+        let status = unsafe { vmi_slat_change_gfn(self.handle, view, old_gfn, new_gfn) };
+        self.slat_status(status, "change gfn")
+    }
+
+    /// listen for events (blocking). handlers run on this thread, inside
+    /// this call, with a VmiRef to self
     pub fn events_listen(&self, timeout: u32) -> Result<()> {
+        let outer = LISTENING.replace(self);
         let status = unsafe { vmi_events_listen(self.handle, timeout) };
+        LISTENING.set(outer);
         if status != status_VMI_SUCCESS {
-            return Err(VmiError::ReadFailed {
-                addr: 0,
-                msg: "error listening for events".into(),
-            });
+            return Err(VmiError::ListenFailed);
         }
         Ok(())
     }
 
+    /// whether the hypervisor still answers, by fetching vcpu 0's RIP
+    pub fn responds(&self) -> bool {
+        self.get_vcpureg(RIP as u64, 0).is_ok()
+    }
+
     /// get vcpu register
     pub fn get_vcpureg(&self, reg: u64, vcpu: u32) -> Result<u64> {
         let mut val: u64 = 0;
@@ -403,6 +1530,33 @@ impl Vmi {
         Ok(val)
     }
 
+    /// read an MSR by its architectural number (IA32_LSTAR, IA32_GS_BASE, ...).
+    /// only the MSRs libvmi tracks per vcpu are readable, others error
+    pub fn read_msr(&self, msr: u32, vcpu: u32) -> Result<u64> {
+        let reg = msr_register(msr).ok_or(VmiError::UnsupportedMsr(msr))?;
+        self.get_vcpureg(reg as u64, vcpu)
+    }
+
+    /// raw register set of one vcpu, x86 only in practice
+    pub fn get_vcpuregs(&self, vcpu: u32) -> Result<registers_t> {
+        get_vcpuregs_with(vcpu, |regs| unsafe {
+            vmi_get_vcpuregs(self.handle, regs, vcpu as u64)
+        })
+    }
+
+    /// write a whole register set back, usually one from get_vcpuregs.
+    /// the vcpu should be paused so it can't run between read and write
+    pub fn set_vcpuregs(&self, regs: &registers_t, vcpu: u32) -> Result<()> {
+        set_vcpuregs_with(regs, vcpu, |regs| unsafe {
+            vmi_set_vcpuregs(self.handle, regs, vcpu as u64)
+        })
+    }
+
+    /// every register of one vcpu in a single call
+    pub fn get_all_regs(&self, vcpu: u32) -> Result<x86_regs> {
+        Ok(unsafe { self.get_vcpuregs(vcpu)?.x86 })
+    }
+
     /// set vcpu register
     pub fn set_vcpureg(&self, reg: u64, val: u64, vcpu: u32) -> Result<()> {
         let status = unsafe { vmi_set_vcpureg(self.handle, val, reg, vcpu as u64) };
@@ -425,6 +1579,7 @@ impl Vmi {
                 msg: "write_16_va failed".into(),
             });
         }
+        self.written();
         Ok(())
     }
 
@@ -438,6 +1593,7 @@ impl Vmi {
                 msg: "write_32_va failed".into(),
             });
         }
+        self.written();
         Ok(())
     }
 
@@ -451,6 +1607,7 @@ impl Vmi {
                 msg: "write_64_va failed".into(),
             });
         }
+        self.written();
         Ok(())
     }
 }
@@ -490,6 +1647,13 @@ impl VmiEvent {
         self.inner.__bindgen_anon_1.ss_event.vcpus = vcpu_id;
     }
 
+    /// singlestep event over a vcpu bitmask, optionally registered disabled
+    pub fn set_singlestep_vcpus(&mut self, vcpus: u32, enable: bool) {
+        self.inner.type_ = VMI_EVENT_SINGLESTEP as u16;
+        self.inner.__bindgen_anon_1.ss_event.vcpus = vcpus;
+        self.inner.__bindgen_anon_1.ss_event.enable = enable as u8;
+    }
+
     pub fn set_mem_event(&mut self, gfn: u64, access: u32, gla: u64) {
         self.inner.type_ = VMI_EVENT_MEMORY as u16;
         self.inner.__bindgen_anon_1.mem_event.gfn = gfn;
@@ -528,6 +1692,23 @@ impl VmiEvent {
             .reinject = reinject;
     }
 
+    /// configure a register access event, e.g. (CR3, VMI_REGACCESS_W)
+    pub fn set_reg_event(&mut self, reg: u64, access: u32) {
+        self.inner.type_ = VMI_EVENT_REGISTER as u16;
+        self.inner.__bindgen_anon_1.reg_event.reg = reg;
+        self.inner.__bindgen_anon_1.reg_event.in_access = access as u8;
+    }
+
+    /// new register value of a reg_event, not yet committed to the vcpu
+    pub unsafe fn get_reg_event_value(&self) -> u64 {
+        unsafe { self.inner.__bindgen_anon_1.reg_event.value }
+    }
+
+    /// register value before the write of a reg_event
+    pub unsafe fn get_reg_event_previous(&self) -> u64 {
+        unsafe { self.inner.__bindgen_anon_1.reg_event.previous }
+    }
+
     /// get gfn from mem_event
     pub unsafe fn get_mem_event_gfn(&self) -> u64 {
         unsafe { self.inner.__bindgen_anon_1.mem_event.gfn }
@@ -544,7 +1725,7 @@ impl VmiEvent {
 
 /// helper functions for raw vmi_event_t pointers (used in FFI callbacks)
 pub mod event_helpers {
-    use crate::ffi::{vmi_event_t, x86_regs};
+    use crate::ffi::{vmi_event_t, x86_regs, VMI_EVENT_MEMORY};
 
     /// set reinject flag on raw event pointer
     pub unsafe fn set_reinject(event: *mut vmi_event_t, val: i8) {
@@ -567,14 +1748,65 @@ pub mod event_helpers {
     pub unsafe fn get_mem_gfn(event: *mut vmi_event_t) -> u64 {
         unsafe { (*event).__bindgen_anon_1.mem_event.gfn }
     }
+
+    /// access that triggered a mem_event, VMI_MEMACCESS_* bits
+    pub unsafe fn get_mem_access(event: *mut vmi_event_t) -> u8 {
+        unsafe { (*event).__bindgen_anon_1.mem_event.out_access }
+    }
+
+    /// faulting guest linear address of a mem_event, when the hardware gave one
+    pub unsafe fn get_mem_gla(event: *mut vmi_event_t) -> Option<u64> {
+        unsafe {
+            let mem = &(*event).__bindgen_anon_1.mem_event;
+            (mem.gla_valid != 0).then_some(mem.gla)
+        }
+    }
+
+    /// byte offset of a mem_event's access within its page
+    pub unsafe fn get_mem_offset(event: *mut vmi_event_t) -> u64 {
+        unsafe { (*event).__bindgen_anon_1.mem_event.offset }
+    }
+
+    /// register snapshot of the vcpu that faulted on a mem_event. the regs
+    /// pointer is shared by every event type, this checks the event really
+    /// is a mem_event and that libvmi filled the pointer in
+    pub unsafe fn get_mem_regs(event: *mut vmi_event_t) -> Option<x86_regs> {
+        unsafe {
+            if (*event).type_ as u32 != VMI_EVENT_MEMORY {
+                return None;
+            }
+            get_x86_regs(event).as_ref().copied()
+        }
+    }
+
+    /// new register value from a reg_event. during a write event the vcpu
+    /// still holds the old value, so get_vcpureg would return that
+    pub unsafe fn get_reg_event_value(event: *mut vmi_event_t) -> u64 {
+        unsafe { (*event).__bindgen_anon_1.reg_event.value }
+    }
+
+    /// register value before the write, from a reg_event
+    pub unsafe fn get_reg_event_previous(event: *mut vmi_event_t) -> u64 {
+        unsafe { (*event).__bindgen_anon_1.reg_event.previous }
+    }
 }
 
 impl Vmi {
     /// translate virtual address to physical address using specific DTB
     pub fn translate_uv2p(&self, dtb: u64, vaddr: u64) -> Result<u64> {
+        let page = page_base(vaddr);
+        if let Some(cache) = &self.translations
+            && let Some(paddr_page) = cache.lock().unwrap().get(dtb, page)
+        {
+            return Ok(paddr_page | page_offset(vaddr));
+        }
+
         let mut paddr: addr_t = 0;
         let status = unsafe { vmi_pagetable_lookup(self.handle, dtb, vaddr, &mut paddr) };
         if status == status_VMI_SUCCESS {
+            if let Some(cache) = &self.translations {
+                cache.lock().unwrap().insert(dtb, page, page_base(paddr));
+            }
             Ok(paddr)
         } else {
             Err(VmiError::ReadFailed {
@@ -584,6 +1816,43 @@ impl Vmi {
         }
     }
 
+    /// page table walk via a specific DTB, keeping the permission bits.
+    /// writable/executable are the effective permissions across all levels.
+    pub fn pagetable_lookup_extended(&self, dtb: u64, vaddr: u64) -> Result<PageInfo> {
+        let mut info: page_info_t = Default::default();
+        let status = unsafe { vmi_pagetable_lookup_extended(self.handle, dtb, vaddr, &mut info) };
+        if status != status_VMI_SUCCESS {
+            return Err(VmiError::TranslateFailed { addr: vaddr });
+        }
+
+        const WRITABLE: u64 = 1 << 1;
+        const NX: u64 = 1 << 63;
+
+        let entries = if self.address_width() == 8 {
+            let e = unsafe { info.__bindgen_anon_1.x86_ia32e };
+            vec![e.pml4e_value, e.pdpte_value, e.pgd_value, e.pte_value]
+        } else {
+            // legacy 32-bit paging has no NX bit, PAE does and shares the layout
+            let e = unsafe { info.__bindgen_anon_1.x86_pae };
+            vec![e.pdpe_value, e.pgd_value, e.pte_value]
+        };
+        // large pages leave the lower levels zeroed
+        let entries: Vec<u64> = entries.into_iter().filter(|&e| e != 0).collect();
+
+        Ok(PageInfo {
+            vaddr: info.vaddr,
+            paddr: info.paddr,
+            size: info.size as u64,
+            writable: entries.iter().all(|e| e & WRITABLE != 0),
+            executable: entries.iter().all(|e| e & NX == 0),
+        })
+    }
+
+    /// kernel page table base libvmi uses for kernel translations
+    pub fn kernel_dtb(&self) -> Result<u64> {
+        self.get_offset("kpgd")
+    }
+
     /// translate kernel virtual address to physical address
     pub fn translate_kv2p(&self, vaddr: u64) -> Result<u64> {
         let mut paddr: addr_t = 0;
@@ -595,21 +1864,40 @@ impl Vmi {
         }
     }
 
-    /// read physical memory
+    /// whether `vaddr` translates in `pid`'s address space (0 for kernel),
+    /// for skipping paged-out pointers before reading through them
+    pub fn is_mapped_va(&self, vaddr: u64, pid: u32) -> bool {
+        // only whether it translates matters, not where to
+        let mut kernel_pa: addr_t = 0;
+        let mut user_pa: addr_t = 0;
+        is_mapped_with(
+            pid,
+            || unsafe { vmi_translate_kv2p(self.handle, vaddr, &mut kernel_pa) },
+            |pid| unsafe { vmi_translate_uv2p(self.handle, vaddr, pid, &mut user_pa) },
+        )
+    }
+
+    /// read physical memory as raw guest bytes (little-endian values)
     pub fn read_pa(&self, paddr: u64, length: usize) -> Result<Vec<u8>> {
         let mut buffer = vec![0u8; length];
+        self.read_pa_into(paddr, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// read_pa into a caller's buffer, filling all of it
+    pub(crate) fn read_pa_into(&self, paddr: u64, buffer: &mut [u8]) -> Result<()> {
         let mut read: usize = 0;
         let status = unsafe {
             vmi_read_pa(
                 self.handle,
                 paddr,
-                length,
+                buffer.len(),
                 buffer.as_mut_ptr() as *mut std::ffi::c_void,
                 &mut read,
             )
         };
-        if status == status_VMI_SUCCESS && read == length {
-            Ok(buffer)
+        if status == status_VMI_SUCCESS && read == buffer.len() {
+            Ok(())
         } else {
             Err(VmiError::ReadFailed {
                 addr: paddr,
@@ -618,63 +1906,141 @@ impl Vmi {
         }
     }
 
-    /// read unicode string using a specific DTB (for new processes not in PID cache)
+    /// write physical memory
+    pub fn write_pa(&self, paddr: u64, data: &[u8]) -> Result<()> {
+        let mut written: usize = 0;
+        let status = unsafe {
+            vmi_write_pa(
+                self.handle,
+                paddr,
+                data.len(),
+                data.as_ptr() as *mut std::ffi::c_void,
+                &mut written,
+            )
+        };
+        self.written();
+        if status == status_VMI_SUCCESS && written == data.len() {
+            Ok(())
+        } else {
+            Err(VmiError::ReadFailed {
+                addr: paddr,
+                msg: "Physical write failed".into(),
+            })
+        }
+    }
+
+    /// write virtual memory through a specific DTB, page by page like read_va_dtb
+    pub fn write_va_dtb(&self, dtb: u64, vaddr: u64, data: &[u8]) -> Result<()> {
+        let mut written = 0usize;
+        while written < data.len() {
+            let curr_vaddr = vaddr + written as u64;
+            let paddr = self.translate_uv2p(dtb, curr_vaddr)?;
+            let remainder = page_remaining(curr_vaddr) as usize;
+            let to_write = std::cmp::min(remainder, data.len() - written);
+
+            self.write_pa(paddr, &data[written..written + to_write])?;
+            written += to_write;
+        }
+        Ok(())
+    }
+
+    /// read virtual memory through a specific DTB, translating page by page
+    /// since contiguous virtual pages are rarely contiguous physically
+    pub fn read_va_dtb(&self, dtb: u64, vaddr: u64, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length];
+        self.read_va_dtb_into(dtb, vaddr, &mut data)?;
+        Ok(data)
+    }
+
+    /// read_va_dtb into a caller's buffer, filling all of it
+    pub(crate) fn read_va_dtb_into(&self, dtb: u64, vaddr: u64, buffer: &mut [u8]) -> Result<()> {
+        let mut done = 0usize;
+        while done < buffer.len() {
+            let curr_vaddr = vaddr + done as u64;
+            // translate current page
+            let paddr = self.translate_uv2p(dtb, curr_vaddr)?;
+            // how much can we read in this page?
+            let remainder = page_remaining(curr_vaddr) as usize;
+            let to_read = std::cmp::min(remainder, buffer.len() - done);
+
+            self.read_pa_into(paddr, &mut buffer[done..done + to_read])?;
+            done += to_read;
+        }
+        Ok(())
+    }
+
+    /// read_va_dtb into the thread's scratch buffer and hand it to `f`, for
+    /// callbacks that only look at the bytes. see with_scratch
+    pub(crate) fn read_va_dtb_with<R>(
+        &self,
+        dtb: u64,
+        vaddr: u64,
+        length: usize,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Result<R> {
+        with_scratch(length, |buf| {
+            self.read_va_dtb_into(dtb, vaddr, buf)?;
+            Ok(f(buf))
+        })
+    }
+
+    /// read_va into the scratch buffer, same contract as read_va_dtb_with
+    pub(crate) fn read_va_with<R>(
+        &self,
+        vaddr: u64,
+        pid: u32,
+        length: usize,
+        f: impl FnOnce(&[u8]) -> R,
+    ) -> Result<R> {
+        with_scratch(length, |buf| {
+            self.read_va_into(vaddr, pid, buf)?;
+            Ok(f(buf))
+        })
+    }
+
+    /// read unicode string using a specific DTB (for new processes not in PID cache).
+    /// strings over MAX_UNICODE_STRING_BYTES come back truncated
     pub fn read_unicode_string_dtb(&self, dtb: u64, vaddr: u64) -> Result<String> {
+        self.read_unicode_string_dtb_max(dtb, vaddr, MAX_UNICODE_STRING_BYTES)
+    }
+
+    /// read_unicode_string_dtb reading at most `max_bytes` of the buffer,
+    /// longer strings return their prefix
+    pub fn read_unicode_string_dtb_max(
+        &self,
+        dtb: u64,
+        vaddr: u64,
+        max_bytes: usize,
+    ) -> Result<String> {
         // read length (first 2 bytes)
         let len_pa = self.translate_uv2p(dtb, vaddr)?;
-        let len_buf = self.read_pa(len_pa, 2)?;
-        let length = u16::from_le_bytes([len_buf[0], len_buf[1]]) as usize;
+        let mut len_buf = [0u8; 2];
+        self.read_pa_into(len_pa, &mut len_buf)?;
+        let length = unicode_read_len(u16::from_le_bytes(len_buf), max_bytes);
 
         if length == 0 {
             return Ok(String::new());
         }
-        if length > 4096 {
-            return Ok("<too_long>".into());
-        }
 
         // read buffer address (offset 8 on x64)
         let buf_ptr_pa = self.translate_uv2p(dtb, vaddr + 8)?;
-        let buf_ptr_raw = self.read_pa(buf_ptr_pa, 8)?;
-        let buf_vaddr = u64::from_le_bytes([
-            buf_ptr_raw[0],
-            buf_ptr_raw[1],
-            buf_ptr_raw[2],
-            buf_ptr_raw[3],
-            buf_ptr_raw[4],
-            buf_ptr_raw[5],
-            buf_ptr_raw[6],
-            buf_ptr_raw[7],
-        ]);
+        let mut buf_ptr_raw = [0u8; 8];
+        self.read_pa_into(buf_ptr_pa, &mut buf_ptr_raw)?;
+        let buf_vaddr = u64::from_le_bytes(buf_ptr_raw);
 
         if buf_vaddr == 0 {
             return Ok(String::new());
         }
 
-        let mut data = Vec::with_capacity(length);
-        let mut curr_vaddr = buf_vaddr;
-        let end_vaddr = buf_vaddr + length as u64;
-
-        while curr_vaddr < end_vaddr {
-            // translate current page
-            let paddr = self.translate_uv2p(dtb, curr_vaddr)?;
-            // how much can we read in this page?
-            let page_offset = curr_vaddr & 0xFFF;
-            let remainder = 0x1000 - page_offset;
-            let to_read = std::cmp::min(remainder, end_vaddr - curr_vaddr);
-
-            let chunk = self.read_pa(paddr, to_read as usize)?;
-            data.extend_from_slice(&chunk);
-
-            curr_vaddr += to_read;
-        }
-
-        // convert UTF-16
-        let u16s: Vec<u16> = data
-            .chunks_exact(2)
-            .map(|c| u16::from_le_bytes([c[0], c[1]]))
-            .collect();
-
-        Ok(String::from_utf16_lossy(&u16s))
+        // convert UTF-16 straight out of the scratch buffer
+        self.read_va_dtb_with(dtb, buf_vaddr, length, |data| {
+            char::decode_utf16(
+                data.chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]])),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+        })
     }
 
     pub fn pause_vm(&self) -> Result<()> {
@@ -704,7 +2070,7 @@ impl Drop for Vmi {
         if !self.handle.is_null() {
             unsafe {
                 // only resume if we are actually paused to avoid heap corruption in libvmi
-                if self.paused.load(Ordering::SeqCst) {
+                if *self.pause_depth.lock().unwrap() > 0 {
                     vmi_resume_vm(self.handle);
                 }
                 vmi_destroy(self.handle);
@@ -713,3 +2079,444 @@ impl Drop for Vmi {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_error(builder: &VmiBuilder) -> Option<ConfigError> {
+        match builder.validate() {
+            Err(VmiError::Config(e)) => Some(e),
+            Err(e) => panic!("expected a ConfigError, got {:?}", e),
+            Ok(()) => None,
+        }
+    }
+
+    #[test]
+    fn builder_defaults() {
+        let builder = VmiBuilder::default();
+        assert_eq!(builder.backend, Backend::default());
+        assert_eq!(
+            builder.backend,
+            Backend::Kvm {
+                socket: DEFAULT_KVMI_SOCKET.into()
+            }
+        );
+        assert!(builder.events);
+        assert!(!builder.no_cache);
+        assert!(!builder.translation_cache);
+        assert_eq!(builder.json_path(), None);
+    }
+
+    #[test]
+    fn validate_accepts_a_complete_builder() {
+        let builder = VmiBuilder::new("win10", "/profiles/win10.json", "/tmp/introspector");
+        assert_eq!(config_error(&builder), None);
+        assert!(matches!(builder.domain(), Ok(Domain::Name(name)) if name == "win10"));
+        assert_eq!(builder.json_path(), Some("/profiles/win10.json"));
+
+        let by_id = VmiBuilder::for_domain_id(3, "/p.json", "/tmp/introspector");
+        assert_eq!(config_error(&by_id), None);
+        assert!(matches!(by_id.domain(), Ok(Domain::Id(3))));
+    }
+
+    #[test]
+    fn validate_wants_exactly_one_domain() {
+        let profile = InitConfig::JsonPath("/p.json".into());
+        let none = VmiBuilder::default().profile(profile.clone());
+        assert_eq!(config_error(&none), Some(ConfigError::MissingDomain));
+
+        let both = none.name("win10").domain_id(3);
+        assert_eq!(config_error(&both), Some(ConfigError::ConflictingDomain));
+    }
+
+    #[test]
+    fn validate_wants_a_profile() {
+        let builder = VmiBuilder::default().name("win10");
+        assert_eq!(config_error(&builder), Some(ConfigError::MissingProfile));
+        assert_eq!(config_error(&builder.profile(InitConfig::GlobalFile)), None);
+    }
+
+    #[test]
+    fn validate_wants_a_socket_for_kvm_only() {
+        let builder = VmiBuilder::default()
+            .name("win10")
+            .profile(InitConfig::GlobalFile);
+        let kvm = builder.clone().backend(Backend::Kvm {
+            socket: String::new(),
+        });
+        assert_eq!(config_error(&kvm), Some(ConfigError::MissingSocket));
+        assert_eq!(config_error(&builder.backend(Backend::Xen)), None);
+    }
+
+    #[test]
+    fn unicode_length_over_the_limit_is_clamped() {
+        assert_eq!(unicode_read_len(0xfffe, 0x100), 0x100);
+        assert_eq!(unicode_read_len(0x200, 0x200), 0x200);
+        // an odd limit never splits a UTF-16 unit
+        assert_eq!(unicode_read_len(0x200, 0x101), 0x100);
+    }
+
+    #[test]
+    fn unicode_length_under_the_limit_is_kept() {
+        assert_eq!(unicode_read_len(12, 0x100), 12);
+        assert_eq!(unicode_read_len(13, 0x100), 12);
+        assert_eq!(unicode_read_len(0, 0x100), 0);
+    }
+
+    /// a 4-byte guest record, two fields so misplaced bytes show
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Entry {
+        lo: u16,
+        hi: u16,
+    }
+    unsafe impl bytemuck::Zeroable for Entry {}
+    unsafe impl bytemuck::Pod for Entry {}
+
+    #[test]
+    fn read_array_lays_elements_at_their_stride() {
+        let guest: Vec<u8> = (0..12).collect();
+        let entries = read_array_with::<Entry>(3, |bytes| {
+            assert_eq!(bytes.len(), 3 * std::mem::size_of::<Entry>());
+            assert_eq!(bytes.as_ptr() as usize % std::mem::align_of::<Entry>(), 0);
+            bytes.copy_from_slice(&guest);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        for (i, entry) in entries.iter().enumerate() {
+            let at = i * 4;
+            assert_eq!(entry.lo, u16::from_le_bytes([guest[at], guest[at + 1]]));
+            assert_eq!(entry.hi, u16::from_le_bytes([guest[at + 2], guest[at + 3]]));
+        }
+    }
+
+    #[test]
+    fn read_array_of_u32_is_aligned() {
+        let values = read_array_with::<u32>(2, |bytes| {
+            assert_eq!(bytes.as_ptr() as usize % std::mem::align_of::<u32>(), 0);
+            bytes.copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0x80]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(values, [1, 0x8000_0000]);
+    }
+
+    #[test]
+    fn failed_array_read_returns_no_values() {
+        let failed = read_array_with::<Entry>(4, |_| {
+            Err(VmiError::ReadFailed {
+                addr: 0x1000,
+                msg: "unmapped".into(),
+            })
+        });
+        assert!(matches!(
+            failed,
+            Err(VmiError::ReadFailed { addr: 0x1000, .. })
+        ));
+        assert!(read_array_with::<Entry>(0, |bytes| {
+            assert!(bytes.is_empty());
+            Ok(())
+        })
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn nested_pauses_reach_the_vm_once() {
+        let vmi = Vmi::detached();
+        let (paused, resumed) = (Cell::new(0), Cell::new(0));
+        let pause = || {
+            paused.set(paused.get() + 1);
+            Ok(())
+        };
+        let resume = || {
+            resumed.set(resumed.get() + 1);
+            Ok(())
+        };
+
+        vmi.pause_with(pause).unwrap();
+        vmi.pause_with(pause).unwrap();
+        vmi.resume_with(resume).unwrap();
+        assert!(vmi.is_paused());
+        vmi.resume_with(resume).unwrap();
+        assert!(!vmi.is_paused());
+        // an unmatched resume changes nothing
+        vmi.resume_with(resume).unwrap();
+        assert_eq!((paused.get(), resumed.get()), (1, 1));
+    }
+
+    #[test]
+    fn nested_read_consistent_keeps_the_outer_pause() {
+        let vmi = Vmi::detached();
+        let resumed = Cell::new(0);
+        let resume = || {
+            resumed.set(resumed.get() + 1);
+            Ok(())
+        };
+        // the outer pause is already held, so no read_consistent below
+        // reaches libvmi
+        vmi.pause_with(|| Ok(())).unwrap();
+        let depth = vmi
+            .read_consistent(|outer| {
+                outer.read_consistent(|inner| Ok(*inner.pause_depth.lock().unwrap()))
+            })
+            .unwrap();
+        assert_eq!(depth, 3);
+        assert_eq!(*vmi.pause_depth.lock().unwrap(), 1);
+        let failed = vmi.read_consistent(|_| -> Result<()> { Err(VmiError::Disconnected) });
+        assert!(failed.is_err());
+        assert_eq!(*vmi.pause_depth.lock().unwrap(), 1);
+        vmi.resume_with(resume).unwrap();
+        assert_eq!(resumed.get(), 1);
+    }
+
+    #[test]
+    fn failed_pause_leaves_nothing_to_resume() {
+        let vmi = Vmi::detached();
+        let refused = vmi.pause_with(|| Err(VmiError::Disconnected));
+        assert!(refused.is_err());
+        assert!(!vmi.is_paused());
+        vmi.resume_with(|| panic!("nothing is paused")).unwrap();
+    }
+
+    /// guest bytes of 0x8877665544332211 as an x86 guest stores it
+    const GUEST_U64: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+    #[test]
+    fn read_64_pa_keeps_the_guest_bytes_as_they_are() {
+        let val = read_64_with(0x1000, |val| {
+            // what libvmi does: a memcpy out of guest memory
+            unsafe { ptr::copy_nonoverlapping(GUEST_U64.as_ptr(), val.cast::<u8>(), 8) };
+            status_VMI_SUCCESS
+        })
+        .unwrap();
+        assert_eq!(val, u64::from_ne_bytes(GUEST_U64));
+        if cfg!(target_endian = "little") {
+            assert_eq!(val, 0x8877_6655_4433_2211);
+        }
+    }
+
+    #[test]
+    fn write_64_pa_round_trips_through_read() {
+        let mut guest = [0u8; 8];
+        write_64_with(0x1000, 0x8877_6655_4433_2211, |val| {
+            unsafe { ptr::copy_nonoverlapping(val.cast::<u8>(), guest.as_mut_ptr(), 8) };
+            status_VMI_SUCCESS
+        })
+        .unwrap();
+        assert_eq!(guest, 0x8877_6655_4433_2211u64.to_ne_bytes());
+        let back = read_64_with(0x1000, |val| {
+            unsafe { ptr::copy_nonoverlapping(guest.as_ptr(), val.cast::<u8>(), 8) };
+            status_VMI_SUCCESS
+        })
+        .unwrap();
+        assert_eq!(back, 0x8877_6655_4433_2211);
+    }
+
+    #[test]
+    fn failed_64_bit_access_names_the_address() {
+        let read = read_64_with(0x2000, |_| status_VMI_FAILURE);
+        assert!(matches!(
+            read,
+            Err(VmiError::ReadFailed { addr: 0x2000, .. })
+        ));
+        let write = write_64_with(0x3000, 1, |_| status_VMI_FAILURE);
+        assert!(matches!(
+            write,
+            Err(VmiError::ReadFailed { addr: 0x3000, .. })
+        ));
+    }
+
+    /// three pages of 0xff starting at 0x10000, zeroed through zero_va_with
+    fn zeroed(vaddr: u64, len: usize) -> (Vec<u8>, Vec<(u64, usize)>) {
+        let mut memory = vec![0xffu8; 3 * PAGE_SIZE as usize];
+        let mut writes = Vec::new();
+        zero_va_with(vaddr, len, |addr, zeros| {
+            let at = (addr - 0x10000) as usize;
+            memory[at..at + zeros.len()].copy_from_slice(zeros);
+            writes.push((addr, zeros.len()));
+            Ok(())
+        })
+        .unwrap();
+        (memory, writes)
+    }
+
+    #[test]
+    fn zero_va_splits_at_page_boundaries() {
+        let (memory, writes) = zeroed(0x10ff0, 0x1020);
+        assert_eq!(
+            writes,
+            [(0x10ff0, 0x10), (0x11000, 0x1000), (0x12000, 0x10)]
+        );
+        assert!(memory[0xff0..0x2010].iter().all(|&b| b == 0));
+        // nothing outside the range is touched
+        assert!(memory[..0xff0].iter().all(|&b| b == 0xff));
+        assert!(memory[0x2010..].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn zero_va_within_a_page_is_one_write() {
+        let (memory, writes) = zeroed(0x10010, 0x20);
+        assert_eq!(writes, [(0x10010, 0x20)]);
+        assert!(memory[0x10..0x30].iter().all(|&b| b == 0));
+        assert_eq!((memory[0xf], memory[0x30]), (0xff, 0xff));
+        assert!(zeroed(0x10010, 0).1.is_empty());
+    }
+
+    #[test]
+    fn zero_va_stops_at_the_first_failed_page() {
+        let mut writes = Vec::new();
+        let result = zero_va_with(0x10800, 0x2000, |addr, _| {
+            writes.push(addr);
+            if addr == 0x11000 {
+                return Err(VmiError::TranslateFailed { addr });
+            }
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(writes, [0x10800, 0x11000]);
+    }
+
+    #[test]
+    fn kernel_pid_translates_through_the_kernel() {
+        let mapped = is_mapped_with(0, || status_VMI_SUCCESS, |_| panic!("pid 0 is the kernel"));
+        assert!(mapped);
+        assert!(!is_mapped_with(
+            0,
+            || status_VMI_FAILURE,
+            |_| status_VMI_SUCCESS
+        ));
+    }
+
+    #[test]
+    fn process_pid_translates_in_its_own_space() {
+        let kernel = || -> status_t { panic!("a process address needs its pid") };
+        assert!(is_mapped_with(4, kernel, |pid| {
+            assert_eq!(pid, 4);
+            status_VMI_SUCCESS
+        }));
+        // paged out in the process, whatever the kernel maps there
+        assert!(!is_mapped_with(
+            4,
+            || status_VMI_SUCCESS,
+            |_| status_VMI_FAILURE
+        ));
+    }
+
+    #[test]
+    fn vmi_ref_has_no_drop() {
+        // dropping a borrowed handle must never reach vmi_destroy
+        assert!(!std::mem::needs_drop::<VmiRef<'static>>());
+    }
+
+    #[test]
+    fn vmi_ref_outlived_by_the_listening_vmi() {
+        let vmi = Vmi::detached();
+        vmi.pause_with(|| Ok(())).unwrap();
+        let outer = LISTENING.replace(&vmi);
+        for _ in 0..3 {
+            // as dispatch_cb does once per event
+            let borrowed = unsafe { VmiRef::listening(vmi.handle) }.unwrap();
+            assert!(ptr::eq(borrowed.get(), &vmi));
+            assert!(borrowed.is_paused());
+        }
+        LISTENING.set(outer);
+        // still whole after the borrows are gone
+        assert!(vmi.is_paused());
+        vmi.resume_with(|| Ok(())).unwrap();
+    }
+
+    #[test]
+    fn vmi_ref_needs_a_listening_vmi() {
+        let vmi = Vmi::detached();
+        assert!(unsafe { VmiRef::listening(vmi.handle) }.is_none());
+        let outer = LISTENING.replace(&vmi);
+        let other = 0x1000 as vmi_instance_t;
+        assert!(unsafe { VmiRef::listening(other) }.is_none());
+        LISTENING.set(outer);
+    }
+
+    /// a vcpu's register file, read and written the way libvmi copies it
+    struct FakeVcpu(RefCell<registers_t>);
+
+    impl FakeVcpu {
+        fn get(&self) -> Result<registers_t> {
+            get_vcpuregs_with(1, |regs| {
+                unsafe { *regs = *self.0.borrow() };
+                status_VMI_SUCCESS
+            })
+        }
+
+        fn set(&self, regs: &registers_t) -> Result<()> {
+            set_vcpuregs_with(regs, 1, |regs| {
+                *self.0.borrow_mut() = unsafe { *regs };
+                status_VMI_SUCCESS
+            })
+        }
+    }
+
+    #[test]
+    fn vcpuregs_round_trip() {
+        let mut boot: registers_t = unsafe { std::mem::zeroed() };
+        boot.x86.rip = 0xfffff800_12340000;
+        boot.x86.rsp = 0xffffa000_00001000;
+        boot.x86.cr3 = 0x1aa000;
+        let vcpu = FakeVcpu(RefCell::new(boot));
+
+        let mut regs = vcpu.get().unwrap();
+        unsafe { regs.x86.rip += 5 };
+        vcpu.set(&regs).unwrap();
+        // what was written is what comes back, fields left alone included
+        let back = unsafe { vcpu.get().unwrap().x86 };
+        assert_eq!(back.rip, 0xfffff800_12340005);
+        assert_eq!(back.rsp, 0xffffa000_00001000);
+        assert_eq!(back.cr3, 0x1aa000);
+        assert_eq!(unsafe { regs.x86.rip }, 0xfffff800_12340005);
+    }
+
+    #[test]
+    fn failed_vcpuregs_name_the_vcpu() {
+        match get_vcpuregs_with(3, |_| status_VMI_FAILURE) {
+            Err(VmiError::ReadFailed { msg, .. }) => assert!(msg.contains("vcpu 3")),
+            other => panic!("expected ReadFailed, got {:?}", other.map(|_| ())),
+        }
+        let regs: registers_t = unsafe { std::mem::zeroed() };
+        match set_vcpuregs_with(&regs, 2, |_| status_VMI_FAILURE) {
+            Err(VmiError::ReadFailed { msg, .. }) => assert!(msg.contains("vcpu 2")),
+            other => panic!("expected ReadFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn scratch_is_reused_across_a_tight_loop() {
+        let first = with_scratch(0x1000, |buf| buf.as_ptr() as usize);
+        for len in [0x10, 0x1000, 0x800, 1] {
+            for _ in 0..100 {
+                let at = with_scratch(len, |buf| {
+                    assert_eq!(buf.len(), len);
+                    // the last call's bytes never leak into the next
+                    assert!(buf.iter().all(|&b| b == 0));
+                    buf.fill(0xcc);
+                    buf.as_ptr() as usize
+                });
+                assert_eq!(at, first);
+            }
+        }
+        assert!(SCRATCH.with(|scratch| scratch.borrow().capacity()) >= 0x1000);
+    }
+
+    #[test]
+    fn nested_scratch_gets_its_own_buffer() {
+        with_scratch(0x20, |outer| {
+            outer.fill(0xaa);
+            with_scratch(0x20, |inner| {
+                assert_ne!(inner.as_ptr(), outer.as_ptr());
+                assert!(inner.iter().all(|&b| b == 0));
+                inner.fill(0xbb);
+            });
+            assert!(outer.iter().all(|&b| b == 0xaa));
+        });
+    }
+}
@@ -0,0 +1,342 @@
+//! memory watchpoints - report every access to a guest address
+//!
+//! the page holding the address gets a mem event that strips the watched
+//! permissions, so any access to that page traps; hits outside the watched
+//! bytes are let through silently. to let the trapped instruction finish,
+//! Singlestep lifts the restriction, steps the vcpu and restores it, Emulate
+//! has the hypervisor emulate the access with the restriction kept (the
+//! generic path, used where singlestep isn't available e.g. AMD).
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+use crate::dispatcher::HandlerId;
+use crate::error::{Result, VmiError};
+use crate::event_stream::{EventSink, GuestEvent};
+use crate::ffi::{
+    event_response_t, vmi_event_t, VMI_EVENTS_VERSION, VMI_EVENT_RESPONSE_EMULATE,
+    VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP, VMI_MEMACCESS_N, VMI_MEMACCESS_R, VMI_MEMACCESS_W,
+    VMI_MEMACCESS_X,
+};
+use crate::hook::Registers;
+use crate::os::{Event, EventContext};
+use crate::vmi::{event_helpers, gfn_to_pa, pa_to_gfn, page_offset, VmiEvent, VmiRef, PAGE_SIZE};
+
+/// access spec such as "w", "rw" or "rwx" to VMI_MEMACCESS_* bits
+pub fn parse_access(spec: &str) -> Result<u32> {
+    let mut access = 0;
+    for c in spec.chars() {
+        access |= match c.to_ascii_lowercase() {
+            'r' => VMI_MEMACCESS_R,
+            'w' => VMI_MEMACCESS_W,
+            'x' => VMI_MEMACCESS_X,
+            _ => {
+                return Err(VmiError::Other(format!(
+                    "bad access '{}', expected a mix of r, w and x",
+                    spec
+                )));
+            }
+        };
+    }
+    if access == 0 {
+        return Err(VmiError::Other("empty access spec".into()));
+    }
+    Ok(access)
+}
+
+/// VMI_MEMACCESS_* bits as "rw-" style flags
+pub fn format_access(access: u32) -> String {
+    [
+        (VMI_MEMACCESS_R, 'r'),
+        (VMI_MEMACCESS_W, 'w'),
+        (VMI_MEMACCESS_X, 'x'),
+    ]
+    .iter()
+    .map(|&(bit, c)| if access & bit != 0 { c } else { '-' })
+    .collect()
+}
+
+/// context passed to watchpoint callbacks, the mem event counterpart of
+/// HookContext
+pub struct MemEventContext<'a> {
+    /// the listening vmi, only valid for the callback
+    pub vmi: VmiRef<'a>,
+    pub vcpu_id: u32,
+    pub gfn: u64,
+    /// byte offset within the page
+    pub offset: u64,
+    /// faulting guest linear address, when the hardware reported one
+    pub gla: Option<u64>,
+    /// VMI_MEMACCESS_* bits of the access
+    pub access: u32,
+    /// the faulting vcpu's registers, rip is the accessing instruction
+    pub regs: Registers,
+}
+
+impl<'a> MemEventContext<'a> {
+    /// None when `event` isn't a mem_event or carries no registers
+    ///
+    /// # Safety
+    /// `event` must be the live event passed to the current callback
+    pub unsafe fn from_event(vmi: VmiRef<'a>, event: *mut vmi_event_t) -> Option<Self> {
+        unsafe {
+            Some(Self {
+                vmi,
+                regs: event_helpers::get_mem_regs(event)?,
+                vcpu_id: (*event).vcpu_id,
+                gfn: event_helpers::get_mem_gfn(event),
+                offset: event_helpers::get_mem_offset(event),
+                gla: event_helpers::get_mem_gla(event),
+                access: event_helpers::get_mem_access(event) as u32,
+            })
+        }
+    }
+
+    /// physical address that was accessed
+    pub fn paddr(&self) -> u64 {
+        gfn_to_pa(self.gfn) + self.offset
+    }
+
+    pub fn rip(&self) -> u64 {
+        self.regs.rip
+    }
+}
+
+/// watchpoint callback, replaces the default MemoryAccess event
+pub type WatchCallback = Arc<dyn Fn(&MemEventContext) + Send + Sync>;
+
+/// how a trapped access is allowed to complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// lift the restriction, singlestep, restore. Intel only, and other vcpus
+    /// touching the page while it is open go unnoticed
+    Singlestep,
+    /// hypervisor emulates the faulting instruction, the page stays protected
+    Emulate,
+}
+
+/// state shared with the event handlers
+struct WatchState {
+    gfn: u64,
+    access: u32,
+    /// watched byte range within the page
+    start: u64,
+    end: u64,
+    mode: WatchMode,
+    /// vcpus stepping over an access with the restriction lifted
+    stepping: Mutex<HashSet<u32>>,
+    events: EventSink,
+    callback: Option<WatchCallback>,
+}
+
+/// reports every access to `len` bytes at a virtual address as MemoryAccess
+pub struct Watchpoint {
+    vaddr: u64,
+    len: u64,
+    access: u32,
+    dtb: Option<u64>,
+    mode: Option<WatchMode>,
+    callback: Option<WatchCallback>,
+    handlers: Vec<HandlerId>,
+}
+
+impl Watchpoint {
+    /// watch a kernel address for VMI_MEMACCESS_* `access`
+    pub fn new(vaddr: u64, access: u32) -> Self {
+        Self {
+            vaddr,
+            len: 1,
+            access,
+            dtb: None,
+            mode: None,
+            callback: None,
+            handlers: Vec::new(),
+        }
+    }
+
+    /// bytes to watch, must not cross into the next page
+    pub fn len(mut self, len: u64) -> Self {
+        self.len = len.max(1);
+        self
+    }
+
+    /// translate through a process's page tables instead of the kernel's
+    pub fn dtb(mut self, dtb: u64) -> Self {
+        self.dtb = Some(dtb);
+        self
+    }
+
+    /// run `callback` on every hit instead of emitting MemoryAccess
+    pub fn on_access<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&MemEventContext) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// force a mode, picked from singlestep support by default
+    pub fn mode(mut self, mode: WatchMode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+}
+
+impl Event for Watchpoint {
+    fn enable(&mut self, ctx: &EventContext) -> Result<()> {
+        if !self.handlers.is_empty() {
+            return Ok(());
+        }
+
+        let vmi_lock = ctx.vmi.lock().unwrap();
+        let paddr = match self.dtb {
+            Some(dtb) => vmi_lock.translate_uv2p(dtb, self.vaddr)?,
+            None => vmi_lock.translate_kv2p(self.vaddr)?,
+        };
+        let start = page_offset(paddr);
+        if start + self.len > PAGE_SIZE {
+            return Err(VmiError::Other(format!(
+                "{:#x}+{:#x} crosses a page boundary",
+                self.vaddr, self.len
+            )));
+        }
+
+        let mode = match self.mode {
+            Some(WatchMode::Singlestep) if !vmi_lock.supports_singlestep() => {
+                return Err(VmiError::Other(
+                    "singlestep watch mode needs singlestep support (Intel)".into(),
+                ));
+            }
+            Some(mode) => mode,
+            None if vmi_lock.supports_singlestep() => WatchMode::Singlestep,
+            None => WatchMode::Emulate,
+        };
+
+        let state = Arc::new(WatchState {
+            gfn: pa_to_gfn(paddr),
+            access: self.access,
+            start,
+            end: start + self.len,
+            mode,
+            stepping: Mutex::new(HashSet::new()),
+            events: ctx.events.clone(),
+            callback: self.callback.clone(),
+        });
+
+        if mode == WatchMode::Singlestep {
+            // registered off, toggled per vcpu from the mem event
+            let vcpus = vmi_lock.num_vcpus();
+            let mask = if vcpus >= 32 {
+                u32::MAX
+            } else {
+                (1u32 << vcpus) - 1
+            };
+            let mut ss_event = VmiEvent::new(VMI_EVENTS_VERSION);
+            ss_event.set_singlestep_vcpus(mask, false);
+            let state = state.clone();
+            self.handlers.push(ctx.dispatcher.register(
+                &vmi_lock,
+                ss_event,
+                move |vmi, event| unsafe { on_singlestep(&state, vmi, event) },
+            )?);
+        }
+
+        let mut mem_event = VmiEvent::new(VMI_EVENTS_VERSION);
+        mem_event.set_mem_event(state.gfn, self.access, 0);
+        let registered = {
+            let state = state.clone();
+            ctx.dispatcher
+                .register(&vmi_lock, mem_event, move |vmi, event| unsafe {
+                    on_access(&state, vmi, event)
+                })
+        };
+        match registered {
+            Ok(id) => self.handlers.push(id),
+            Err(e) => {
+                for id in self.handlers.drain(..) {
+                    let _ = ctx.dispatcher.unregister(&vmi_lock, id);
+                }
+                return Err(e);
+            }
+        }
+
+        log::info!(
+            "[Watch] Enabled on {:#x} (gfn {:#x}, {}, {:?})",
+            self.vaddr,
+            state.gfn,
+            format_access(self.access),
+            mode
+        );
+        Ok(())
+    }
+
+    fn disable(&mut self, ctx: &EventContext) -> Result<()> {
+        if self.handlers.is_empty() {
+            return Ok(());
+        }
+        let vmi_lock = ctx.vmi.lock().unwrap();
+        for id in self.handlers.drain(..) {
+            ctx.dispatcher.unregister(&vmi_lock, id)?;
+        }
+        log::info!("[Watch] Disabled");
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "Watch"
+    }
+}
+
+unsafe fn on_access(
+    state: &WatchState,
+    vmi: VmiRef<'_>,
+    event: *mut vmi_event_t,
+) -> event_response_t {
+    unsafe {
+        let offset = event_helpers::get_mem_offset(event);
+        if (state.start..state.end).contains(&offset) {
+            match (&state.callback, MemEventContext::from_event(vmi, event)) {
+                (Some(callback), Some(ctx)) => callback(&ctx),
+                (Some(_), None) => log::warn!("[Watch] hit without registers, callback skipped"),
+                (None, ctx) => state.events.emit(GuestEvent::MemoryAccess {
+                    vcpu: (*event).vcpu_id,
+                    rip: ctx.map(|c| c.rip()).unwrap_or(0),
+                    access: event_helpers::get_mem_access(event) as u32,
+                    gla: event_helpers::get_mem_gla(event),
+                }),
+            }
+        }
+
+        match state.mode {
+            WatchMode::Emulate => VMI_EVENT_RESPONSE_EMULATE,
+            WatchMode::Singlestep => {
+                if let Err(e) = vmi.set_mem_access(state.gfn, VMI_MEMACCESS_N) {
+                    log::warn!("[Watch] unprotect failed: {}", e);
+                    return VMI_EVENT_RESPONSE_EMULATE;
+                }
+                state.stepping.lock().unwrap().insert((*event).vcpu_id);
+                VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP
+            }
+        }
+    }
+}
+
+/// reprotect once no vcpu is stepping through the open page anymore
+unsafe fn on_singlestep(
+    state: &WatchState,
+    vmi: VmiRef<'_>,
+    event: *mut vmi_event_t,
+) -> event_response_t {
+    unsafe {
+        let mut stepping = state.stepping.lock().unwrap();
+        if !stepping.remove(&(*event).vcpu_id) {
+            return 0;
+        }
+        if stepping.is_empty()
+            && let Err(e) = vmi.set_mem_access(state.gfn, state.access)
+        {
+            log::warn!("[Watch] reprotect failed: {}", e);
+        }
+        VMI_EVENT_RESPONSE_TOGGLE_SINGLESTEP
+    }
+}